@@ -11,6 +11,7 @@ CREATE TABLE IF NOT EXISTS asset_chunks (
     chunk_index INTEGER NOT NULL,
     data BLOB NOT NULL,
     size INTEGER NOT NULL CHECK (size > 0),
+    checksum TEXT,
     created_at INTEGER NOT NULL DEFAULT (CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER))
 )STRICT;
 
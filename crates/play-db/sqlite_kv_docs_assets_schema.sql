@@ -71,6 +71,9 @@ CREATE INDEX IF NOT EXISTS idx_assets_created_at
 CREATE INDEX IF NOT EXISTS idx_assets_raw_file_path
     ON assets (raw_file_path);
 
+CREATE INDEX IF NOT EXISTS idx_assets_checksum
+    ON assets (checksum);
+
 
 
 -- =========================================================
@@ -4,8 +4,9 @@ use sha2::{Digest, Sha256};
 use tokio::task;
 
 use crate::{
-    asset_chunk_create, asset_chunks_delete_for_asset, assets_get, init_asset_chunk_db,
-    init_main_db, random_id, AssetChunkRef, AssetMetadata, AssetMetadataInput,
+    asset_chunk_create, asset_chunks_delete_for_asset, assets_delete, assets_find_by_checksum,
+    assets_get, chunk_checksum, init_asset_chunk_db, init_main_db, random_id, AssetChunkRef,
+    AssetMetadata, AssetMetadataInput,
 };
 use crate::assets::assets_create_with_id;
 use rusqlite::types::Value as SqlValue;
@@ -37,7 +38,8 @@ fn detect_mime_type(data: &[u8]) -> Option<String> {
     infer::get(data).map(|kind| kind.mime_type().to_string())
 }
 
-fn parse_chunk_size(value: &str) -> rusqlite::Result<i64> {
+/// parse sizes like "512KB" or "4M" into bytes.
+pub fn parse_chunk_size(value: &str) -> rusqlite::Result<i64> {
     let raw = value.trim();
     if raw.is_empty() {
         return Err(service_error("chunk_size must not be empty"));
@@ -134,7 +136,7 @@ fn read_chunks_for_db(
         .collect::<Vec<_>>()
         .join(",");
     let sql = format!(
-        "SELECT chunk_index, data FROM asset_chunks WHERE asset_id = ?1 AND chunk_index IN ({})",
+        "SELECT chunk_index, data, checksum FROM asset_chunks WHERE asset_id = ?1 AND chunk_index IN ({})",
         placeholders
     );
     let mut params: Vec<SqlValue> = Vec::with_capacity(1 + chunk_indices.len());
@@ -146,9 +148,35 @@ fn read_chunks_for_db(
     init_asset_chunk_db(&conn)?;
     let mut stmt = conn.prepare(&sql)?;
     let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
-        Ok((row.get(0)?, row.get(1)?))
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
     })?;
-    rows.collect()
+    let mut chunks = Vec::new();
+    for row in rows {
+        let (chunk_index, data, checksum): (i64, Vec<u8>, Option<String>) = row?;
+        ensure_chunk_checksum(chunk_index, &data, checksum.as_deref())?;
+        chunks.push((chunk_index, data));
+    }
+    Ok(chunks)
+}
+
+fn ensure_chunk_checksum(
+    chunk_index: i64,
+    data: &[u8],
+    checksum: Option<&str>,
+) -> rusqlite::Result<()> {
+    match checksum {
+        Some(expected) if !chunk_checksum(data).eq_ignore_ascii_case(expected) => Err(
+            service_error(&format!("chunk {} checksum mismatch", chunk_index)),
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn checksum_for_file(file_path: &Path) -> rusqlite::Result<String> {
+    let mut file = std::fs::File::open(file_path).map_err(wrap_error)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(wrap_error)?;
+    Ok(hex::encode(hasher.finalize()))
 }
 
 pub async fn insert_asset_from_path(
@@ -501,6 +529,114 @@ pub async fn read_asset_bytes(
     Ok(combined)
 }
 
+/// insert the file unless an identical (same sha256) valid asset already exists.
+/// returns the stored asset and whether an existing one was reused.
+pub async fn insert_asset_dedup_from_path(
+    meta_db_path: impl AsRef<Path>,
+    chunk_db_paths: Vec<PathBuf>,
+    name: Option<String>,
+    mime_type: Option<String>,
+    chunk_size: i64,
+    file_path: impl AsRef<Path>,
+) -> rusqlite::Result<(AssetMetadata, bool)> {
+    let meta_db_path = meta_db_path.as_ref().to_path_buf();
+    let file_path = file_path.as_ref().to_path_buf();
+
+    let meta_db_path_for_find = meta_db_path.clone();
+    let file_path_for_find = file_path.clone();
+    let existing = task::spawn_blocking(move || -> rusqlite::Result<Option<AssetMetadata>> {
+        let checksum = checksum_for_file(&file_path_for_find)?;
+        let conn = rusqlite::Connection::open(meta_db_path_for_find)?;
+        init_main_db(&conn)?;
+        assets_find_by_checksum(&conn, &checksum)
+    })
+    .await
+    .map_err(wrap_error)??;
+    if let Some(existing) = existing {
+        return Ok((existing, true));
+    }
+
+    let metadata = insert_asset_from_path(
+        meta_db_path,
+        chunk_db_paths,
+        name,
+        mime_type,
+        chunk_size,
+        file_path,
+    )
+    .await?;
+    Ok((metadata, false))
+}
+
+pub async fn get_asset(
+    meta_db_path: impl AsRef<Path>,
+    asset_id: &str,
+) -> rusqlite::Result<Option<AssetMetadata>> {
+    let meta_db_path = meta_db_path.as_ref().to_path_buf();
+    let asset_id = asset_id.to_string();
+    task::spawn_blocking(move || -> rusqlite::Result<Option<AssetMetadata>> {
+        let conn = rusqlite::Connection::open(meta_db_path)?;
+        init_main_db(&conn)?;
+        assets_get(&conn, &asset_id)
+    })
+    .await
+    .map_err(wrap_error)?
+}
+
+/// read and verify a single chunk, so large assets can be streamed chunk by chunk.
+pub async fn read_asset_chunk(
+    chunk_db_paths: &[PathBuf],
+    metadata: &AssetMetadata,
+    chunk_index: i64,
+) -> rusqlite::Result<Vec<u8>> {
+    let chunk_ref = metadata
+        .chunks
+        .iter()
+        .find(|chunk| chunk.chunk_index == chunk_index)
+        .ok_or_else(|| service_error("chunk_index out of range"))?;
+    let db_path = usize::try_from(chunk_ref.db_index)
+        .ok()
+        .and_then(|index| chunk_db_paths.get(index))
+        .cloned()
+        .ok_or_else(|| service_error("chunk db_index out of range"))?;
+
+    let asset_id = metadata.id.clone();
+    let mut rows = task::spawn_blocking(move || {
+        read_chunks_for_db(db_path, asset_id, vec![chunk_index])
+    })
+    .await
+    .map_err(wrap_error)??;
+    let (_, data) = rows.pop().ok_or_else(|| service_error("chunk data missing"))?;
+
+    let expected_size = if chunk_index == metadata.chunks.len() as i64 - 1 {
+        metadata.size - chunk_index * metadata.chunk_size
+    } else {
+        metadata.chunk_size
+    };
+    if data.len() as i64 != expected_size {
+        return Err(service_error("chunk size mismatch"));
+    }
+    Ok(data)
+}
+
+pub async fn delete_asset(
+    meta_db_path: impl AsRef<Path>,
+    chunk_db_paths: Vec<PathBuf>,
+    asset_id: &str,
+) -> rusqlite::Result<usize> {
+    let meta_db_path = meta_db_path.as_ref().to_path_buf();
+    let asset_id_for_db = asset_id.to_string();
+    let deleted = task::spawn_blocking(move || -> rusqlite::Result<usize> {
+        let conn = rusqlite::Connection::open(meta_db_path)?;
+        init_main_db(&conn)?;
+        assets_delete(&conn, &asset_id_for_db)
+    })
+    .await
+    .map_err(wrap_error)??;
+    cleanup_chunks(asset_id, &chunk_db_paths).await;
+    Ok(deleted)
+}
+
 pub async fn read_asset_to_file(
     meta_db_path: impl AsRef<Path>,
     chunk_db_paths: Vec<PathBuf>,
@@ -584,6 +720,54 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn asset_service_dedups_and_streams_chunks() -> rusqlite::Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "play_db_asset_service_dedup_{}",
+            Uuid::new_v4().as_simple()
+        ));
+        fs::create_dir_all(&dir).await.map_err(wrap_error)?;
+
+        let main_db = dir.join("main.db");
+        let chunk_dbs = vec![dir.join("chunk0.db"), dir.join("chunk1.db")];
+        let file_a = dir.join("a.bin");
+        let file_b = dir.join("b.bin");
+        let data = b"same content in two files";
+        fs::write(&file_a, data).await.map_err(wrap_error)?;
+        fs::write(&file_b, data).await.map_err(wrap_error)?;
+
+        let (first, reused) =
+            insert_asset_dedup_from_path(&main_db, chunk_dbs.clone(), None, None, 8, &file_a)
+                .await?;
+        assert!(!reused);
+        let (second, reused) =
+            insert_asset_dedup_from_path(&main_db, chunk_dbs.clone(), None, None, 8, &file_b)
+                .await?;
+        assert!(reused);
+        assert_eq!(first.id, second.id);
+
+        let mut streamed = Vec::new();
+        for index in 0..first.chunks.len() as i64 {
+            streamed.extend(read_asset_chunk(&chunk_dbs, &first, index).await?);
+        }
+        assert_eq!(streamed, data);
+
+        // corrupt one chunk and make sure it is detected
+        let conn = rusqlite::Connection::open(&chunk_dbs[1])?;
+        conn.execute(
+            "UPDATE asset_chunks SET data = X'00000000' WHERE asset_id = ?1 AND chunk_index = 1",
+            [&first.id],
+        )?;
+        assert!(read_asset_chunk(&chunk_dbs, &first, 1).await.is_err());
+        assert!(read_asset_bytes(&main_db, chunk_dbs.clone(), &first.id).await.is_err());
+
+        assert_eq!(delete_asset(&main_db, chunk_dbs.clone(), &first.id).await?, 1);
+        assert!(get_asset(&main_db, &first.id).await?.is_none());
+
+        let _ = fs::remove_dir_all(&dir).await;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn asset_service_inserts_real_file_with_mime() -> rusqlite::Result<()> {
         let file_path = PathBuf::from(
@@ -86,9 +86,19 @@ pub struct AssetChunk {
     pub chunk_index: i64,
     pub data: Vec<u8>,
     pub size: i64,
+    /// sha256 of `data`, missing for chunks written before checksums were stored
+    pub checksum: Option<String>,
     pub created_at: i64,
 }
 
+impl AssetChunk {
+    pub fn checksum_matches(&self) -> bool {
+        self.checksum
+            .as_ref()
+            .map_or(true, |expected| chunk_checksum(&self.data).eq_ignore_ascii_case(expected))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct AssetWithChunks {
     pub metadata: AssetMetadata,
@@ -103,6 +113,10 @@ fn expected_chunk_count(size: i64, chunk_size: i64) -> i64 {
     }
 }
 
+pub fn chunk_checksum(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
 fn checksum_for_chunks(chunks: &[AssetChunk]) -> String {
     let mut ordered: Vec<&AssetChunk> = chunks.iter().collect();
     ordered.sort_by_key(|chunk| chunk.chunk_index);
@@ -138,7 +152,7 @@ fn asset_chunks_match_metadata(metadata: &AssetMetadata, chunks: &[AssetChunk])
     }
 
     let total_size: i64 = chunks.iter().map(|chunk| chunk.size).sum();
-    total_size == metadata.size
+    total_size == metadata.size && chunks.iter().all(AssetChunk::checksum_matches)
 }
 
 fn asset_checksum_matches(metadata: &AssetMetadata, chunks: &[AssetChunk]) -> bool {
@@ -208,6 +222,25 @@ pub fn assets_get(conn: &Connection, id: &str) -> rusqlite::Result<Option<AssetM
     .optional()
 }
 
+/// the newest valid asset with the given content checksum, used to deduplicate uploads.
+pub fn assets_find_by_checksum(
+    conn: &Connection,
+    checksum: &str,
+) -> rusqlite::Result<Option<AssetMetadata>> {
+    let id: Option<String> = conn
+        .query_row(
+            "SELECT id FROM assets WHERE checksum = ?1 AND valid = 1
+             ORDER BY created_at DESC LIMIT 1",
+            params![checksum],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match id {
+        Some(id) => assets_get(conn, &id),
+        None => Ok(None),
+    }
+}
+
 pub fn assets_update(
     conn: &Connection,
     id: &str,
@@ -271,16 +304,16 @@ pub fn asset_chunk_create(
 ) -> rusqlite::Result<i64> {
     let size = blob_size(data)?;
     conn.execute(
-        "INSERT INTO asset_chunks (asset_id, chunk_index, data, size)
-         VALUES (?1, ?2, ?3, ?4)",
-        params![asset_id, chunk_index, data, size],
+        "INSERT INTO asset_chunks (asset_id, chunk_index, data, size, checksum)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![asset_id, chunk_index, data, size, chunk_checksum(data)],
     )?;
     Ok(conn.last_insert_rowid())
 }
 
 pub fn asset_chunk_get(conn: &Connection, id: i64) -> rusqlite::Result<Option<AssetChunk>> {
     conn.query_row(
-        "SELECT id, asset_id, chunk_index, data, size, created_at
+        "SELECT id, asset_id, chunk_index, data, size, checksum, created_at
          FROM asset_chunks WHERE id = ?1",
         params![id],
         |row| {
@@ -290,7 +323,8 @@ pub fn asset_chunk_get(conn: &Connection, id: i64) -> rusqlite::Result<Option<As
                 chunk_index: row.get(2)?,
                 data: row.get(3)?,
                 size: row.get(4)?,
-                created_at: row.get(5)?,
+                checksum: row.get(5)?,
+                created_at: row.get(6)?,
             })
         },
     )
@@ -303,7 +337,7 @@ pub fn asset_chunk_get_by_index(
     chunk_index: i64,
 ) -> rusqlite::Result<Option<AssetChunk>> {
     conn.query_row(
-        "SELECT id, asset_id, chunk_index, data, size, created_at
+        "SELECT id, asset_id, chunk_index, data, size, checksum, created_at
          FROM asset_chunks WHERE asset_id = ?1 AND chunk_index = ?2",
         params![asset_id, chunk_index],
         |row| {
@@ -313,7 +347,8 @@ pub fn asset_chunk_get_by_index(
                 chunk_index: row.get(2)?,
                 data: row.get(3)?,
                 size: row.get(4)?,
-                created_at: row.get(5)?,
+                checksum: row.get(5)?,
+                created_at: row.get(6)?,
             })
         },
     )
@@ -327,8 +362,8 @@ pub fn asset_chunk_update(
 ) -> rusqlite::Result<usize> {
     let size = blob_size(data)?;
     conn.execute(
-        "UPDATE asset_chunks SET data = ?2, size = ?3 WHERE id = ?1",
-        params![id, data, size],
+        "UPDATE asset_chunks SET data = ?2, size = ?3, checksum = ?4 WHERE id = ?1",
+        params![id, data, size, chunk_checksum(data)],
     )
 }
 
@@ -351,7 +386,7 @@ pub fn asset_chunks_for_asset(
     asset_id: &str,
 ) -> rusqlite::Result<Vec<AssetChunk>> {
     let mut stmt = conn.prepare(
-        "SELECT id, asset_id, chunk_index, data, size, created_at
+        "SELECT id, asset_id, chunk_index, data, size, checksum, created_at
          FROM asset_chunks WHERE asset_id = ?1 ORDER BY chunk_index ASC",
     )?;
     let rows = stmt.query_map(params![asset_id], |row| {
@@ -361,7 +396,8 @@ pub fn asset_chunks_for_asset(
             chunk_index: row.get(2)?,
             data: row.get(3)?,
             size: row.get(4)?,
-            created_at: row.get(5)?,
+            checksum: row.get(5)?,
+            created_at: row.get(6)?,
         })
     })?;
     rows.collect()
@@ -10,7 +10,23 @@ pub fn init_main_db(conn: &Connection) -> rusqlite::Result<()> {
 }
 
 pub fn init_asset_chunk_db(conn: &Connection) -> rusqlite::Result<()> {
-    conn.execute_batch(ASSET_CHUNK_SCHEMA)
+    conn.execute_batch(ASSET_CHUNK_SCHEMA)?;
+    // shards created before chunk checksums existed
+    if !table_has_column(conn, "asset_chunks", "checksum")? {
+        conn.execute_batch("ALTER TABLE asset_chunks ADD COLUMN checksum TEXT")?;
+    }
+    Ok(())
+}
+
+fn table_has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
 }
 
 fn json_to_string(value: &Value) -> rusqlite::Result<String> {
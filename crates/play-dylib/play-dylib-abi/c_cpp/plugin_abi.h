@@ -88,6 +88,38 @@ typedef struct {
 #endif
 void handle_request(int64_t request_id);
 
+/**
+ * In-process ABI (v2), used instead of handle_request when play_abi_version is exported
+ *
+ * The request is the JSON of HttpRequest in a buffer owned by the host for the call.
+ * Since v4 its "headers" are a list of [name, value] in the order they came, "body" is a list
+ * of bytes, and it has "client_ip" and "path_params"; before v4 the host sends headers as an
+ * object and the body as a string.
+ * Answer with host->send_head (JSON: {"status_code":200,"headers":[],"error":null}) once,
+ * then any number of host->send_chunk calls. The host copies every buffer before the
+ * callback returns. Callbacks return non-zero when the client went away.
+ */
+#define PLAY_ABI_VERSION 4
+
+/* receives the reply of host_call, the buffer is only valid during the callback */
+typedef void (*PlayReplyFn)(void* reply_ctx, const uint8_t* reply, size_t reply_len);
+
+typedef struct {
+    void* ctx;
+    int32_t (*send_head)(void* ctx, const uint8_t* head, size_t head_len);
+    int32_t (*send_chunk)(void* ctx, const uint8_t* chunk, size_t chunk_len);
+    /* since v3: call is the JSON of a host service call, e.g. {"service":"kv_get","key":"a"},
+     * the reply is {"Ok":<value>} or {"Err":"<message>"} */
+    int32_t (*host_call)(void* ctx, const uint8_t* call, size_t call_len,
+                         void* reply_ctx, PlayReplyFn on_reply);
+} HostCallbacks;
+
+/* return PLAY_ABI_VERSION */
+uint32_t play_abi_version(void);
+
+/* return 0 once the response is sent */
+int32_t handle_request_v2(const uint8_t* request, size_t request_len, const HostCallbacks* host);
+
 /**
  * Helper function to get host URL from environment
  * Returns "http://127.0.0.1:3000" if HOST env var is not set
@@ -0,0 +1,301 @@
+//! services of the host, the same for dylib and wasm plugins. each one needs its permission in
+//! the `permissions` of the plugin's config, calls without it fail.
+//!
+//! the calls block until the host replied. in a dylib plugin they work on the thread of the
+//! request handler, not in tasks it spawns.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::http_abi::HttpResponse;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "service", rename_all = "snake_case")]
+pub enum HostCall {
+    /// replied with `HttpResponse`, only hosts in `http_allowlist`, through the host's proxy
+    Http {
+        #[serde(default = "default_http_method")]
+        method: String,
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default)]
+        body: String,
+    },
+    /// replied with a list of `DataRow`, newest first
+    DataList {
+        cat: String,
+        limit: u32,
+    },
+    DataGet {
+        cat: String,
+        id: u32,
+    },
+    /// replied with the new id
+    DataInsert {
+        cat: String,
+        data: Value,
+    },
+    DataUpdate {
+        cat: String,
+        id: u32,
+        data: Value,
+    },
+    DataDelete {
+        cat: String,
+        id: u32,
+    },
+    /// replied with the bytes, paths are relative to the plugin's files dir
+    FileRead {
+        path: String,
+    },
+    FileWrite {
+        path: String,
+        content: Vec<u8>,
+    },
+    /// replied with a list of `FileEntry`
+    FileList {
+        #[serde(default)]
+        dir: String,
+    },
+    FileDelete {
+        path: String,
+    },
+    /// replied with the value or null, keys are private to the plugin
+    KvGet {
+        key: String,
+    },
+    KvSet {
+        key: String,
+        value: String,
+        #[serde(default)]
+        ttl_secs: Option<u64>,
+    },
+    KvDelete {
+        key: String,
+    },
+    /// written to the host's log, tagged with the plugin
+    Log {
+        level: LogLevel,
+        message: String,
+        #[serde(default)]
+        fields: Map<String, Value>,
+    },
+}
+
+fn default_http_method() -> String {
+    "GET".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// a row of `general_data`, `data` is its parsed json.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DataRow {
+    pub id: u32,
+    pub data: Value,
+    /// unix millis
+    pub created: i64,
+    pub updated: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// the host side of `HostCall`, made for one plugin and checking its permissions.
+#[async_trait]
+pub trait HostServices: Send + Sync {
+    async fn call(&self, call: HostCall) -> anyhow::Result<Value>;
+}
+
+/// sends `call` to the host, the reply is the json of `Result<Value, String>`.
+pub fn call_host(call: &HostCall) -> anyhow::Result<Value> {
+    let call = serde_json::to_vec(call)?;
+    #[cfg(target_arch = "wasm32")]
+    let reply = crate::wasm_abi::host_call_raw(&call)?;
+    #[cfg(not(target_arch = "wasm32"))]
+    let reply = crate::plugin_abi::host_call_raw(&call)?;
+    let reply: Result<Value, String> =
+        serde_json::from_slice(&reply).context("invalid reply from host")?;
+    reply.map_err(|e| anyhow!("{}", e))
+}
+
+fn call<T: DeserializeOwned>(call: HostCall) -> anyhow::Result<T> {
+    Ok(serde_json::from_value(call_host(&call)?)?)
+}
+
+pub fn http(
+    method: &str,
+    url: &str,
+    headers: HashMap<String, String>,
+    body: String,
+) -> anyhow::Result<HttpResponse> {
+    call(HostCall::Http {
+        method: method.to_string(),
+        url: url.to_string(),
+        headers,
+        body,
+    })
+}
+
+pub fn data_list(cat: &str, limit: u32) -> anyhow::Result<Vec<DataRow>> {
+    call(HostCall::DataList {
+        cat: cat.to_string(),
+        limit,
+    })
+}
+
+pub fn data_get(cat: &str, id: u32) -> anyhow::Result<DataRow> {
+    call(HostCall::DataGet {
+        cat: cat.to_string(),
+        id,
+    })
+}
+
+pub fn data_insert(cat: &str, data: Value) -> anyhow::Result<u32> {
+    call(HostCall::DataInsert {
+        cat: cat.to_string(),
+        data,
+    })
+}
+
+pub fn data_update(cat: &str, id: u32, data: Value) -> anyhow::Result<()> {
+    call_host(&HostCall::DataUpdate {
+        cat: cat.to_string(),
+        id,
+        data,
+    })
+    .map(|_| ())
+}
+
+pub fn data_delete(cat: &str, id: u32) -> anyhow::Result<()> {
+    call_host(&HostCall::DataDelete {
+        cat: cat.to_string(),
+        id,
+    })
+    .map(|_| ())
+}
+
+pub fn file_read(path: &str) -> anyhow::Result<Vec<u8>> {
+    call(HostCall::FileRead {
+        path: path.to_string(),
+    })
+}
+
+pub fn file_write(path: &str, content: &[u8]) -> anyhow::Result<()> {
+    call_host(&HostCall::FileWrite {
+        path: path.to_string(),
+        content: content.to_vec(),
+    })
+    .map(|_| ())
+}
+
+pub fn file_list(dir: &str) -> anyhow::Result<Vec<FileEntry>> {
+    call(HostCall::FileList {
+        dir: dir.to_string(),
+    })
+}
+
+pub fn file_delete(path: &str) -> anyhow::Result<()> {
+    call_host(&HostCall::FileDelete {
+        path: path.to_string(),
+    })
+    .map(|_| ())
+}
+
+pub fn kv_get(key: &str) -> anyhow::Result<Option<String>> {
+    call(HostCall::KvGet {
+        key: key.to_string(),
+    })
+}
+
+pub fn kv_set(key: &str, value: &str, ttl: Option<Duration>) -> anyhow::Result<()> {
+    call_host(&HostCall::KvSet {
+        key: key.to_string(),
+        value: value.to_string(),
+        ttl_secs: ttl.map(|ttl| ttl.as_secs()),
+    })
+    .map(|_| ())
+}
+
+pub fn kv_delete(key: &str) -> anyhow::Result<()> {
+    call_host(&HostCall::KvDelete {
+        key: key.to_string(),
+    })
+    .map(|_| ())
+}
+
+pub fn log(level: LogLevel, message: &str, fields: Map<String, Value>) -> anyhow::Result<()> {
+    call_host(&HostCall::Log {
+        level,
+        message: message.to_string(),
+        fields,
+    })
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_host_call_json() {
+        let call: HostCall =
+            serde_json::from_value(json!({"service": "http", "url": "https://example.com"}))
+                .unwrap();
+        assert_eq!(
+            call,
+            HostCall::Http {
+                method: "GET".to_string(),
+                url: "https://example.com".to_string(),
+                headers: HashMap::new(),
+                body: String::new(),
+            }
+        );
+        let call = HostCall::KvSet {
+            key: "a".to_string(),
+            value: "1".to_string(),
+            ttl_secs: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&call).unwrap(),
+            json!({"service": "kv_set", "key": "a", "value": "1", "ttl_secs": null})
+        );
+        let call: HostCall =
+            serde_json::from_value(json!({"service": "log", "level": "warn", "message": "hi"}))
+                .unwrap();
+        assert!(matches!(
+            call,
+            HostCall::Log {
+                level: LogLevel::Warn,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_no_host() {
+        // outside of a request there is nobody to answer
+        let e = kv_get("a").unwrap_err();
+        assert!(e.to_string().contains("only available"), "{e}");
+    }
+}
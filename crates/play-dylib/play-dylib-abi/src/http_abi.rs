@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use anyhow::Context;
+#[cfg(not(target_arch = "wasm32"))]
 use reqwest::Client;
 use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
@@ -10,17 +11,199 @@ use crate::HostContext;
 pub type HandleRequestFn = unsafe extern "C" fn(i64);
 pub const HANDLE_REQUEST_FN_NAME: &'static str = "handle_request";
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
 pub enum HttpMethod {
     #[default]
     GET,
     POST,
     PUT,
-    DELETE
+    DELETE,
+    PATCH,
+    HEAD,
+    OPTIONS,
+    CONNECT,
+    TRACE,
+    /// any other method, e.g. `PROPFIND`
+    Other(String),
 }
 
+impl HttpMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::Other(method) => method,
+        }
+    }
+}
+
+impl From<&str> for HttpMethod {
+    fn from(method: &str) -> Self {
+        match method {
+            "GET" => HttpMethod::GET,
+            "POST" => HttpMethod::POST,
+            "PUT" => HttpMethod::PUT,
+            "DELETE" => HttpMethod::DELETE,
+            "PATCH" => HttpMethod::PATCH,
+            "HEAD" => HttpMethod::HEAD,
+            "OPTIONS" => HttpMethod::OPTIONS,
+            "CONNECT" => HttpMethod::CONNECT,
+            "TRACE" => HttpMethod::TRACE,
+            other => HttpMethod::Other(other.to_string()),
+        }
+    }
+}
+
+/// headers in the order they came, a name repeats for each of its values (e.g. `Set-Cookie`).
+/// names are compared case-insensitively.
+///
+/// serialized as a list of `[name, value]`, a json object is accepted too.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// replaces all values of `name`.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.0.push((name, value.into()));
+    }
+
+    /// adds a value, keeping the ones `name` already has.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.push((name.into(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// the first value of each name, as the request json before ABI v4 had them.
+    pub fn to_map(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        for (k, v) in &self.0 {
+            map.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+        map
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Headers {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        Self(iter.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl From<HashMap<String, String>> for Headers {
+    fn from(map: HashMap<String, String>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<'de> Deserialize<'de> for Headers {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HeadersVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for HeadersVisitor {
+            type Value = Headers;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a list of [name, value] or a map of headers")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Headers, A::Error> {
+                let mut headers = Vec::new();
+                while let Some(header) = seq.next_element::<(String, String)>()? {
+                    headers.push(header);
+                }
+                Ok(Headers(headers))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Headers, A::Error> {
+                let mut headers = Vec::new();
+                while let Some(header) = map.next_entry::<String, String>()? {
+                    headers.push(header);
+                }
+                Ok(Headers(headers))
+            }
+        }
+
+        deserializer.deserialize_any(HeadersVisitor)
+    }
+}
+
+/// the request json since ABI v4.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct HttpRequest {
+    pub method:HttpMethod,
+    pub headers: Headers,
+    pub query: String,
+    pub url: String,
+    pub body: Vec<u8>,
+    pub rendered_config:Option<String>,
+    /// the address of the connecting client, proxies in front of the host are not looked through
+    #[serde(default)]
+    pub client_ip: Option<String>,
+    /// `{name}` segments of the plugin's `url_prefix`, e.g. `/users/{id}`
+    #[serde(default)]
+    pub path_params: HashMap<String, String>,
+}
+
+/// the request json of ABI v2 and v3 and of the request id ABI, which plugins built for
+/// them still expect.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct LegacyHttpRequest {
     pub method:HttpMethod,
     pub headers: HashMap<String, String>,
     pub query: String,
@@ -29,17 +212,51 @@ pub struct HttpRequest {
     pub rendered_config:Option<String>,
 }
 
+impl TryFrom<&HttpRequest> for LegacyHttpRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(request: &HttpRequest) -> anyhow::Result<Self> {
+        if !matches!(request.method, HttpMethod::GET | HttpMethod::POST | HttpMethod::PUT | HttpMethod::DELETE) {
+            anyhow::bail!("plugins built before ABI v4 don't support the {} method", request.method.as_str());
+        }
+        Ok(Self {
+            method: request.method.clone(),
+            headers: request.headers.to_map(),
+            query: request.query.clone(),
+            url: request.url.clone(),
+            body: String::from_utf8(request.body.clone())
+                .context("plugins built before ABI v4 only take utf-8 bodies")?,
+            rendered_config: request.rendered_config.clone(),
+        })
+    }
+}
+
+impl From<LegacyHttpRequest> for HttpRequest {
+    fn from(request: LegacyHttpRequest) -> Self {
+        Self {
+            method: request.method,
+            headers: request.headers.into(),
+            query: request.query,
+            url: request.url,
+            body: request.body.into_bytes(),
+            rendered_config: request.rendered_config,
+            ..Self::default()
+        }
+    }
+}
+
 
 
 
 impl HttpRequest {
+    #[cfg(not(target_arch = "wasm32"))]
     pub async fn fetch_from_host(request_id: i64) -> anyhow::Result<Self> {
         let context = HostContext::from_env(false)?;
         let client = Client::new();
         let url = format!("{}/admin/get-request-info?request_id={}", context.host_url, request_id);
         let response = client.get(&url).send().await?;
-        let request: HttpRequest = response.json().await?;
-        Ok(request)
+        let request: LegacyHttpRequest = response.json().await?;
+        Ok(request.into())
     }
 
     pub fn parse_query<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
@@ -47,13 +264,17 @@ impl HttpRequest {
         Ok(p)
     }
     pub fn parse_body_form<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
-        let p: T = serde_urlencoded::from_str(&self.body).context("parse body str error!")?;
+        let p: T = serde_urlencoded::from_bytes(&self.body).context("parse body str error!")?;
         Ok(p)
     }
     pub fn parse_body_json<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
-        let p: T = serde_json::from_str(&self.body).context("parse body str error!")?;
+        let p: T = serde_json::from_slice(&self.body).context("parse body str error!")?;
         Ok(p)
     }
+    /// the body as text, fails for binary bodies.
+    pub fn body_str(&self) -> anyhow::Result<&str> {
+        std::str::from_utf8(&self.body).context("body is not utf-8")
+    }
 
     /// Parse configuration with priority: rendered_config first, then HostContext
     pub fn parse_config<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
@@ -73,7 +294,8 @@ impl HttpRequest {
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct HttpResponse {
-    pub headers: HashMap<String, String>,
+    /// may repeat a name, e.g. one `Set-Cookie` per cookie
+    pub headers: Headers,
     pub body: Vec<u8>,
     #[serde(default = "default_status_code")]
     pub status_code: u16,
@@ -106,6 +328,7 @@ fn print_error(err: &anyhow::Error) -> String {
 
 
 impl HttpResponse {
+    #[cfg(not(target_arch = "wasm32"))]
     pub async fn push_to_host(&self, request_id: i64) -> anyhow::Result<()> {
         let context = HostContext::from_env(false)?;
         let client = Client::new();
@@ -133,7 +356,7 @@ impl HttpResponse {
     }
 
     pub fn text(body: &str) -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = Headers::new();
         headers.insert("Content-Type".to_string(), "text/plain;charset=UTF-8".to_string());
         Self {
             headers,
@@ -143,7 +366,7 @@ impl HttpResponse {
         }
     }
     pub fn bytes(body: &[u8], content_type: &str) -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = Headers::new();
         headers.insert("Content-Type".to_string(), content_type.to_string());
         Self {
             headers,
@@ -153,7 +376,7 @@ impl HttpResponse {
         }
     }
     pub fn page_404() -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = Headers::new();
         headers.insert("Content-Type".to_string(), "text/plain;charset=UTF-8".to_string());
         Self {
             headers,
@@ -163,7 +386,7 @@ impl HttpResponse {
         }
     }
     pub fn html(body: &str) -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = Headers::new();
         headers.insert("Content-Type".to_string(), "text/html;charset=UTF-8".to_string());
         Self {
             headers,
@@ -172,8 +395,13 @@ impl HttpResponse {
             ..Self::default()
         }
     }
+    /// adds a header, keeping the ones of the same name.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
     pub fn json<T: Serialize>(body: &T) -> Self {
-        let mut headers = HashMap::new();
+        let mut headers = Headers::new();
         headers.insert("Content-Type".to_string(), "application/json;charset=UTF-8".to_string());
         Self {
             headers,
@@ -191,43 +419,65 @@ impl HttpResponse {
 /// ```rust
 /// async fn handle_request_impl(request: HttpRequest) -> anyhow::Result<HttpResponse>
 /// ```
-/// 
-/// The macro handles all the host communication:
-/// 1. Fetches request from host using request_id
-/// 2. Calls your function with the HttpRequest
-/// 3. Pushes the HttpResponse back to host
+///
+/// exports `handle_request_v2` of the in-process ABI (see `plugin_abi`):
+/// 1. Reads the request the host passes in
+/// 2. Calls your function with the HttpRequest on a runtime kept across requests
+/// 3. Hands the HttpResponse back to the host, errors and panics included
 #[macro_export]
 macro_rules! async_request_handler {
     ($func:ident) => {
-        #[unsafe(no_mangle)]
-        pub extern "C" fn handle_request(request_id: i64) {
-            use std::panic::{self, AssertUnwindSafe};
-            use tokio::runtime::Runtime;
-            use play_dylib_abi::http_abi::{HttpRequest, HttpResponse};
-                let rt = Runtime::new().unwrap();
-                rt.block_on(async move {
-                    // Fetch request from host
-                    let request = match HttpRequest::fetch_from_host(request_id).await {
-                        Ok(req) => req,
-                        Err(e) => {
-                            eprintln!("Failed to fetch request {}: {:?}", request_id, e);
-                            return Err(e);
-                        }
-                    };
-                    
-                    // Call user's handler function
-                    let response = HttpResponse::from_anyhow($func(request).await);
-                    
-                    // Push response back to host
-                    if let Err(e) = response.push_to_host(request_id).await {
-                        eprintln!("Failed to push response for request {}: {:?}", request_id, e);
-                        return Err(e);
-                    }
-                    
-                    Ok(())
-                });
+        async fn __play_handle_request(
+            request: $crate::http_abi::HttpRequest,
+            stream: &mut $crate::plugin_abi::ResponseStream,
+        ) -> anyhow::Result<()> {
+            stream.send_response($crate::http_abi::HttpResponse::from_anyhow(
+                $func(request).await,
+            ))
         }
+
+        $crate::async_stream_handler!(__play_handle_request);
     };
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let mut headers = Headers::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        headers.insert("Content-Type", "text/plain");
+        assert_eq!(headers.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), vec!["a=1", "b=2"]);
 
+        let json = serde_json::to_value(&headers).unwrap();
+        assert_eq!(json, json!([["Set-Cookie", "a=1"], ["set-cookie", "b=2"], ["Content-Type", "text/plain"]]));
+        assert_eq!(serde_json::from_value::<Headers>(json).unwrap(), headers);
+        // heads of plugins built before ABI v4
+        let headers: Headers = serde_json::from_value(json!({"Content-Type": "text/html"})).unwrap();
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+    }
+
+    #[test]
+    fn test_legacy_request() {
+        let mut request = HttpRequest {
+            method: HttpMethod::POST,
+            headers: [("Accept", "a"), ("Accept", "b")].into_iter().collect(),
+            body: b"hi".to_vec(),
+            ..Default::default()
+        };
+        let legacy = LegacyHttpRequest::try_from(&request).unwrap();
+        assert_eq!(legacy.headers["Accept"], "a");
+        assert_eq!(legacy.body, "hi");
+
+        request.body = vec![0xff];
+        assert!(LegacyHttpRequest::try_from(&request).is_err());
+        request.body.clear();
+        request.method = HttpMethod::from("PATCH");
+        assert!(LegacyHttpRequest::try_from(&request).is_err());
+        assert_eq!(HttpMethod::from("PROPFIND").as_str(), "PROPFIND");
+    }
+}
@@ -1,7 +1,11 @@
+pub mod host_services;
 pub mod http_abi;
+pub mod plugin_abi;
 pub mod server_abi;
+pub mod wasm_abi;
 
 use anyhow::Context;
+#[cfg(not(target_arch = "wasm32"))]
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
@@ -0,0 +1,276 @@
+use std::cell::Cell;
+use std::ffi::c_void;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::http_abi::{Headers, HttpRequest, HttpResponse};
+
+/// bumped on every change of the types below or of the request/response json.
+pub const ABI_VERSION: u32 = 4;
+/// the oldest version the host still runs, `HostCallbacks` only grew at its end since.
+/// plugins before v4 get the request as `LegacyHttpRequest`.
+pub const MIN_ABI_VERSION: u32 = 2;
+
+pub type AbiVersionFn = unsafe extern "C" fn() -> u32;
+pub const ABI_VERSION_FN_NAME: &'static str = "play_abi_version";
+
+/// `request` is the json of `HttpRequest`, in a buffer owned by the host for the duration of the call.
+/// returns 0 when the plugin finished the response.
+pub type HandleRequestV2Fn =
+    unsafe extern "C" fn(request: *const u8, request_len: usize, host: *const HostCallbacks) -> i32;
+pub const HANDLE_REQUEST_V2_FN_NAME: &'static str = "handle_request_v2";
+
+/// functions of the host a plugin answers a request with.
+///
+/// the host copies every buffer into its own memory before the call returns, so plugins
+/// keep ownership of what they pass and nothing is freed across the boundary.
+#[repr(C)]
+pub struct HostCallbacks {
+    /// the host's state for the current request, passed back to every callback
+    pub ctx: *mut c_void,
+    /// json of `ResponseHead`, once and before any body chunk
+    pub send_head: unsafe extern "C" fn(ctx: *mut c_void, head: *const u8, head_len: usize) -> i32,
+    /// a part of the body, returns non-zero when the client went away
+    pub send_chunk:
+        unsafe extern "C" fn(ctx: *mut c_void, chunk: *const u8, chunk_len: usize) -> i32,
+    /// since v3. `call` is the json of `HostCall`, the host passes the json of
+    /// `Result<Value, String>` to `on_reply` before it returns
+    pub host_call: unsafe extern "C" fn(
+        ctx: *mut c_void,
+        call: *const u8,
+        call_len: usize,
+        reply_ctx: *mut c_void,
+        on_reply: ReplyFn,
+    ) -> i32,
+}
+
+pub type ReplyFn = unsafe extern "C" fn(reply_ctx: *mut c_void, reply: *const u8, reply_len: usize);
+
+thread_local! {
+    static CURRENT_HOST: Cell<*const HostCallbacks> = const { Cell::new(std::ptr::null()) };
+}
+
+/// `host_services` calls go to the host of the request handled on this thread until it's dropped.
+pub struct HostGuard {
+    previous: *const HostCallbacks,
+}
+
+/// # Safety
+/// `host` must stay valid until the guard is dropped.
+pub unsafe fn enter_host(host: *const HostCallbacks) -> HostGuard {
+    HostGuard {
+        previous: CURRENT_HOST.replace(host),
+    }
+}
+
+impl Drop for HostGuard {
+    fn drop(&mut self) {
+        CURRENT_HOST.set(self.previous);
+    }
+}
+
+pub(crate) fn host_call_raw(call: &[u8]) -> anyhow::Result<Vec<u8>> {
+    unsafe extern "C" fn on_reply(reply_ctx: *mut c_void, reply: *const u8, reply_len: usize) {
+        let buf = unsafe { &mut *(reply_ctx as *mut Vec<u8>) };
+        buf.extend_from_slice(unsafe { std::slice::from_raw_parts(reply, reply_len) });
+    }
+
+    let host = CURRENT_HOST.get();
+    if host.is_null() {
+        bail!("host services are only available while handling a request, on its thread");
+    }
+    let host = unsafe { &*host };
+    let mut reply = Vec::new();
+    let code = unsafe {
+        (host.host_call)(
+            host.ctx,
+            call.as_ptr(),
+            call.len(),
+            &mut reply as *mut Vec<u8> as *mut c_void,
+            on_reply,
+        )
+    };
+    if code != 0 {
+        bail!("host call failed");
+    }
+    Ok(reply)
+}
+
+/// the status and headers of a response, its body follows as chunks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ResponseHead {
+    pub status_code: u16,
+    /// a map before v4
+    pub headers: Headers,
+    /// same as `HttpResponse.error`
+    pub error: Option<String>,
+}
+
+/// reads the request the host passed to `handle_request_v2`.
+///
+/// # Safety
+/// `request` must point to `request_len` readable bytes.
+pub unsafe fn read_request(request: *const u8, request_len: usize) -> anyhow::Result<HttpRequest> {
+    let bytes = unsafe { std::slice::from_raw_parts(request, request_len) };
+    serde_json::from_slice(bytes).context("invalid request from host")
+}
+
+/// writes a response to the host, either at once with `send_response` or streamed.
+pub struct ResponseStream {
+    host: *const HostCallbacks,
+    head_sent: bool,
+}
+
+// the callbacks are only used during the `handle_request_v2` call the stream was made in.
+unsafe impl Send for ResponseStream {}
+
+impl ResponseStream {
+    /// # Safety
+    /// `host` must stay valid for as long as the stream is used.
+    pub unsafe fn new(host: *const HostCallbacks) -> Self {
+        Self {
+            host,
+            head_sent: false,
+        }
+    }
+
+    pub fn head_sent(&self) -> bool {
+        self.head_sent
+    }
+
+    pub fn send_head(&mut self, status_code: u16, headers: Headers) -> anyhow::Result<()> {
+        self.write_head(&ResponseHead {
+            status_code,
+            headers,
+            error: None,
+        })
+    }
+
+    fn write_head(&mut self, head: &ResponseHead) -> anyhow::Result<()> {
+        if self.head_sent {
+            bail!("response head was already sent");
+        }
+        let head = serde_json::to_vec(head)?;
+        let host = unsafe { &*self.host };
+        if unsafe { (host.send_head)(host.ctx, head.as_ptr(), head.len()) } != 0 {
+            bail!("host rejected the response head");
+        }
+        self.head_sent = true;
+        Ok(())
+    }
+
+    /// sends a part of the body, with a 200 head first when none was sent.
+    pub fn send(&mut self, chunk: &[u8]) -> anyhow::Result<()> {
+        if !self.head_sent {
+            self.send_head(200, Headers::new())?;
+        }
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        let host = unsafe { &*self.host };
+        if unsafe { (host.send_chunk)(host.ctx, chunk.as_ptr(), chunk.len()) } != 0 {
+            bail!("client closed the response");
+        }
+        Ok(())
+    }
+
+    pub fn send_response(&mut self, response: HttpResponse) -> anyhow::Result<()> {
+        self.write_head(&ResponseHead {
+            status_code: response.status_code,
+            headers: response.headers,
+            error: response.error,
+        })?;
+        self.send(&response.body)
+    }
+
+    /// reports `error` as the response, or logs it when part of the response is already out.
+    pub fn send_error(&mut self, error: String) {
+        if self.head_sent {
+            eprintln!("plugin failed while streaming its response: {}", error);
+            return;
+        }
+        let _ = self.write_head(&ResponseHead {
+            error: Some(error),
+            ..Default::default()
+        });
+    }
+}
+
+pub fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = panic.downcast_ref::<String>() {
+        format!("Panic occurred: {}", s)
+    } else if let Some(s) = panic.downcast_ref::<&str>() {
+        format!("Panic occurred: {}", s)
+    } else {
+        "Panic occurred: Unknown panic info".to_string()
+    }
+}
+
+/// exports the ABI version the host checks before calling `handle_request_v2`.
+#[macro_export]
+macro_rules! export_abi_version {
+    () => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn play_abi_version() -> u32 {
+            $crate::plugin_abi::ABI_VERSION
+        }
+    };
+}
+
+/// the tokio runtime of the plugin, created by the first request and kept for the next ones.
+#[macro_export]
+macro_rules! plugin_runtime {
+    () => {{
+        static RUNTIME: std::sync::LazyLock<tokio::runtime::Runtime> =
+            std::sync::LazyLock::new(|| tokio::runtime::Runtime::new().unwrap());
+        &*RUNTIME
+    }};
+}
+
+/// needs tokio runtime.
+/// usage: `async_stream_handler!(handle_request_impl);`
+/// ```ignore
+/// async fn handle_request_impl(request: HttpRequest, stream: &mut ResponseStream) -> anyhow::Result<()>
+/// ```
+///
+/// the handler sends the head and body chunks itself, the client gets them as they are sent.
+#[macro_export]
+macro_rules! async_stream_handler {
+    ($func:ident) => {
+        $crate::export_abi_version!();
+
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn handle_request_v2(
+            request: *const u8,
+            request_len: usize,
+            host: *const $crate::plugin_abi::HostCallbacks,
+        ) -> i32 {
+            use std::panic::{self, AssertUnwindSafe};
+            use $crate::plugin_abi::{panic_message, read_request, ResponseStream};
+
+            let mut stream = unsafe { ResponseStream::new(host) };
+            let _host = unsafe { $crate::plugin_abi::enter_host(host) };
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let request = unsafe { read_request(request, request_len) }?;
+                $crate::plugin_runtime!().block_on($func(request, &mut stream))
+            }));
+            let error = match result {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(format!("[plugin error] {:?}", e)),
+                Err(panic) => Some(panic_message(panic)),
+            };
+            match error {
+                Some(error) => {
+                    stream.send_error(error);
+                    1
+                }
+                None if !stream.head_sent() => {
+                    stream.send_error("plugin returned without a response".to_string());
+                    1
+                }
+                None => 0,
+            }
+        }
+    };
+}
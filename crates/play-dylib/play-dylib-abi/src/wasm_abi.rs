@@ -0,0 +1,134 @@
+//! a wasm plugin is a WASI command: it reads the json of `HttpRequest` from stdin, writes the
+//! json of `HttpResponse` to stdout and exits. stderr goes to the host's log.
+
+use std::io::{Read, Write};
+
+use anyhow::Context;
+
+pub use crate::host_services::{call_host, HostCall};
+use crate::http_abi::{HttpRequest, HttpResponse};
+
+/// import module of the host functions.
+pub const HOST_MODULE: &'static str = "play";
+
+/// `host_call(call: *const u8, call_len: usize) -> i64`, `call` is the json of `HostCall`.
+/// the json of `Result<Value, String>` is written into a buffer from `play_alloc`,
+/// returned as `ptr << 32 | len`. plugins use `host_services` instead of calling it.
+pub const HOST_CALL_FN_NAME: &'static str = "host_call";
+
+/// `play_alloc(len: usize) -> *mut u8`, exported by the plugin for the replies of `host_call`.
+pub const ALLOC_FN_NAME: &'static str = "play_alloc";
+
+/// where the plugin's files dir is mounted, when it was granted one.
+pub const FILES_DIR: &'static str = "/data";
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn host_call_raw(call: &[u8]) -> anyhow::Result<Vec<u8>> {
+    #[link(wasm_import_module = "play")]
+    unsafe extern "C" {
+        fn host_call(call: *const u8, call_len: usize) -> i64;
+    }
+
+    let ret = unsafe { host_call(call.as_ptr(), call.len()) };
+    if ret < 0 {
+        anyhow::bail!("host call failed");
+    }
+    let (ptr, len) = (
+        (ret >> 32) as usize as *mut u8,
+        (ret & 0xffff_ffff) as usize,
+    );
+    Ok(unsafe { take_alloc(ptr, len) })
+}
+
+/// used by `wasm_handler!` for `play_alloc`.
+pub fn alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// takes back a buffer of `alloc`.
+///
+/// # Safety
+/// `ptr` and `len` must be from one `alloc` call, and it can only be taken once.
+pub unsafe fn take_alloc(ptr: *mut u8, len: usize) -> Vec<u8> {
+    unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) }.into_vec()
+}
+
+/// answers the request on stdin, errors of the handler are reported in `HttpResponse.error`.
+pub fn run_command(handler: fn(HttpRequest) -> anyhow::Result<HttpResponse>) {
+    let response = handle(&mut std::io::stdin(), handler);
+    let response = serde_json::to_vec(&response).unwrap_or_default();
+    let mut stdout = std::io::stdout();
+    if let Err(e) = stdout.write_all(&response).and_then(|_| stdout.flush()) {
+        eprintln!("failed to write the response: {}", e);
+    }
+}
+
+fn handle(
+    input: &mut impl Read,
+    handler: fn(HttpRequest) -> anyhow::Result<HttpResponse>,
+) -> HttpResponse {
+    let result = (|| {
+        let mut request = Vec::new();
+        input.read_to_end(&mut request)?;
+        let request = serde_json::from_slice(&request).context("invalid request from host")?;
+        handler(request)
+    })();
+    result.unwrap_or_else(|e| HttpResponse {
+        error: Some(format!("[plugin error] {:?}", e)),
+        ..Default::default()
+    })
+}
+
+/// usage: `wasm_handler!(handle_request_impl);` in the `main.rs` of a `wasm32-wasip1` binary.
+/// ```ignore
+/// fn handle_request_impl(request: HttpRequest) -> anyhow::Result<HttpResponse>
+/// ```
+#[macro_export]
+macro_rules! wasm_handler {
+    ($func:ident) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn play_alloc(len: usize) -> *mut u8 {
+            $crate::wasm_abi::alloc(len)
+        }
+
+        fn main() {
+            $crate::wasm_abi::run_command($func);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(request: HttpRequest) -> anyhow::Result<HttpResponse> {
+        Ok(HttpResponse {
+            body: request.body,
+            status_code: 200,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_handle() {
+        let request = serde_json::to_vec(&HttpRequest {
+            url: "/echo".to_string(),
+            body: b"hi".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+        let response = handle(&mut request.as_slice(), echo);
+        assert_eq!(response.body, b"hi");
+        assert_eq!(response.error, None);
+
+        let response = handle(&mut &b"not json"[..], echo);
+        assert!(response.error.unwrap().contains("invalid request"));
+    }
+
+    #[test]
+    fn test_alloc() {
+        let ptr = alloc(3);
+        let buf = unsafe { take_alloc(ptr, 3) };
+        assert_eq!(buf, vec![0, 0, 0]);
+    }
+}
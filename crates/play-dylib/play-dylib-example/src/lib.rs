@@ -9,7 +9,7 @@ struct Param {
     b: i32,
 }
 
-// 新的异步处理函数 - 使用 request_id
+// 异步处理函数，请求由宿主在进程内直接传入
 async fn async_handle_request_impl(request: HttpRequest) -> anyhow::Result<HttpResponse> {
     println!("Handling request with ID: {:?}", request);
 
@@ -20,17 +20,15 @@ async fn async_handle_request_impl(request: HttpRequest) -> anyhow::Result<HttpR
         "query": request.query,
         "age": 20,
         "url": request.url,
-        "method": format!("{:?}", request.method),
+        "method": request.method.as_str(),
+        "client_ip": request.client_ip,
     }));
 
     Ok(response)
 }
 
-// 同步版本也更新为新模式（虽然实际上需要异步来获取/推送数据）
-// 注意：同步版本无法直接使用 async 函数，所以这里仅作为示例
-// 实际使用中推荐使用 async_request_handler
-
-// 使用新的异步处理器宏
+// 导出 ABI v2 的 handle_request_v2 与 play_abi_version；
+// 需要流式返回时改用 async_stream_handler!，两者只能选其一
 async_request_handler!(async_handle_request_impl);
 
 async_run!(run_server);
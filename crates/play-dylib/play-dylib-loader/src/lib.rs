@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
 use anyhow::{bail, ensure, Context};
@@ -16,6 +15,15 @@ use dashmap::DashMap;
 use tokio::sync::OnceCell;
 use tokio::task::JoinHandle;
 use play_dylib_abi::server_abi::{RunFn, RUN_FN_NAME};
+pub use play_dylib_abi::host_services;
+use play_dylib_abi::host_services::{HostCall, HostServices};
+pub use play_dylib_abi::plugin_abi::{ResponseHead, ABI_VERSION};
+use play_dylib_abi::plugin_abi::{
+    AbiVersionFn, HandleRequestV2Fn, HostCallbacks, ReplyFn, ABI_VERSION_FN_NAME,
+    HANDLE_REQUEST_V2_FN_NAME, MIN_ABI_VERSION,
+};
+use std::ffi::c_void;
+use tokio::sync::{mpsc, oneshot};
 
 // Global counter for generating unique request IDs
 pub static REQUEST_ID_COUNTER: AtomicI64 = AtomicI64::new(0);
@@ -59,24 +67,33 @@ pub async fn load_and_run_server(dylib_path: &str) -> anyhow::Result<()> {
 
     let copy_path = dylib_path.to_string();
     let _: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-        unsafe {
-            // 加载动态库
-            let lib = Library::new(&copy_path)?;
-            info!("load_and_run_server lib load ok. path: {}", copy_path);
-            let run: Symbol<RunFn> = lib.get(RUN_FN_NAME.as_ref())?;
+        run_server(&copy_path).await?;
+        warn!("run exited, dylib_path: {}", copy_path);
+        Ok(())
+    });
 
+    Ok(())
+}
 
-            // 调用新的简化接口（无参数）
-            run();
-            
-            warn!("run exited, dylib_path: {}", copy_path);
+/// runs the `run` function of a server plugin on a blocking thread, returns when it does.
+///
+/// `run` can't be interrupted, dropping the future leaves it running.
+pub async fn run_server(dylib_path: &str) -> anyhow::Result<()> {
+    ensure!(fs::try_exists(dylib_path).await?);
+    let path = dylib_path.to_string();
+    tokio::task::spawn_blocking(move || unsafe {
+        // 加载动态库
+        let lib = Library::new(&path)?;
+        info!("run_server lib load ok. path: {}", path);
+        let run: Symbol<RunFn> = lib.get(RUN_FN_NAME.as_ref())?;
 
-            drop(lib);
-            Ok(())
-        }
-    });
+        // 调用新的简化接口（无参数）
+        run();
 
-    Ok(())
+        drop(lib);
+        Ok(())
+    })
+    .await?
 }
 
 
@@ -88,8 +105,21 @@ static PLUGIN_CACHE: LazyLock<DashMap<String,Arc<PluginLib>>> = LazyLock::new(||
 
 struct PluginLib{
     library: Library,
+    abi: PluginAbi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PluginAbi {
+    /// `handle_request(request_id)`, the plugin fetches the request and pushes the response over http
+    Legacy,
+    /// `handle_request_v2`, request and response are passed in process.
+    /// the plugin's ABI version, before v4 it takes `LegacyHttpRequest`
+    V2(u32),
 }
 
+/// Time a plugin has to send the response head
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
 // Public functions to manage plugin cache
 pub fn clear_plugin_cache() {
     PLUGIN_CACHE.clear();
@@ -102,40 +132,230 @@ pub fn remove_plugin_from_cache(lib_path: &str) {
     }
 }
 
+pub fn is_plugin_cached(lib_path: &str) -> bool {
+    PLUGIN_CACHE.contains_key(lib_path)
+}
+
 pub fn get_cached_plugin_count() -> usize {
     PLUGIN_CACHE.len()
 }
 
 
-unsafe fn run_plugin_with_id(lib_path: &str, request_id: i64) -> anyhow::Result<()> {
-    info!("run_plugin_with_id begin path: {}, request_id: {}", lib_path, request_id);
+/// Loads the library, or takes it from the cache, after checking its ABI version
+unsafe fn load_plugin(lib_path: &str) -> anyhow::Result<Arc<PluginLib>> {
+    if let Some(cached) = PLUGIN_CACHE.get(lib_path) {
+        return Ok(cached.clone());
+    }
 
-    // Try to use cached library first
-    let lib = match PLUGIN_CACHE.get(lib_path) {
-        Some(cached) => {
-            info!("Using cached library for: {}", lib_path);
-            cached.clone()
-        },
-        None => {
-            info!("Loading new library: {}", lib_path);
-            let lib = Library::new(&lib_path)?;
-            let plugin_lib = Arc::new(PluginLib { library: lib });
-            PLUGIN_CACHE.insert(lib_path.to_string(), plugin_lib.clone());
-            plugin_lib
+    info!("Loading new library: {}", lib_path);
+    let library = unsafe { Library::new(lib_path)? };
+    let abi = match unsafe { library.get::<AbiVersionFn>(ABI_VERSION_FN_NAME.as_ref()) } {
+        Ok(abi_version) => {
+            let version = unsafe { abi_version() };
+            ensure!(
+                (MIN_ABI_VERSION..=ABI_VERSION).contains(&version),
+                "plugin {} was built for plugin ABI v{}, but this server supports v{} to v{}. rebuild it against the play-dylib-abi of this server",
+                lib_path,
+                version,
+                MIN_ABI_VERSION,
+                ABI_VERSION
+            );
+            PluginAbi::V2(version)
+        }
+        Err(_) => {
+            warn!("plugin {} uses the legacy request id ABI, rebuild it to pass requests in process", lib_path);
+            PluginAbi::Legacy
         }
     };
-    
-    let handle_request: Symbol<HandleRequestFn> = lib.library.get(HANDLE_REQUEST_FN_NAME.as_ref())
+    let plugin_lib = Arc::new(PluginLib { library, abi });
+    PLUGIN_CACHE.insert(lib_path.to_string(), plugin_lib.clone());
+    Ok(plugin_lib)
+}
+
+unsafe fn run_plugin_with_id(lib_path: &str, request_id: i64) -> anyhow::Result<()> {
+    info!("run_plugin_with_id begin path: {}, request_id: {}", lib_path, request_id);
+
+    let lib = unsafe { load_plugin(lib_path)? };
+
+    let handle_request: Symbol<HandleRequestFn> = unsafe { lib.library.get(HANDLE_REQUEST_FN_NAME.as_ref()) }
         .context("`handle_request` method not found.")?;
 
     // Simply call the plugin with the request_id
-    handle_request(request_id);
+    unsafe { handle_request(request_id) };
 
     info!("run_plugin_with_id finish path: {}", lib_path);
     // Note: We don't drop the lib anymore since it's cached
     Ok(())
 }
 
+/// A plugin response, the body is received while the plugin is still sending it
+pub struct PluginResponse {
+    pub head: ResponseHead,
+    pub body: mpsc::Receiver<Vec<u8>>,
+}
+
+impl From<HttpResponse> for PluginResponse {
+    fn from(response: HttpResponse) -> Self {
+        let (sender, body) = mpsc::channel(1);
+        let _ = sender.try_send(response.body);
+        PluginResponse {
+            head: ResponseHead {
+                status_code: response.status_code,
+                headers: response.headers,
+                error: response.error,
+            },
+            body,
+        }
+    }
+}
+
+/// Runs a plugin of either ABI, returning as soon as it sent the response head.
+/// `host` answers the plugin's `host_services` calls, legacy plugins can't make them
+pub async fn call_plugin(
+    dylib_path: &str,
+    request: HttpRequest,
+    host: Option<Arc<dyn HostServices>>,
+) -> anyhow::Result<PluginResponse> {
+    ensure!(fs::try_exists(dylib_path).await?, "plugin file {} not found", dylib_path);
+    let lib = unsafe { load_plugin(dylib_path)? };
+    let request = match lib.abi {
+        PluginAbi::Legacy => {
+            // fails early for requests the plugin can't be given
+            LegacyHttpRequest::try_from(&request)?;
+            return Ok(load_and_run_coordinated(dylib_path, request).await?.into());
+        }
+        PluginAbi::V2(version) if version < 4 => {
+            serde_json::to_vec(&LegacyHttpRequest::try_from(&request)?)?
+        }
+        PluginAbi::V2(_) => serde_json::to_vec(&request)?,
+    };
+    let (head_sender, head) = oneshot::channel();
+    let (body_sender, body) = mpsc::channel(16);
+    let runtime = tokio::runtime::Handle::current();
+    let call = tokio::task::spawn_blocking(move || {
+        let mut ctx = CallContext {
+            head: Some(head_sender),
+            body: body_sender,
+            host,
+            runtime,
+        };
+        unsafe { call_v2(&lib, &request, &mut ctx) }
+    });
+
+    match tokio::time::timeout(RESPONSE_TIMEOUT, head).await {
+        Ok(Ok(head)) => Ok(PluginResponse { head, body }),
+        Ok(Err(_)) => Err(call
+            .await?
+            .err()
+            .unwrap_or_else(|| anyhow::anyhow!("plugin returned without a response"))),
+        Err(_) => bail!("Plugin response timeout after {:?}", RESPONSE_TIMEOUT),
+    }
+}
+
+/// The host side of one `handle_request_v2` call
+struct CallContext {
+    head: Option<oneshot::Sender<ResponseHead>>,
+    body: mpsc::Sender<Vec<u8>>,
+    host: Option<Arc<dyn HostServices>>,
+    /// host calls come from threads of the plugin and wait for the host's runtime
+    runtime: tokio::runtime::Handle,
+}
+
+impl CallContext {
+    fn callbacks(&mut self) -> HostCallbacks {
+        HostCallbacks {
+            ctx: self as *mut CallContext as *mut c_void,
+            send_head: on_send_head,
+            send_chunk: on_send_chunk,
+            host_call: on_host_call,
+        }
+    }
+}
+
+unsafe extern "C" fn on_send_head(ctx: *mut c_void, head: *const u8, head_len: usize) -> i32 {
+    let ctx = unsafe { &mut *(ctx as *mut CallContext) };
+    if head.is_null() || ctx.head.is_none() {
+        return 1;
+    }
+    let head = unsafe { std::slice::from_raw_parts(head, head_len) };
+    match serde_json::from_slice::<ResponseHead>(head) {
+        Ok(head) => match ctx.head.take().map(|sender| sender.send(head)) {
+            Some(Ok(())) => 0,
+            _ => 1,
+        },
+        Err(e) => {
+            error!("invalid response head from plugin: {:?}", e);
+            1
+        }
+    }
+}
+
+unsafe extern "C" fn on_send_chunk(ctx: *mut c_void, chunk: *const u8, chunk_len: usize) -> i32 {
+    let ctx = unsafe { &mut *(ctx as *mut CallContext) };
+    if ctx.head.is_some() {
+        error!("plugin sent a body chunk before the response head");
+        return 1;
+    }
+    if chunk_len == 0 {
+        return 0;
+    }
+    if chunk.is_null() {
+        return 1;
+    }
+    // copied, the plugin keeps its buffer
+    let chunk = unsafe { std::slice::from_raw_parts(chunk, chunk_len) }.to_vec();
+    match ctx.body.blocking_send(chunk) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+unsafe extern "C" fn on_host_call(
+    ctx: *mut c_void,
+    call: *const u8,
+    call_len: usize,
+    reply_ctx: *mut c_void,
+    on_reply: ReplyFn,
+) -> i32 {
+    let ctx = unsafe { &*(ctx as *const CallContext) };
+    if call.is_null() {
+        return 1;
+    }
+    let call = unsafe { std::slice::from_raw_parts(call, call_len) };
+    let reply = match (serde_json::from_slice::<HostCall>(call), &ctx.host) {
+        (Ok(call), Some(host)) => ctx
+            .runtime
+            .block_on(host.call(call))
+            .map_err(|e| format!("{:#}", e)),
+        (Ok(_), None) => Err("host services are not available to this plugin".to_string()),
+        (Err(e), _) => Err(format!("invalid host call: {}", e)),
+    };
+    if let Err(e) = &reply {
+        warn!("plugin host call failed: {}", e);
+    }
+    match serde_json::to_vec(&reply) {
+        Ok(reply) => {
+            unsafe { on_reply(reply_ctx, reply.as_ptr(), reply.len()) };
+            0
+        }
+        Err(_) => 1,
+    }
+}
+
+unsafe fn call_v2(lib: &PluginLib, request: &[u8], ctx: &mut CallContext) -> anyhow::Result<()> {
+    let handle_request: Symbol<HandleRequestV2Fn> = unsafe { lib.library.get(HANDLE_REQUEST_V2_FN_NAME.as_ref()) }
+        .context("`handle_request_v2` method not found.")?;
+    let callbacks = ctx.callbacks();
+    let code = unsafe { handle_request(request.as_ptr(), request.len(), &callbacks) };
+    if ctx.head.is_some() {
+        bail!("plugin returned {} without a response", code);
+    }
+    if code != 0 {
+        warn!("plugin handle_request_v2 returned {}", code);
+    }
+    Ok(())
+}
+
 /// Coordinated load and run function that uses internal request stores
 pub async fn load_and_run_coordinated(
     dylib_path: &str,
@@ -188,3 +408,96 @@ pub async fn load_and_run_with_id(
         tokio::time::sleep(Duration::from_millis(10)).await;
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use play_dylib_abi::plugin_abi::ResponseStream;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stream_through_callbacks() {
+        let (head_sender, head) = oneshot::channel();
+        let (body_sender, mut body) = mpsc::channel(16);
+        let runtime = tokio::runtime::Handle::current();
+        let sent = tokio::task::spawn_blocking(move || {
+            let mut ctx = CallContext {
+                head: Some(head_sender),
+                body: body_sender,
+                host: None,
+                runtime,
+            };
+            let callbacks = ctx.callbacks();
+            let mut stream = unsafe { ResponseStream::new(&callbacks) };
+            stream.send(b"hello ")?;
+            stream.send(b"world")?;
+            // the head goes out once
+            assert!(stream.send_head(500, Headers::new()).is_err());
+            anyhow::Ok(())
+        });
+
+        let head = head.await.unwrap();
+        assert_eq!(head.status_code, 200);
+        sent.await.unwrap().unwrap();
+        let mut received = vec![];
+        while let Some(chunk) = body.recv().await {
+            received.extend(chunk);
+        }
+        assert_eq!(received, b"hello world");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_error_response() {
+        let (head_sender, head) = oneshot::channel();
+        let (body_sender, _body) = mpsc::channel(16);
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let mut ctx = CallContext {
+                head: Some(head_sender),
+                body: body_sender,
+                host: None,
+                runtime,
+            };
+            let callbacks = ctx.callbacks();
+            let mut stream = unsafe { ResponseStream::new(&callbacks) };
+            stream.send_error("boom".to_string());
+        })
+        .await
+        .unwrap();
+        assert_eq!(head.await.unwrap().error.as_deref(), Some("boom"));
+    }
+
+    struct KvHost;
+
+    #[async_trait::async_trait]
+    impl HostServices for KvHost {
+        async fn call(&self, call: HostCall) -> anyhow::Result<serde_json::Value> {
+            match call {
+                HostCall::KvGet { key } => Ok(serde_json::json!(format!("value of {}", key))),
+                _ => bail!("not permitted"),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_host_call() {
+        let (head_sender, _head) = oneshot::channel();
+        let (body_sender, _body) = mpsc::channel(16);
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let mut ctx = CallContext {
+                head: Some(head_sender),
+                body: body_sender,
+                host: Some(Arc::new(KvHost)),
+                runtime,
+            };
+            let callbacks = ctx.callbacks();
+            let _host = unsafe { play_dylib_abi::plugin_abi::enter_host(&callbacks) };
+            assert_eq!(host_services::kv_get("a").unwrap().as_deref(), Some("value of a"));
+            let e = host_services::kv_delete("a").unwrap_err();
+            assert_eq!(e.to_string(), "not permitted");
+        })
+        .await
+        .unwrap();
+    }
+}
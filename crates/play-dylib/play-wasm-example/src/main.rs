@@ -0,0 +1,52 @@
+use play_dylib_abi::host_services;
+use play_dylib_abi::http_abi::*;
+use play_dylib_abi::wasm_abi::FILES_DIR;
+use play_dylib_abi::wasm_handler;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+
+#[derive(Deserialize, Debug, Default)]
+struct Query {
+    /// 需要在 permissions.http_allowlist 中允许该 host
+    fetch: Option<String>,
+}
+
+// 每个请求都在新的沙箱实例中运行，状态只能存在 /data（permissions.files）或宿主服务里
+fn handle_request_impl(request: HttpRequest) -> anyhow::Result<HttpResponse> {
+    eprintln!("handling {:?} {}", request.method, request.url);
+    let query: Query = request.parse_query()?;
+
+    let visits = count_visit().ok();
+    let fetched = match query.fetch {
+        Some(url) => {
+            let response = host_services::http("GET", &url, Default::default(), String::new())?;
+            Some(json!({
+                "status": response.status_code,
+                "body": String::from_utf8_lossy(&response.body),
+            }))
+        }
+        None => None,
+    };
+
+    Ok(HttpResponse::json(&json!({
+        "url": request.url,
+        "method": format!("{:?}", request.method),
+        "visits": visits,
+        "fetched": fetched,
+    })))
+}
+
+fn count_visit() -> anyhow::Result<u64> {
+    let path = Path::new(FILES_DIR).join("visits.txt");
+    let visits = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(0)
+        + 1;
+    std::fs::write(&path, visits.to_string())?;
+    Ok(visits)
+}
+
+// 导出 play_alloc 和 main（即 WASI 的 _start）
+wasm_handler!(handle_request_impl);
@@ -0,0 +1,370 @@
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use dashmap::DashMap;
+use log::{info, warn};
+pub use play_dylib_abi::host_services::{HostCall, HostServices};
+pub use play_dylib_abi::http_abi::{HttpRequest, HttpResponse};
+use play_dylib_abi::wasm_abi::{ALLOC_FN_NAME, FILES_DIR, HOST_CALL_FN_NAME, HOST_MODULE};
+use wasmtime::{
+    Caller, Config, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    Trap,
+};
+use wasmtime_wasi::pipe::{MemoryInputPipe, MemoryOutputPipe};
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, WasiCtxBuilder};
+
+/// how often the epoch of the engine advances, the granularity of `WasmLimits.timeout`.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+/// fuel a plugin burns between giving the executor back to other tasks.
+const FUEL_YIELD_INTERVAL: u64 = 100_000;
+const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+const MAX_LOG_SIZE: usize = 1024 * 1024;
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut config = Config::new();
+    config
+        .async_support(true)
+        .consume_fuel(true)
+        .epoch_interruption(true);
+    let engine = Engine::new(&config).expect("invalid wasm engine config");
+
+    let ticker = engine.clone();
+    std::thread::Builder::new()
+        .name("wasm-epoch".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(EPOCH_TICK);
+            ticker.increment_epoch();
+        })
+        .expect("failed to start the wasm epoch thread");
+    engine
+});
+
+static LINKER: LazyLock<Linker<StoreState>> = LazyLock::new(|| {
+    let mut linker = Linker::new(&ENGINE);
+    wasmtime_wasi::preview1::add_to_linker_async(&mut linker, |s: &mut StoreState| &mut s.wasi)
+        .expect("failed to add wasi to the linker");
+    linker
+        .func_wrap_async(HOST_MODULE, HOST_CALL_FN_NAME, host_call)
+        .expect("failed to add host_call to the linker");
+    linker
+});
+
+/// compiled plugins by file path, a new version of a plugin comes with a new path.
+static MODULES: LazyLock<DashMap<String, InstancePre<StoreState>>> = LazyLock::new(DashMap::new);
+
+/// what a single request of a plugin may use.
+#[derive(Debug, Clone)]
+pub struct WasmLimits {
+    /// roughly the number of wasm instructions
+    pub fuel: u64,
+    pub memory_bytes: usize,
+    /// includes the time spent in host calls
+    pub timeout: Duration,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 5_000_000_000,
+            memory_bytes: 64 * 1024 * 1024,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// the environment a plugin runs in, nothing outside of it is reachable.
+pub struct WasmSandbox {
+    /// used in logs and as the program name
+    pub name: String,
+    pub limits: WasmLimits,
+    /// mounted at `/data` when set
+    pub files_dir: Option<PathBuf>,
+    pub host: Arc<dyn HostServices>,
+}
+
+struct StoreState {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+    host: Arc<dyn HostServices>,
+}
+
+pub fn is_module_cached(path: &str) -> bool {
+    MODULES.contains_key(path)
+}
+
+pub fn remove_module_from_cache(path: &str) -> bool {
+    MODULES.remove(path).is_some()
+}
+
+pub fn clear_module_cache() {
+    MODULES.clear();
+}
+
+async fn load_module(path: &str) -> anyhow::Result<InstancePre<StoreState>> {
+    if let Some(module) = MODULES.get(path) {
+        return Ok(module.clone());
+    }
+    let file_path = path.to_string();
+    let module = tokio::task::spawn_blocking(move || Module::from_file(&ENGINE, &file_path))
+        .await?
+        .with_context(|| format!("failed to compile wasm plugin: {}", path))?;
+    let module = LINKER.instantiate_pre(&module).with_context(|| {
+        format!(
+            "wasm plugin imports what the host doesn't provide: {}",
+            path
+        )
+    })?;
+    info!("wasm plugin compiled: {}", path);
+    MODULES.insert(path.to_string(), module.clone());
+    Ok(module)
+}
+
+/// runs the plugin at `path` on `request` in a fresh instance.
+pub async fn call_wasm_plugin(
+    path: &str,
+    request: HttpRequest,
+    sandbox: WasmSandbox,
+) -> anyhow::Result<HttpResponse> {
+    let module = load_module(path).await?;
+    let timeout = sandbox.limits.timeout;
+    // the epoch deadline stops plugins that spin, this one those waiting on the host
+    match tokio::time::timeout(timeout, run(module, request, sandbox)).await {
+        Ok(response) => response,
+        Err(_) => bail!("wasm plugin timed out after {:?}", timeout),
+    }
+}
+
+async fn run(
+    module: InstancePre<StoreState>,
+    request: HttpRequest,
+    sandbox: WasmSandbox,
+) -> anyhow::Result<HttpResponse> {
+    let stdout = MemoryOutputPipe::new(MAX_RESPONSE_SIZE);
+    let stderr = MemoryOutputPipe::new(MAX_LOG_SIZE);
+    let mut wasi = WasiCtxBuilder::new();
+    wasi.stdin(MemoryInputPipe::new(serde_json::to_vec(&request)?))
+        .stdout(stdout.clone())
+        .stderr(stderr.clone())
+        .arg(&sandbox.name);
+    if let Some(dir) = &sandbox.files_dir {
+        tokio::fs::create_dir_all(dir).await?;
+        wasi.preopened_dir(dir, FILES_DIR, DirPerms::all(), FilePerms::all())?;
+    }
+
+    let limits = &sandbox.limits;
+    let mut store = Store::new(
+        &ENGINE,
+        StoreState {
+            wasi: wasi.build_p1(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.memory_bytes)
+                .build(),
+            host: sandbox.host.clone(),
+        },
+    );
+    store.limiter(|s| &mut s.limits);
+    store.set_fuel(limits.fuel)?;
+    store.fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL))?;
+    store.set_epoch_deadline((limits.timeout.as_millis() / EPOCH_TICK.as_millis()) as u64 + 1);
+    store.epoch_deadline_trap();
+
+    let result = async {
+        let instance = module.instantiate_async(&mut store).await?;
+        let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+        start.call_async(&mut store, ()).await
+    }
+    .await;
+
+    let log = stderr.contents();
+    for line in String::from_utf8_lossy(&log).lines() {
+        info!("[wasm:{}] {}", sandbox.name, line);
+    }
+
+    if let Err(e) = result {
+        match e.downcast_ref::<I32Exit>() {
+            Some(I32Exit(0)) => {}
+            Some(I32Exit(code)) => bail!("wasm plugin exited with code {}", code),
+            None => return Err(trap_error(e)),
+        }
+    }
+    serde_json::from_slice(&stdout.contents()).context("invalid response from wasm plugin")
+}
+
+fn trap_error(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => anyhow::anyhow!("wasm plugin ran out of fuel"),
+        Some(Trap::Interrupt) => anyhow::anyhow!("wasm plugin timed out"),
+        _ => e.context("wasm plugin failed"),
+    }
+}
+
+/// `play.host_call`, see `play_dylib_abi::wasm_abi::HOST_CALL_FN_NAME`.
+fn host_call(
+    mut caller: Caller<'_, StoreState>,
+    (ptr, len): (i32, i32),
+) -> Box<dyn Future<Output = anyhow::Result<i64>> + Send + '_> {
+    Box::new(async move {
+        let memory = caller
+            .get_export("memory")
+            .and_then(|e| e.into_memory())
+            .context("wasm plugin doesn't export its memory")?;
+        let mut call = vec![0u8; len as u32 as usize];
+        memory.read(&caller, ptr as u32 as usize, &mut call)?;
+
+        let reply = match serde_json::from_slice::<HostCall>(&call) {
+            Ok(call) => {
+                let host = caller.data().host.clone();
+                host.call(call).await.map_err(|e| format!("{:#}", e))
+            }
+            Err(e) => Err(format!("invalid host call: {}", e)),
+        };
+        if let Err(e) = &reply {
+            warn!("wasm plugin host call failed: {}", e);
+        }
+        let reply = serde_json::to_vec(&reply)?;
+
+        let alloc = caller
+            .get_export(ALLOC_FN_NAME)
+            .and_then(|e| e.into_func())
+            .with_context(|| format!("wasm plugin doesn't export {}", ALLOC_FN_NAME))?
+            .typed::<i32, i32>(&caller)?;
+        let reply_ptr = alloc.call_async(&mut caller, reply.len() as i32).await?;
+        memory.write(&mut caller, reply_ptr as u32 as usize, &reply)?;
+        Ok(((reply_ptr as u32 as i64) << 32) | reply.len() as i64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use serde_json::Value;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingHost {
+        calls: Mutex<Vec<HostCall>>,
+    }
+
+    #[async_trait]
+    impl HostServices for RecordingHost {
+        async fn call(&self, call: HostCall) -> anyhow::Result<Value> {
+            self.calls.lock().unwrap().push(call);
+            Ok(Value::Bool(true))
+        }
+    }
+
+    fn wat_string(s: &str) -> String {
+        s.replace('"', "\\22")
+    }
+
+    /// a plugin that writes `response` to stdout after running `body`.
+    fn plugin(response: &str, body: &str, extra: &str) -> String {
+        format!(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                {extra}
+                (memory (export "memory") 1)
+                (data (i32.const 1024) "{}")
+                (func (export "_start")
+                    {body}
+                    (i32.store (i32.const 0) (i32.const 1024))
+                    (i32.store (i32.const 4) (i32.const {}))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+            wat_string(response),
+            response.len(),
+        )
+    }
+
+    async fn call(
+        wat: &str,
+        limits: WasmLimits,
+        host: Arc<dyn HostServices>,
+    ) -> anyhow::Result<HttpResponse> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("plugin.wat");
+        std::fs::write(&path, wat)?;
+        let sandbox = WasmSandbox {
+            name: "test".to_string(),
+            limits,
+            files_dir: None,
+            host,
+        };
+        call_wasm_plugin(path.to_str().unwrap(), HttpRequest::default(), sandbox).await
+    }
+
+    #[tokio::test]
+    async fn test_response() {
+        let wat = plugin(
+            r#"{"headers":{},"body":[104,105],"status_code":201}"#,
+            "",
+            "",
+        );
+        let response = call(
+            &wat,
+            WasmLimits::default(),
+            Arc::new(RecordingHost::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status_code, 201);
+        assert_eq!(response.body, b"hi");
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let host = Arc::new(RecordingHost::default());
+        let spin = plugin("{}", "(loop $l (br $l))", "");
+        let limits = WasmLimits {
+            fuel: 1_000_000,
+            ..Default::default()
+        };
+        let e = call(&spin, limits, host.clone()).await.unwrap_err();
+        assert!(e.to_string().contains("fuel"), "{:?}", e);
+
+        let limits = WasmLimits {
+            fuel: u64::MAX,
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let e = call(&spin, limits, host.clone()).await.unwrap_err();
+        assert!(e.to_string().contains("timed out"), "{:?}", e);
+
+        let big = plugin("{}", "", "").replace(
+            "(memory (export \"memory\") 1)",
+            "(memory (export \"memory\") 100)",
+        );
+        let limits = WasmLimits {
+            memory_bytes: 1024 * 1024,
+            ..Default::default()
+        };
+        assert!(call(&big, limits, host).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_host_call() {
+        let host = Arc::new(RecordingHost::default());
+        let extra = format!(
+            r#"(import "play" "host_call" (func $host_call (param i32 i32) (result i64)))
+               (data (i32.const 4096) "{}")
+               (func (export "play_alloc") (param i32) (result i32) (i32.const 8192))"#,
+            wat_string(r#"{"service":"data_get","cat":"notes","id":7}"#)
+        );
+        let body = "(drop (call $host_call (i32.const 4096) (i32.const 43)))";
+        let wat = plugin(r#"{"headers":{},"body":[]}"#, body, &extra);
+        let response = call(&wat, WasmLimits::default(), host.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            host.calls.lock().unwrap().as_slice(),
+            &[HostCall::DataGet {
+                cat: "notes".to_string(),
+                id: 7
+            }]
+        );
+    }
+}
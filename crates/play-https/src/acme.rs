@@ -0,0 +1,343 @@
+//! a small RFC 8555 client, enough to order single-domain certificates
+//! with `tls-alpn-01` or `dns-01` challenges.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub const LETS_ENCRYPT_PRODUCTION_DIRECTORY: &str =
+    "https://acme-v02.api.letsencrypt.org/directory";
+pub const LETS_ENCRYPT_STAGING_DIRECTORY: &str =
+    "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 60;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Order {
+    pub status: String,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    pub certificate: Option<String>,
+    pub error: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Identifier {
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Authorization {
+    pub status: String,
+    pub identifier: Identifier,
+    pub challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Challenge {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub url: String,
+    pub token: String,
+    pub error: Option<Value>,
+}
+
+impl Authorization {
+    pub fn challenge(&self, typ: &str) -> anyhow::Result<&Challenge> {
+        self.challenges
+            .iter()
+            .find(|c| c.typ == typ)
+            .ok_or_else(|| anyhow!("no {} challenge offered for {}", typ, self.identifier.value))
+    }
+}
+
+pub struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    /// account url, used as `kid` once registered
+    kid: String,
+}
+
+/// a new P-256 account key in pkcs8 der.
+pub fn generate_account_key() -> anyhow::Result<Vec<u8>> {
+    let pkcs8 =
+        EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+            .map_err(|_| anyhow!("generate acme account key failed"))?;
+    Ok(pkcs8.as_ref().to_vec())
+}
+
+fn b64(data: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    digest(&SHA256, data).as_ref().to_vec()
+}
+
+impl AcmeClient {
+    /// register (or look up) the account of `account_key` at the directory.
+    pub async fn connect(
+        directory_url: &str,
+        account_key: &[u8],
+        contact: &[String],
+    ) -> anyhow::Result<Self> {
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key, &rng)
+            .map_err(|e| anyhow!("invalid acme account key : {}", e))?;
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        let directory: Directory = http
+            .get(directory_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("read acme directory failed")?;
+
+        let mut client = Self {
+            http,
+            directory,
+            key,
+            rng,
+            kid: String::new(),
+        };
+        let payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": contact.iter().map(|c| format!("mailto:{}", c)).collect::<Vec<_>>(),
+        });
+        let url = client.directory.new_account.clone();
+        let response = client.post(&url, Some(&payload)).await?;
+        client.kid = header(&response, "location")?;
+        Ok(client)
+    }
+
+    fn jwk(&self) -> Value {
+        // uncompressed point : 0x04 | x | y
+        let public = self.key.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": b64(&public[1..33]),
+            "y": b64(&public[33..65]),
+        })
+    }
+
+    /// `token.thumbprint`, the key authorization of a challenge.
+    pub fn key_authorization(&self, token: &str) -> String {
+        let jwk = self.jwk();
+        // members in lexicographic order without whitespace (RFC 7638)
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk["x"].as_str().unwrap_or_default(),
+            jwk["y"].as_str().unwrap_or_default()
+        );
+        format!("{}.{}", token, b64(sha256(canonical.as_bytes())))
+    }
+
+    /// the TXT record value of a `dns-01` challenge.
+    pub fn dns01_value(&self, token: &str) -> String {
+        b64(sha256(self.key_authorization(token).as_bytes()))
+    }
+
+    async fn nonce(&self) -> anyhow::Result<String> {
+        let response = self.http.head(&self.directory.new_nonce).send().await?;
+        header(&response, "replay-nonce")
+    }
+
+    fn sign(&self, url: &str, nonce: String, payload: Option<&Value>) -> anyhow::Result<Value> {
+        let mut protected = json!({
+            "alg": "ES256",
+            "nonce": nonce,
+            "url": url,
+        });
+        if self.kid.is_empty() {
+            protected["jwk"] = self.jwk();
+        } else {
+            protected["kid"] = json!(self.kid);
+        }
+        let protected = b64(serde_json::to_vec(&protected)?);
+        // POST-as-GET has an empty payload
+        let payload = match payload {
+            Some(payload) => b64(serde_json::to_vec(payload)?),
+            None => String::new(),
+        };
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| anyhow!("sign acme request failed"))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(signature.as_ref()),
+        }))
+    }
+
+    async fn post(&self, url: &str, payload: Option<&Value>) -> anyhow::Result<reqwest::Response> {
+        let mut retried = false;
+        loop {
+            let body = self.sign(url, self.nonce().await?, payload)?;
+            let response = self
+                .http
+                .post(url)
+                .header("content-type", "application/jose+json")
+                .body(serde_json::to_vec(&body)?)
+                .send()
+                .await?;
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let status = response.status();
+            let problem: Value = response.json().await.unwrap_or_default();
+            // nonces may expire between fetching and using them
+            if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            bail!("acme request {} failed : {} {}", url, status, problem);
+        }
+    }
+
+    pub async fn new_order(&self, domain: &str) -> anyhow::Result<(String, Order)> {
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": domain }] });
+        let response = self.post(&self.directory.new_order, Some(&payload)).await?;
+        let url = header(&response, "location")?;
+        Ok((url, response.json().await?))
+    }
+
+    pub async fn authorization(&self, url: &str) -> anyhow::Result<Authorization> {
+        Ok(self.post(url, None).await?.json().await?)
+    }
+
+    /// tell the server the challenge is ready to be validated.
+    pub async fn respond(&self, challenge: &Challenge) -> anyhow::Result<()> {
+        self.post(&challenge.url, Some(&json!({}))).await?;
+        Ok(())
+    }
+
+    pub async fn wait_authorization(&self, url: &str) -> anyhow::Result<()> {
+        for _ in 0..POLL_ATTEMPTS {
+            let auth = self.authorization(url).await?;
+            match auth.status.as_str() {
+                "valid" => return Ok(()),
+                "pending" | "processing" => tokio::time::sleep(POLL_INTERVAL).await,
+                status => {
+                    let errors: Vec<&Value> = auth
+                        .challenges
+                        .iter()
+                        .filter_map(|c| c.error.as_ref())
+                        .collect();
+                    bail!(
+                        "authorization of {} is {} : {:?}",
+                        auth.identifier.value,
+                        status,
+                        errors
+                    );
+                }
+            }
+        }
+        bail!("authorization {} timed out", url)
+    }
+
+    async fn wait_order(&self, url: &str, until: &str) -> anyhow::Result<Order> {
+        for _ in 0..POLL_ATTEMPTS {
+            let order: Order = self.post(url, None).await?.json().await?;
+            if order.status == until {
+                return Ok(order);
+            }
+            if order.status == "invalid" {
+                bail!("order {} is invalid : {:?}", url, order.error);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        bail!("order {} timed out waiting for {}", url, until)
+    }
+
+    /// submit the csr once every authorization is valid, returns the pem certificate chain.
+    pub async fn finalize(
+        &self,
+        order_url: &str,
+        order: &Order,
+        csr_der: &[u8],
+    ) -> anyhow::Result<String> {
+        self.wait_order(order_url, "ready").await?;
+        self.post(&order.finalize, Some(&json!({ "csr": b64(csr_der) })))
+            .await?;
+        let order = self.wait_order(order_url, "valid").await?;
+        let certificate = order
+            .certificate
+            .ok_or_else(|| anyhow!("order {} has no certificate", order_url))?;
+        Ok(self.post(&certificate, None).await?.text().await?)
+    }
+}
+
+fn header(response: &reqwest::Response, name: &str) -> anyhow::Result<String> {
+    Ok(response
+        .headers()
+        .get(name)
+        .ok_or_else(|| anyhow!("acme response has no {} header", name))?
+        .to_str()?
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jws_and_key_authorization() -> anyhow::Result<()> {
+        let key = generate_account_key()?;
+        let rng = SystemRandom::new();
+        let client = AcmeClient {
+            http: reqwest::Client::new(),
+            directory: Directory {
+                new_nonce: String::new(),
+                new_account: String::new(),
+                new_order: String::new(),
+            },
+            key: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &key, &rng).unwrap(),
+            rng,
+            kid: String::new(),
+        };
+
+        let key_auth = client.key_authorization("token");
+        let thumbprint = key_auth.strip_prefix("token.").unwrap();
+        // base64url of a sha256 digest
+        assert_eq!(thumbprint.len(), 43);
+        assert_eq!(client.dns01_value("token").len(), 43);
+
+        let jws = client.sign("https://acme/new-acct", "nonce".to_string(), None)?;
+        let protected: Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(jws["protected"].as_str().unwrap())?)?;
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["jwk"]["crv"], "P-256");
+        assert_eq!(jws["payload"], "");
+        // fixed size r | s signature
+        assert_eq!(
+            URL_SAFE_NO_PAD
+                .decode(jws["signature"].as_str().unwrap())?
+                .len(),
+            64
+        );
+        Ok(())
+    }
+}
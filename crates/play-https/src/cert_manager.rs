@@ -0,0 +1,461 @@
+//! per-domain certificates : issuing, renewing, and picking one by SNI.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context};
+use log::{error, info, warn};
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName, KeyPair};
+use rustls::sign::CertifiedKey;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use serde::Serialize;
+
+use crate::acme::{self, AcmeClient};
+use crate::local_ca::LocalCa;
+use crate::{dns, AcmeChallenge, CertSource, HttpsConfig};
+
+pub const ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+const RETRY_INTERVAL: Duration = Duration::from_secs(3600);
+
+static MANAGER: OnceLock<Arc<CertManager>> = OnceLock::new();
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CertStatus {
+    pub domain: String,
+    /// `acme` or `local_ca`
+    pub source: String,
+    pub challenge: Option<String>,
+    pub issuer: Option<String>,
+    pub subject: Option<String>,
+    /// unix seconds
+    pub not_before: Option<i64>,
+    pub not_after: Option<i64>,
+    pub days_left: Option<i64>,
+    /// unix seconds of the last successful renewal
+    pub last_renewal: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+pub struct CertManager {
+    config: HttpsConfig,
+    certs: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+    /// `tls-alpn-01` validation certs by domain, only live while an order is pending
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+    status: RwLock<BTreeMap<String, CertStatus>>,
+    local_ca: Option<LocalCa>,
+    renewing: tokio::sync::Mutex<()>,
+}
+
+pub(crate) fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// `*.example.com` is stored as `_wildcard.example.com`.
+fn domain_dir_name(domain: &str) -> String {
+    domain.replace('*', "_wildcard")
+}
+
+pub(crate) fn certified_key(cert_pem: &str, key_pem: &str) -> anyhow::Result<CertifiedKey> {
+    let certs = CertificateDer::pem_slice_iter(cert_pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("parse certificate pem failed : {:?}", e))?;
+    if certs.is_empty() {
+        bail!("no certificate found in pem");
+    }
+    let key = PrivateKeyDer::from_pem_slice(key_pem.as_bytes())
+        .map_err(|e| anyhow!("parse private key pem failed : {:?}", e))?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| anyhow!("unsupported private key : {:?}", e))?;
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// fill issuer, subject and validity from the leaf certificate.
+pub(crate) fn describe(status: &mut CertStatus, cert: &CertifiedKey) {
+    let Some(leaf) = cert.cert.first() else {
+        return;
+    };
+    match x509_parser::parse_x509_certificate(leaf.as_ref()) {
+        Ok((_, parsed)) => {
+            let validity = parsed.validity();
+            let not_after = validity.not_after.timestamp();
+            status.issuer = Some(parsed.issuer().to_string());
+            status.subject = Some(parsed.subject().to_string());
+            status.not_before = Some(validity.not_before.timestamp());
+            status.not_after = Some(not_after);
+            status.days_left = Some((not_after - now_secs()) / 86400);
+        }
+        Err(e) => warn!("parse certificate failed : {:?}", e),
+    }
+}
+
+impl CertManager {
+    pub(crate) fn new(config: HttpsConfig) -> anyhow::Result<Self> {
+        let local_ca = match config.source {
+            CertSource::LocalCa => Some(LocalCa::load_or_create(
+                &Path::new(&config.cache_dir).join("local-ca"),
+            )?),
+            CertSource::Acme => None,
+        };
+        Ok(Self {
+            config,
+            certs: Default::default(),
+            challenges: Default::default(),
+            status: Default::default(),
+            local_ca,
+            renewing: Default::default(),
+        })
+    }
+
+    fn source_name(&self) -> &'static str {
+        match self.config.source {
+            CertSource::Acme => "acme",
+            CertSource::LocalCa => "local_ca",
+        }
+    }
+
+    fn store_dir(&self) -> PathBuf {
+        let env = match (&self.config.source, self.config.prod) {
+            (CertSource::LocalCa, _) => "local-ca",
+            (CertSource::Acme, true) => "acme-prod",
+            (CertSource::Acme, false) => "acme-staging",
+        };
+        Path::new(&self.config.cache_dir).join(env)
+    }
+
+    fn challenge_for(&self, domain: &str) -> AcmeChallenge {
+        // wildcard certificates can only be validated through DNS
+        if domain.starts_with("*.") {
+            AcmeChallenge::Dns01
+        } else {
+            self.config.challenge.clone()
+        }
+    }
+
+    fn install(&self, domain: &str, cert: CertifiedKey) {
+        let mut status = self.status.write().unwrap();
+        let entry = status.entry(domain.to_string()).or_default();
+        describe(entry, &cert);
+        self.certs
+            .write()
+            .unwrap()
+            .insert(domain.to_string(), Arc::new(cert));
+    }
+
+    /// load certificates issued by previous runs.
+    fn load_cached(&self) {
+        for domain in &self.config.domains {
+            {
+                let mut status = self.status.write().unwrap();
+                let entry = status.entry(domain.clone()).or_default();
+                entry.domain = domain.clone();
+                entry.source = self.source_name().to_string();
+                entry.challenge = match self.config.source {
+                    CertSource::Acme => Some(self.challenge_for(domain).name().to_string()),
+                    CertSource::LocalCa => None,
+                };
+            }
+            let dir = self.store_dir().join(domain_dir_name(domain));
+            let (Ok(cert_pem), Ok(key_pem)) = (
+                std::fs::read_to_string(dir.join("cert.pem")),
+                std::fs::read_to_string(dir.join("key.pem")),
+            ) else {
+                continue;
+            };
+            match certified_key(&cert_pem, &key_pem) {
+                Ok(cert) => self.install(domain, cert),
+                Err(e) => warn!("ignore cached certificate of {} : {:?}", domain, e),
+            }
+        }
+    }
+
+    fn needs_renewal(&self, domain: &str) -> bool {
+        let status = self.status.read().unwrap();
+        match status.get(domain).and_then(|s| s.not_after) {
+            Some(not_after) => {
+                not_after - now_secs() < self.config.renew_before_days as i64 * 86400
+            }
+            None => true,
+        }
+    }
+
+    /// issue a new certificate for `domain` and start serving it.
+    pub(crate) async fn renew(&self, domain: &str) -> anyhow::Result<()> {
+        let _guard = self.renewing.lock().await;
+        info!("renewing certificate of {}", domain);
+        let result = async {
+            let (cert_pem, key_pem) = match &self.local_ca {
+                Some(ca) => ca.issue(domain)?,
+                None => self.order_acme(domain).await?,
+            };
+            let cert = certified_key(&cert_pem, &key_pem)?;
+            let dir = self.store_dir().join(domain_dir_name(domain));
+            tokio::fs::create_dir_all(&dir).await?;
+            tokio::fs::write(dir.join("cert.pem"), &cert_pem).await?;
+            tokio::fs::write(dir.join("key.pem"), &key_pem).await?;
+            anyhow::Ok(cert)
+        }
+        .await;
+
+        match result {
+            Ok(cert) => {
+                self.install(domain, cert);
+                let mut status = self.status.write().unwrap();
+                let entry = status.entry(domain.to_string()).or_default();
+                entry.last_renewal = Some(now_secs());
+                entry.last_error = None;
+                info!("certificate of {} renewed", domain);
+                Ok(())
+            }
+            Err(e) => {
+                error!("renew certificate of {} failed : {:?}", domain, e);
+                let mut status = self.status.write().unwrap();
+                status.entry(domain.to_string()).or_default().last_error = Some(format!("{:#}", e));
+                Err(e)
+            }
+        }
+    }
+
+    async fn account_key(&self) -> anyhow::Result<Vec<u8>> {
+        let path = self.store_dir().join("account.key");
+        if let Ok(key) = tokio::fs::read(&path).await {
+            return Ok(key);
+        }
+        let key = acme::generate_account_key()?;
+        tokio::fs::create_dir_all(self.store_dir()).await?;
+        tokio::fs::write(&path, &key).await?;
+        Ok(key)
+    }
+
+    async fn order_acme(&self, domain: &str) -> anyhow::Result<(String, String)> {
+        let directory = if self.config.prod {
+            acme::LETS_ENCRYPT_PRODUCTION_DIRECTORY
+        } else {
+            acme::LETS_ENCRYPT_STAGING_DIRECTORY
+        };
+        let client =
+            AcmeClient::connect(directory, &self.account_key().await?, &self.config.email).await?;
+        let (order_url, order) = client.new_order(domain).await?;
+
+        for auth_url in &order.authorizations {
+            let auth = client.authorization(auth_url).await?;
+            if auth.status == "valid" {
+                continue;
+            }
+            match self.challenge_for(domain) {
+                AcmeChallenge::TlsAlpn01 => {
+                    let challenge = auth.challenge(AcmeChallenge::TlsAlpn01.name())?;
+                    let key_auth = client.key_authorization(&challenge.token);
+                    let cert = challenge_cert(&auth.identifier.value, &key_auth)?;
+                    self.challenges
+                        .write()
+                        .unwrap()
+                        .insert(auth.identifier.value.clone(), Arc::new(cert));
+                    let result = async {
+                        client.respond(challenge).await?;
+                        client.wait_authorization(auth_url).await
+                    }
+                    .await;
+                    self.challenges
+                        .write()
+                        .unwrap()
+                        .remove(&auth.identifier.value);
+                    result?;
+                }
+                AcmeChallenge::Dns01 => {
+                    let cloudflare = self
+                        .config
+                        .cloudflare
+                        .as_ref()
+                        .context("dns-01 needs a cloudflare api token and zone id")?;
+                    let challenge = auth.challenge(AcmeChallenge::Dns01.name())?;
+                    let name = dns::challenge_record_name(domain);
+                    let value = client.dns01_value(&challenge.token);
+                    let record_id = dns::create_txt_record(cloudflare, &name, &value).await?;
+                    let result = async {
+                        dns::wait_for_txt_record(
+                            &name,
+                            &value,
+                            Duration::from_secs(self.config.dns_propagation_timeout_secs),
+                        )
+                        .await?;
+                        client.respond(challenge).await?;
+                        client.wait_authorization(auth_url).await
+                    }
+                    .await;
+                    if let Err(e) = dns::delete_txt_record(cloudflare, &record_id).await {
+                        warn!("delete txt record {} failed : {:?}", name, e);
+                    }
+                    result?;
+                }
+            }
+        }
+
+        let key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(vec![domain.to_string()])?;
+        params.distinguished_name = DistinguishedName::new();
+        let csr = params.serialize_request(&key)?;
+        let chain = client.finalize(&order_url, &order, csr.der()).await?;
+        Ok((chain, key.serialize_pem()))
+    }
+
+    async fn renew_due(&self) -> bool {
+        let mut ok = true;
+        for domain in &self.config.domains {
+            if self.needs_renewal(domain) && self.renew(domain).await.is_err() {
+                ok = false;
+            }
+        }
+        ok
+    }
+
+    async fn renew_loop(self: Arc<Self>) {
+        loop {
+            let interval = if self.renew_due().await {
+                CHECK_INTERVAL
+            } else {
+                RETRY_INTERVAL
+            };
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// the certificate issued for exactly `name`, `*.example.com` included.
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        self.certs.read().unwrap().get(name).cloned()
+    }
+
+    /// the main domain certificate, for requests by ip or unknown names.
+    pub(crate) fn default_cert(&self) -> Option<Arc<CertifiedKey>> {
+        let certs = self.certs.read().unwrap();
+        self.config
+            .domains
+            .iter()
+            .find_map(|d| certs.get(d))
+            .cloned()
+    }
+
+    /// the pending `tls-alpn-01` validation cert of `name`.
+    pub(crate) fn challenge(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        self.challenges.read().unwrap().get(name).cloned()
+    }
+
+    pub(crate) fn manages(&self, domain: &str) -> bool {
+        self.config.domains.iter().any(|d| d == domain)
+    }
+}
+
+/// the self-signed cert answering a `tls-alpn-01` validation (RFC 8737).
+fn challenge_cert(domain: &str, key_authorization: &str) -> anyhow::Result<CertifiedKey> {
+    let mut params = CertificateParams::new(vec![domain.to_string()])?;
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(&acme::sha256(
+        key_authorization.as_bytes(),
+    ))];
+    let key = KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+    certified_key(&cert.pem(), &key.serialize_pem())
+}
+
+/// set up the global manager, load cached certs and start the renewal loop.
+pub(crate) fn start(config: HttpsConfig) -> anyhow::Result<Arc<CertManager>> {
+    let manager = Arc::new(CertManager::new(config)?);
+    manager.load_cached();
+    MANAGER
+        .set(manager.clone())
+        .map_err(|_| anyhow!("certificate manager already started"))?;
+    tokio::spawn(manager.clone().renew_loop());
+    Ok(manager)
+}
+
+/// status of every managed certificate, empty before the https server starts.
+pub(crate) fn cert_status() -> Vec<CertStatus> {
+    MANAGER
+        .get()
+        .map(|m| m.status.read().unwrap().values().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// renew a certificate right away, regardless of its expiry.
+pub(crate) async fn renew_certificate(domain: &str) -> anyhow::Result<CertStatus> {
+    let manager = MANAGER.get().context("https server is not running")?;
+    if !manager.manages(domain) {
+        bail!("{} is not a managed domain", domain);
+    }
+    manager.renew(domain).await?;
+    let status = manager.status.read().unwrap();
+    Ok(status.get(domain).cloned().unwrap_or_default())
+}
+
+/// the local CA certificate, for clients to trust in `local_ca` mode.
+pub fn local_ca_pem() -> Option<String> {
+    MANAGER
+        .get()?
+        .local_ca
+        .as_ref()
+        .map(|ca| ca.cert_pem.clone())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn subject(cert: &CertifiedKey) -> String {
+        let (_, parsed) = x509_parser::parse_x509_certificate(cert.cert[0].as_ref()).unwrap();
+        parsed.subject().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_local_ca_certificates() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("play-https-test-{}", now_secs()));
+        let manager = CertManager::new(HttpsConfig {
+            domains: vec!["example.com".to_string(), "*.example.com".to_string()],
+            cache_dir: dir.to_str().unwrap().to_string(),
+            source: CertSource::LocalCa,
+            ..Default::default()
+        })?;
+        manager.load_cached();
+        assert!(manager.needs_renewal("example.com"));
+        assert!(manager.renew_due().await);
+
+        let status = manager
+            .status
+            .read()
+            .unwrap()
+            .get("*.example.com")
+            .cloned()
+            .unwrap();
+        assert_eq!(status.source, "local_ca");
+        assert!(status.issuer.unwrap().contains("Play Local CA"));
+        assert!(status.days_left.unwrap() >= 89);
+        assert!(status.last_error.is_none());
+        assert!(!manager.needs_renewal("example.com"));
+
+        assert_eq!(
+            subject(&manager.get("example.com").unwrap()),
+            "CN=example.com"
+        );
+        assert_eq!(
+            subject(&manager.get("*.example.com").unwrap()),
+            "CN=*.example.com"
+        );
+        assert_eq!(subject(&manager.default_cert().unwrap()), "CN=example.com");
+
+        // a restart picks up the stored certificates and the same CA
+        let reloaded = CertManager::new(manager.config.clone())?;
+        reloaded.load_cached();
+        assert!(!reloaded.needs_renewal("*.example.com"));
+        assert_eq!(
+            reloaded.local_ca.unwrap().cert_pem,
+            manager.local_ca.as_ref().unwrap().cert_pem
+        );
+        let _ = std::fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
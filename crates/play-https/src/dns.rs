@@ -0,0 +1,110 @@
+//! cloudflare TXT records for `dns-01` challenges.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use log::info;
+use serde_json::{json, Value};
+
+use crate::CloudflareDns;
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+const DOH_URL: &str = "https://cloudflare-dns.com/dns-query";
+
+/// `_acme-challenge.` name of a domain, wildcards share the name of their base domain.
+pub fn challenge_record_name(domain: &str) -> String {
+    format!("_acme-challenge.{}", domain.trim_start_matches("*."))
+}
+
+fn client() -> anyhow::Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?)
+}
+
+fn check(body: &Value) -> anyhow::Result<()> {
+    if body["success"].as_bool() != Some(true) {
+        bail!("cloudflare api error : {}", body["errors"]);
+    }
+    Ok(())
+}
+
+/// create the TXT record, returns its record id.
+pub async fn create_txt_record(
+    cf: &CloudflareDns,
+    name: &str,
+    value: &str,
+) -> anyhow::Result<String> {
+    let body: Value = client()?
+        .post(format!(
+            "{}/zones/{}/dns_records",
+            CLOUDFLARE_API, cf.zone_id
+        ))
+        .bearer_auth(&cf.api_token)
+        .json(&json!({
+            "type": "TXT",
+            "name": name,
+            "content": value,
+            "ttl": 60,
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    check(&body)?;
+    body["result"]["id"]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| anyhow!("cloudflare returned no record id"))
+}
+
+pub async fn delete_txt_record(cf: &CloudflareDns, record_id: &str) -> anyhow::Result<()> {
+    let body: Value = client()?
+        .delete(format!(
+            "{}/zones/{}/dns_records/{}",
+            CLOUDFLARE_API, cf.zone_id, record_id
+        ))
+        .bearer_auth(&cf.api_token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    check(&body)
+}
+
+/// poll DNS over HTTPS until the TXT record is visible, so the CA doesn't validate too early.
+pub async fn wait_for_txt_record(name: &str, value: &str, timeout: Duration) -> anyhow::Result<()> {
+    let client = client()?;
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        let answer: anyhow::Result<Value> = async {
+            Ok(client
+                .get(DOH_URL)
+                .query(&[("name", name), ("type", "TXT")])
+                .header("accept", "application/dns-json")
+                .send()
+                .await?
+                .json()
+                .await?)
+        }
+        .await;
+        if let Ok(answer) = answer {
+            let found = answer["Answer"].as_array().is_some_and(|records| {
+                records
+                    .iter()
+                    .filter_map(|r| r["data"].as_str())
+                    .any(|data| data.trim_matches('"') == value)
+            });
+            if found {
+                info!(
+                    "txt record {} is visible after {:?}",
+                    name,
+                    started.elapsed()
+                );
+                return Ok(());
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    bail!("txt record {} not visible after {:?}", name, timeout)
+}
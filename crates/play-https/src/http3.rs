@@ -0,0 +1,206 @@
+//! HTTP/3 over QUIC, serving the same router with the same certificates.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::header::{CONNECTION, HOST, TRANSFER_ENCODING, UPGRADE};
+use axum::http::{HeaderValue, Request, Response};
+use axum::Router;
+use bytes::{Buf, Bytes};
+use h3::server::RequestResolver;
+use http_body_util::BodyExt;
+use log::{info, warn};
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::Endpoint;
+use tower::Service;
+
+use crate::tls::{HostGuard, TlsConfigs};
+
+/// headers of HTTP/1.1 connections that HTTP/3 forbids.
+const CONNECTION_HEADERS: [&str; 2] = ["keep-alive", "proxy-connection"];
+
+pub(crate) fn bind(addr: SocketAddr, tls: rustls::ServerConfig) -> anyhow::Result<Endpoint> {
+    let crypto = QuicServerConfig::try_from(Arc::new(tls))
+        .map_err(|e| anyhow::anyhow!("invalid QUIC tls config : {:?}", e))?;
+    let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    Endpoint::server(config, addr).with_context(|| format!("bind udp {} failed", addr))
+}
+
+pub(crate) async fn serve(endpoint: Endpoint, app: Router, configs: Arc<TlsConfigs>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let app = app.clone();
+        let configs = configs.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(incoming, app, configs).await {
+                info!("http3 connection closed : {:?}", e);
+            }
+        });
+    }
+}
+
+async fn serve_connection(
+    incoming: quinn::Incoming,
+    app: Router,
+    configs: Arc<TlsConfigs>,
+) -> anyhow::Result<()> {
+    let connection = incoming.await?;
+    let remote = connection.remote_address();
+    let mut h3_conn: h3::server::Connection<h3_quinn::Connection, Bytes> =
+        h3::server::Connection::new(h3_quinn::Connection::new(connection)).await?;
+    loop {
+        match h3_conn.accept().await {
+            Ok(Some(resolver)) => {
+                // connections over QUIC never carry client certificates
+                let service = HostGuard::plain(app.clone(), configs.clone(), true);
+                tokio::spawn(async move {
+                    if let Err(e) = serve_request(resolver, service, remote).await {
+                        warn!("http3 request from {} failed : {:?}", remote, e);
+                    }
+                });
+            }
+            Ok(None) => return Ok(()),
+            Err(e) if e.is_h3_no_error() => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+async fn serve_request(
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    mut service: HostGuard<Router>,
+    remote: SocketAddr,
+) -> anyhow::Result<()> {
+    let (request, stream) = resolver.resolve_request().await?;
+    let (mut send, recv) = stream.split();
+
+    let body = Body::from_stream(futures_util::stream::unfold(
+        Some(recv),
+        |recv| async move {
+            let mut recv = recv?;
+            match recv.recv_data().await {
+                Ok(Some(mut data)) => Some((Ok(data.copy_to_bytes(data.remaining())), Some(recv))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        },
+    ));
+    let (parts, ()) = request.into_parts();
+    let mut request = Request::from_parts(parts, body);
+    // the rest of the server reads the host from the `Host` header, http3 only has `:authority`
+    if !request.headers().contains_key(HOST) {
+        if let Some(authority) = request.uri().authority() {
+            let authority = HeaderValue::from_str(authority.as_str())?;
+            request.headers_mut().insert(HOST, authority);
+        }
+    }
+    request.extensions_mut().insert(ConnectInfo(remote));
+
+    std::future::poll_fn(|cx| Service::<Request<Body>>::poll_ready(&mut service, cx)).await?;
+    let response = service.call(request).await?;
+
+    let (mut parts, mut body) = response.into_parts();
+    for name in [CONNECTION, TRANSFER_ENCODING, UPGRADE] {
+        parts.headers.remove(name);
+    }
+    for name in CONNECTION_HEADERS {
+        parts.headers.remove(name);
+    }
+    send.send_response(Response::from_parts(parts, ())).await?;
+    while let Some(frame) = body.frame().await {
+        match frame?.into_data() {
+            Ok(data) => send.send_data(data).await?,
+            Err(frame) => {
+                if let Ok(trailers) = frame.into_trailers() {
+                    send.send_trailers(trailers).await?;
+                }
+            }
+        }
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_manager::CertManager;
+    use crate::static_certs::StaticCerts;
+    use crate::tls::CertResolver;
+    use crate::{CertSource, HttpsConfig};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    /// a POST over HTTP/3 with a self-signed local CA.
+    #[tokio::test]
+    async fn test_http3_request() -> anyhow::Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("play-https-h3-{}", crate::cert_manager::now_secs()));
+        let manager = Arc::new(CertManager::new(HttpsConfig {
+            domains: vec!["example.com".to_string()],
+            cache_dir: dir.to_str().unwrap().to_string(),
+            source: CertSource::LocalCa,
+            ..Default::default()
+        })?);
+        manager.renew("example.com").await?;
+        let ca_pem = std::fs::read(dir.join("local-ca/ca.pem"))?;
+        let configs = Arc::new(TlsConfigs::new(
+            CertResolver::new(manager, Arc::new(StaticCerts::new(&[]))),
+            &[],
+        )?);
+
+        let app = Router::new().route(
+            "/hello",
+            axum::routing::post(
+                |ConnectInfo(addr): ConnectInfo<SocketAddr>,
+                 headers: axum::http::HeaderMap,
+                 body: String| async move {
+                    format!(
+                        "{} {} {}",
+                        headers[HOST].to_str().unwrap(),
+                        addr.ip().is_loopback(),
+                        body
+                    )
+                },
+            ),
+        );
+        let endpoint = bind("127.0.0.1:0".parse()?, configs.h3_server_config()?)?;
+        let server_addr = endpoint.local_addr()?;
+        tokio::spawn(serve(endpoint, app, configs));
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_slice(&ca_pem).unwrap())?;
+        let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        tls.alpn_protocols = vec![b"h3".to_vec()];
+        let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(Arc::new(tls))?;
+        let mut client = Endpoint::client("127.0.0.1:0".parse()?)?;
+        client.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+
+        let connection = client.connect(server_addr, "example.com")?.await?;
+        let (mut driver, mut send_request) =
+            h3::client::new(h3_quinn::Connection::new(connection)).await?;
+        tokio::spawn(async move { driver.wait_idle().await });
+
+        let request = Request::post("https://example.com/hello").body(())?;
+        let mut stream = send_request.send_request(request).await?;
+        stream.send_data(Bytes::from_static(b"over quic")).await?;
+        stream.finish().await?;
+        let response = stream.recv_response().await?;
+        assert_eq!(response.status(), 200);
+        let mut body = Vec::new();
+        while let Some(mut data) = stream.recv_data().await? {
+            body.extend_from_slice(&data.copy_to_bytes(data.remaining()));
+        }
+        assert_eq!(String::from_utf8(body)?, "example.com true over quic");
+
+        let _ = std::fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
@@ -1,43 +1,138 @@
-use std::net::SocketAddr;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use axum::{BoxError, Router};
-use axum::extract::Host;
 use axum::handler::HandlerWithoutStateExt;
-use axum::http::{StatusCode, Uri};
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::http::header::HOST;
 use axum::response::Redirect;
 use log::{info, warn};
-use rustls_acme::AcmeConfig;
-use rustls_acme::caches::DirCache;
-use tokio_stream::StreamExt;
 
+mod acme;
+mod cert_manager;
+mod dns;
+#[cfg(feature = "http3")]
+mod http3;
+mod local_ca;
+mod static_certs;
+mod tls;
+
+pub use cert_manager::{local_ca_pem, CertStatus};
+pub use tls::TlsConnection;
+
+/// where certificates come from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum CertSource {
+    /// Let's Encrypt
+    #[default]
+    Acme,
+    /// a local CA created under `cache_dir/local-ca`, for offline and dev environments
+    LocalCa,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AcmeChallenge {
+    #[default]
+    TlsAlpn01,
+    /// needs `cloudflare`, always used for wildcard domains
+    Dns01,
+}
+
+impl AcmeChallenge {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AcmeChallenge::TlsAlpn01 => "tls-alpn-01",
+            AcmeChallenge::Dns01 => "dns-01",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CloudflareDns {
+    pub api_token: String,
+    pub zone_id: String,
+}
+
+/// a certificate brought as PEM files, e.g. from a corporate CA.
+#[derive(Clone, Debug, Default)]
+pub struct StaticCert {
+    /// served for these SNI names instead of an ACME certificate, `*.example.com` is allowed
+    pub hosts: Vec<String>,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// require client certificates signed by `ca_path` for `hosts`.
+#[derive(Clone, Debug, Default)]
+pub struct ClientAuth {
+    pub hosts: Vec<String>,
+    pub ca_path: PathBuf,
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct HttpsConfig{
+    /// one certificate per domain, `*.example.com` is allowed with dns-01
     pub domains: Vec<String>,
     pub email: Vec<String>,
     pub cache_dir : String,
     /// (see https://letsencrypt.org/docs/staging-environment/)
     pub prod: bool,
+    pub source: CertSource,
+    pub challenge: AcmeChallenge,
+    pub cloudflare: Option<CloudflareDns>,
+    /// renew certificates expiring within this many days
+    pub renew_before_days: u32,
+    pub dns_propagation_timeout_secs: u64,
+    /// reloaded when the files change
+    pub static_certs: Vec<StaticCert>,
+    pub client_auth: Vec<ClientAuth>,
+    /// udp port of the HTTP/3 listener, needs the `http3` feature
+    pub http3_port: Option<u16>,
     pub http_port: u16,
     pub https_port: u16,
     pub auto_redirect : bool,
 }
 
-pub async fn start_https_server(config : &HttpsConfig, app: Router){
+/// status of every certificate, acme and static, empty before the https server starts.
+pub fn cert_status() -> Vec<CertStatus> {
+    let mut status = cert_manager::cert_status();
+    status.extend(static_certs::cert_status());
+    status
+}
 
-    let mut state = AcmeConfig::new(&config.domains)
-        .contact(config.email.iter().map(|e| format!("mailto:{}", e)))
-        .cache_option(Some(DirCache::new(config.cache_dir.clone())))
-        .directory_lets_encrypt(config.prod)
-        .state();
-    let acceptor = state.axum_acceptor(state.default_rustls_config());
+/// renew an acme certificate right away, or reload a static one from its files.
+pub async fn renew_certificate(domain: &str) -> anyhow::Result<CertStatus> {
+    match static_certs::reload_host(domain) {
+        Some(status) => Ok(status),
+        None => cert_manager::renew_certificate(domain).await,
+    }
+}
 
-    tokio::spawn(async move {
-        loop {
-            match state.next().await.unwrap() {
-                Ok(ok) => log::info!("event: {:?}", ok),
-                Err(err) => log::error!("error: {:?}", err),
-            }
+pub async fn start_https_server(config : &HttpsConfig, app: Router) -> anyhow::Result<()> {
+    let static_certs = static_certs::start(&config.static_certs)?;
+    // hosts with a static certificate don't need an acme one
+    let mut acme_config = config.clone();
+    acme_config.domains.retain(|d| !static_certs.hosts().any(|h| h.eq_ignore_ascii_case(d)));
+    let manager = cert_manager::start(acme_config)?;
+
+    let configs = Arc::new(tls::TlsConfigs::new(
+        tls::CertResolver::new(manager, static_certs),
+        &config.client_auth,
+    )?);
+    tokio::spawn(tls::reload_loop(configs.clone()));
+
+    if let Some(http3_port) = config.http3_port {
+        #[cfg(feature = "http3")]
+        {
+            let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, http3_port));
+            let endpoint = http3::bind(addr, configs.h3_server_config()?)?;
+            info!("start a http3 server at : {:?}", addr);
+            tokio::spawn(http3::serve(endpoint, app.clone(), configs.clone()));
         }
-    });
+        #[cfg(not(feature = "http3"))]
+        warn!("http3_port {} is ignored, play-https is built without the http3 feature", http3_port);
+    }
 
     if config.auto_redirect{
         //spawn a second server to redirect http requests to this server
@@ -46,17 +141,20 @@ pub async fn start_https_server(config : &HttpsConfig, app: Router){
         //listen 80 port too.
         let app_clone = app.clone();
         let http_port = config.http_port;
+        let acceptor = tls::PlainAcceptor::new(configs.clone());
         tokio::spawn(async move{
-            let addr = SocketAddr::from(([0, 0, 0, 0], http_port));
-            axum_server::bind(addr).serve(app_clone.into_make_service()).await.unwrap();
+            let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, http_port));
+            axum_server::bind(addr).acceptor(acceptor).serve(app_clone.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
         });
     }
 
-
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.https_port));
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, config.https_port));
     info!("start a https server at : {:?}", addr);
-    axum_server::bind(addr).acceptor(acceptor).serve(app.into_make_service()).await.unwrap();
+    axum_server::bind(addr)
+        .acceptor(tls::SniAcceptor::new(configs))
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
 }
 
 #[derive(Clone, Copy)]
@@ -80,7 +178,13 @@ async fn redirect_http_to_https(ports: Ports) {
         Ok(Uri::from_parts(parts)?)
     }
 
-    let redirect = move |Host(host): Host, uri: Uri| async move {
+    let redirect = move |headers: HeaderMap, uri: Uri| async move {
+        let host = headers
+            .get(HOST)
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.to_string())
+            .or_else(|| uri.host().map(|h| h.to_string()))
+            .ok_or(StatusCode::BAD_REQUEST)?;
         match make_https(host, uri, ports) {
             Ok(uri) => Ok(Redirect::permanent(&uri.to_string())),
             Err(error) => {
@@ -91,9 +195,9 @@ async fn redirect_http_to_https(ports: Ports) {
     };
 
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], ports.http));
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, ports.http));
     info!("start a http server at : {:?}", addr);
     axum_server::bind(addr).serve(redirect.into_make_service())
         .await
         .unwrap();
-}
\ No newline at end of file
+}
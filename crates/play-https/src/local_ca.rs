@@ -0,0 +1,93 @@
+//! a self-managed CA for offline and dev environments, clients need to trust `ca.pem`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    Issuer, KeyPair, KeyUsagePurpose,
+};
+use time::{Duration, OffsetDateTime};
+
+const CA_VALID_DAYS: i64 = 3650;
+const LEAF_VALID_DAYS: i64 = 90;
+
+pub struct LocalCa {
+    pub cert_pem: String,
+    issuer: Issuer<'static, KeyPair>,
+}
+
+fn paths(dir: &Path) -> (PathBuf, PathBuf) {
+    (dir.join("ca.pem"), dir.join("ca.key"))
+}
+
+impl LocalCa {
+    /// load the CA from `dir`, creating it on first use.
+    pub fn load_or_create(dir: &Path) -> anyhow::Result<Self> {
+        let (cert_path, key_path) = paths(dir);
+        if cert_path.exists() && key_path.exists() {
+            let cert_pem = std::fs::read_to_string(&cert_path)
+                .with_context(|| format!("read {}", cert_path.display()))?;
+            let key_pem = std::fs::read_to_string(&key_path)
+                .with_context(|| format!("read {}", key_path.display()))?;
+            let key = KeyPair::from_pem(&key_pem).context("parse local CA private key failed")?;
+            let issuer = Issuer::from_ca_cert_pem(&cert_pem, key)
+                .context("parse local CA certificate failed")?;
+            return Ok(Self { cert_pem, issuer });
+        }
+
+        let mut params = CertificateParams::new(Vec::<String>::new())?;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, "Play Local CA");
+        name.push(DnType::OrganizationName, "Play");
+        params.distinguished_name = name;
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::days(1);
+        params.not_after = now + Duration::days(CA_VALID_DAYS);
+
+        let key = KeyPair::generate().context("generate local CA key failed")?;
+        let cert = params
+            .self_signed(&key)
+            .context("generate local CA certificate failed")?;
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&cert_path, cert.pem())?;
+        std::fs::write(&key_path, key.serialize_pem())?;
+        Ok(Self {
+            cert_pem: cert.pem(),
+            issuer: Issuer::new(params, key),
+        })
+    }
+
+    /// issue a leaf certificate for `domain`, returns (chain pem, key pem).
+    pub fn issue(&self, domain: &str) -> anyhow::Result<(String, String)> {
+        let mut params = CertificateParams::new(vec![domain.to_string()])
+            .with_context(|| format!("invalid domain : {}", domain))?;
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, domain);
+        params.distinguished_name = name;
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::hours(1);
+        params.not_after = now + Duration::days(LEAF_VALID_DAYS);
+
+        let key = KeyPair::generate()?;
+        let cert = params
+            .signed_by(&key, &self.issuer)
+            .with_context(|| format!("issue local certificate for {} failed", domain))?;
+        Ok((
+            format!("{}{}", cert.pem(), self.cert_pem),
+            key.serialize_pem(),
+        ))
+    }
+}
@@ -0,0 +1,214 @@
+//! certificates brought as PEM files, reloaded when the files change.
+
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Context};
+use log::{info, warn};
+use rustls::sign::CertifiedKey;
+
+use crate::cert_manager::{certified_key, describe, CertStatus};
+use crate::StaticCert;
+
+static STATIC_CERTS: OnceLock<Arc<StaticCerts>> = OnceLock::new();
+
+pub(crate) fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+struct Loaded {
+    cert: Option<Arc<CertifiedKey>>,
+    /// modification times of (cert, key) when last loaded
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    status: CertStatus,
+}
+
+struct Entry {
+    config: StaticCert,
+    loaded: RwLock<Loaded>,
+}
+
+impl Entry {
+    fn files_modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        (
+            modified(&self.config.cert_path),
+            modified(&self.config.key_path),
+        )
+    }
+
+    fn read(&self) -> anyhow::Result<CertifiedKey> {
+        let cert_pem = std::fs::read_to_string(&self.config.cert_path)
+            .with_context(|| format!("read {}", self.config.cert_path.display()))?;
+        let key_pem = std::fs::read_to_string(&self.config.key_path)
+            .with_context(|| format!("read {}", self.config.key_path.display()))?;
+        let cert = certified_key(&cert_pem, &key_pem)?;
+        cert.keys_match()
+            .map_err(|e| anyhow!("certificate and key don't match : {:?}", e))?;
+        Ok(cert)
+    }
+
+    /// reload the files, a broken file keeps the previous certificate in use.
+    fn load(&self) {
+        let files_modified = self.files_modified();
+        let result = self.read();
+        let mut loaded = self.loaded.write().unwrap();
+        loaded.modified = files_modified;
+        match result {
+            Ok(cert) => {
+                describe(&mut loaded.status, &cert);
+                loaded.status.last_renewal = Some(crate::cert_manager::now_secs());
+                loaded.status.last_error = None;
+                loaded.cert = Some(Arc::new(cert));
+                info!("static certificate of {:?} loaded", self.config.hosts);
+            }
+            Err(e) => {
+                warn!(
+                    "load static certificate of {:?} failed : {:?}",
+                    self.config.hosts, e
+                );
+                loaded.status.last_error = Some(format!("{:#}", e));
+            }
+        }
+    }
+}
+
+pub(crate) struct StaticCerts {
+    entries: Vec<Entry>,
+}
+
+impl StaticCerts {
+    pub(crate) fn new(configs: &[StaticCert]) -> Self {
+        let entries: Vec<Entry> = configs
+            .iter()
+            .map(|config| Entry {
+                config: config.clone(),
+                loaded: RwLock::new(Loaded {
+                    cert: None,
+                    modified: (None, None),
+                    status: CertStatus {
+                        domain: config.hosts.join(","),
+                        source: "static".to_string(),
+                        ..Default::default()
+                    },
+                }),
+            })
+            .collect();
+        for entry in &entries {
+            entry.load();
+        }
+        Self { entries }
+    }
+
+    /// reload every entry whose files changed since they were last read.
+    pub(crate) fn reload_changed(&self) {
+        for entry in &self.entries {
+            let changed = entry.loaded.read().unwrap().modified != entry.files_modified();
+            if changed {
+                entry.load();
+            }
+        }
+    }
+
+    /// `host` is one of the entry's hosts, or all of them joined as in its status.
+    pub(crate) fn reload_host(&self, host: &str) -> Option<CertStatus> {
+        let entry = self.entries.iter().find(|e| {
+            e.config.hosts.join(",") == host || e.config.hosts.iter().any(|h| h == host)
+        })?;
+        entry.load();
+        let status = entry.loaded.read().unwrap().status.clone();
+        Some(status)
+    }
+
+    /// the certificate configured for exactly `name`, `*.example.com` included.
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        self.entries
+            .iter()
+            .filter(|e| e.config.hosts.iter().any(|h| h.eq_ignore_ascii_case(name)))
+            .find_map(|e| e.loaded.read().unwrap().cert.clone())
+    }
+
+    pub(crate) fn first(&self) -> Option<Arc<CertifiedKey>> {
+        self.entries
+            .iter()
+            .find_map(|e| e.loaded.read().unwrap().cert.clone())
+    }
+
+    pub(crate) fn hosts(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().flat_map(|e| e.config.hosts.iter())
+    }
+
+    fn status(&self) -> Vec<CertStatus> {
+        self.entries
+            .iter()
+            .map(|e| e.loaded.read().unwrap().status.clone())
+            .collect()
+    }
+}
+
+pub(crate) fn start(configs: &[StaticCert]) -> anyhow::Result<Arc<StaticCerts>> {
+    let certs = Arc::new(StaticCerts::new(configs));
+    STATIC_CERTS
+        .set(certs.clone())
+        .map_err(|_| anyhow!("static certificates already loaded"))?;
+    Ok(certs)
+}
+
+pub(crate) fn cert_status() -> Vec<CertStatus> {
+    STATIC_CERTS.get().map(|c| c.status()).unwrap_or_default()
+}
+
+pub(crate) fn reload_host(host: &str) -> Option<CertStatus> {
+    STATIC_CERTS.get()?.reload_host(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_manager::tests::subject;
+    use crate::local_ca::LocalCa;
+
+    #[test]
+    fn test_reload_static_cert() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "play-https-static-{}",
+            crate::cert_manager::now_secs()
+        ));
+        let ca = LocalCa::load_or_create(&dir)?;
+        let config = StaticCert {
+            hosts: vec!["intranet.corp".to_string(), "*.corp".to_string()],
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+        };
+
+        // missing files are reported, not fatal
+        let certs = StaticCerts::new(std::slice::from_ref(&config));
+        assert!(certs.get("intranet.corp").is_none());
+        assert!(certs.status()[0].last_error.is_some());
+
+        let (cert, key) = ca.issue("intranet.corp")?;
+        std::fs::write(&config.cert_path, cert)?;
+        std::fs::write(&config.key_path, key)?;
+        certs.reload_changed();
+        assert_eq!(
+            subject(&certs.get("INTRANET.corp").unwrap()),
+            "CN=intranet.corp"
+        );
+        assert!(certs.get("*.corp").is_some());
+        assert!(certs.status()[0].last_error.is_none());
+
+        // a key of another certificate is rejected and the old one kept
+        let (_, other_key) = ca.issue("other.corp")?;
+        std::fs::write(&config.key_path, other_key)?;
+        certs.reload_host("intranet.corp").unwrap();
+        assert!(certs.status()[0]
+            .last_error
+            .as_ref()
+            .unwrap()
+            .contains("match"));
+        assert!(certs.get("intranet.corp").is_some());
+
+        let _ = std::fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
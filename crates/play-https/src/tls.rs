@@ -0,0 +1,492 @@
+//! TLS accept path : SNI certificate selection and per-host client certificates (mTLS).
+
+use std::convert::Infallible;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail};
+use axum::http::header::HOST;
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum_server::accept::Accept;
+use futures_util::future::BoxFuture;
+use log::{info, warn};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{Acceptor, ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::LazyConfigAcceptor;
+use tower::Service;
+
+use crate::cert_manager::{CertManager, ACME_TLS_ALPN_NAME};
+use crate::static_certs::{modified, StaticCerts};
+use crate::ClientAuth;
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `host` is `pattern` itself or, for `*.example.com`, one of its subdomains.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    if pattern.eq_ignore_ascii_case(host) {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => {
+            let host = host.to_ascii_lowercase();
+            let suffix = suffix.to_ascii_lowercase();
+            host.len() > suffix.len() + 1
+                && host.ends_with(&suffix)
+                && host.as_bytes()[host.len() - suffix.len() - 1] == b'.'
+        }
+        None => false,
+    }
+}
+
+fn is_acme_challenge(client_hello: &ClientHello<'_>) -> bool {
+    client_hello
+        .alpn()
+        .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN_NAME))
+}
+
+/// picks the certificate of a connection by its SNI :
+/// exact static, exact acme, then the same for `*.parent`, then the main domain.
+pub(crate) struct CertResolver {
+    manager: Arc<CertManager>,
+    static_certs: Arc<StaticCerts>,
+}
+
+impl std::fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertResolver").finish_non_exhaustive()
+    }
+}
+
+impl CertResolver {
+    pub(crate) fn new(manager: Arc<CertManager>, static_certs: Arc<StaticCerts>) -> Self {
+        Self {
+            manager,
+            static_certs,
+        }
+    }
+
+    fn find(&self, name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        if let Some(name) = name {
+            let name = name.to_ascii_lowercase();
+            let wildcard = name
+                .split_once('.')
+                .map(|(_, parent)| format!("*.{}", parent));
+            for candidate in std::iter::once(name.clone()).chain(wildcard) {
+                let cert = self
+                    .static_certs
+                    .get(&candidate)
+                    .or_else(|| self.manager.get(&candidate));
+                if cert.is_some() {
+                    return cert;
+                }
+            }
+        }
+        // requests by ip or unknown names
+        self.manager
+            .default_cert()
+            .or_else(|| self.static_certs.first())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        if is_acme_challenge(&client_hello) {
+            let name = client_hello.server_name()?.to_ascii_lowercase();
+            return self.manager.challenge(&name);
+        }
+        self.find(client_hello.server_name())
+    }
+}
+
+fn server_config(
+    resolver: Arc<CertResolver>,
+    verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> anyhow::Result<ServerConfig> {
+    let builder =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?;
+    let mut config = match verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    }
+    .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![
+        b"h2".to_vec(),
+        b"http/1.1".to_vec(),
+        ACME_TLS_ALPN_NAME.to_vec(),
+    ];
+    Ok(config)
+}
+
+fn client_verifier(ca_path: &Path) -> anyhow::Result<Arc<dyn ClientCertVerifier>> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_path)
+        .map_err(|e| anyhow!("read {} failed : {:?}", ca_path.display(), e))?
+    {
+        let cert = cert.map_err(|e| anyhow!("parse {} failed : {:?}", ca_path.display(), e))?;
+        roots.add(cert)?;
+    }
+    if roots.is_empty() {
+        bail!("no CA certificate found in {}", ca_path.display());
+    }
+    Ok(WebPkiClientVerifier::builder_with_provider(
+        Arc::new(roots),
+        Arc::new(rustls::crypto::ring::default_provider()),
+    )
+    .build()?)
+}
+
+struct ClientAuthGroup {
+    config: ClientAuth,
+    /// CA file modification time and the server config built from it
+    state: RwLock<(Option<SystemTime>, Option<Arc<ServerConfig>>)>,
+}
+
+/// the server config of every connection, chosen from its client hello.
+pub(crate) struct TlsConfigs {
+    resolver: Arc<CertResolver>,
+    default: Arc<ServerConfig>,
+    groups: Vec<ClientAuthGroup>,
+}
+
+impl TlsConfigs {
+    pub(crate) fn new(resolver: CertResolver, client_auth: &[ClientAuth]) -> anyhow::Result<Self> {
+        let resolver = Arc::new(resolver);
+        let configs = Self {
+            default: Arc::new(server_config(resolver.clone(), None)?),
+            resolver,
+            groups: client_auth
+                .iter()
+                .map(|config| ClientAuthGroup {
+                    config: config.clone(),
+                    state: RwLock::new((None, None)),
+                })
+                .collect(),
+        };
+        for group in &configs.groups {
+            configs.load_group(group);
+        }
+        Ok(configs)
+    }
+
+    /// a broken CA file keeps the previous one, a missing one rejects the hosts.
+    fn load_group(&self, group: &ClientAuthGroup) {
+        let ca_modified = modified(&group.config.ca_path);
+        let config = client_verifier(&group.config.ca_path)
+            .and_then(|verifier| server_config(self.resolver.clone(), Some(verifier)));
+        let mut state = group.state.write().unwrap();
+        state.0 = ca_modified;
+        match config {
+            Ok(config) => {
+                info!("client CA of {:?} loaded", group.config.hosts);
+                state.1 = Some(Arc::new(config));
+            }
+            Err(e) => warn!(
+                "load client CA of {:?} failed : {:?}",
+                group.config.hosts, e
+            ),
+        }
+    }
+
+    /// QUIC needs TLS 1.3 and has a single config, so no client certificates.
+    /// 0-RTT stays off, early data could replay writes to the admin api and uploads.
+    #[cfg(feature = "http3")]
+    pub(crate) fn h3_server_config(&self) -> anyhow::Result<ServerConfig> {
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_protocol_versions(&[&rustls::version::TLS13])?
+                .with_no_client_auth()
+                .with_cert_resolver(self.resolver.clone());
+        config.alpn_protocols = vec![b"h3".to_vec()];
+        Ok(config)
+    }
+
+    pub(crate) fn reload_changed(&self) {
+        self.resolver.static_certs.reload_changed();
+        for group in &self.groups {
+            let changed = group.state.read().unwrap().0 != modified(&group.config.ca_path);
+            if changed {
+                self.load_group(group);
+            }
+        }
+    }
+
+    /// index of the client auth group protecting `host`.
+    fn group_of(&self, host: &str) -> Option<usize> {
+        self.groups
+            .iter()
+            .position(|g| g.config.hosts.iter().any(|p| host_matches(p, host)))
+    }
+
+    fn select(
+        &self,
+        client_hello: &ClientHello<'_>,
+    ) -> io::Result<(Arc<ServerConfig>, Option<usize>)> {
+        // CA validators don't have client certificates
+        if is_acme_challenge(client_hello) {
+            return Ok((self.default.clone(), None));
+        }
+        let Some(group) = client_hello
+            .server_name()
+            .and_then(|name| self.group_of(name))
+        else {
+            return Ok((self.default.clone(), None));
+        };
+        match &self.groups[group].state.read().unwrap().1 {
+            Some(config) => Ok((config.clone(), Some(group))),
+            None => Err(io::Error::other(format!(
+                "client CA of {:?} is not loaded",
+                self.groups[group].config.hosts
+            ))),
+        }
+    }
+}
+
+pub(crate) async fn reload_loop(configs: Arc<TlsConfigs>) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        configs.reload_changed();
+    }
+}
+
+/// terminates TLS with the config chosen by [`TlsConfigs::select`].
+#[derive(Clone)]
+pub(crate) struct SniAcceptor {
+    configs: Arc<TlsConfigs>,
+}
+
+impl SniAcceptor {
+    pub(crate) fn new(configs: Arc<TlsConfigs>) -> Self {
+        Self { configs }
+    }
+}
+
+impl<S: Send + 'static> Accept<TcpStream, S> for SniAcceptor {
+    type Stream = TlsStream<TcpStream>;
+    type Service = HostGuard<S>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let configs = self.configs.clone();
+        Box::pin(async move {
+            let handshake = async {
+                let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+                let (config, group) = configs.select(&start.client_hello())?;
+                Ok::<_, io::Error>((start.into_stream(config).await?, group))
+            };
+            let (stream, group) = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+                .await
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::TimedOut, "tls handshake timed out")
+                })??;
+            Ok((
+                stream,
+                HostGuard {
+                    inner: service,
+                    configs,
+                    group,
+                    tls: true,
+                },
+            ))
+        })
+    }
+}
+
+/// plain http connections never carry client certificates.
+#[derive(Clone)]
+pub(crate) struct PlainAcceptor {
+    configs: Arc<TlsConfigs>,
+}
+
+impl PlainAcceptor {
+    pub(crate) fn new(configs: Arc<TlsConfigs>) -> Self {
+        Self { configs }
+    }
+}
+
+impl<S: Send + 'static> Accept<TcpStream, S> for PlainAcceptor {
+    type Stream = TcpStream;
+    type Service = HostGuard<S>;
+    type Future = std::future::Ready<io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        std::future::ready(Ok((
+            stream,
+            HostGuard::plain(service, self.configs.clone(), false),
+        )))
+    }
+}
+
+/// answers 421 when a request's host needs a client certificate its connection didn't verify,
+/// e.g. a reused HTTP/2 connection or a `Host` header that differs from the SNI.
+#[derive(Clone)]
+pub(crate) struct HostGuard<S> {
+    inner: S,
+    configs: Arc<TlsConfigs>,
+    group: Option<usize>,
+    tls: bool,
+}
+
+impl<S> HostGuard<S> {
+    /// a guard for connections without client certificates.
+    pub(crate) fn plain(inner: S, configs: Arc<TlsConfigs>, tls: bool) -> Self {
+        Self {
+            inner,
+            configs,
+            group: None,
+            tls,
+        }
+    }
+}
+
+/// request extension telling whether the connection was TLS (QUIC included), so the app
+/// doesn't have to trust a client's `X-Forwarded-Proto`.
+#[derive(Clone, Copy, Debug)]
+pub struct TlsConnection(pub bool);
+
+fn request_host<B>(request: &Request<B>) -> Option<String> {
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| request.uri().host())?;
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+impl<S, B> Service<Request<B>> for HostGuard<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        request.extensions_mut().insert(TlsConnection(self.tls));
+        let required = request_host(&request).and_then(|host| self.configs.group_of(&host));
+        if required.is_some() && required != self.group {
+            return Box::pin(async {
+                Ok((
+                    StatusCode::MISDIRECTED_REQUEST,
+                    "client certificate required",
+                )
+                    .into_response())
+            });
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_manager::tests::subject;
+    use crate::local_ca::LocalCa;
+    use crate::{CertSource, HttpsConfig, StaticCert};
+
+    #[tokio::test]
+    async fn test_resolver_and_host_guard() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "play-https-tls-{}",
+            crate::cert_manager::now_secs()
+        ));
+        let ca = LocalCa::load_or_create(&dir.join("corp-ca"))?;
+        let (cert, key) = ca.issue("*.corp.example.com")?;
+        std::fs::write(dir.join("corp.pem"), cert)?;
+        std::fs::write(dir.join("corp.key"), key)?;
+
+        let manager = crate::cert_manager::start(HttpsConfig {
+            domains: vec![
+                "example.com".to_string(),
+                "app.corp.example.com".to_string(),
+            ],
+            cache_dir: dir.to_str().unwrap().to_string(),
+            source: CertSource::LocalCa,
+            ..Default::default()
+        })?;
+        crate::cert_manager::renew_certificate("example.com").await?;
+        crate::cert_manager::renew_certificate("app.corp.example.com").await?;
+        let static_certs = Arc::new(StaticCerts::new(&[StaticCert {
+            hosts: vec!["*.corp.example.com".to_string()],
+            cert_path: dir.join("corp.pem"),
+            key_path: dir.join("corp.key"),
+        }]));
+        let resolver = CertResolver::new(manager, static_certs);
+
+        let find = |name| subject(&resolver.find(name).unwrap());
+        assert_eq!(find(Some("Example.com")), "CN=example.com");
+        // an exact acme domain wins over a static wildcard
+        assert_eq!(
+            find(Some("app.corp.example.com")),
+            "CN=app.corp.example.com"
+        );
+        assert_eq!(find(Some("wiki.corp.example.com")), "CN=*.corp.example.com");
+        assert_eq!(find(Some("unknown.org")), "CN=example.com");
+        assert_eq!(find(None), "CN=example.com");
+
+        let configs = Arc::new(TlsConfigs::new(
+            resolver,
+            &[ClientAuth {
+                hosts: vec!["admin.example.com".to_string()],
+                ca_path: dir.join("corp-ca/ca.pem"),
+            }],
+        )?);
+        assert_eq!(configs.group_of("admin.example.com"), Some(0));
+        assert_eq!(configs.group_of("example.com"), None);
+        assert!(configs.groups[0].state.read().unwrap().1.is_some());
+
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::get(
+                |axum::Extension(TlsConnection(tls)): axum::Extension<TlsConnection>| async move {
+                    tls.to_string()
+                },
+            ),
+        );
+        let mut guard = HostGuard {
+            inner: router,
+            configs,
+            group: None,
+            tls: true,
+        };
+        let request = |host: &str| {
+            Request::builder()
+                .uri("/")
+                .header(HOST, host)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let response = guard.call(request("admin.example.com:443")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+        let response = guard.call(request("example.com")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), 16).await?;
+        assert_eq!(&body[..], b"true");
+        guard.group = Some(0);
+        let response = guard.call(request("admin.example.com")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let _ = std::fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
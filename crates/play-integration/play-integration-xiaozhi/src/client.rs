@@ -1,6 +1,6 @@
 use anyhow::Result;
 use play_mcp::tools::ToolRegistry;
-use crate::config::{McpConfig, ClientConfig, RetryConfig};
+use crate::config::{McpConfig, ClientConfig, RetryConfig, ToolFilter};
 use std::time::Duration;
 
 /// Builder for creating Xiaozhi MCP client
@@ -13,6 +13,7 @@ pub struct XiaozhiClientBuilder {
     retry_enabled: bool,
     retry_max_attempts: u32,
     retry_interval: Duration,
+    tools: ToolFilter,
     custom_registry: Option<ToolRegistry>,
 }
 
@@ -27,6 +28,7 @@ impl Default for XiaozhiClientBuilder {
             retry_enabled: true,
             retry_max_attempts: 5,
             retry_interval: Duration::from_secs(5),
+            tools: ToolFilter::default(),
             custom_registry: None,
         }
     }
@@ -77,6 +79,18 @@ impl XiaozhiClientBuilder {
         self
     }
     
+    /// Only expose these tools, `*` at the end matches a name prefix
+    pub fn allow_tools(mut self, tools: Vec<String>) -> Self {
+        self.tools.allow = tools;
+        self
+    }
+    
+    /// Never expose these tools
+    pub fn deny_tools(mut self, tools: Vec<String>) -> Self {
+        self.tools.deny = tools;
+        self
+    }
+    
     pub fn with_tools(mut self, registry: ToolRegistry) -> Self {
         self.custom_registry = Some(registry);
         self
@@ -84,6 +98,8 @@ impl XiaozhiClientBuilder {
     
     pub fn build(self) -> XiaozhiClient {
         let config = McpConfig {
+            name: String::new(),
+            enabled: true,
             client: ClientConfig {
                 name: self.name,
                 version: self.version,
@@ -96,6 +112,7 @@ impl XiaozhiClientBuilder {
                 max_attempts: self.retry_max_attempts,
                 interval_seconds: self.retry_interval.as_secs(),
             },
+            tools: self.tools,
         };
         
         XiaozhiClient {
@@ -1,8 +1,13 @@
 use serde::{Deserialize, Serialize};
 
-/// Main MCP configuration
+/// Main MCP configuration, one per endpoint
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct McpConfig {
+    /// Endpoint name shown in the status, defaults to the client name
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
     pub url: String,
     #[serde(default)]
     pub client: ClientConfig,
@@ -10,6 +15,35 @@ pub struct McpConfig {
     pub retry: RetryConfig,
     #[serde(default)]
     pub tool_name_prefix: String,
+    #[serde(default)]
+    pub tools: ToolFilter,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Which tools an endpoint exposes, by their name without the prefix.
+///
+/// A pattern ending with `*` matches every name starting with the rest.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToolFilter {
+    /// Tools to expose, all of them when empty
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Tools never exposed, even when allowed
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl ToolFilter {
+    pub fn allows(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => pattern == name,
+        };
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
 }
 
 /// Client information configuration
@@ -31,10 +65,13 @@ pub struct RetryConfig {
 impl Default for McpConfig {
     fn default() -> Self {
         Self {
+            name: String::new(),
+            enabled: true,
             url: "ws://localhost:5173/ws".to_string(),  // Default to Xiaozhi endpoint
             client: ClientConfig::default(),
             retry: RetryConfig::default(),
             tool_name_prefix: String::new(),
+            tools: ToolFilter::default(),
         }
     }
 }
@@ -63,18 +100,34 @@ impl McpConfig {
     pub fn builder() -> McpConfigBuilder {
         McpConfigBuilder::default()
     }
+
+    /// The endpoint name, or the client name when not set
+    pub fn display_name(&self) -> &str {
+        if self.name.is_empty() {
+            &self.client.name
+        } else {
+            &self.name
+        }
+    }
 }
 
 /// Builder for McpConfig
 #[derive(Default)]
 pub struct McpConfigBuilder {
+    name: String,
     url: Option<String>,
     client: ClientConfig,
     retry: RetryConfig,
     tool_name_prefix: String,
+    tools: ToolFilter,
 }
 
 impl McpConfigBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
     pub fn url(mut self, url: impl Into<String>) -> Self {
         self.url = Some(url.into());
         self
@@ -100,6 +153,16 @@ impl McpConfigBuilder {
         self
     }
 
+    pub fn allow_tools(mut self, tools: Vec<String>) -> Self {
+        self.tools.allow = tools;
+        self
+    }
+
+    pub fn deny_tools(mut self, tools: Vec<String>) -> Self {
+        self.tools.deny = tools;
+        self
+    }
+
     pub fn retry_enabled(mut self, enabled: bool) -> Self {
         self.retry.enabled = enabled;
         self
@@ -117,10 +180,13 @@ impl McpConfigBuilder {
 
     pub fn build(self) -> McpConfig {
         McpConfig {
+            name: self.name,
+            enabled: true,
             url: self.url.unwrap_or_else(|| McpConfig::default().url),
             client: self.client,
             retry: self.retry,
             tool_name_prefix: self.tool_name_prefix,
+            tools: self.tools,
         }
     }
 }
\ No newline at end of file
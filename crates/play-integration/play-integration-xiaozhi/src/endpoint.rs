@@ -0,0 +1,135 @@
+use play_mcp::tools::{Tool, ToolRegistry};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::McpConfig;
+
+/// Running endpoints by name, for [`endpoints_status`]
+static ENDPOINTS: Mutex<BTreeMap<String, Arc<Endpoint>>> = Mutex::new(BTreeMap::new());
+
+/// Connection state and call counts of an endpoint
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EndpointStatus {
+    pub name: String,
+    /// The endpoint url without its query, which usually carries the token
+    pub url: String,
+    pub tool_name_prefix: String,
+    /// `connecting`, `connected`, `retrying` or `stopped`
+    pub state: &'static str,
+    /// Unix time in milliseconds
+    pub connected_at: Option<i64>,
+    /// Failed attempts since the last successful connection
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+    pub tool_calls: u64,
+    pub tool_errors: u64,
+    pub calls_by_tool: BTreeMap<String, u64>,
+}
+
+/// One endpoint: its config, the tools it may expose and its status
+pub struct Endpoint {
+    pub config: McpConfig,
+    registry: Arc<ToolRegistry>,
+    status: Mutex<EndpointStatus>,
+}
+
+impl Endpoint {
+    /// Creates the endpoint and makes it visible to [`endpoints_status`],
+    /// replacing an endpoint registered under the same name
+    pub fn register(config: McpConfig, registry: Arc<ToolRegistry>) -> Arc<Self> {
+        let status = EndpointStatus {
+            name: config.display_name().to_string(),
+            url: config.url.split('?').next().unwrap_or_default().to_string(),
+            tool_name_prefix: config.tool_name_prefix.clone(),
+            state: "connecting",
+            ..Default::default()
+        };
+        let endpoint = Arc::new(Self {
+            config,
+            registry,
+            status: Mutex::new(status),
+        });
+        ENDPOINTS
+            .lock()
+            .unwrap()
+            .insert(endpoint.status().name, endpoint.clone());
+        endpoint
+    }
+
+    /// Removes the endpoint from [`endpoints_status`] unless it was replaced meanwhile
+    pub fn unregister(self: &Arc<Self>) {
+        let mut endpoints = ENDPOINTS.lock().unwrap();
+        let name = self.config.display_name();
+        if endpoints.get(name).is_some_and(|e| Arc::ptr_eq(e, self)) {
+            endpoints.remove(name);
+        }
+    }
+
+    pub fn status(&self) -> EndpointStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub(crate) fn update(&self, f: impl FnOnce(&mut EndpointStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+
+    pub(crate) fn record_error(&self, error: String) {
+        self.update(|s| {
+            s.last_error = Some(error);
+            s.last_error_at = Some(now_millis());
+        });
+    }
+
+    pub(crate) fn record_call(&self, name: &str, ok: bool) {
+        self.update(|s| {
+            s.tool_calls += 1;
+            if !ok {
+                s.tool_errors += 1;
+            }
+            *s.calls_by_tool.entry(name.to_string()).or_default() += 1;
+        });
+    }
+
+    /// Whether the filter lets the tool listed as `name` (prefix included) through
+    fn allows(&self, name: &str) -> bool {
+        name.strip_prefix(self.config.tool_name_prefix.as_str())
+            .is_some_and(|name| self.config.tools.allows(name))
+    }
+
+    /// The registry's tools this endpoint may expose
+    pub fn list_tools(&self) -> Vec<Value> {
+        self.registry
+            .list()
+            .into_iter()
+            .filter(|tool| tool["name"].as_str().is_some_and(|name| self.allows(name)))
+            .collect()
+    }
+
+    pub fn get_tool(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        if !self.allows(name) {
+            return None;
+        }
+        self.registry.get(name)
+    }
+}
+
+/// Status of every running endpoint, sorted by name
+pub fn endpoints_status() -> Vec<EndpointStatus> {
+    ENDPOINTS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|e| e.status())
+        .collect()
+}
+
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
@@ -1,7 +1,9 @@
-use anyhow::{Result, Context};
+use anyhow::{bail, Result, Context};
 use futures::{SinkExt, StreamExt};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::sync::Arc;
+use tokio::task::JoinSet;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use tracing::{error, info, warn};
 
@@ -9,16 +11,18 @@ use play_mcp::tools::ToolRegistry;
 
 pub mod client;
 pub mod config;
+pub mod endpoint;
 pub mod protocol;
 #[cfg(test)]
 mod tests;
 
 pub use client::{XiaozhiClient, XiaozhiClientBuilder, quick_start};
-pub use config::{McpConfig, ClientConfig, RetryConfig};
+pub use config::{McpConfig, ClientConfig, RetryConfig, ToolFilter};
+pub use endpoint::{endpoints_status, Endpoint, EndpointStatus};
 pub use protocol::{JsonRpcRequest, JsonRpcResponse, JsonRpcError};
 
 /// Handle incoming server requests and generate appropriate responses
-async fn handle_server_request(request: protocol::JsonRpcRequest, registry: &ToolRegistry) -> Option<protocol::JsonRpcResponse> {
+async fn handle_server_request(request: protocol::JsonRpcRequest, endpoint: &Endpoint) -> Option<protocol::JsonRpcResponse> {
     match request.method.as_str() {
         "ping" => {
             Some(protocol::JsonRpcResponse {
@@ -36,7 +40,7 @@ async fn handle_server_request(request: protocol::JsonRpcRequest, registry: &Too
             Some(protocol::JsonRpcResponse {
                 jsonrpc: "2.0".to_string(),
                 result: Some(json!({
-                    "tools": registry.list()
+                    "tools": endpoint.list_tools()
                 })),
                 error: None,
                 id: request.id,
@@ -45,11 +49,12 @@ async fn handle_server_request(request: protocol::JsonRpcRequest, registry: &Too
         "tools/call" => {
             if let Some(params) = request.params.clone() {
                 if let Some(name) = params.get("name").and_then(|v| v.as_str()) {
-                    if let Some(tool) = registry.get(name) {
+                    if let Some(tool) = endpoint.get_tool(name) {
                         let default_args = json!({});
                         let arguments = params.get("arguments").unwrap_or(&default_args);
                         
                         let result = tool.execute(arguments.clone()).await;
+                        endpoint.record_call(name, result.is_ok());
                         
                         match result {
                             Ok(result) => {
@@ -112,10 +117,14 @@ async fn handle_server_request(request: protocol::JsonRpcRequest, registry: &Too
 }
 
 /// Run the MCP connection to Xiaozhi
-async fn run_mcp_connection(url: String, client_config: &config::ClientConfig, registry: Arc<ToolRegistry>) -> Result<()> {
-    info!("Connecting to Xiaozhi MCP server at: {}", url);
+///
+/// Returns `Ok` when interrupted, and an error when the connection fails or is closed.
+async fn run_mcp_connection(endpoint: &Endpoint) -> Result<()> {
+    let url = &endpoint.config.url;
+    let client_config = &endpoint.config.client;
+    info!("Connecting to Xiaozhi MCP server at: {}", endpoint.status().url);
     
-    let (ws_stream, _) = connect_async(&url).await
+    let (ws_stream, _) = connect_async(url).await
         .context("Failed to connect to Xiaozhi MCP server")?;
     info!("Connected to Xiaozhi MCP server");
     
@@ -134,7 +143,9 @@ async fn run_mcp_connection(url: String, client_config: &config::ClientConfig, r
                     result: Some(json!({
                         "protocolVersion": "2024-11-05",
                         "capabilities": {
-                            "tools": {}
+                            "tools": {
+                                "listChanged": true
+                            }
                         },
                         "serverInfo": {
                             "name": client_config.name,
@@ -150,6 +161,11 @@ async fn run_mcp_connection(url: String, client_config: &config::ClientConfig, r
                 info!(">>>> Sending to Xiaozhi:\n{}", response_msg);
                 write.send(Message::Text(response_msg.clone())).await
                     .context("Failed to send initialize response")?;
+                endpoint.update(|s| {
+                    s.state = "connected";
+                    s.connected_at = Some(endpoint::now_millis());
+                    s.attempts = 0;
+                });
             }
         }
     }
@@ -157,9 +173,22 @@ async fn run_mcp_connection(url: String, client_config: &config::ClientConfig, r
     // Wait for tools/list request from server
     info!("Waiting for tools/list request from Xiaozhi...");
     
+    // Tools defined at runtime can come and go while connected
+    let mut tool_changes = play_mcp::tools::subscribe_tool_changes();
+    tool_changes.mark_unchanged();
+
     // Handle incoming requests from server
     loop {
         tokio::select! {
+            Ok(()) = tool_changes.changed() => {
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/tools/list_changed"
+                }).to_string();
+                info!(">>>> Sending to Xiaozhi:\n{}", notification);
+                write.send(Message::Text(notification)).await
+                    .context("Failed to send tools list_changed notification")?;
+            }
             Some(msg) = read.next() => {
                 match msg {
                     Ok(Message::Text(text)) => {
@@ -168,7 +197,7 @@ async fn run_mcp_connection(url: String, client_config: &config::ClientConfig, r
                         match serde_json::from_str::<protocol::JsonRpcRequest>(&text) {
                             Ok(request) => {
                                 // Handle the request from server
-                                if let Some(response) = handle_server_request(request, &registry).await {
+                                if let Some(response) = handle_server_request(request, endpoint).await {
                                     let response_text = serde_json::to_string(&response)
                                         .context("Failed to serialize response")?;
                                     info!(">>>> Sending to Xiaozhi:\n{}", response_text);
@@ -183,7 +212,7 @@ async fn run_mcp_connection(url: String, client_config: &config::ClientConfig, r
                     }
                     Ok(Message::Close(_)) => {
                         info!("Xiaozhi closed connection");
-                        break;
+                        bail!("connection closed by server");
                     }
                     Ok(Message::Ping(data)) => {
                         write.send(Message::Pong(data)).await
@@ -191,7 +220,7 @@ async fn run_mcp_connection(url: String, client_config: &config::ClientConfig, r
                     }
                     Err(e) => {
                         error!("WebSocket error: {}", e);
-                        break;
+                        bail!("websocket error: {}", e);
                     }
                     _ => {}
                 }
@@ -223,31 +252,37 @@ pub async fn start_xiaozhi_client(config: &config::McpConfig) -> Result<()> {
 
 /// Start Xiaozhi MCP client service with custom tool registry
 pub async fn start_xiaozhi_client_with_tools(config: &config::McpConfig, registry: ToolRegistry) -> Result<()> {
-    info!("Starting Xiaozhi MCP client: {}", config.client.name);
+    info!("Starting Xiaozhi MCP client: {}", config.display_name());
     info!("Description: {}", config.client.description);
-    info!("Connecting to: {}", config.url);
     
     if !config.tool_name_prefix.is_empty() {
         info!("Tool name prefix: '{}'", config.tool_name_prefix);
     }
     
-    let registry = Arc::new(registry);
+    let endpoint = Endpoint::register(config.clone(), Arc::new(registry));
+    info!("Connecting to: {}", endpoint.status().url);
     
-    let mut attempts = 0u32;
     loop {
-        match run_mcp_connection(config.url.clone(), &config.client, registry.clone()).await {
+        endpoint.update(|s| s.state = "connecting");
+        match run_mcp_connection(&endpoint).await {
             Ok(_) => {
                 info!("Xiaozhi MCP client disconnected normally");
                 break;
             }
             Err(e) => {
-                error!("Xiaozhi MCP client error: {}", e);
+                error!("Xiaozhi MCP client `{}` error: {}", config.display_name(), e);
+                endpoint.record_error(format!("{:#}", e));
+                endpoint.update(|s| {
+                    s.connected_at = None;
+                    s.attempts += 1;
+                });
                 
                 if !config.retry.enabled {
                     break;
                 }
                 
-                attempts += 1;
+                // attempts restart from zero after every successful connection
+                let attempts = endpoint.status().attempts;
                 if config.retry.max_attempts > 0 && attempts >= config.retry.max_attempts {
                     error!("Max retry attempts ({}) reached", config.retry.max_attempts);
                     break;
@@ -262,10 +297,39 @@ pub async fn start_xiaozhi_client_with_tools(config: &config::McpConfig, registr
                         "unlimited".to_string() 
                     }
                 );
+                endpoint.update(|s| s.state = "retrying");
                 tokio::time::sleep(tokio::time::Duration::from_secs(config.retry.interval_seconds)).await;
             }
         }
     }
     
+    endpoint.update(|s| {
+        s.state = "stopped";
+        s.connected_at = None;
+    });
+    endpoint.unregister();
+    Ok(())
+}
+
+/// Start one client per enabled endpoint, each with its own prefix, tool filter and retry policy.
+///
+/// Returns once every client stopped. A client that fails or panics is logged with its endpoint name
+/// and doesn't stop the others.
+pub async fn start_xiaozhi_clients(configs: &[config::McpConfig]) -> Result<()> {
+    let mut clients = JoinSet::new();
+    let mut names = HashMap::new();
+    for config in configs.iter().filter(|config| config.enabled) {
+        let name = config.display_name().to_string();
+        let config = config.clone();
+        let client = clients.spawn(async move { start_xiaozhi_client(&config).await });
+        names.insert(client.id(), name);
+    }
+    while let Some(joined) = clients.join_next_with_id().await {
+        match joined {
+            Ok((id, Ok(()))) => info!("Xiaozhi MCP client `{}` stopped", names[&id]),
+            Ok((id, Err(e))) => error!("Xiaozhi MCP client `{}` failed: {:#}", names[&id], e),
+            Err(e) => error!("Xiaozhi MCP client `{}` panicked: {}", names[&e.id()], e),
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
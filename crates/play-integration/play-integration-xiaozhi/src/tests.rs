@@ -44,4 +44,95 @@ mod tests {
         
         assert_eq!(client.config().retry.interval_seconds, 10);
     }
+
+    #[test]
+    fn test_tool_filter() {
+        let filter = crate::ToolFilter {
+            allow: vec!["sys_*".to_string(), "echo".to_string()],
+            deny: vec!["sys_process".to_string()],
+        };
+        assert!(filter.allows("echo"));
+        assert!(filter.allows("sys_cpu"));
+        assert!(!filter.allows("sys_process"));
+        assert!(!filter.allows("file_write"));
+        assert!(crate::ToolFilter::default().allows("file_write"));
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config: crate::McpConfig = serde_json::from_value(serde_json::json!({
+            "url": "ws://localhost/ws",
+            "tools": {"deny": ["shell_exec"]}
+        }))
+        .unwrap();
+        assert!(config.enabled);
+        assert_eq!(config.display_name(), "xiaozhi-mcp-client");
+        assert!(!config.tools.allows("shell_exec"));
+        assert!(config.tools.allows("echo"));
+    }
+
+    #[test]
+    fn test_endpoint_tools_and_status() {
+        use play_mcp::tools::ToolRegistry;
+        use std::sync::Arc;
+
+        let config = crate::McpConfig::builder()
+            .name("kitchen")
+            .url("wss://api.xiaozhi.me/mcp/?token=secret")
+            .tool_prefix("k.")
+            .allow_tools(vec!["echo".to_string(), "sys_*".to_string()])
+            .deny_tools(vec!["sys_process".to_string()])
+            .build();
+        let registry = Arc::new(ToolRegistry::with_prefix("k.".to_string()));
+        let endpoint = crate::Endpoint::register(config, registry);
+
+        let names: Vec<String> = endpoint
+            .list_tools()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap().to_string())
+            .collect();
+        assert!(names.contains(&"k.echo".to_string()));
+        assert!(names.contains(&"k.sys_cpu".to_string()));
+        assert!(!names.contains(&"k.sys_process".to_string()));
+        assert!(!names.contains(&"k.file_read".to_string()));
+        assert!(endpoint.get_tool("k.echo").is_some());
+        assert!(endpoint.get_tool("k.file_read").is_none());
+
+        endpoint.record_call("k.echo", true);
+        endpoint.record_call("k.echo", false);
+        let status = crate::endpoints_status()
+            .into_iter()
+            .find(|s| s.name == "kitchen")
+            .unwrap();
+        assert_eq!(status.url, "wss://api.xiaozhi.me/mcp/");
+        assert_eq!(status.tool_calls, 2);
+        assert_eq!(status.tool_errors, 1);
+        assert_eq!(status.calls_by_tool["k.echo"], 2);
+    }
+
+    #[test]
+    fn test_endpoints_keyed_by_name() {
+        use play_mcp::tools::ToolRegistry;
+        use std::sync::Arc;
+
+        let config = crate::McpConfig::builder()
+            .name("garage")
+            .url("wss://api.xiaozhi.me/mcp/")
+            .build();
+        let count = || {
+            crate::endpoints_status()
+                .iter()
+                .filter(|s| s.name == "garage")
+                .count()
+        };
+        let first = crate::Endpoint::register(config.clone(), Arc::new(ToolRegistry::new()));
+        let second = crate::Endpoint::register(config, Arc::new(ToolRegistry::new()));
+        assert_eq!(count(), 1);
+
+        // the replaced endpoint stopping leaves the new one listed
+        first.unregister();
+        assert_eq!(count(), 1);
+        second.unregister();
+        assert_eq!(count(), 0);
+    }
 }
\ No newline at end of file
@@ -1,14 +1,31 @@
 use std::cell::RefCell;
 use std::{env, fs};
 use std::sync::{Arc, Mutex};
-use mlua::{ExternalResult, Function, Lua, LuaSerdeExt, MultiValue, Result, Table, Value};
+use std::time::{Duration, Instant};
+use mlua::{ExternalResult, Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, MultiValue, Result, StdLib, Table, Value, VmState};
 use mlua::prelude::{LuaError, LuaResult, LuaValue};
 use reqwest;
 
 
 pub  fn create_lua() -> Result<(Lua, Arc<Mutex<String>>)> {
     let lua = Lua::new();
+    let output = register_globals(&lua, false)?;
+    Ok((lua, output))
+}
 
+/// 沙箱环境: 只有 base/string/table/math 标准库和 http 模块
+/// 没有 os/io/package, 也不能用 require/load/loadfile/dofile 加载其他代码
+pub fn create_sandbox_lua() -> Result<(Lua, Arc<Mutex<String>>)> {
+    let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE | StdLib::MATH, LuaOptions::default())?;
+    let output = register_globals(&lua, true)?;
+    for name in ["require", "load", "loadfile", "dofile"] {
+        lua.globals().raw_set(name, Value::Nil)?;
+    }
+    Ok((lua, output))
+}
+
+/// 注册 print/to_string/http 等全局函数, 非沙箱环境还有 redis 和 require
+fn register_globals(lua: &Lua, sandbox: bool) -> Result<Arc<Mutex<String>>> {
     // 创建一个用于存储输出的字符串容器
     let output = Arc::new(Mutex::new(String::new()));
     let output_clone = output.clone();
@@ -119,13 +136,15 @@ pub  fn create_lua() -> Result<(Lua, Arc<Mutex<String>>)> {
 
     // 设置全局HTTP模块
     lua.globals().set("http", http_module)?;
-    lua.globals().set("redis", redis_module)?;
     lua.globals().set("print", print_override)?;
     lua.globals().set("to_string", to_string)?;
-    lua.globals().set("require", require_override)?;
+    if !sandbox {
+        lua.globals().set("redis", redis_module)?;
+        lua.globals().set("require", require_override)?;
+    }
 
 
-    Ok((lua, output))
+    Ok(output)
 }
 pub async fn run_lua(lua_code: &str) -> Result<String> {
     let (lua,output) = create_lua()?;
@@ -137,6 +156,38 @@ pub async fn run_lua(lua_code: &str) -> Result<String> {
 
     Ok(format!("{output_log}"))
 }
+/// 单个脚本可用的内存上限
+const MAX_SCRIPT_MEMORY: usize = 64 * 1024 * 1024;
+
+/// 在沙箱环境 (见 create_sandbox_lua) 中执行脚本, 参数以全局变量 `args` 传入, 返回脚本的返回值和 print 输出
+/// 超过 `timeout` 仍在执行 (如死循环) 时报错, 内存不超过 MAX_SCRIPT_MEMORY
+pub async fn run_lua_with_args(lua_code: &str, args: serde_json::Value, timeout: Duration) -> Result<(serde_json::Value, String)> {
+    let (lua,output) = create_sandbox_lua()?;
+
+    lua.set_memory_limit(MAX_SCRIPT_MEMORY)?;
+    lua.globals().set("args", json_to_lua_value(&lua, &args)?)?;
+
+    // 同步代码不会让出执行权, 只能靠指令计数钩子检查是否超时
+    // 钩子要设在执行脚本的协程上, 设在主线程上不会生效
+    let thread = lua.create_thread(lua.load(lua_code).into_function()?)?;
+    let deadline = Instant::now() + timeout;
+    thread.set_hook(HookTriggers::new().every_nth_instruction(1000), move |_, _| {
+        if Instant::now() > deadline {
+            Err(LuaError::runtime("script timed out"))
+        } else {
+            Ok(VmState::Continue)
+        }
+    });
+    let result: LuaValue = thread.into_async(()).await?;
+    let result = if result.is_nil() {
+        serde_json::Value::Null
+    } else {
+        lua.from_value(result)?
+    };
+
+    let output_log = output.lock().unwrap();
+    Ok((result, format!("{output_log}")))
+}
 // 将 serde_json::Value 转换为 mlua::Value
 fn json_to_lua_value(lua: &Lua, json: &serde_json::Value) -> Result<Value> {
     match json {
@@ -252,6 +303,30 @@ mod tests {
         println!("{}", output);
     }
     #[tokio::test]
+    async fn test_run_with_args() {
+        let (result, output) = run_lua_with_args(r#"
+            print("adding")
+            return {sum = args.a + args.b}
+        "#, json!({"a": 1, "b": 2}), Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(result, json!({"sum": 3}));
+        assert_eq!(output, "adding\n");
+
+        let err = run_lua_with_args("while true do end", json!({}), Duration::from_millis(100)).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        let err = run_lua_with_args("local t = {} while true do t[#t + 1] = 'x' end", json!({}), Duration::from_secs(30)).await.unwrap_err();
+        assert!(matches!(err, LuaError::MemoryError(_)), "{err}");
+    }
+    #[tokio::test]
+    async fn test_run_with_args_sandbox() {
+        let (result, _) = run_lua_with_args(r#"
+            return {os = os, io = io, package = package, require = require, load = load, loadfile = loadfile, dofile = dofile,
+                    http = type(http), upper = string.upper("a"), max = math.max(1, 2), joined = table.concat({"a", "b"})}
+        "#, json!({}), Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(result, json!({"http": "table", "upper": "A", "max": 2, "joined": "ab"}));
+    }
+    #[tokio::test]
     async fn test_require() {
 
         let output = run_lua(r#"
@@ -17,6 +17,8 @@ pub struct ToolDefinition {
     pub description: String,
     #[serde(rename = "inputSchema")]
     pub input_schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Value>,
 }
 
 /// Root structure of mcp_tools.json
@@ -47,6 +49,7 @@ pub fn load_tool_metadata(tool_name: &str) -> Option<ToolMetadata> {
             def.description.clone(),
             def.input_schema.clone(),
         )
+        .with_annotations(def.annotations.clone())
     })
 }
 
@@ -75,10 +78,18 @@ pub const fn validate_tool_name_chars(name: &str) -> bool {
 /// Define a complete MCP tool with struct, impl, and registration
 #[macro_export]
 macro_rules! define_mcp_tool {
+    // Tool without arguments, `||` is a single token so it gets its own arm
+    (
+        $tool_key:expr,
+        || $body:block
+    ) => {
+        $crate::define_mcp_tool!($tool_key, | | $body);
+    };
+
     // Tool without struct name - auto-generate unique struct  
     (
         $tool_key:expr,
-        |$($param:ident : $param_type:ty),+| $body:block
+        |$($param:ident : $param_type:ty),*| $body:block
     ) => {
         
         // Use a const _ block to encapsulate the auto-generated tool
@@ -120,6 +131,7 @@ macro_rules! define_mcp_tool {
                 }
                 
                 async fn execute(&self, input: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+                    let _ = &input;
                     // Extract individual parameters from JSON
                     $( 
                         let $param: $param_type = {
@@ -136,10 +148,10 @@ macro_rules! define_mcp_tool {
                                 serde_json::from_value(field_value.unwrap().clone())?
                             }
                         };
-                    )+
+                    )*
                     
-                    let execute_fn = |$($param: $param_type),+| async move $body;
-                    execute_fn($($param),+).await
+                    let execute_fn = |$($param: $param_type),*| async move $body;
+                    execute_fn($($param),*).await
                 }
             }
             
@@ -148,8 +160,6 @@ macro_rules! define_mcp_tool {
             static AUTO_GEN_REGISTER: ToolFactory = || {
                 Box::new(AutoGenTool::new())
             };
-            
-            ()
         };
     };
     
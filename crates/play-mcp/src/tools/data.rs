@@ -0,0 +1,34 @@
+use crate::define_mcp_tool;
+use crate::tools::host::{host, DataQuery};
+use serde_json::{json, Map, Value};
+
+define_mcp_tool!(
+    "data_query",
+    |category: String, filter: Option<String>, order_by: Option<String>, limit: Option<u32>| {
+        let rows = host()?
+            .data_query(
+                &category,
+                DataQuery {
+                    where_: filter,
+                    order_by,
+                    limit,
+                },
+            )
+            .await?;
+        Ok(json!({ "count": rows.len(), "rows": rows }))
+    }
+);
+
+define_mcp_tool!(
+    "data_insert",
+    |category: String, data: Map<String, Value>| { host()?.data_insert(&category, data).await }
+);
+
+define_mcp_tool!(
+    "data_update",
+    |category: String, id: u32, data: Map<String, Value>, replace: Option<bool>| {
+        host()?
+            .data_update(&category, id, data, replace.unwrap_or(false))
+            .await
+    }
+);
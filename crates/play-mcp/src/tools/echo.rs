@@ -0,0 +1,6 @@
+use crate::define_mcp_tool;
+use serde_json::json;
+
+define_mcp_tool!("echo", |message: String| {
+    Ok(json!({ "message": message }))
+});
@@ -0,0 +1,17 @@
+use crate::define_mcp_tool;
+use crate::tools::host::host;
+use serde_json::json;
+
+define_mcp_tool!("file_list", |dir: Option<String>| {
+    let entries = host()?.file_list(dir.as_deref().unwrap_or("")).await?;
+    Ok(json!({ "entries": entries }))
+});
+
+define_mcp_tool!("file_read", |path: String| {
+    let content = host()?.file_read(&path).await?;
+    Ok(json!({ "path": path, "content": content }))
+});
+
+define_mcp_tool!("file_write", |path: String, content: String| {
+    host()?.file_write(&path, &content).await
+});
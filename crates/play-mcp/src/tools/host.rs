@@ -0,0 +1,61 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Query options for `data_query`, mirroring `/api/v4/data/{category}/query`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DataQuery {
+    #[serde(default, rename = "where")]
+    pub where_: Option<String>,
+    #[serde(default)]
+    pub order_by: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+/// Capabilities the built-in tools need from the application hosting them.
+///
+/// `play-server` installs its implementation with [`set_host`] at startup; without one,
+/// the data, file and page tools fail with an error.
+#[async_trait]
+pub trait ToolHost: Send + Sync {
+    async fn data_query(&self, category: &str, query: DataQuery) -> Result<Vec<Value>>;
+
+    async fn data_insert(&self, category: &str, data: Map<String, Value>) -> Result<Value>;
+
+    /// Merges `data` into the row, or replaces it when `replace` is set.
+    async fn data_update(
+        &self,
+        category: &str,
+        id: u32,
+        data: Map<String, Value>,
+        replace: bool,
+    ) -> Result<Value>;
+
+    /// Entries of a directory under the files dir, `""` being the root.
+    async fn file_list(&self, dir: &str) -> Result<Vec<Value>>;
+
+    async fn file_read(&self, path: &str) -> Result<String>;
+
+    async fn file_write(&self, path: &str, content: &str) -> Result<Value>;
+
+    /// Renders the page stored at `url` with `params` as template variables.
+    async fn render_page(&self, url: &str, params: HashMap<String, String>) -> Result<String>;
+}
+
+static HOST: Lazy<RwLock<Option<Arc<dyn ToolHost>>>> = Lazy::new(|| RwLock::new(None));
+
+pub fn set_host(host: Arc<dyn ToolHost>) {
+    *HOST.write().unwrap() = Some(host);
+}
+
+pub(crate) fn host() -> Result<Arc<dyn ToolHost>> {
+    HOST.read()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| anyhow!("tool host is not available"))
+}
@@ -1,25 +1,49 @@
 use crate::define_mcp_tool;
-use anyhow::bail;
+use anyhow::Context;
 use serde_json::json;
 use std::collections::HashMap;
-use crate::tools::Tool;
+use std::time::Duration;
+
+/// responses longer than this are cut, the agent rarely needs more.
+const MAX_BODY_CHARS: usize = 64 * 1024;
 
 define_mcp_tool!(
     "http_request",
     |url: String,
      method: Option<String>,
      headers: Option<HashMap<String, String>>,
-     body: Option<String>| {
-        // This is a mock implementation
-        // In a real implementation, you would use reqwest or similar
+     body: Option<String>,
+     timeout_secs: Option<u64>| {
+        let method = reqwest::Method::from_bytes(method.as_deref().unwrap_or("GET").to_uppercase().as_bytes())
+            .context("invalid http method")?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(timeout_secs.unwrap_or(30)))
+            .build()?;
+
+        let mut request = client.request(method, &url);
+        for (name, value) in headers.unwrap_or_default() {
+            request = request.header(name, value);
+        }
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+
+        let response = request.send().await?;
+        let status = response.status().as_u16();
+        let headers: HashMap<String, String> = response
+            .headers()
+            .iter()
+            .map(|(k, v)| (k.to_string(), String::from_utf8_lossy(v.as_bytes()).to_string()))
+            .collect();
+        let text = response.text().await?;
+        let truncated = text.chars().count() > MAX_BODY_CHARS;
+        let body: String = text.chars().take(MAX_BODY_CHARS).collect();
 
         Ok(json!({
-            "status": 200,
-            "headers": {},
-            "body": format!("Mock response for {} request to {}",
-                method.unwrap_or_else(|| "GET".to_string()),
-                url),
-            "error": null
+            "status": status,
+            "headers": headers,
+            "body": body,
+            "truncated": truncated,
         }))
     }
 );
@@ -1,12 +1,24 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_trait::async_trait;
 use linkme::distributed_slice;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::sync::Arc;
-
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
 
+pub mod host;
+mod data;
+mod echo;
+mod files;
 mod http_request;
+mod pages;
+mod shell;
+mod sys;
+
+pub use host::{set_host, DataQuery, ToolHost};
 
 
 /// Tool metadata containing static information about a tool or operation
@@ -15,6 +27,9 @@ pub struct ToolMetadata {
     pub name: String,
     pub description: String,
     pub input_schema: Value,
+    /// MCP tool annotations, e.g. `readOnlyHint` and `destructiveHint`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Value>,
 }
 
 impl ToolMetadata {
@@ -23,10 +38,209 @@ impl ToolMetadata {
             name: name.into(),
             description: description.into(),
             input_schema,
+            annotations: None,
+        }
+    }
+
+    pub fn with_annotations(mut self, annotations: Option<Value>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Whether the tool is annotated with `destructiveHint: true`
+    pub fn is_destructive(&self) -> bool {
+        self.annotations
+            .as_ref()
+            .and_then(|a| a.get("destructiveHint"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+}
+
+/// Which tools are served and how destructive ones are guarded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolPolicy {
+    /// Tools to serve, all of them when empty
+    #[serde(default)]
+    pub enabled: Vec<String>,
+    /// Tools never served, even when listed in `enabled`
+    #[serde(default = "default_disabled")]
+    pub disabled: Vec<String>,
+    /// Destructive tools only run when called again with the confirmation token they return
+    #[serde(default = "default_confirm_destructive")]
+    pub confirm_destructive: bool,
+}
+
+fn default_confirm_destructive() -> bool {
+    true
+}
+
+/// `shell_exec` has to be turned on explicitly
+fn default_disabled() -> Vec<String> {
+    vec!["shell_exec".to_string()]
+}
+
+impl Default for ToolPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: vec![],
+            disabled: default_disabled(),
+            confirm_destructive: default_confirm_destructive(),
+        }
+    }
+}
+
+impl ToolPolicy {
+    pub fn is_enabled(&self, name: &str) -> bool {
+        (self.enabled.is_empty() || self.enabled.iter().any(|n| n == name))
+            && !self.disabled.iter().any(|n| n == name)
+    }
+}
+
+static POLICY: Lazy<RwLock<ToolPolicy>> = Lazy::new(|| RwLock::new(ToolPolicy::default()));
+
+/// Replaces the policy used by every registry
+pub fn set_policy(policy: ToolPolicy) {
+    *POLICY.write().unwrap() = policy;
+}
+
+pub fn policy() -> ToolPolicy {
+    POLICY.read().unwrap().clone()
+}
+
+/// Tools defined at runtime, e.g. from data rows or other MCP servers, by source.
+/// They are served after the built-in ones.
+static DYNAMIC_TOOLS: Lazy<RwLock<DynamicTools>> = Lazy::new(|| RwLock::new(BTreeMap::new()));
+
+type DynamicTools = BTreeMap<String, Vec<Arc<dyn Tool>>>;
+
+/// Bumped every time the dynamic tools are replaced
+static TOOLS_VERSION: Lazy<watch::Sender<u64>> = Lazy::new(|| watch::channel(0).0);
+
+/// Replaces the dynamic tools of `source` and notifies subscribers.
+///
+/// Tools whose name is invalid, taken by a built-in tool or another source, or repeated
+/// are skipped; their names are returned with the reason.
+pub fn set_dynamic_tools(source: &str, tools: Vec<Box<dyn Tool>>) -> Vec<String> {
+    let mut dynamic_tools = DYNAMIC_TOOLS.write().unwrap();
+    let mut seen: HashSet<String> = TOOL_FACTORIES
+        .iter()
+        .map(|factory| factory().metadata().name.clone())
+        .chain(
+            dynamic_tools
+                .iter()
+                .filter(|(s, _)| s.as_str() != source)
+                .flat_map(|(_, tools)| tools.iter().map(|t| t.metadata().name.clone())),
+        )
+        .collect();
+    let mut accepted: Vec<Arc<dyn Tool>> = Vec::new();
+    let mut rejected = Vec::new();
+    for tool in tools {
+        let name = tool.metadata().name.clone();
+        if !crate::metadata_loader::validate_tool_name_chars(&name) {
+            rejected.push(format!("{}: invalid name", name));
+        } else if !seen.insert(name.clone()) {
+            rejected.push(format!("{}: name already in use", name));
+        } else {
+            accepted.push(Arc::from(tool));
+        }
+    }
+    if accepted.is_empty() {
+        dynamic_tools.remove(source);
+    } else {
+        dynamic_tools.insert(source.to_string(), accepted);
+    }
+    drop(dynamic_tools);
+    TOOLS_VERSION.send_modify(|version| *version += 1);
+    rejected
+}
+
+/// Changes whenever the tool list changes, for `notifications/tools/list_changed`
+pub fn subscribe_tool_changes() -> watch::Receiver<u64> {
+    TOOLS_VERSION.subscribe()
+}
+
+/// How long a confirmation token stays valid
+const CONFIRMATION_TTL: Duration = Duration::from_secs(120);
+
+/// A destructive call waiting for its confirmation token
+struct PendingConfirmation {
+    tool: String,
+    arguments: Value,
+    expires_at: Instant,
+}
+
+static PENDING_CONFIRMATIONS: Lazy<Mutex<HashMap<String, PendingConfirmation>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Runs a destructive tool only when the call carries the `confirmation_token` handed out for
+/// the same tool and arguments; otherwise it issues a new single-use token and asks the caller
+/// to confirm with the user first.
+struct ConfirmGuard {
+    inner: Arc<dyn Tool>,
+}
+
+#[async_trait]
+impl Tool for ConfirmGuard {
+    fn metadata(&self) -> &ToolMetadata {
+        self.inner.metadata()
+    }
+
+    async fn execute(&self, mut input: Value) -> Result<Value> {
+        let name = &self.metadata().name;
+        let token = input
+            .as_object_mut()
+            .and_then(|args| args.remove("confirmation_token"));
+        if let Some(token) = token {
+            if !take_confirmation(&token, name, &input) {
+                bail!(
+                    "invalid or expired confirmation token for `{}`, call it again without one to get a new token",
+                    name
+                );
+            }
+            return self.inner.execute(input).await;
         }
+
+        Ok(json!({
+            "status": "confirmation_required",
+            "message": format!(
+                "`{}` changes data. Ask the user to confirm, then call it again with the same arguments and this `confirmation_token`.",
+                name
+            ),
+            "confirmation_token": issue_confirmation(name, &input),
+            "expires_in_secs": CONFIRMATION_TTL.as_secs(),
+            "arguments": input,
+        }))
     }
 }
 
+/// A new single-use token for calling `tool` with `arguments`
+fn issue_confirmation(tool: &str, arguments: &Value) -> String {
+    let now = Instant::now();
+    let mut pending = PENDING_CONFIRMATIONS.lock().unwrap();
+    pending.retain(|_, p| p.expires_at > now);
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    pending.insert(
+        token.clone(),
+        PendingConfirmation {
+            tool: tool.to_string(),
+            arguments: arguments.clone(),
+            expires_at: now + CONFIRMATION_TTL,
+        },
+    );
+    token
+}
+
+/// Whether `token` was issued for this call; it is used up either way
+fn take_confirmation(token: &Value, tool: &str, arguments: &Value) -> bool {
+    let pending = token
+        .as_str()
+        .and_then(|token| PENDING_CONFIRMATIONS.lock().unwrap().remove(token));
+    pending.is_some_and(|p| {
+        p.expires_at > Instant::now() && p.tool == tool && &p.arguments == arguments
+    })
+}
+
 /// Function type for creating tool instances
 pub type ToolFactory = fn() -> Box<dyn Tool>;
 
@@ -106,6 +320,25 @@ impl ToolRegistry {
     }
     
     pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        let policy = policy();
+        let tool = self.find(name)?;
+        if !policy.is_enabled(&tool.metadata().name) {
+            return None;
+        }
+        if policy.confirm_destructive && tool.metadata().is_destructive() {
+            return Some(Arc::new(ConfirmGuard { inner: tool }));
+        }
+        Some(tool)
+    }
+
+    /// Registered tools followed by the dynamic ones
+    fn tools(&self) -> Vec<Arc<dyn Tool>> {
+        let mut tools = self.tools.clone();
+        tools.extend(DYNAMIC_TOOLS.read().unwrap().values().flatten().cloned());
+        tools
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<dyn Tool>> {
         if !self.name_prefix.is_empty() {
             // When prefix is set, only accept names that start with the prefix
             if !name.starts_with(&self.name_prefix) {
@@ -113,19 +346,18 @@ impl ToolRegistry {
             }
             // Strip the prefix and search for the base tool name
             let search_name = &name[self.name_prefix.len()..];
-            self.tools.iter()
+            self.tools().into_iter()
                 .find(|t| t.metadata().name == search_name)
-                .cloned()
         } else {
             // No prefix, direct name match
-            self.tools.iter()
+            self.tools().into_iter()
                 .find(|t| t.metadata().name == name)
-                .cloned()
         }
     }
     
     pub fn list(&self) -> Vec<Value> {
-        self.tools.iter().map(|tool| {
+        let policy = policy();
+        self.tools().iter().filter(|tool| policy.is_enabled(&tool.metadata().name)).map(|tool| {
             let metadata = tool.metadata();
             // Apply prefix to the tool name when listing
             let name_with_prefix = if self.name_prefix.is_empty() {
@@ -134,11 +366,25 @@ impl ToolRegistry {
                 format!("{}{}", self.name_prefix, metadata.name)
             };
             
-            json!({
+            let mut input_schema = metadata.input_schema.clone();
+            if policy.confirm_destructive && metadata.is_destructive() {
+                if let Some(properties) = input_schema.get_mut("properties").and_then(|p| p.as_object_mut()) {
+                    properties.insert("confirmation_token".to_string(), json!({
+                        "type": "string",
+                        "description": "Token returned by the unconfirmed call, pass it once the user has confirmed this change"
+                    }));
+                }
+            }
+            
+            let mut tool = json!({
                 "name": name_with_prefix,
                 "description": metadata.description,
-                "inputSchema": metadata.input_schema,
-            })
+                "inputSchema": input_schema,
+            });
+            if let Some(annotations) = &metadata.annotations {
+                tool["annotations"] = annotations.clone();
+            }
+            tool
         }).collect()
     }
 }
@@ -225,4 +471,111 @@ mod tests {
             assert!(registry.get("echo").is_none());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_policy() {
+        let policy = ToolPolicy::default();
+        assert!(policy.is_enabled("echo"));
+        assert!(!policy.is_enabled("shell_exec"));
+
+        let policy = ToolPolicy {
+            enabled: vec!["echo".to_string(), "file_read".to_string()],
+            disabled: vec!["file_read".to_string()],
+            confirm_destructive: true,
+        };
+        assert!(policy.is_enabled("echo"));
+        assert!(!policy.is_enabled("file_read"));
+        assert!(!policy.is_enabled("sys_info"));
+    }
+
+    #[tokio::test]
+    async fn test_builtin_tools() {
+        let registry = ToolRegistry::new();
+        let tools = registry.list();
+        let find = |name: &str| tools.iter().find(|t| t["name"] == name).cloned();
+
+        // destructive tools ask for confirmation, shell_exec is off by default
+        let data_update = find("data_update").unwrap();
+        assert_eq!(data_update["annotations"]["destructiveHint"], true);
+        assert!(data_update["inputSchema"]["properties"].get("confirmation_token").is_some());
+        assert_eq!(find("data_insert").unwrap()["annotations"]["destructiveHint"], true);
+        assert!(find("data_query").unwrap()["inputSchema"]["properties"].get("confirmation_token").is_none());
+        assert!(find("shell_exec").is_none());
+        assert!(registry.get("shell_exec").is_none());
+
+        let echo = registry.get("echo").unwrap();
+        assert_eq!(echo.execute(json!({"message": "hi"})).await.unwrap()["message"], "hi");
+        let sys_info = registry.get("sys_info").unwrap();
+        assert!(sys_info.execute(json!({})).await.unwrap()["cpu_count"].as_u64() > Some(0));
+
+        let update = registry.get("data_update").unwrap();
+        let args = json!({"category": "notes", "id": 1, "data": {"a": 1}});
+        // asking for confirmation on its own does not run anything
+        let with_token = |token: &Value, args: &Value| {
+            let mut args = args.clone();
+            args["confirmation_token"] = token.clone();
+            args
+        };
+        assert!(update.execute(with_token(&json!(true), &args)).await.is_err());
+        let result = update.execute(args.clone()).await.unwrap();
+        assert_eq!(result["status"], "confirmation_required");
+        let token = result["confirmation_token"].clone();
+
+        // the token is bound to the arguments and used up by a mismatch
+        let other = json!({"category": "notes", "id": 2, "data": {"a": 1}});
+        assert!(update.execute(with_token(&token, &other)).await.is_err());
+        assert!(update.execute(with_token(&token, &args)).await.is_err());
+
+        // confirmed, it reaches the host, which is not installed here
+        let token = update.execute(args.clone()).await.unwrap()["confirmation_token"].clone();
+        let result = update.execute(with_token(&token, &args)).await;
+        assert!(result.unwrap_err().to_string().contains("tool host"));
+    }
+
+    struct Greet {
+        metadata: ToolMetadata,
+    }
+
+    #[async_trait]
+    impl Tool for Greet {
+        fn metadata(&self) -> &ToolMetadata {
+            &self.metadata
+        }
+
+        async fn execute(&self, input: Value) -> Result<Value> {
+            Ok(json!({"greeting": format!("hi {}", input["name"].as_str().unwrap_or_default())}))
+        }
+    }
+
+    fn greet(name: &str) -> Box<dyn Tool> {
+        Box::new(Greet {
+            metadata: ToolMetadata::new(name, "greets", json!({"type": "object", "properties": {}})),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_tools() {
+        let changes = subscribe_tool_changes();
+        let rejected = set_dynamic_tools(
+            "test",
+            vec![
+                greet("dynamic_greet"),
+                greet("echo"),
+                greet("Bad Name"),
+                greet("dynamic_greet"),
+            ],
+        );
+        assert_eq!(rejected.len(), 3);
+        assert!(changes.has_changed().unwrap());
+        // names are unique across sources too
+        assert_eq!(set_dynamic_tools("other", vec![greet("dynamic_greet")]).len(), 1);
+
+        let registry = ToolRegistry::with_prefix("p_".to_string());
+        assert!(registry.list().iter().any(|t| t["name"] == "p_dynamic_greet"));
+        let tool = registry.get("p_dynamic_greet").unwrap();
+        assert_eq!(tool.execute(json!({"name": "bob"})).await.unwrap()["greeting"], "hi bob");
+
+        set_dynamic_tools("test", vec![]);
+        assert!(registry.get("p_dynamic_greet").is_none());
+    }
+}
@@ -0,0 +1,14 @@
+use crate::define_mcp_tool;
+use crate::tools::host::host;
+use serde_json::json;
+use std::collections::HashMap;
+
+define_mcp_tool!(
+    "page_render",
+    |url: String, params: Option<HashMap<String, String>>| {
+        let output = host()?
+            .render_page(&url, params.unwrap_or_default())
+            .await?;
+        Ok(json!({ "url": url, "output": output }))
+    }
+);
@@ -0,0 +1,42 @@
+use crate::define_mcp_tool;
+use anyhow::Context;
+use serde_json::json;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// output longer than this is cut.
+const MAX_OUTPUT_CHARS: usize = 32 * 1024;
+
+fn truncate(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .chars()
+        .take(MAX_OUTPUT_CHARS)
+        .collect()
+}
+
+define_mcp_tool!(
+    "shell_exec",
+    |command: String, timeout_secs: Option<u64>| {
+        let mut cmd = if cfg!(windows) {
+            let mut cmd = Command::new("cmd");
+            cmd.arg("/C").arg(&command);
+            cmd
+        } else {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(&command);
+            cmd
+        };
+        cmd.kill_on_drop(true);
+
+        let timeout = Duration::from_secs(timeout_secs.unwrap_or(30));
+        let output = tokio::time::timeout(timeout, cmd.output())
+            .await
+            .with_context(|| format!("command timed out after {:?}", timeout))??;
+
+        Ok(json!({
+            "exit_code": output.status.code(),
+            "stdout": truncate(&output.stdout),
+            "stderr": truncate(&output.stderr),
+        }))
+    }
+);
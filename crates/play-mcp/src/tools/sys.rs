@@ -0,0 +1,138 @@
+use crate::define_mcp_tool;
+use serde_json::{json, Value};
+use sysinfo::{Disks, System, MINIMUM_CPU_UPDATE_INTERVAL};
+
+const GB: f64 = 1024.0 * 1024.0 * 1024.0;
+const MB: f64 = 1024.0 * 1024.0;
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// cpu usage is a delta, so it needs two refreshes apart.
+async fn refreshed_system() -> System {
+    let mut sys = System::new_all();
+    tokio::time::sleep(MINIMUM_CPU_UPDATE_INTERVAL).await;
+    sys.refresh_all();
+    sys
+}
+
+define_mcp_tool!("sys_info", || {
+    let mut sys = System::new_all();
+    sys.refresh_all();
+    let load = System::load_average();
+    Ok(json!({
+        "os": System::name(),
+        "os_version": System::long_os_version(),
+        "kernel_version": System::kernel_version(),
+        "host_name": System::host_name(),
+        "arch": std::env::consts::ARCH,
+        "uptime_secs": System::uptime(),
+        "cpu_count": sys.cpus().len(),
+        "total_memory_gb": round2(sys.total_memory() as f64 / GB),
+        "load_average": [load.one, load.five, load.fifteen],
+    }))
+});
+
+define_mcp_tool!("sys_disk", |path: Option<String>| {
+    let disks = Disks::new_with_refreshed_list();
+    let mut list: Vec<_> = disks.list().iter().collect();
+    if let Some(path) = &path {
+        // the disk holding `path` is the one with the longest matching mount point.
+        let path = std::path::Path::new(path);
+        list.retain(|d| path.starts_with(d.mount_point()));
+        list.sort_by_key(|d| std::cmp::Reverse(d.mount_point().as_os_str().len()));
+        list.truncate(1);
+    }
+    let disks: Vec<Value> = list
+        .into_iter()
+        .map(|disk| {
+            let total = disk.total_space() as f64;
+            let available = disk.available_space() as f64;
+            let used = total - available;
+            json!({
+                "path": disk.mount_point().to_string_lossy(),
+                "file_system": disk.file_system().to_string_lossy(),
+                "total_gb": round2(total / GB),
+                "available_gb": round2(available / GB),
+                "used_gb": round2(used / GB),
+                "used_percentage": if total > 0.0 { round2(used / total * 100.0) } else { 0.0 },
+            })
+        })
+        .collect();
+    Ok(json!({ "disks": disks }))
+});
+
+define_mcp_tool!("sys_memory", |detailed: Option<bool>| {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    let total = sys.total_memory() as f64;
+    let used = sys.used_memory() as f64;
+    let mut result = json!({
+        "total_gb": round2(total / GB),
+        "used_gb": round2(used / GB),
+        "used_percentage": if total > 0.0 { round2(used / total * 100.0) } else { 0.0 },
+    });
+    if detailed.unwrap_or(false) {
+        result["available_gb"] = json!(round2(sys.available_memory() as f64 / GB));
+        result["free_gb"] = json!(round2(sys.free_memory() as f64 / GB));
+        result["swap_total_gb"] = json!(round2(sys.total_swap() as f64 / GB));
+        result["swap_used_gb"] = json!(round2(sys.used_swap() as f64 / GB));
+    }
+    Ok(result)
+});
+
+define_mcp_tool!(
+    "sys_process",
+    |filter: Option<String>, sort_by: Option<String>, limit: Option<usize>| {
+        let sys = refreshed_system().await;
+        let filter = filter.map(|f| f.to_lowercase());
+        let mut processes: Vec<_> = sys
+            .processes()
+            .values()
+            .filter(|p| match &filter {
+                Some(filter) => p.name().to_string_lossy().to_lowercase().contains(filter),
+                None => true,
+            })
+            .collect();
+        match sort_by.as_deref().unwrap_or("cpu") {
+            "memory" => processes.sort_by_key(|p| std::cmp::Reverse(p.memory())),
+            "name" => processes.sort_by_key(|p| p.name().to_os_string()),
+            _ => processes.sort_by(|a, b| b.cpu_usage().total_cmp(&a.cpu_usage())),
+        }
+        let processes: Vec<Value> = processes
+            .into_iter()
+            .take(limit.unwrap_or(10).clamp(1, 1000))
+            .map(|p| {
+                json!({
+                    "pid": p.pid().as_u32(),
+                    "name": p.name().to_string_lossy(),
+                    "cpu_usage": round2(p.cpu_usage() as f64),
+                    "memory_mb": round2(p.memory() as f64 / MB),
+                })
+            })
+            .collect();
+        Ok(json!({ "processes": processes }))
+    }
+);
+
+define_mcp_tool!("sys_cpu", |per_core: Option<bool>| {
+    let sys = refreshed_system().await;
+    let cpus = sys.cpus();
+    let mut result = json!({
+        "brand": cpus.first().map(|c| c.brand().to_string()),
+        "cores": cpus.len(),
+        "usage": round2(sys.global_cpu_usage() as f64),
+    });
+    if per_core.unwrap_or(false) {
+        result["per_core"] = json!(cpus
+            .iter()
+            .map(|c| json!({
+                "name": c.name(),
+                "usage": round2(c.cpu_usage() as f64),
+                "frequency_mhz": c.frequency(),
+            }))
+            .collect::<Vec<_>>());
+    }
+    Ok(result)
+});
@@ -39,17 +39,37 @@ pub struct Config {
     #[serde(default)]
     pub cache_config: CacheConfig,
     #[serde(default)]
+    pub http_cache: HttpCacheConfig,
+    #[serde(default)]
+    pub image_config: ImageConfig,
+    #[serde(default)]
+    pub file_storage: FileStorageConfig,
+    #[serde(default)]
     pub plugin_config: Vec<PluginConfig>,
+    /// 插件通过宿主发出的 http 请求使用的代理，如 http://127.0.0.1:7890，不设置时使用 HTTPS_PROXY 等环境变量
+    #[serde(default)]
+    pub http_proxy: Option<String>,
     #[serde(default)]
     pub frp_server: FrpServerConfig,
     #[serde(default)]
     pub ikev2_server: Ikev2ServerConfig,
     #[serde(default)]
     pub one_key_change_ip: OneKeyChangeIpConfig,
+    /// /mcp 和小智客户端共用的内置工具开关：enabled 为空时全部启用，disabled 中的始终关闭（默认关闭 shell_exec），
+    /// confirm_destructive 为 true 时修改类工具需带服务端下发的一次性 confirmation_token 重试才会执行
+    #[serde(default)]
+    pub mcp_tools: play_mcp::tools::ToolPolicy,
+    /// 汇总到 /mcp 的外部 MCP 服务，其工具、资源和提示词以 prefix 为命名空间镜像过来
+    #[serde(default)]
+    pub mcp_hub: McpHubConfig,
 
     #[cfg(feature = "play-integration-xiaozhi")]
     #[serde(default)]
     pub mcp_config: play_integration_xiaozhi::McpConfig,
+    /// 多个小智接入点，各自的 url、工具前缀、重试策略和工具白名单/黑名单；为空时只连接 mcp_config
+    #[cfg(feature = "play-integration-xiaozhi")]
+    #[serde(default)]
+    pub xiaozhi_endpoints: Vec<play_integration_xiaozhi::McpConfig>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
@@ -60,6 +80,166 @@ pub struct CacheConfig {
     pub cf_purge_cache_url: String,
 }
 
+/// 缓存 domain_proxy 和 /pages 的 GET 响应，遵循 Cache-Control / Vary
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HttpCacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 响应没有 max-age / s-maxage 时的缓存时间（秒），0 表示不缓存
+    #[serde(default)]
+    pub default_ttl_secs: u64,
+    /// 过期后还可以先返回旧内容、后台刷新的时间（秒）
+    #[serde(default)]
+    pub stale_while_revalidate_secs: u64,
+    /// 内存中缓存的总大小，超出后淘汰最久未访问的（磁盘上的仍保留）
+    #[serde(default = "default_http_cache_memory_size")]
+    pub memory_size: String,
+    /// 单个响应体超过这个大小不缓存
+    #[serde(default = "default_http_cache_max_entry_size")]
+    pub max_entry_size: String,
+    /// 同时写入 DATA_DIR/http_cache，重启后仍然有效
+    #[serde(default = "default_true")]
+    pub disk: bool,
+    #[serde(default)]
+    pub rules: Vec<HttpCacheRule>,
+}
+
+impl Default for HttpCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_ttl_secs: 0,
+            stale_while_revalidate_secs: 0,
+            memory_size: default_http_cache_memory_size(),
+            max_entry_size: default_http_cache_max_entry_size(),
+            disk: true,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// 按 host（支持 `*.example.com`）和路径前缀覆盖缓存策略，最长前缀优先
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HttpCacheRule {
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default = "default_route_path_prefix")]
+    pub path_prefix: String,
+    /// 覆盖响应里的 max-age（no-store / private / Set-Cookie 的响应，以及带 Cookie 或 Authorization 的请求仍然不缓存，除非响应标了 public）
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub stale_while_revalidate_secs: Option<u64>,
+    /// 写入缓存时附加的 tag，用于按 tag 清除
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 不缓存
+    #[serde(default)]
+    pub bypass: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+fn default_http_cache_memory_size() -> String {
+    "64MB".to_string()
+}
+fn default_http_cache_max_entry_size() -> String {
+    "8MB".to_string()
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ImageConfig {
+    /// largest width/height an image transform (`/files/xx.jpg?w=200`) may produce
+    #[serde(default = "default_image_max_dimension")]
+    pub max_dimension: u32,
+    /// source images wider or taller than this are refused to decode
+    #[serde(default = "default_image_max_source_dimension")]
+    pub max_source_dimension: u32,
+    /// jpeg quality used when `q` is not given; webp is always lossless and refuses `q`
+    #[serde(default = "default_image_quality")]
+    pub default_quality: u8,
+    /// total size of cached variants, e.g. "1GB"; the least recently used ones are removed beyond it
+    #[serde(default = "default_image_cache_max_size")]
+    pub cache_max_size: String,
+    /// image transforms decoded and encoded at the same time, later ones wait for a free slot
+    #[serde(default = "default_image_max_concurrent_transforms")]
+    pub max_concurrent_transforms: usize,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            max_dimension: default_image_max_dimension(),
+            max_source_dimension: default_image_max_source_dimension(),
+            default_quality: default_image_quality(),
+            cache_max_size: default_image_cache_max_size(),
+            max_concurrent_transforms: default_image_max_concurrent_transforms(),
+        }
+    }
+}
+
+fn default_image_max_dimension() -> u32 {
+    4096
+}
+
+fn default_image_max_source_dimension() -> u32 {
+    16384
+}
+
+fn default_image_quality() -> u8 {
+    80
+}
+
+fn default_image_cache_max_size() -> String {
+    "1GB".to_string()
+}
+
+fn default_image_max_concurrent_transforms() -> usize {
+    4
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStorageBackend {
+    /// 直接存放在 DATA_DIR/files 下
+    #[default]
+    Local,
+    /// 内容去重后分块存入 play-db 的 assets，files 下只留指针文件
+    Asset,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FileStorageConfig {
+    /// where uploaded files are stored, existing files can be imported with `play-server migrate-files`
+    #[serde(default)]
+    pub backend: FileStorageBackend,
+    /// e.g. "1MB", "512KB"
+    #[serde(default = "default_asset_chunk_size")]
+    pub chunk_size: String,
+    /// number of chunk shard dbs, only ever increase it
+    #[serde(default = "default_asset_shards")]
+    pub shards: usize,
+}
+
+impl Default for FileStorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: FileStorageBackend::default(),
+            chunk_size: default_asset_chunk_size(),
+            shards: default_asset_shards(),
+        }
+    }
+}
+
+fn default_asset_chunk_size() -> String {
+    "1MB".to_string()
+}
+
+fn default_asset_shards() -> usize {
+    4
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct PluginConfig {
     #[serde(default)]
@@ -77,8 +257,159 @@ pub struct PluginConfig {
     pub is_server: bool,
     #[serde(default)]
     pub disable: bool,
+    /// is_server 插件作为独立进程运行，由 supervisor 管理
     #[serde(default)]
     pub create_process: bool,
+    /// create_process 时传给进程的参数
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// create_process 时额外设置的环境变量
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// is_server 插件的重启策略和健康检查
+    #[serde(default)]
+    pub supervisor: SupervisorConfig,
+    #[serde(default)]
+    pub kind: PluginKind,
+    /// kind 为 wasm 时每个请求的资源限制
+    #[serde(default)]
+    pub wasm: WasmPluginConfig,
+    /// 插件可以使用的宿主服务，默认都不允许
+    #[serde(default)]
+    pub permissions: PluginPermissions,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// 退出后不再启动
+    #[default]
+    Never,
+    /// 每次退出后等待 restart_delay_ms 再启动
+    Always,
+    /// 只在失败（退出码非 0、启动失败、健康检查失败）后重启
+    OnFailure,
+    /// 每次退出后重启，连续快速退出时等待时间从 restart_delay_ms 翻倍到 max_restart_delay_ms
+    Backoff,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SupervisorConfig {
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default = "default_restart_delay_ms")]
+    pub restart_delay_ms: u64,
+    #[serde(default = "default_max_restart_delay_ms")]
+    pub max_restart_delay_ms: u64,
+    /// 健康检查地址，如 http://127.0.0.1:3000/health，返回 2xx 为健康
+    #[serde(default)]
+    pub health_url: Option<String>,
+    #[serde(default = "default_health_interval_secs")]
+    pub health_interval_secs: u64,
+    /// 连续失败多少次后重启进程（进程内插件只记录状态）
+    #[serde(default = "default_health_failures")]
+    pub health_failures: u32,
+    /// 停止时先发送 SIGTERM，超时后强制结束
+    #[serde(default = "default_stop_timeout_secs")]
+    pub stop_timeout_secs: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            restart: RestartPolicy::default(),
+            restart_delay_ms: default_restart_delay_ms(),
+            max_restart_delay_ms: default_max_restart_delay_ms(),
+            health_url: None,
+            health_interval_secs: default_health_interval_secs(),
+            health_failures: default_health_failures(),
+            stop_timeout_secs: default_stop_timeout_secs(),
+        }
+    }
+}
+
+fn default_restart_delay_ms() -> u64 {
+    1000
+}
+
+fn default_max_restart_delay_ms() -> u64 {
+    60_000
+}
+
+fn default_health_interval_secs() -> u64 {
+    10
+}
+
+fn default_health_failures() -> u32 {
+    3
+}
+
+fn default_stop_timeout_secs() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginKind {
+    /// 动态库（.so / .dylib / .dll），在服务进程内运行
+    #[default]
+    Dylib,
+    /// wasm32-wasip1 编译的 WASI 程序，在沙箱中运行
+    Wasm,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct WasmPluginConfig {
+    /// 每个请求最多执行的指令数（大约）
+    #[serde(default = "default_wasm_fuel")]
+    pub fuel: u64,
+    /// 内存上限，如 "64MB"
+    #[serde(default = "default_wasm_memory_limit")]
+    pub memory_limit: String,
+    /// 每个请求的超时时间（毫秒），包括等待宿主服务的时间
+    #[serde(default = "default_wasm_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Default for WasmPluginConfig {
+    fn default() -> Self {
+        Self {
+            fuel: default_wasm_fuel(),
+            memory_limit: default_wasm_memory_limit(),
+            timeout_ms: default_wasm_timeout_ms(),
+        }
+    }
+}
+
+fn default_wasm_fuel() -> u64 {
+    5_000_000_000
+}
+
+fn default_wasm_memory_limit() -> String {
+    "64MB".to_string()
+}
+
+fn default_wasm_timeout_ms() -> u64 {
+    10_000
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct PluginPermissions {
+    /// 允许请求的外部 host，支持 `*.example.com`
+    #[serde(default)]
+    pub http_allowlist: Vec<String>,
+    /// 允许读写的 general_data 分类
+    #[serde(default)]
+    pub data_categories: Vec<String>,
+    /// 读写 DATA_DIR/plugins/<name>/files，wasm 插件中挂载为 /data 目录
+    #[serde(default)]
+    pub files: bool,
+    /// 插件私有的 KV，配置了 redis 时存在 redis 中，否则存在 general_data
+    #[serde(default)]
+    pub kv: bool,
+    /// 写入服务日志
+    #[serde(default)]
+    pub log: bool,
 }
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DomainProxy {
@@ -94,6 +425,60 @@ pub struct DomainProxy {
     pub ignore_cert: bool,
     #[serde(flatten)]
     pub websocket_config: WebSocketConfig,
+    /// 转发失败（连接失败、超时、502/503/504）时换一个 upstream 重试的次数，只对幂等请求生效
+    #[serde(default)]
+    pub retries: u32,
+    /// 单次转发等待 upstream 响应的超时时间（秒）
+    #[serde(default = "default_proxy_timeout_secs")]
+    pub timeout_secs: u64,
+    /// 未匹配到 `routes` 时使用的改写规则
+    #[serde(flatten)]
+    pub rewrite: ProxyRewrite,
+    /// 按路径前缀/请求方法分流，最长前缀优先；都不匹配时使用上面的 `type`
+    #[serde(default)]
+    pub routes: Vec<ProxyRoute>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ProxyRoute {
+    /// 匹配的路径前缀，按路径段匹配（`/api` 匹配 `/api` 和 `/api/x`，不匹配 `/apix`）
+    #[serde(default = "default_route_path_prefix")]
+    pub path_prefix: String,
+    /// 匹配的请求方法，为空时匹配所有方法
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(flatten)]
+    pub proxy_target: ProxyTarget,
+    #[serde(flatten)]
+    pub rewrite: ProxyRewrite,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ProxyRewrite {
+    /// 转发前去掉匹配到的 `path_prefix`
+    #[serde(default)]
+    pub strip_prefix: bool,
+    /// 转发前在路径前面加上的前缀（在 strip_prefix 之后）
+    #[serde(default)]
+    pub add_prefix: Option<String>,
+    #[serde(default)]
+    pub request_headers: HeaderRules,
+    #[serde(default)]
+    pub response_headers: HeaderRules,
+    /// 注入 X-Forwarded-For / X-Forwarded-Proto / X-Forwarded-Host（以及 strip 时的 X-Forwarded-Prefix）
+    #[serde(default)]
+    pub x_forwarded: bool,
+}
+
+/// 头部改写，按 remove -> set -> append 的顺序执行
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct HeaderRules {
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    #[serde(default)]
+    pub append: BTreeMap<String, String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -151,6 +536,111 @@ pub enum ProxyTarget {
         #[serde(default = "default_proxy_port")]
         port: u16,
     },
+    /// 多个 upstream 负载均衡
+    #[serde(rename = "pool")]
+    Pool {
+        #[serde(default)]
+        members: Vec<UpstreamServer>,
+        #[serde(default)]
+        balance: BalanceStrategy,
+        #[serde(default)]
+        health_check: HealthCheckConfig,
+    },
+    /// 转发到内置 FRP 服务的隧道，多个隧道按 Host 共用 HTTP/HTTPS 端口，
+    /// service 为 frp_server.services 中的名字，其 bind_addr 可以留空（不单独监听端口）
+    #[serde(rename = "tunnel")]
+    Tunnel { service: String },
+    /// 直接返回重定向
+    #[serde(rename = "redirect")]
+    Redirect {
+        to: String,
+        /// 301/302/307/308
+        #[serde(default = "default_redirect_status")]
+        status: u16,
+        /// 把（改写后的）请求路径和查询参数拼接到 `to` 后面
+        #[serde(default)]
+        keep_path: bool,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UpstreamServer {
+    #[serde(default = "default_proxy_ip")]
+    pub ip: String,
+    #[serde(default = "default_proxy_port")]
+    pub port: u16,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    #[default]
+    RoundRobin,
+    LeastConn,
+    /// 同一个客户端 IP 总是落到同一个成员上
+    IpHash,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HealthCheckConfig {
+    /// 主动健康检查：定时 GET `path`，2xx/3xx 视为健康
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_health_check_path")]
+    pub path: String,
+    #[serde(default = "default_health_check_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_health_check_timeout_secs")]
+    pub timeout_secs: u64,
+    /// 连续失败多少次标记为不健康
+    #[serde(default = "default_health_check_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+    /// 连续成功多少次恢复为健康
+    #[serde(default = "default_health_check_healthy_threshold")]
+    pub healthy_threshold: u32,
+    /// 被动检测：真实请求连续失败多少次后摘除
+    #[serde(default = "default_passive_max_fails")]
+    pub max_fails: u32,
+    /// 被动摘除的时长（秒）
+    #[serde(default = "default_passive_fail_timeout_secs")]
+    pub fail_timeout_secs: u64,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_health_check_path(),
+            interval_secs: default_health_check_interval_secs(),
+            timeout_secs: default_health_check_timeout_secs(),
+            unhealthy_threshold: default_health_check_unhealthy_threshold(),
+            healthy_threshold: default_health_check_healthy_threshold(),
+            max_fails: default_passive_max_fails(),
+            fail_timeout_secs: default_passive_fail_timeout_secs(),
+        }
+    }
+}
+
+fn default_health_check_path() -> String {
+    "/".to_string()
+}
+fn default_health_check_interval_secs() -> u64 {
+    10
+}
+fn default_health_check_timeout_secs() -> u64 {
+    3
+}
+fn default_health_check_unhealthy_threshold() -> u32 {
+    2
+}
+fn default_health_check_healthy_threshold() -> u32 {
+    1
+}
+fn default_passive_max_fails() -> u32 {
+    3
+}
+fn default_passive_fail_timeout_secs() -> u64 {
+    30
 }
 
 impl Default for DomainProxy {
@@ -163,10 +653,25 @@ impl Default for DomainProxy {
             use_https: None,
             ignore_cert: false,
             websocket_config: WebSocketConfig::default(),
+            retries: 0,
+            timeout_secs: default_proxy_timeout_secs(),
+            rewrite: ProxyRewrite::default(),
+            routes: Vec::new(),
         }
     }
 }
 
+fn default_route_path_prefix() -> String {
+    "/".to_string()
+}
+fn default_redirect_status() -> u16 {
+    302
+}
+
+fn default_proxy_timeout_secs() -> u64 {
+    60
+}
+
 fn default_proxy_ip() -> String {
     "127.0.0.1".to_string()
 }
@@ -447,6 +952,9 @@ pub struct Ikev2ServerConfig {
     pub dns_servers: Vec<String>,
     #[serde(default)]
     pub eap_users: BTreeMap<String, String>,
+    // 同时接受 EAP-TLS 客户端证书登录（证书由 /admin/ikev2 生成的配置文件签发）
+    #[serde(default)]
+    pub eap_tls: bool,
     #[serde(default = "default_ikev2_fragmentation")]
     pub fragmentation: bool,
     #[serde(default = "default_ikev2_mobike")]
@@ -463,6 +971,62 @@ pub struct Ikev2ServerConfig {
     pub startup_timeout_secs: u64,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct McpHubConfig {
+    #[serde(default)]
+    pub servers: Vec<McpHubServerConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct McpHubServerConfig {
+    /// 只能包含小写字母、数字和 `_`
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 工具和提示词名的前缀，默认为 `{name}.`
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// 连接、初始化和每次调用的超时
+    #[serde(default = "default_mcp_hub_timeout_secs")]
+    pub timeout_secs: u64,
+    /// 断线重连的最大间隔，从 1 秒开始翻倍
+    #[serde(default = "default_mcp_hub_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    #[serde(flatten)]
+    pub transport: McpHubTransport,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "transport", rename_all = "snake_case")]
+pub enum McpHubTransport {
+    /// 子进程，按行收发 JSON-RPC
+    Stdio {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        #[serde(default)]
+        cwd: Option<String>,
+    },
+    /// Streamable HTTP
+    Http {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    #[serde(rename = "websocket")]
+    WebSocket { url: String },
+}
+
+impl McpHubServerConfig {
+    pub fn prefix(&self) -> String {
+        self.prefix
+            .clone()
+            .unwrap_or_else(|| format!("{}.", self.name))
+    }
+}
+
 impl Default for FrpServerConfig {
     fn default() -> Self {
         let mut services = BTreeMap::new();
@@ -509,6 +1073,7 @@ impl Default for Ikev2ServerConfig {
             local_ts: default_ikev2_local_ts(),
             dns_servers: vec!["1.1.1.1".to_string(), "8.8.8.8".to_string()],
             eap_users,
+            eap_tls: false,
             fragmentation: default_ikev2_fragmentation(),
             mobike: default_ikev2_mobike(),
             dpd_delay_secs: default_ikev2_dpd_delay_secs(),
@@ -524,6 +1089,14 @@ fn default_log_level() -> String {
     "INFO".to_string()
 }
 
+fn default_mcp_hub_timeout_secs() -> u64 {
+    30
+}
+
+fn default_mcp_hub_max_backoff_secs() -> u64 {
+    60
+}
+
 fn default_frp_server_bind_addr() -> String {
     "0.0.0.0:2333".to_string()
 }
@@ -604,14 +1177,122 @@ fn default_ikev2_startup_timeout_secs() -> u64 {
     15
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct HttpsCert {
     pub https_port: u16,
     #[serde(default)]
     pub auto_redirect: bool,
     /// first domain is main domain ,other domain will serve folder under $files/$domain_name
+    #[serde(default)]
     pub domains: Vec<String>,
+    #[serde(default)]
     pub emails: Vec<String>,
+    /// 证书来源：acme (Let's Encrypt) 或 local_ca (本地自签 CA，离线/开发环境用)
+    #[serde(default)]
+    pub mode: CertMode,
+    /// ACME 验证方式，通配符域名总是用 dns_01
+    #[serde(default)]
+    pub challenge: CertChallenge,
+    /// 使用 Let's Encrypt 测试环境
+    #[serde(default)]
+    pub staging: bool,
+    /// 同时为所有 domain_proxy 的域名签发证书
+    #[serde(default = "default_true")]
+    pub include_proxy_domains: bool,
+    /// 到期前多少天开始续期
+    #[serde(default = "default_renew_before_days")]
+    pub renew_before_days: u32,
+    /// 等待 TXT 记录生效的最长时间
+    #[serde(default = "default_dns_propagation_timeout_secs")]
+    pub dns_propagation_timeout_secs: u64,
+    /// dns_01 用的 Cloudflare 凭据，留空时使用 one_key_change_ip 里的配置
+    #[serde(default)]
+    pub cloudflare_api_token: String,
+    #[serde(default)]
+    pub cloudflare_zone_id: String,
+    /// 自带的 PEM 证书（如企业 CA 签发），按 SNI 选择，文件变化后自动重新加载
+    #[serde(default)]
+    pub static_certs: Vec<StaticCertConfig>,
+    /// 这些域名要求客户端证书 (mTLS)
+    #[serde(default)]
+    pub client_auth: Vec<ClientAuthConfig>,
+    /// 开启 HTTP/3 (QUIC)，需要 http3 feature，响应中会带上 Alt-Svc
+    #[serde(default)]
+    pub http3: bool,
+    /// HTTP/3 监听的 UDP 端口，默认与 https_port 相同
+    #[serde(default)]
+    pub http3_port: Option<u16>,
+}
+
+impl HttpsCert {
+    /// HTTP/3 的 UDP 端口，未开启时为 None
+    pub fn http3_port(&self) -> Option<u16> {
+        self.http3
+            .then(|| self.http3_port.unwrap_or(self.https_port))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StaticCertConfig {
+    /// 支持 `*.example.com`
+    pub hosts: Vec<String>,
+    /// 证书链和私钥路径，相对路径基于 DATA_DIR
+    pub cert: String,
+    pub key: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ClientAuthConfig {
+    pub hosts: Vec<String>,
+    /// 签发客户端证书的 CA，相对路径基于 DATA_DIR
+    pub ca: String,
+}
+
+impl Default for HttpsCert {
+    fn default() -> Self {
+        Self {
+            https_port: 0,
+            auto_redirect: false,
+            domains: Vec::new(),
+            emails: Vec::new(),
+            mode: CertMode::default(),
+            challenge: CertChallenge::default(),
+            staging: false,
+            include_proxy_domains: default_true(),
+            renew_before_days: default_renew_before_days(),
+            dns_propagation_timeout_secs: default_dns_propagation_timeout_secs(),
+            cloudflare_api_token: String::new(),
+            cloudflare_zone_id: String::new(),
+            static_certs: Vec::new(),
+            client_auth: Vec::new(),
+            http3: false,
+            http3_port: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CertMode {
+    #[default]
+    Acme,
+    LocalCa,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CertChallenge {
+    #[default]
+    TlsAlpn01,
+    Dns01,
+}
+
+fn default_renew_before_days() -> u32 {
+    30
+}
+
+fn default_dns_propagation_timeout_secs() -> u64 {
+    300
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -696,6 +1377,73 @@ pub fn save_config_file(content: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// 用 `value` 替换 config.toml 内容中的 `[name]` 表及其子表 (`[name.xxx]`), 替换后的表追加到末尾
+/// 其余内容和注释原样保留, 用于把运行时的修改写回配置文件
+pub fn replace_config_section<T: Serialize>(
+    content: &str,
+    name: &str,
+    value: &T,
+) -> anyhow::Result<String> {
+    let sub_table = format!("{}.", name);
+    let mut kept = String::new();
+    // 表末尾的注释和空行, 紧跟着下一个表头时属于下一个表
+    let mut pending = String::new();
+    let mut in_root = true;
+    let mut in_section = false;
+    for line in content.lines() {
+        if let Some(table) = table_header(line) {
+            in_root = false;
+            in_section = table == name || table.starts_with(&sub_table);
+            if !in_section {
+                kept.push_str(&pending);
+            }
+            pending.clear();
+        } else if in_root {
+            // 写在根表里的 `name = {..}` / `name.xxx = ..` 无法按表替换
+            let key: String = line
+                .split('=')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect();
+            if key == name || key.starts_with(&sub_table) {
+                return Err(anyhow!("`{}` must be written as a [{}] table", name, name));
+            }
+        }
+        let trimmed = line.trim();
+        if !in_section {
+            kept.push_str(line);
+            kept.push('\n');
+        } else if trimmed.is_empty() || trimmed.starts_with('#') {
+            pending.push_str(line);
+            pending.push('\n');
+        } else {
+            pending.clear();
+        }
+    }
+
+    let section = toml::to_string(&BTreeMap::from([(name, value)]))?;
+    Ok(format!("{}\n\n{}", kept.trim_end(), section))
+}
+
+/// `[a.b]` 或 `[[a.b]]` 这样的表头中的表名 (去掉空白), 其他行返回 None
+fn table_header(line: &str) -> Option<String> {
+    let line = line.split('#').next()?.trim();
+    let inner = line
+        .strip_prefix("[[")
+        .and_then(|l| l.strip_suffix("]]"))
+        .or_else(|| line.strip_prefix('[').and_then(|l| l.strip_suffix(']')))?;
+    // 多行数组里的 `[1, 2],` 之类不是表头
+    if inner.is_empty()
+        || !inner
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-. \"'".contains(c))
+    {
+        return None;
+    }
+    Some(inner.split_whitespace().collect())
+}
+
 pub fn get_config_path() -> anyhow::Result<String> {
     let file_path = format!("config.toml");
     let final_path = Path::new(env::var(DATA_DIR)?.as_str()).join(file_path.as_str());
@@ -732,6 +1480,54 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_replace_config_section() -> anyhow::Result<()> {
+        let content = r#"server_port = 3000
+dns_servers = [
+    [1, 2],
+]
+
+[frp_server]
+enabled = true # 注释
+bind_addr = "0.0.0.0:2333"
+
+[frp_server.services.old]
+bind_addr = "0.0.0.0:8081"
+
+# 数据库
+[database]
+url = ":memory:"
+"#;
+        let mut services = BTreeMap::new();
+        services.insert(
+            "new".to_string(),
+            FrpServerServiceConfig {
+                bind_addr: "0.0.0.0:8082".to_string(),
+                ..Default::default()
+            },
+        );
+        let frp = FrpServerConfig {
+            enabled: true,
+            bind_addr: "0.0.0.0:2333".to_string(),
+            default_token: Some("token".to_string()),
+            services,
+            transport: FrpTransportConfig::default(),
+            heartbeat_interval: 30,
+        };
+
+        let replaced = replace_config_section(content, "frp_server", &frp)?;
+        assert!(replaced.starts_with("server_port = 3000\ndns_servers = [\n    [1, 2],\n]\n"));
+        assert!(replaced.contains("# 数据库\n[database]"));
+        assert!(!replaced.contains("# 注释"));
+        let table: toml::Table = toml::from_str(&replaced)?;
+        assert_eq!(table["database"]["url"].as_str(), Some(":memory:"));
+        let services = table["frp_server"]["services"].as_table().unwrap();
+        assert!(services.contains_key("new") && !services.contains_key("old"));
+        assert_eq!(table["frp_server"]["default_token"].as_str(), Some("token"));
+        assert!(replace_config_section("frp_server = {}", "frp_server", &frp).is_err());
+        Ok(())
+    }
+
     #[test]
     fn parses_one_key_change_ip_config() {
         let content = r#"
@@ -769,4 +1565,45 @@ proxied = false
         assert!(config.one_key_change_ip.cloudflare_dns_records[0].proxied);
         assert!(!config.one_key_change_ip.cloudflare_dns_records[1].proxied);
     }
+
+    #[test]
+    fn parses_mcp_hub_config() {
+        let content = r#"
+server_port = 3000
+log_level = "DEBUG"
+
+[database]
+url = ":memory:"
+
+[[mcp_hub.servers]]
+name = "fs"
+transport = "stdio"
+command = "npx"
+args = ["-y", "@modelcontextprotocol/server-filesystem", "/tmp"]
+env = { NODE_ENV = "production" }
+
+[[mcp_hub.servers]]
+name = "remote"
+prefix = "r:"
+transport = "http"
+url = "https://example.com/mcp"
+headers = { Authorization = "Bearer x" }
+timeout_secs = 10
+"#;
+
+        let config: Config = toml::from_str(content).unwrap();
+        let servers = &config.mcp_hub.servers;
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].prefix(), "fs.");
+        assert_eq!(servers[0].timeout_secs, 30);
+        assert!(matches!(
+            &servers[0].transport,
+            McpHubTransport::Stdio { args, env, .. } if args.len() == 3 && env["NODE_ENV"] == "production"
+        ));
+        assert_eq!(servers[1].prefix(), "r:");
+        assert_eq!(servers[1].timeout_secs, 10);
+        assert!(
+            matches!(&servers[1].transport, McpHubTransport::Http { headers, .. } if headers.len() == 1)
+        );
+    }
 }
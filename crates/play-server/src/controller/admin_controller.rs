@@ -40,8 +40,13 @@ use crate::config::{
     get_config_path, read_config_file, save_config_file, CloudflareDnsRecordConfig, Config,
     OneKeyChangeIpConfig,
 };
+use crate::service::asset_store_service;
+use crate::service::upstream_service;
+use crate::service::upstream_service::PoolStatus;
 use crate::tables::change_log::ChangeLog;
-use crate::{data_dir, files_dir, method_router, promise, return_error, template, HTML, R, S};
+use crate::{
+    data_dir, files_dir, method_router, promise, return_error, template, HTML, JSON, R, S,
+};
 
 // Create the init function manually to handle conditional compilation
 pub fn init() -> axum::Router<std::sync::Arc<crate::AppState>> {
@@ -65,6 +70,8 @@ pub fn init() -> axum::Router<std::sync::Arc<crate::AppState>> {
     );
     router = router.route("/admin/restore", axum::routing::post(restore));
     router = router.route("/admin/logs", axum::routing::get(display_logs));
+    router = router.route("/admin/upstreams", axum::routing::get(upstream_status));
+    router = router.route("/admin/mcp-hub", axum::routing::get(mcp_hub_status));
     router = router.route(
         "/admin/clean-change-logs",
         axum::routing::get(clean_change_logs),
@@ -72,6 +79,64 @@ pub fn init() -> axum::Router<std::sync::Arc<crate::AppState>> {
     router = router.route("/admin/translator", axum::routing::get(translator_page));
     router = router.route("/admin/translate", axum::routing::post(translate_text));
 
+    #[cfg(feature = "play-https")]
+    {
+        router = router.route("/admin/certs", axum::routing::get(cert_status));
+        router = router.route("/admin/certs/renew", axum::routing::post(renew_cert));
+        router = router.route(
+            "/admin/certs/local-ca.pem",
+            axum::routing::get(local_ca_pem),
+        );
+    }
+
+    #[cfg(feature = "play-integration-xiaozhi")]
+    {
+        router = router.route("/admin/xiaozhi", axum::routing::get(xiaozhi_status));
+    }
+
+    #[cfg(feature = "frp-server")]
+    {
+        router = router.route("/admin/frp", axum::routing::get(frp_status));
+        router = router.route("/admin/frp/services", axum::routing::post(save_frp_service));
+        router = router.route(
+            "/admin/frp/services/{name}",
+            axum::routing::delete(remove_frp_service),
+        );
+        router = router.route(
+            "/admin/frp/rotate-token",
+            axum::routing::post(rotate_frp_token),
+        );
+    }
+
+    #[cfg(feature = "ikev2-server")]
+    {
+        router = router.route(
+            "/admin/ikev2/users",
+            axum::routing::get(ikev2_users).post(save_ikev2_user),
+        );
+        router = router.route(
+            "/admin/ikev2/users/{name}",
+            axum::routing::delete(remove_ikev2_user),
+        );
+        router = router.route(
+            "/admin/ikev2/reload",
+            axum::routing::post(reload_ikev2_users),
+        );
+        router = router.route(
+            "/admin/ikev2/users/{name}/profile-link",
+            axum::routing::post(create_ikev2_profile_link),
+        );
+        router = router.route(
+            "/ikev2/profile/{token}",
+            axum::routing::get(download_ikev2_profile),
+        );
+        router = router.route("/admin/ikev2/sas", axum::routing::get(ikev2_sas));
+        router = router.route(
+            "/admin/ikev2/sas/{id}",
+            axum::routing::delete(disconnect_ikev2_sa),
+        );
+    }
+
     #[cfg(feature = "play-dylib-loader")]
     {
         router = router.route(
@@ -86,6 +151,38 @@ pub fn init() -> axum::Router<std::sync::Arc<crate::AppState>> {
             "/admin/store-request-info",
             axum::routing::post(store_request_info),
         );
+        router = router.route(
+            "/admin/plugins",
+            axum::routing::get(plugin_list).post(save_plugin),
+        );
+        router = router.route(
+            "/admin/plugins/{name}",
+            axum::routing::delete(remove_plugin),
+        );
+        router = router.route(
+            "/admin/plugins/{name}/upload",
+            axum::routing::post(upload_plugin),
+        );
+        router = router.route(
+            "/admin/plugins/{name}/enable",
+            axum::routing::post(enable_plugin),
+        );
+        router = router.route(
+            "/admin/plugins/{name}/disable",
+            axum::routing::post(disable_plugin),
+        );
+        router = router.route(
+            "/admin/plugins/{name}/reload",
+            axum::routing::post(reload_plugin),
+        );
+        router = router.route(
+            "/admin/plugins/{name}/rollback",
+            axum::routing::post(rollback_plugin),
+        );
+        router = router.route(
+            "/admin/plugins/{name}/logs",
+            axum::routing::get(plugin_logs),
+        );
     }
 
     router
@@ -105,6 +202,55 @@ struct UpgradeRequest {
     url: Option<String>,
 }
 
+#[cfg(feature = "play-https")]
+#[derive(Deserialize)]
+struct RenewCertReq {
+    domain: String,
+}
+
+#[cfg(feature = "frp-server")]
+#[derive(Deserialize)]
+struct SaveFrpServiceReq {
+    name: String,
+    #[serde(flatten)]
+    service: crate::config::FrpServerServiceConfig,
+}
+
+/// rotate `default_token` when `service` is missing.
+#[cfg(feature = "frp-server")]
+#[derive(Deserialize)]
+struct RotateFrpTokenReq {
+    #[serde(default)]
+    service: Option<String>,
+}
+
+/// `password` may be left out to only enable or disable an existing user.
+#[cfg(feature = "ikev2-server")]
+#[derive(Deserialize)]
+struct SaveIkev2UserReq {
+    name: String,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    disabled: Option<bool>,
+}
+
+#[cfg(feature = "ikev2-server")]
+#[derive(Deserialize)]
+struct CreateIkev2ProfileLinkReq {
+    format: crate::ikev2_profile::ProfileFormat,
+    #[serde(default)]
+    client_cert: bool,
+}
+
+/// the version before the current one when `version` is missing.
+#[cfg(feature = "play-dylib-loader")]
+#[derive(Deserialize)]
+struct RollbackPluginReq {
+    #[serde(default)]
+    version: Option<u32>,
+}
+
 #[derive(Deserialize)]
 struct SaveConfigReq {
     new_content: String,
@@ -215,10 +361,16 @@ fn default_days() -> u32 {
     3
 }
 
+/// for plugins of the request id ABI, they take the request json from before ABI v4.
 #[cfg(feature = "play-dylib-loader")]
 async fn get_request_info(Query(RequestIdQuery { request_id }): Query<RequestIdQuery>) -> Response {
+    use play_dylib_loader::LegacyHttpRequest;
+
     match get_request(request_id) {
-        Some(request) => Json(request).into_response(),
+        Some(request) => match LegacyHttpRequest::try_from(&request) {
+            Ok(request) => Json(request).into_response(),
+            Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        },
         None => (
             StatusCode::NOT_FOUND,
             format!("Request with id {} not found", request_id),
@@ -239,11 +391,129 @@ async fn push_response_info(
 #[cfg(feature = "play-dylib-loader")]
 async fn store_request_info(
     Query(RequestIdQuery { request_id }): Query<RequestIdQuery>,
-    Json(request): Json<play_dylib_loader::HttpRequest>,
+    Json(request): Json<play_dylib_loader::LegacyHttpRequest>,
 ) -> Response {
-    play_dylib_loader::store_request(request_id, request);
+    play_dylib_loader::store_request(request_id, request.into());
     (StatusCode::OK, "Request stored successfully").into_response()
 }
+#[cfg(feature = "play-dylib-loader")]
+async fn plugin_list() -> JSON<Vec<crate::plugins::PluginStatus>> {
+    Ok(Json(crate::plugins::list()))
+}
+
+/// `file_path` may be left out to keep the current file, or to upload one later.
+#[cfg(feature = "play-dylib-loader")]
+async fn save_plugin(
+    s: S,
+    Json(req): Json<crate::config::PluginConfig>,
+) -> JSON<Vec<crate::plugins::PluginStatus>> {
+    crate::plugins::save(&s.db, &s.config.plugin_config, req).await?;
+    plugin_list().await
+}
+
+/// takes the file from the `file` field and switches the plugin to it.
+#[cfg(feature = "play-dylib-loader")]
+async fn upload_plugin(
+    s: S,
+    axum::extract::Path(name): axum::extract::Path<String>,
+    mut multipart: Multipart,
+) -> JSON<crate::plugins::PluginVersion> {
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let file_name = field.file_name().unwrap_or_default().to_string();
+        let content = field.bytes().await?;
+        let version =
+            crate::plugins::upload(&s.db, &s.config.plugin_config, &name, &file_name, &content)
+                .await?;
+        return Ok(Json(version));
+    }
+    return_error!("the `file` field is missing");
+}
+
+#[cfg(feature = "play-dylib-loader")]
+async fn enable_plugin(
+    s: S,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> JSON<Vec<crate::plugins::PluginStatus>> {
+    crate::plugins::set_disabled(&s.db, &s.config.plugin_config, &name, false).await?;
+    plugin_list().await
+}
+
+#[cfg(feature = "play-dylib-loader")]
+async fn disable_plugin(
+    s: S,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> JSON<Vec<crate::plugins::PluginStatus>> {
+    crate::plugins::set_disabled(&s.db, &s.config.plugin_config, &name, true).await?;
+    plugin_list().await
+}
+
+#[cfg(feature = "play-dylib-loader")]
+async fn reload_plugin(
+    s: S,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> JSON<Vec<crate::plugins::PluginStatus>> {
+    crate::plugins::reload(&s.db, &s.config.plugin_config, &name).await?;
+    plugin_list().await
+}
+
+#[cfg(feature = "play-dylib-loader")]
+async fn rollback_plugin(
+    s: S,
+    axum::extract::Path(name): axum::extract::Path<String>,
+    Query(req): Query<RollbackPluginReq>,
+) -> JSON<Vec<crate::plugins::PluginStatus>> {
+    crate::plugins::rollback(&s.db, &s.config.plugin_config, &name, req.version).await?;
+    plugin_list().await
+}
+
+#[cfg(feature = "play-dylib-loader")]
+async fn remove_plugin(
+    s: S,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> JSON<Vec<crate::plugins::PluginStatus>> {
+    crate::plugins::remove(&s.db, &s.config.plugin_config, &name).await?;
+    plugin_list().await
+}
+
+/// the kept output of a server plugin, then the new lines as they come.
+#[cfg(feature = "play-dylib-loader")]
+async fn plugin_logs(
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> R<impl IntoResponse> {
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use futures_util::{stream, StreamExt};
+    use tokio::sync::broadcast::error::RecvError;
+
+    let Some(logs) = crate::plugin_supervisor::logs(&name) else {
+        return_error!(
+            "plugin `{}` has no logs, only running server plugins have",
+            name
+        );
+    };
+    let to_event = |line: &crate::plugin_supervisor::LogLine| {
+        Event::default()
+            .event(line.stream)
+            .data(serde_json::to_string(line).unwrap_or_default())
+    };
+    let (lines, rx) = logs.subscribe();
+    let kept = stream::iter(lines.iter().map(to_event).collect::<Vec<_>>());
+    let new = stream::unfold(rx, move |mut rx| async move {
+        match rx.recv().await {
+            Ok(line) => Some((to_event(&line), rx)),
+            // a slow client missed lines
+            Err(RecvError::Lagged(n)) => {
+                Some((Event::default().event("lagged").data(n.to_string()), rx))
+            }
+            Err(RecvError::Closed) => None,
+        }
+    });
+    let events = kept.chain(new).map(Ok::<_, std::convert::Infallible>);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 async fn clean_change_logs(
     s: S,
     Query(DeleteChangelogReq { days }): Query<DeleteChangelogReq>,
@@ -285,6 +555,167 @@ async fn display_logs(s: S) -> HTML {
     Ok(Html(converted))
 }
 
+async fn upstream_status() -> JSON<Vec<PoolStatus>> {
+    Ok(Json(upstream_service::pools_status()))
+}
+
+async fn mcp_hub_status() -> JSON<Vec<crate::mcp_hub::HubServerStatus>> {
+    Ok(Json(crate::mcp_hub::status()))
+}
+
+#[cfg(feature = "play-integration-xiaozhi")]
+async fn xiaozhi_status() -> JSON<Vec<play_integration_xiaozhi::EndpointStatus>> {
+    Ok(Json(play_integration_xiaozhi::endpoints_status()))
+}
+
+#[cfg(feature = "play-https")]
+async fn cert_status() -> JSON<Vec<play_https::CertStatus>> {
+    Ok(Json(play_https::cert_status()))
+}
+
+#[cfg(feature = "play-https")]
+async fn renew_cert(Json(req): Json<RenewCertReq>) -> JSON<play_https::CertStatus> {
+    Ok(Json(play_https::renew_certificate(&req.domain).await?))
+}
+
+/// trust this CA on clients when `https_cert.mode = "local_ca"`.
+#[cfg(feature = "play-https")]
+async fn local_ca_pem() -> R<impl IntoResponse> {
+    let Some(pem) = play_https::local_ca_pem() else {
+        return_error!("local CA is not enabled");
+    };
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, "application/x-pem-file"),
+            (
+                http::header::CONTENT_DISPOSITION,
+                "attachment; filename=\"play-local-ca.pem\"",
+            ),
+        ],
+        pem,
+    ))
+}
+
+#[cfg(feature = "frp-server")]
+async fn frp_status() -> JSON<crate::frp::FrpStatus> {
+    let Some(status) = crate::frp::status().await else {
+        return_error!("FRP server is not running");
+    };
+    Ok(Json(status))
+}
+
+/// also saved to config.toml, so the change survives a restart.
+#[cfg(feature = "frp-server")]
+async fn save_frp_service(Json(req): Json<SaveFrpServiceReq>) -> JSON<crate::frp::FrpStatus> {
+    crate::frp::upsert_service(&req.name, req.service).await?;
+    frp_status().await
+}
+
+#[cfg(feature = "frp-server")]
+async fn remove_frp_service(
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> JSON<crate::frp::FrpStatus> {
+    crate::frp::remove_service(&name).await?;
+    frp_status().await
+}
+
+/// the new token is only shown in this response.
+#[cfg(feature = "frp-server")]
+async fn rotate_frp_token(Json(req): Json<RotateFrpTokenReq>) -> JSON<Value> {
+    let token = crate::frp::rotate_token(req.service.as_deref()).await?;
+    Ok(Json(json!({ "token": token })))
+}
+
+#[cfg(feature = "ikev2-server")]
+async fn ikev2_users(s: S) -> JSON<Value> {
+    let users = crate::ikev2::list_users(&s.db).await?;
+    Ok(Json(json!({
+        "running": crate::ikev2::is_running(),
+        "users": users,
+    })))
+}
+
+#[cfg(feature = "ikev2-server")]
+async fn save_ikev2_user(s: S, Json(req): Json<SaveIkev2UserReq>) -> JSON<Value> {
+    crate::ikev2::save_user(&s.db, &req.name, req.password.as_deref(), req.disabled).await?;
+    ikev2_users(s).await
+}
+
+/// the user's connected clients are disconnected as well.
+#[cfg(feature = "ikev2-server")]
+async fn remove_ikev2_user(
+    s: S,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> JSON<Value> {
+    crate::ikev2::remove_user(&s.db, &name).await?;
+    ikev2_users(s).await
+}
+
+#[cfg(feature = "ikev2-server")]
+async fn reload_ikev2_users(s: S) -> JSON<Value> {
+    if !crate::ikev2::reload_users(&s.db).await? {
+        return_error!("IKEv2 server is not running");
+    }
+    ikev2_users(s).await
+}
+
+#[cfg(feature = "ikev2-server")]
+async fn create_ikev2_profile_link(
+    s: S,
+    axum::extract::Path(name): axum::extract::Path<String>,
+    Json(req): Json<CreateIkev2ProfileLinkReq>,
+) -> JSON<crate::ikev2_profile::CreatedProfileLink> {
+    let link = crate::ikev2_profile::create_link(
+        &s.config.ikev2_server,
+        &s.db,
+        &name,
+        req.format,
+        req.client_cert,
+    )
+    .await?;
+    Ok(Json(link))
+}
+
+/// public, the token is removed by the first download.
+#[cfg(feature = "ikev2-server")]
+async fn download_ikev2_profile(
+    s: S,
+    axum::extract::Path(token): axum::extract::Path<String>,
+) -> R<Response> {
+    let Some(file) = crate::ikev2_profile::take_link(&s.config.ikev2_server, &s.db, &token).await?
+    else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            "this profile link is invalid, expired or already used",
+        )
+            .into_response());
+    };
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, file.content_type.to_string()),
+            (
+                http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file.file_name),
+            ),
+        ],
+        file.content,
+    )
+        .into_response())
+}
+
+#[cfg(feature = "ikev2-server")]
+async fn ikev2_sas() -> JSON<Vec<crate::ikev2::Ikev2Sa>> {
+    Ok(Json(crate::ikev2::list_sas().await?))
+}
+
+#[cfg(feature = "ikev2-server")]
+async fn disconnect_ikev2_sa(
+    axum::extract::Path(id): axum::extract::Path<u64>,
+) -> JSON<Vec<crate::ikev2::Ikev2Sa>> {
+    crate::ikev2::disconnect(id).await?;
+    ikev2_sas().await
+}
+
 async fn save_config(s: S, Form(req): Form<SaveConfigReq>) -> R<String> {
     toml::from_str::<Config>(&req.new_content)?;
     save_config_file(&req.new_content)?;
@@ -300,6 +731,17 @@ async fn reboot() -> R<String> {
     Ok("will reboot in a sec.".to_string())
 }
 
+/// the files dir, db and config, plus the asset store holding the content of
+/// files kept as asset pointers.
+fn backup_items(files_path: PathBuf, db_path: PathBuf, config_file_path: PathBuf) -> Vec<PathBuf> {
+    let mut items = vec![files_path, db_path, config_file_path];
+    let store_dir = asset_store_service::store_dir();
+    if store_dir.exists() {
+        items.push(store_dir);
+    }
+    items
+}
+
 async fn backup(s: S) -> R<impl IntoResponse> {
     let files_path = files_dir!();
 
@@ -318,7 +760,7 @@ async fn backup(s: S) -> R<impl IntoResponse> {
     let config_file_path = get_config_path()?;
 
     fs_extra::copy_items(
-        &vec![files_path, db_path, config_file_path.into()],
+        &backup_items(files_path, db_path, config_file_path.into()),
         &folder_path,
         &CopyOptions {
             copy_inside: true,
@@ -386,7 +828,7 @@ async fn backup_encrypted_to_cloud(s: S) -> R<String> {
             let config_file_path = get_config_path()?;
 
             fs_extra::copy_items(
-                &vec![files_path, db_path, config_file_path.into()],
+                &backup_items(files_path, db_path, config_file_path.into()),
                 &folder_path,
                 &CopyOptions {
                     copy_inside: true,
@@ -1407,7 +1849,7 @@ async fn backup_encrypted(s: S) -> R<impl IntoResponse> {
     let config_file_path = get_config_path()?;
 
     fs_extra::copy_items(
-        &vec![files_path, db_path, config_file_path.into()],
+        &backup_items(files_path, db_path, config_file_path.into()),
         &folder_path,
         &CopyOptions {
             copy_inside: true,
@@ -1,9 +1,10 @@
-use crate::{files_dir, get_file_modify_time, method_router, HTML, S};
+use crate::service::http_cache_service::{self, CachePurge, PurgeResult};
+use crate::{files_dir, get_file_modify_time, method_router, HTML, JSON, S};
 use anyhow::{bail, ensure};
 use axum::body::HttpBody;
 use axum::extract::Query;
 use axum::response::Html;
-use axum::Form;
+use axum::{Form, Json};
 use http::Uri;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -16,6 +17,7 @@ method_router!(
     get : "/cache/html" -> cache_html,
     post : "/cache/save" -> save_cache,
     post : "/cache/delete" -> delete_cache,
+    post : "/cache/purge" -> purge_cache,
 );
 
 #[derive(Deserialize)]
@@ -99,6 +101,13 @@ async fn delete_cache(Form(param): Form<DeleteCacheParam>) -> HTML {
 
     Ok(Html("Ok.".to_string()))
 }
+
+// 同时清除本地 http 缓存和 cloudflare 缓存
+async fn purge_cache(s: S, Json(param): Json<CachePurge>) -> JSON<PurgeResult> {
+    let result = http_cache_service::purge(&s.config.cache_config, &param).await?;
+    Ok(Json(result))
+}
+
 #[cfg(feature = "play-cache")]
 async fn update_cache_in_remote(s: S, param: &CacheRequestParam) -> anyhow::Result<()> {
     //upload to remote server
@@ -125,15 +134,13 @@ async fn update_cache_in_remote(s: S, param: &CacheRequestParam) -> anyhow::Resu
         resp.status()
     );
 
-    //delete cf cache
-    let resp = client
-        .post(&s.config.cache_config.cf_purge_cache_url)
-        .header("Authorization", &s.config.cache_config.cf_token)
-        // .json(&json!({"files": [&param.url]}))
-        .json(&json!({"purge_everything": true}))
-        .send()
-        .await?;
-    info!("delete cf cache : resp: {}", resp.status());
+    //delete cf cache and local http cache
+    let purge_everything = CachePurge {
+        everything: true,
+        ..Default::default()
+    };
+    let result = http_cache_service::purge(&s.config.cache_config, &purge_everything).await?;
+    info!("delete cf cache : {:?}", result);
 
     tokio::time::sleep(Duration::from_secs(5)).await;
 
@@ -157,14 +164,8 @@ async fn update_cache_in_remote(s: S, param: &CacheRequestParam) -> anyhow::Resu
     );
 
     //delete cf cache (again to make sure cache is latest)
-    let resp = client
-        .post(&s.config.cache_config.cf_purge_cache_url)
-        .header("Authorization", &s.config.cache_config.cf_token)
-        // .json(&json!({"files": [&param.url]}))
-        .json(&json!({"purge_everything": true}))
-        .send()
-        .await?;
-    info!("delete cf cache again : resp: {}", resp.status());
+    let result = http_cache_service::purge(&s.config.cache_config, &purge_everything).await?;
+    info!("delete cf cache again : {:?}", result);
 
     tokio::time::sleep(Duration::from_secs(5)).await;
 
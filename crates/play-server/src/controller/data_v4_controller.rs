@@ -121,7 +121,8 @@ fn default_limit() -> LimitParam {
     LimitParam((0, NonZeroU32::new(10).unwrap()))
 }
 
-const SYSTEM_FIELDS: [&str; 6] = ["id", "cat", "data", "is_deleted", "created", "updated"];
+pub(crate) const SYSTEM_FIELDS: [&str; 6] =
+    ["id", "cat", "data", "is_deleted", "created", "updated"];
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
@@ -153,7 +154,7 @@ struct CountResp {
     rows: u32,
 }
 
-fn parse_and_convert_to_json_extract(condition_str: &str) -> String {
+pub(crate) fn parse_and_convert_to_json_extract(condition_str: &str) -> String {
     // 存储条件和连接操作符
     let mut result = Vec::new();
     let mut current_part = String::new();
@@ -302,7 +303,7 @@ fn check_action_valid(action: &str) -> Result<()> {
     Ok(())
 }
 
-fn check_category_valid(category: &str) -> Result<()> {
+pub(crate) fn check_category_valid(category: &str) -> Result<()> {
     ensure!(
         Regex::new(r"^[a-zA-Z0-9-_]{2,20}$")?.is_match(&category),
         "invalid `category` path : {} , not match with : {}",
@@ -623,7 +624,11 @@ fn parse_set_string_to_hashmap(input: &str) -> HashMap<String, String> {
     result
 }
 
-async fn insert_data(s: S, Path(cat): Path<String>, body: String) -> Result<GeneralData> {
+pub(crate) async fn insert_data(
+    s: S,
+    Path(cat): Path<String>,
+    body: String,
+) -> Result<GeneralData> {
     //validation
     // ensure!(!vec!["id", "data","get","update","delete","list", "query"].contains(&cat.as_str()), "please use another category name ! ");
     // check!(serde_json::from_str::<Value>(&body).is_ok());
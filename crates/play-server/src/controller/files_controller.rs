@@ -1,42 +1,69 @@
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, ensure, Context};
 use std::error::Error;
 use std::io;
 use std::io::{Cursor, Write};
 use std::path::{Component, PathBuf};
 use std::time::UNIX_EPOCH;
 
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
 use axum::body::{Body, Bytes, HttpBody};
 use axum::extract::{Path, Query};
 use axum::response::{IntoResponse, Response};
 use axum::{BoxError, Json};
 use chrono::{DateTime, Local};
-use futures::Stream;
+use futures::{AsyncWriteExt, Stream, StreamExt};
 use futures_util::TryStreamExt;
-use http::{header, HeaderValue, StatusCode};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
 use infer::Infer;
 use serde::{Deserialize, Serialize};
 use sqlx::Row;
 use tokio::fs;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, BufWriter};
+use tokio::io::{AsyncReadExt, BufWriter, DuplexStream};
 use tokio_util::codec::{BytesCodec, FramedRead};
-use tokio_util::io::StreamReader;
-use tracing::info;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::{error, info};
+use uuid::Uuid;
 
 use play_shared::{current_timestamp, file_path};
 
+use crate::config::{FileStorageBackend, FileStorageConfig};
+use crate::controller::cache_controller::CACHE_FOLDER;
 use crate::extractor::custom_file_upload::CustomFileExtractor;
-use crate::{data_dir, files_dir, method_router, return_error, JSON, R};
+use crate::service::asset_store_service;
+use crate::service::file_serve_service;
+use crate::service::image_service;
+use crate::service::image_service::{ImageTransform, THUMB_FOLDER};
+use crate::{data_dir, files_dir, method_router, return_error, JSON, R, S};
 
 method_router!(
     post : "/files/upload" -> upload_file,
     put : "/files/upload" -> upload_file,
     get : "/files/{*path}" -> download_file,
     delete : "/files/{*path}" -> delete_file,
-    get : "/files/packed" -> pack_files,
+    get : "/files/_api/packed" -> pack_files,
+    get : "/files/_api/list" -> list_dir,
+    get : "/files/_api/search" -> search_files,
+    post : "/files/_api/mkdir" -> make_dir,
+    post : "/files/_api/move" -> move_file,
+    post : "/files/_api/rename" -> rename_file,
+    post : "/files/_api/copy" -> copy_file,
+    get : "/files/_api/trash" -> list_trash,
+    delete : "/files/_api/trash" -> empty_trash,
+    post : "/files/_api/trash/restore" -> restore_trash,
     get : "/files" -> list_files,
 );
 
+/// file operations live under `/files/_api/`, so no user file can be named like one of them;
+/// a top level `_api` entry would be unreachable and is refused.
+const API_FOLDER: &'static str = "_api";
+
+/// deleted files are moved here (unless `permanent=true`), one sub folder per deletion.
+pub(crate) const TRASH_FOLDER: &'static str = "__trash__";
+const TRASH_META_FILE: &'static str = "trash.json";
+
 #[derive(Serialize, Debug)]
 struct FileInfo {
     filename: String,
@@ -63,7 +90,7 @@ async fn list_files() -> JSON<Vec<FileInfo>> {
                             files_info.push(FileInfo {
                                 filename,
                                 modify_time,
-                                size: metadata.len(),
+                                size: content_size(&path, &metadata),
                             });
                         }
                     }
@@ -78,35 +105,449 @@ async fn list_files() -> JSON<Vec<FileInfo>> {
     Ok(Json(files_info))
 }
 
-async fn pack_files() -> R<impl IntoResponse> {
-    let folder_path = files_dir!();
-    let target_file = data_dir!().join("packed_files.zip");
-    if target_file.exists() {
-        fs::remove_file(&target_file).await?;
+#[derive(Serialize, Debug)]
+struct EntryInfo {
+    name: String,
+    /// path relative to files dir, e.g. "images/2024/a.png"
+    path: String,
+    is_dir: bool,
+    size: u64,
+    modify_time: i64,
+    /// number of direct children (directories only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ListOption {
+    #[serde(default)]
+    dir: String,
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default = "default_page")]
+    page: usize,
+    #[serde(default = "default_page_size")]
+    page_size: usize,
+}
+
+fn default_page() -> usize {
+    1
+}
+fn default_page_size() -> usize {
+    100
+}
+
+const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ListResult {
+    dir: String,
+    total: usize,
+    page: usize,
+    page_size: usize,
+    entries: Vec<EntryInfo>,
+}
+
+pub(crate) async fn list_dir(Query(option): Query<ListOption>) -> JSON<ListResult> {
+    let dir = resolve_files_path(&option.dir)?;
+    if !dir.is_dir() {
+        return_error!("directory not found : {}", option.dir);
     }
 
-    zip_dir(&folder_path, &target_file)?;
-    match File::open(&target_file).await {
-        Ok(file) => {
-            // 使用 FramedRead 和 BytesCodec 将文件转换为 Stream
-            let stream = FramedRead::new(file, BytesCodec::new())
-                .map_ok(|bytes| bytes.freeze())
-                .map_err(|e| {
-                    info!("File streaming error: {}", e);
-                    // 在流中发生错误时，将错误转换为 HTTP 500 状态码
-                    anyhow!("file stream error")
+    let recursive = option.recursive;
+    let files_root = files_dir!();
+    let mut entries = tokio::task::spawn_blocking(move || {
+        let max_depth = if recursive { usize::MAX } else { 1 };
+        WalkDir::new(&dir)
+            .min_depth(1)
+            .max_depth(max_depth)
+            .into_iter()
+            .filter_entry(|e| !is_reserved_entry(&files_root, e.path()))
+            .filter_map(|e| e.ok())
+            .filter_map(|e| entry_info(&files_root, e.path()))
+            .collect::<Vec<EntryInfo>>()
+    })
+    .await?;
+
+    // 目录在前，其余按修改时间倒序
+    entries.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then(b.modify_time.cmp(&a.modify_time))
+    });
+
+    let page = option.page.max(1);
+    let page_size = option.page_size.clamp(1, MAX_PAGE_SIZE);
+    let total = entries.len();
+    let entries = entries
+        .into_iter()
+        .skip((page - 1) * page_size)
+        .take(page_size)
+        .collect();
+
+    Ok(Json(ListResult {
+        dir: option.dir.trim_matches('/').to_string(),
+        total,
+        page,
+        page_size,
+        entries,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchOption {
+    #[serde(default)]
+    dir: String,
+    /// case-insensitive substring of the file name
+    #[serde(default)]
+    q: Option<String>,
+    /// glob pattern matched against the path relative to `dir`, e.g. "**/*.jpg"
+    #[serde(default)]
+    glob: Option<String>,
+    #[serde(default = "default_search_limit")]
+    limit: usize,
+}
+
+fn default_search_limit() -> usize {
+    200
+}
+
+async fn search_files(Query(option): Query<SearchOption>) -> JSON<Vec<EntryInfo>> {
+    let dir = resolve_files_path(&option.dir)?;
+    if !dir.is_dir() {
+        return_error!("directory not found : {}", option.dir);
+    }
+
+    let keyword = option.q.map(|q| q.to_lowercase()).filter(|q| !q.is_empty());
+    let pattern = match option.glob.filter(|g| !g.is_empty()) {
+        Some(g) => Some(glob::Pattern::new(&g).context("invalid glob pattern")?),
+        None => None,
+    };
+    if keyword.is_none() && pattern.is_none() {
+        return_error!("either `q` or `glob` is required");
+    }
+
+    let limit = option.limit.clamp(1, MAX_PAGE_SIZE);
+    let match_options = glob::MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+
+    let files_root = files_dir!();
+    let result = tokio::task::spawn_blocking(move || {
+        WalkDir::new(&dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|e| !is_reserved_entry(&files_root, e.path()))
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                let name_ok = keyword.as_ref().map_or(true, |k| {
+                    e.file_name().to_string_lossy().to_lowercase().contains(k)
                 });
+                let glob_ok = pattern.as_ref().map_or(true, |p| {
+                    p.matches_with(&relative_path(&dir, e.path()), match_options)
+                });
+                name_ok && glob_ok
+            })
+            .filter_map(|e| entry_info(&files_root, e.path()))
+            .take(limit)
+            .collect::<Vec<EntryInfo>>()
+    })
+    .await?;
+
+    Ok(Json(result))
+}
+
+#[derive(Deserialize, Debug)]
+struct MkdirParam {
+    path: String,
+}
+
+async fn make_dir(Json(param): Json<MkdirParam>) -> JSON<EntryInfo> {
+    let dir = resolve_files_path(&param.path)?;
+    if dir == files_dir!() {
+        return_error!("path is required");
+    }
+    fs::create_dir_all(&dir).await?;
+    Ok(Json(entry_info(&files_dir!(), &dir).ok_or_else(|| {
+        anyhow!("create dir failed : {}", param.path)
+    })?))
+}
+
+#[derive(Deserialize, Debug)]
+struct TransferParam {
+    from: String,
+    to: String,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+async fn move_file(Json(param): Json<TransferParam>) -> JSON<EntryInfo> {
+    let (from, to) = prepare_transfer(&param).await?;
+    fs::rename(&from, &to).await?;
+    image_service::remove_variants(&param.from).await;
+    info!("moved {:?} -> {:?}", from, to);
+    Ok(Json(
+        entry_info(&files_dir!(), &to).ok_or_else(|| anyhow!("move failed : {}", param.to))?,
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+struct RenameParam {
+    path: String,
+    new_name: String,
+}
+
+async fn rename_file(Json(param): Json<RenameParam>) -> JSON<EntryInfo> {
+    if param.new_name.is_empty() || param.new_name.contains('/') || param.new_name == ".." {
+        return_error!("invalid new name : {}", param.new_name);
+    }
+    let parent = match param.path.trim_matches('/').rsplit_once('/') {
+        Some((parent, _)) => format!("{}/", parent),
+        None => String::new(),
+    };
+    move_file(Json(TransferParam {
+        from: param.path.clone(),
+        to: format!("{}{}", parent, param.new_name),
+        overwrite: false,
+    }))
+    .await
+}
+
+async fn copy_file(Json(param): Json<TransferParam>) -> JSON<EntryInfo> {
+    let (from, to) = prepare_transfer(&param).await?;
+    let (src, dst) = (from.clone(), to.clone());
+    tokio::task::spawn_blocking(move || copy_recursively(&src, &dst)).await??;
+    info!("copied {:?} -> {:?}", from, to);
+    Ok(Json(
+        entry_info(&files_dir!(), &to).ok_or_else(|| anyhow!("copy failed : {}", param.to))?,
+    ))
+}
+
+/// validate source/target of a move or copy, clearing the target when `overwrite` is set.
+async fn prepare_transfer(param: &TransferParam) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let from = resolve_files_path(&param.from)?;
+    let to = resolve_files_path(&param.to)?;
+    let root = files_dir!();
+    ensure!(
+        from != root && to != root,
+        "can not move or copy the root dir"
+    );
+    ensure!(from.exists(), "source not found : {}", param.from);
+    ensure!(
+        !to.starts_with(&from),
+        "target is inside source : {}",
+        param.to
+    );
+
+    if to.exists() {
+        ensure!(param.overwrite, "target already exists : {}", param.to);
+        if to.is_dir() {
+            fs::remove_dir_all(&to).await?;
+        } else {
+            fs::remove_file(&to).await?;
+        }
+        image_service::remove_variants(&param.to).await;
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    Ok((from, to))
+}
+
+fn copy_recursively(from: &std::path::Path, to: &std::path::Path) -> anyhow::Result<()> {
+    if from.is_file() {
+        std::fs::copy(from, to)?;
+        return Ok(());
+    }
+    for entry in WalkDir::new(from) {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrashItem {
+    id: String,
+    original_path: String,
+    is_dir: bool,
+    deleted_at: i64,
+}
+
+async fn move_to_trash(rel_path: &str, path: &std::path::Path) -> anyhow::Result<TrashItem> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid path : {}", rel_path))?;
+    let deleted_at = current_timestamp!();
+    let id = format!(
+        "{}-{}",
+        deleted_at,
+        &Uuid::new_v4().simple().to_string()[..8]
+    );
+    let item_dir = files_dir!().join(TRASH_FOLDER).join(&id);
+    fs::create_dir_all(&item_dir).await?;
+
+    let item = TrashItem {
+        id,
+        original_path: rel_path.trim_matches('/').to_string(),
+        is_dir: path.is_dir(),
+        deleted_at,
+    };
+    fs::rename(path, item_dir.join(name)).await?;
+    fs::write(item_dir.join(TRASH_META_FILE), serde_json::to_vec(&item)?).await?;
+    Ok(item)
+}
+
+async fn list_trash() -> JSON<Vec<TrashItem>> {
+    let mut items = vec![];
+    if let Ok(mut entries) = fs::read_dir(files_dir!().join(TRASH_FOLDER)).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Ok(meta) = fs::read(entry.path().join(TRASH_META_FILE)).await {
+                if let Ok(item) = serde_json::from_slice::<TrashItem>(&meta) {
+                    items.push(item);
+                }
+            }
+        }
+    }
+    items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(Json(items))
+}
+
+#[derive(Deserialize, Debug)]
+struct RestoreParam {
+    id: String,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+async fn restore_trash(Json(param): Json<RestoreParam>) -> JSON<EntryInfo> {
+    let item_dir = resolve_files_path(&format!("{}/{}", TRASH_FOLDER, param.id))?;
+    let meta = fs::read(item_dir.join(TRASH_META_FILE))
+        .await
+        .with_context(|| format!("trash item not found : {}", param.id))?;
+    let item: TrashItem = serde_json::from_slice(&meta)?;
 
-            // In axum 0.8 we use Body::from_stream instead of StreamBody
-            let body = axum::body::Body::from_stream(stream);
-            Ok(Response::new(body))
+    let target = resolve_files_path(&item.original_path)?;
+    let name = target
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid original path : {}", item.original_path))?;
+    if target.exists() {
+        if !param.overwrite {
+            return_error!("target already exists : {}", item.original_path);
         }
-        Err(_) => {
-            // 文件无法打开时，返回 HTTP 404 状态码
-            return_error!("file not found!")
+        if target.is_dir() {
+            fs::remove_dir_all(&target).await?;
+        } else {
+            fs::remove_file(&target).await?;
         }
+        image_service::remove_variants(&item.original_path).await;
     }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::rename(item_dir.join(name), &target).await?;
+    fs::remove_dir_all(&item_dir).await?;
+    info!("restored trash item {} -> {}", item.id, item.original_path);
+
+    Ok(Json(entry_info(&files_dir!(), &target).ok_or_else(
+        || anyhow!("restore failed : {}", item.original_path),
+    )?))
+}
+
+async fn empty_trash() -> R<String> {
+    let trash_dir = files_dir!().join(TRASH_FOLDER);
+    if trash_dir.exists() {
+        fs::remove_dir_all(&trash_dir).await?;
+    }
+    spawn_asset_gc();
+    Ok("trash emptied.".to_string())
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct PackOption {
+    /// sub directory to pack, defaults to the whole files dir.
+    #[serde(default)]
+    dir: String,
 }
+
+async fn pack_files(Query(option): Query<PackOption>) -> R<impl IntoResponse> {
+    let folder_path = resolve_files_path(&option.dir)?;
+    if !folder_path.is_dir() {
+        return_error!("directory not found : {}", option.dir);
+    }
+
+    let zip_name = match option.dir.trim_matches('/').rsplit('/').next() {
+        Some(name) if !name.is_empty() => format!("{}.zip", name),
+        _ => "packed_files.zip".to_string(),
+    };
+
+    // 边压缩边输出，不再先落盘 packed_files.zip
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        if let Err(e) = write_zip_stream(folder_path, writer).await {
+            error!("pack files error : {:?}", e);
+        }
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", zip_name),
+        )
+        .body(Body::from_stream(ReaderStream::new(reader)))?)
+}
+
+/// write all files under `src_dir` into a zip archive, streaming it to `writer`.
+async fn write_zip_stream(src_dir: PathBuf, writer: DuplexStream) -> anyhow::Result<()> {
+    let root = src_dir.clone();
+    let files_root = files_dir!();
+    let entries = tokio::task::spawn_blocking(move || {
+        WalkDir::new(&root)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|e| !is_reserved_entry(&files_root, e.path()))
+            .filter_map(|e| e.ok())
+            .map(|e| (e.path().to_path_buf(), e.file_type().is_dir()))
+            .collect::<Vec<(PathBuf, bool)>>()
+    })
+    .await?;
+
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    for (path, is_dir) in entries {
+        let name = relative_path(&src_dir, &path);
+        if is_dir {
+            let entry = ZipEntryBuilder::new(format!("{}/", name).into(), Compression::Stored);
+            zip.write_entry_whole(entry, &[]).await?;
+            continue;
+        }
+
+        let entry = ZipEntryBuilder::new(name.into(), Compression::Deflate);
+        let mut entry_writer = zip.write_entry_stream(entry).await?;
+        if let Some(pointer) = asset_store_service::read_pointer(&path) {
+            let blob = asset_store_service::open_blob(&pointer).await?;
+            let mut chunks = blob.range_stream(0, blob.len());
+            while let Some(chunk) = chunks.next().await {
+                entry_writer.write_all(&chunk?).await?;
+            }
+        } else {
+            let file = File::open(&path).await?;
+            futures::io::copy(file.compat(), &mut entry_writer).await?;
+        }
+        entry_writer.close().await?;
+    }
+    zip.close().await?;
+    Ok(())
+}
+
 use walkdir::WalkDir;
 use zip::write::{FileOptions, ZipWriter};
 use zip_extensions::zip_create_from_directory;
@@ -134,8 +575,14 @@ struct UploadOption {
 }
 
 // #[debug_handler]
-async fn upload_file(Query(option): Query<UploadOption>, body: CustomFileExtractor) -> R<String> {
+async fn upload_file(
+    s: S,
+    Query(option): Query<UploadOption>,
+    body: CustomFileExtractor,
+) -> R<String> {
     info!("upload option : {:?}", option);
+    let storage = &s.config.file_storage;
+    let use_asset_store = storage.backend == FileStorageBackend::Asset;
     return match body {
         CustomFileExtractor::MULTIPART(mut multipart) => {
             let mut target_path = vec![];
@@ -192,6 +639,9 @@ async fn upload_file(Query(option): Query<UploadOption>, body: CustomFileExtract
                     (dir, prefix)
                 };
 
+                if is_api_path(&target_dir.join(&file_name).strip_prefix(files_dir!())?) {
+                    return_error!("`{}` is reserved", API_FOLDER);
+                }
                 if !target_dir.exists() {
                     fs::create_dir_all(&target_dir).await?;
                 }
@@ -199,13 +649,17 @@ async fn upload_file(Query(option): Query<UploadOption>, body: CustomFileExtract
                 if option.unzip && file_name.ends_with(".zip") {
                     let archive = Cursor::new(field.bytes().await?);
                     zip_extract::extract(archive, &target_dir, false).unwrap();
+                    if use_asset_store {
+                        asset_store_service::import_dir(storage, &target_dir).await?;
+                    }
                     target_path.push(format!(
                         "{}{}",
                         url_prefix,
                         &file_name.as_str()[0..(file_name.len() - ".zip".len())]
                     ));
                 } else {
-                    stream_to_file(&target_dir.join(&file_name), field).await?;
+                    let path = stream_to_file(&target_dir.join(&file_name), field).await?;
+                    finish_stored_file(storage, &path).await?;
                     target_path.push(format!("{}{}", url_prefix, file_name));
                 }
             }
@@ -226,6 +680,9 @@ async fn upload_file(Query(option): Query<UploadOption>, body: CustomFileExtract
                 // 清理子目录路径
                 let clean_subdir = subdir.trim_matches('/').replace("..", "");
                 if !clean_subdir.is_empty() {
+                    if is_api_path(std::path::Path::new(&clean_subdir)) {
+                        return_error!("`{}` is reserved", API_FOLDER);
+                    }
                     target_dir = target_dir.join(&clean_subdir);
                     url_prefix = format!("/files/{}/", clean_subdir);
                     // 确保目录存在
@@ -242,26 +699,37 @@ async fn upload_file(Query(option): Query<UploadOption>, body: CustomFileExtract
             tokio::fs::write(&path, body_bytes).await?;
 
             let new_path = rename_file_with_correct_extension(&path).await?;
+            finish_stored_file(storage, &target_dir.join(&new_path)).await?;
             Ok(format!("{}{}", url_prefix, new_path))
         }
     };
 }
 
-pub async fn download_file(Path(file_path): Path<String>) -> impl IntoResponse {
-    // Sanitize file path and prevent directory traversal
-    let safe_path = files_dir!().join(file_path.trim_start_matches('/'));
-    if safe_path
-        .components()
-        .any(|component| component == Component::ParentDir)
-    {
-        return Err((StatusCode::FORBIDDEN, "Access denied"));
-    }
+pub async fn download_file(
+    s: S,
+    Path(file_path): Path<String>,
+    uri: Uri,
+    method: Method,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    // unrelated or malformed query params (cache busters, `?w=abc`) just serve the original
+    let transform = Query::<ImageTransform>::try_from_uri(&uri)
+        .map(|Query(transform)| transform)
+        .unwrap_or_default();
+    // internal folders stay hidden here too, as in every listing
+    let root = files_dir!();
+    let mut safe_path = match resolve_files_path(&file_path) {
+        Ok(path) if !in_reserved_folder(&root, &path) => path,
+        _ => return Err((StatusCode::FORBIDDEN, "Access denied")),
+    };
 
     if !safe_path.exists() {
         return Err((StatusCode::FORBIDDEN, "Access denied"));
     }
 
-    let mime_type = mime_guess::from_path(&safe_path).first_or_octet_stream();
+    let mut mime_type = mime_guess::from_path(&safe_path)
+        .first_or_octet_stream()
+        .to_string();
 
     if safe_path.is_dir() {
         // 要列举的目录路径
@@ -307,62 +775,85 @@ pub async fn download_file(Path(file_path): Path<String>) -> impl IntoResponse {
         return Ok(response);
     }
 
-    // Attempt to open the file
-    match File::open(&safe_path).await {
-        Ok(mut file) => {
-            let mut contents = Vec::new();
-            // Read the file contents into a buffer
-            if let Ok(_) = file.read_to_end(&mut contents).await {
-                // Create a response with the file contents
-                let mut res_builder = Response::builder()
-                    .status(StatusCode::OK)
-                    .header(
-                        header::CONTENT_TYPE,
-                        HeaderValue::from_str(mime_type.as_ref()).unwrap(),
-                    )
-                    .header("Cross-Origin-Opener-Policy", "same-origin")
-                    .header("Cross-Origin-Embedder-Policy", "require-corp");
-                // if mime_type.as_ref().contains("wasm") {
-                //     //dont compress wasm file , because ios safari has issue with it.
-                //     res_builder = res_builder
-                //         .header("Content-Encoding", "identity")
-                //         .header("Cache-Control", "no-transform");
-                // }
-                let response = res_builder
-                    .body(Body::from(contents))
-                    .expect("Failed to build response"); // Convert Vec<u8> into Body
-
-                // You can add or modify response headers here
-                // response.headers_mut().insert(
-                //     "Content-Disposition",
-                //     HeaderValue::from_str(&format!("attachment; filename=\"{}\"", safe_path.file_name().unwrap().to_str().unwrap())).unwrap(),
-                // );
-
-                Ok(response)
-            } else {
-                // If file reading fails
-                Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the file"))
+    // 图片缩放/裁剪/转格式，结果缓存在 __thumbs__ 下
+    if transform.is_requested() && mime_type.starts_with("image/") {
+        match image_service::transform_image(
+            &safe_path,
+            &file_path,
+            &transform,
+            &s.config.image_config,
+        )
+        .await
+        {
+            Ok((variant_path, variant_mime)) => {
+                safe_path = variant_path;
+                mime_type = variant_mime.to_string();
+            }
+            Err(e) => {
+                info!("image transform error : {:?}", e);
+                return Err((StatusCode::BAD_REQUEST, "Invalid image transform"));
             }
         }
-        Err(_) => {
-            // If file opening fails
-            Err((StatusCode::NOT_FOUND, "File not found"))
+    }
+
+    match file_serve_service::serve_file(&safe_path, &mime_type, &method, &headers).await {
+        Ok(mut response) => {
+            let res_headers = response.headers_mut();
+            res_headers.insert(
+                "Cross-Origin-Opener-Policy",
+                HeaderValue::from_static("same-origin"),
+            );
+            res_headers.insert(
+                "Cross-Origin-Embedder-Policy",
+                HeaderValue::from_static("require-corp"),
+            );
+            Ok(response)
+        }
+        Err(e) => {
+            info!("serve file error : {:?}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the file"))
         }
     }
 }
 
-async fn delete_file(Path(file_path): Path<String>) -> impl IntoResponse {
+#[derive(Deserialize, Debug, Default)]
+struct DeleteOption {
+    /// skip the trash folder and remove immediately
+    #[serde(default)]
+    permanent: bool,
+}
+
+async fn delete_file(
+    Path(file_path): Path<String>,
+    Query(option): Query<DeleteOption>,
+) -> impl IntoResponse {
     // Sanitize file path and prevent directory traversal
-    let safe_path = files_dir!().join(file_path.trim_start_matches('/'));
-    if safe_path
-        .components()
-        .any(|component| component == Component::ParentDir)
-    {
-        return Err((StatusCode::FORBIDDEN, "Access denied".to_string()));
+    let safe_path = match resolve_files_path(&file_path) {
+        Ok(path) if path != files_dir!() => path,
+        _ => return Err((StatusCode::FORBIDDEN, "Access denied".to_string())),
+    };
+    if !safe_path.exists() {
+        return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+    }
+
+    // 回收站里的文件直接删除
+    let permanent = option.permanent || safe_path.starts_with(files_dir!().join(TRASH_FOLDER));
+    let result: anyhow::Result<()> = if !permanent {
+        move_to_trash(&file_path, &safe_path).await.map(|_| ())
+    } else if safe_path.is_dir() {
+        fs::remove_dir_all(&safe_path).await.map_err(Into::into)
+    } else {
+        fs::remove_file(&safe_path).await.map_err(Into::into)
+    };
+
+    if result.is_ok() {
+        image_service::remove_variants(&file_path).await;
+    }
+    if permanent {
+        spawn_asset_gc();
     }
 
-    // 尝试删除文件
-    match fs::remove_file(safe_path).await {
+    match result {
         Ok(_) => Ok(Response::new("文件删除成功".to_string())),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -370,6 +861,114 @@ async fn delete_file(Path(file_path): Path<String>) -> impl IntoResponse {
         )),
     }
 }
+
+/// every newly written file goes through here, uploads and mcp tools alike: its stale image
+/// variants are dropped and its content moves into the asset store when that backend is on.
+pub(crate) async fn finish_stored_file(
+    storage: &FileStorageConfig,
+    path: &std::path::Path,
+) -> anyhow::Result<()> {
+    image_service::remove_variants(&relative_path(&files_dir!(), path)).await;
+    if storage.backend == FileStorageBackend::Asset {
+        asset_store_service::store_file(storage, path).await?;
+    }
+    Ok(())
+}
+
+/// release asset store content that is no longer referenced after a permanent deletion.
+fn spawn_asset_gc() {
+    tokio::spawn(async {
+        if let Err(e) = asset_store_service::gc().await {
+            error!("asset store gc error : {:?}", e);
+        }
+    });
+}
+
+/// resolve a path relative to files dir, refusing anything that could escape it.
+pub(crate) fn resolve_files_path(rel_path: &str) -> anyhow::Result<PathBuf> {
+    resolve_path_in(&files_dir!(), rel_path)
+}
+
+fn resolve_path_in(root: &std::path::Path, rel_path: &str) -> anyhow::Result<PathBuf> {
+    let rel_path = std::path::Path::new(rel_path.trim_matches('/'));
+    ensure!(
+        rel_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir)),
+        "invalid path : {}",
+        rel_path.display()
+    );
+    ensure!(
+        !is_api_path(rel_path),
+        "`{}` is reserved : {}",
+        API_FOLDER,
+        rel_path.display()
+    );
+    Ok(root.join(rel_path))
+}
+
+fn is_api_path(rel_path: &std::path::Path) -> bool {
+    rel_path
+        .components()
+        .find(|c| *c != Component::CurDir)
+        .is_some_and(|c| c.as_os_str() == API_FOLDER)
+}
+
+/// path of `path` relative to `base`, always using '/' as separator.
+fn relative_path(base: &std::path::Path, path: &std::path::Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// internal folders (trash, html cache, thumbnails) directly under files dir are hidden from listings.
+pub(crate) fn is_reserved_entry(root: &std::path::Path, path: &std::path::Path) -> bool {
+    path.parent() == Some(root)
+        && path.file_name().map_or(false, |name| {
+            name == TRASH_FOLDER || name == CACHE_FOLDER || name == THUMB_FOLDER
+        })
+}
+
+/// whether `path` is one of the internal folders or inside one.
+fn in_reserved_folder(root: &std::path::Path, path: &std::path::Path) -> bool {
+    path.ancestors().any(|p| is_reserved_entry(root, p))
+}
+
+fn entry_info(root: &std::path::Path, path: &std::path::Path) -> Option<EntryInfo> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modify_time = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_millis() as i64;
+    let is_dir = metadata.is_dir();
+    Some(EntryInfo {
+        name: path.file_name()?.to_string_lossy().to_string(),
+        path: relative_path(root, path),
+        is_dir,
+        size: if is_dir {
+            0
+        } else {
+            content_size(path, &metadata)
+        },
+        modify_time,
+        children: if is_dir {
+            std::fs::read_dir(path).ok().map(|r| r.count())
+        } else {
+            None
+        },
+    })
+}
+
+/// size of the file content, asset pointers report the size of the stored asset.
+fn content_size(path: &std::path::Path, metadata: &std::fs::Metadata) -> u64 {
+    asset_store_service::read_pointer(path).map_or(metadata.len(), |pointer| pointer.size)
+}
+
 fn extract_extension(filename: &str) -> &str {
     let path = std::path::Path::new(filename);
     let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
@@ -454,6 +1053,36 @@ mod test {
         println!("{}", path_str);
     }
 
+    #[test]
+    fn test_resolve_files_path() {
+        let root = std::path::Path::new("/data/files");
+        assert_eq!(
+            resolve_path_in(root, "docs/2024/a.txt").unwrap(),
+            root.join("docs/2024/a.txt")
+        );
+        assert_eq!(resolve_path_in(root, "/docs/").unwrap(), root.join("docs"));
+        assert!(resolve_path_in(root, "../config.toml").is_err());
+        assert!(resolve_path_in(root, "docs/../../config.toml").is_err());
+        assert!(resolve_path_in(root, "_api/list").is_err());
+        assert!(resolve_path_in(root, "./_api").is_err());
+        assert!(resolve_path_in(root, "docs/_api").is_ok());
+
+        let reserved = |rel| in_reserved_folder(root, &resolve_path_in(root, rel).unwrap());
+        assert!(reserved("__trash__"));
+        assert!(reserved("./__thumbs__/a.png/x.webp"));
+        assert!(reserved("__cache__/abc"));
+        assert!(!reserved("docs/__trash__/a.txt"));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let base = std::path::Path::new("/data/files");
+        assert_eq!(
+            relative_path(base, std::path::Path::new("/data/files/a/b.png")),
+            "a/b.png"
+        );
+    }
+
     #[test]
     fn test_split() {
         let extension = extract_extension("sdfsdf . sddf sdfs sd.png");
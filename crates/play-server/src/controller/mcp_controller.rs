@@ -1,35 +1,166 @@
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use axum::{
-    extract::{Query, State},
-    http::{header, HeaderMap, StatusCode},
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{sse::Event, IntoResponse, Response, Sse},
-    routing::{get, post},
+    routing::post,
     Json, Router,
 };
-use futures_util::stream::{self, Stream};
+use futures_util::stream::Stream;
 use play_mcp::tools::ToolRegistry;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tokio::sync::RwLock;
-use tokio::time::interval;
-use tokio_stream::wrappers::IntervalStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt;
-use tracing::{error, info, warn};
+use tracing::{info, warn};
 use uuid::Uuid;
 
+use crate::service::mcp_resource_service::{self, category_marker, PAGES_CAT, PROMPTS_CAT};
+use crate::tables::general_data::GeneralData;
+use crate::tables::DBPool;
 use crate::AppState;
 
+/// newest first; the first one is offered when the client asks for something else.
+const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+const SESSION_HEADER: &str = "mcp-session-id";
+/// sessions idle for longer than this are dropped.
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+/// events kept per session for `Last-Event-ID` resumption.
+const MAX_BUFFERED_EVENTS: usize = 256;
+/// the stream opened by GET, carrying server initiated messages.
+const STANDALONE_STREAM: &str = "standalone";
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+/// a request still running after this is answered with an error, tools included.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+const INVALID_REQUEST: i32 = -32600;
+const INVALID_PARAMS: i32 = -32602;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INTERNAL_ERROR: i32 = -32603;
+const RESOURCE_NOT_FOUND: i32 = -32002;
+
 #[derive(Debug, Clone)]
+struct BufferedEvent {
+    id: u64,
+    stream: String,
+    data: String,
+    /// the last event of its stream, the stream closes after it.
+    last: bool,
+}
+
+#[derive(Debug)]
 struct McpSession {
-    id: String,
-    created_at: chrono::DateTime<chrono::Utc>,
-    last_event_id: Option<String>,
+    initialized: bool,
+    last_seen: Instant,
+    next_event_id: u64,
+    events: VecDeque<BufferedEvent>,
+    streams: HashMap<String, mpsc::UnboundedSender<BufferedEvent>>,
+    /// requests still running, keyed by their json encoded id.
+    in_flight: HashMap<String, oneshot::Sender<()>>,
+    /// numbers request streams, so a reused request id never shares a stream.
+    next_request: u64,
 }
 
-type SessionStore = Arc<RwLock<HashMap<String, McpSession>>>;
+/// a request registered in its session, see [`McpSession::start_request`].
+struct StartedRequest {
+    stream_key: String,
+    events: mpsc::UnboundedReceiver<BufferedEvent>,
+    cancel: oneshot::Receiver<()>,
+}
+
+impl McpSession {
+    fn new() -> Self {
+        McpSession {
+            initialized: false,
+            last_seen: Instant::now(),
+            next_event_id: 1,
+            events: VecDeque::new(),
+            streams: HashMap::new(),
+            in_flight: HashMap::new(),
+            next_request: 1,
+        }
+    }
+
+    /// registers a running request and opens its stream,
+    /// `None` while another request with the same id is still running.
+    fn start_request(&mut self, id: &Value) -> Option<StartedRequest> {
+        let key = id.to_string();
+        if self.in_flight.contains_key(&key) {
+            return None;
+        }
+        let (cancel_tx, cancel) = oneshot::channel();
+        self.in_flight.insert(key, cancel_tx);
+        let stream_key = format!("request-{}-{}", self.next_request, id);
+        self.next_request += 1;
+        let events = self.open_stream(&stream_key);
+        Some(StartedRequest {
+            stream_key,
+            events,
+            cancel,
+        })
+    }
+
+    fn publish(&mut self, stream: &str, data: String, last: bool) -> u64 {
+        let event = BufferedEvent {
+            id: self.next_event_id,
+            stream: stream.to_string(),
+            data,
+            last,
+        };
+        self.next_event_id += 1;
+        if self.events.len() >= MAX_BUFFERED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event.clone());
+        if let Some(sender) = self.streams.get(stream) {
+            if sender.send(event.clone()).is_err() {
+                self.streams.remove(stream);
+            }
+        }
+        if last {
+            self.streams.remove(stream);
+        }
+        event.id
+    }
+
+    fn open_stream(&mut self, stream: &str) -> mpsc::UnboundedReceiver<BufferedEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.streams.insert(stream.to_string(), tx);
+        rx
+    }
+
+    /// reopens the stream `last_event_id` was sent on, replaying what came after it.
+    fn resume_stream(
+        &mut self,
+        last_event_id: u64,
+    ) -> Option<mpsc::UnboundedReceiver<BufferedEvent>> {
+        let stream = self
+            .events
+            .iter()
+            .find(|e| e.id == last_event_id)?
+            .stream
+            .clone();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut finished = false;
+        for event in self
+            .events
+            .iter()
+            .filter(|e| e.id > last_event_id && e.stream == stream)
+        {
+            let _ = tx.send(event.clone());
+            finished |= event.last;
+        }
+        if !finished {
+            self.streams.insert(stream, tx);
+        }
+        Some(rx)
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "jsonrpc")]
@@ -75,13 +206,30 @@ struct JsonRpcError {
     data: Option<Value>,
 }
 
+impl JsonRpcError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        JsonRpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
 lazy_static::lazy_static! {
-    static ref SESSIONS: SessionStore = Arc::new(RwLock::new(HashMap::new()));
+    static ref SESSIONS: Mutex<HashMap<String, McpSession>> = Mutex::new(HashMap::new());
     static ref TOOL_REGISTRY: Arc<ToolRegistry> = Arc::new(ToolRegistry::new());
 }
 
+static WATCHER_STARTED: AtomicBool = AtomicBool::new(false);
+
 pub fn init() -> Router<Arc<AppState>> {
-    Router::new().route("/mcp", post(handle_mcp_post).get(handle_mcp_sse))
+    Router::new().route(
+        "/mcp",
+        post(handle_mcp_post)
+            .get(handle_mcp_sse)
+            .delete(handle_mcp_delete),
+    )
 }
 
 async fn validate_origin(headers: &HeaderMap) -> Result<(), StatusCode> {
@@ -102,78 +250,201 @@ async fn validate_origin(headers: &HeaderMap) -> Result<(), StatusCode> {
     Ok(())
 }
 
-fn get_or_create_session(headers: &HeaderMap) -> String {
+fn get_protocol_version(headers: &HeaderMap) -> String {
     headers
-        .get("mcp-session-id")
+        .get("mcp-protocol-version")
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string())
-        .unwrap_or_else(|| Uuid::new_v4().to_string())
+        .unwrap_or_else(|| "2025-03-26".to_string())
 }
 
-fn get_protocol_version(headers: &HeaderMap) -> String {
+fn negotiate_protocol_version(requested: Option<&str>) -> &'static str {
+    SUPPORTED_PROTOCOL_VERSIONS
+        .iter()
+        .find(|v| Some(**v) == requested)
+        .unwrap_or(&SUPPORTED_PROTOCOL_VERSIONS[0])
+}
+
+fn accepts(headers: &HeaderMap, mime: &str) -> bool {
     headers
-        .get("mcp-protocol-version")
+        .get(header::ACCEPT)
         .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| "2025-03-26".to_string())
+        .map(|accept| accept.contains(mime) || accept.contains("*/*"))
+        .unwrap_or(false)
+}
+
+fn expire_sessions(sessions: &mut HashMap<String, McpSession>) {
+    sessions.retain(|id, session| {
+        let alive = session.last_seen.elapsed() < SESSION_TTL;
+        if !alive {
+            info!("MCP session expired: {}", id);
+        }
+        alive
+    });
+}
+
+fn create_session() -> String {
+    let session_id = Uuid::new_v4().to_string();
+    let mut sessions = SESSIONS.lock().unwrap();
+    expire_sessions(&mut sessions);
+    sessions.insert(session_id.clone(), McpSession::new());
+    session_id
+}
+
+/// the session named by the `Mcp-Session-Id` header: 400 when missing, 404 when unknown or expired.
+fn require_session(headers: &HeaderMap) -> Result<String, StatusCode> {
+    let session_id = headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+    let mut sessions = SESSIONS.lock().unwrap();
+    expire_sessions(&mut sessions);
+    let session = sessions.get_mut(&session_id).ok_or(StatusCode::NOT_FOUND)?;
+    session.last_seen = Instant::now();
+    Ok(session_id)
+}
+
+fn publish(session_id: &str, stream: &str, message: &Value, last: bool) {
+    if let Some(session) = SESSIONS.lock().unwrap().get_mut(session_id) {
+        session.publish(stream, message.to_string(), last);
+    }
+}
+
+/// sends a notification to every initialized session on its standalone stream.
+fn notify_all(method: &str) {
+    let message = json!({"jsonrpc": "2.0", "method": method}).to_string();
+    let mut sessions = SESSIONS.lock().unwrap();
+    for session in sessions.values_mut().filter(|s| s.initialized) {
+        session.publish(STANDALONE_STREAM, message.clone(), false);
+    }
+}
+
+fn event_stream(
+    rx: mpsc::UnboundedReceiver<BufferedEvent>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let stream = UnboundedReceiverStream::new(rx).map(|event| {
+        Ok(Event::default()
+            .id(event.id.to_string())
+            .event("message")
+            .data(event.data))
+    });
+    Sse::new(stream).keep_alive(
+        axum::response::sse::KeepAlive::new()
+            .interval(Duration::from_secs(30))
+            .text("keep-alive"),
+    )
+}
+
+fn with_session_header(mut response: Response, session_id: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(session_id) {
+        response.headers_mut().insert(SESSION_HEADER, value);
+    }
+    response
+}
+
+fn rpc_response(id: Value, result: Result<Value, JsonRpcError>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+    }
 }
 
 async fn handle_mcp_post(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     Json(payload): Json<JsonRpcMessage>,
 ) -> Result<Response, StatusCode> {
     validate_origin(&headers).await?;
 
-    let session_id = get_or_create_session(&headers);
-    let protocol_version = get_protocol_version(&headers);
-
-    info!(
-        "MCP POST request - session: {}, protocol: {}",
-        session_id, protocol_version
-    );
-    info!("Request payload: {:?}", payload);
-
-    let mut sessions = SESSIONS.write().await;
-    sessions
-        .entry(session_id.clone())
-        .or_insert_with(|| McpSession {
-            id: session_id.clone(),
-            created_at: chrono::Utc::now(),
-            last_event_id: None,
-        });
-
-    let accept_header = headers
-        .get(header::ACCEPT)
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
+    let sse = accepts(&headers, "text/event-stream");
+    if !sse && !accepts(&headers, "application/json") {
+        warn!("Request accepts neither json nor SSE");
+        return Err(StatusCode::NOT_ACCEPTABLE);
+    }
 
     match payload.content {
+        JsonRpcContent::Request { id, method, params } if method == "initialize" => {
+            let requested = params
+                .as_ref()
+                .and_then(|p| p.get("protocolVersion"))
+                .and_then(|v| v.as_str());
+            let protocol_version = negotiate_protocol_version(requested);
+            let session_id = create_session();
+            start_watcher(state.db.clone());
+            info!(
+                "MCP session created: {}, protocol: {}",
+                session_id, protocol_version
+            );
+
+            let response = rpc_response(id, Ok(initialize_result(protocol_version)));
+            let response = if sse {
+                let mut sessions = SESSIONS.lock().unwrap();
+                let session = sessions.get_mut(&session_id).ok_or(StatusCode::NOT_FOUND)?;
+                let rx = session.open_stream("initialize");
+                session.publish("initialize", response.to_string(), true);
+                event_stream(rx).into_response()
+            } else {
+                Json(response).into_response()
+            };
+            Ok(with_session_header(response, &session_id))
+        }
         JsonRpcContent::Request { id, method, params } => {
-            if !accept_header.contains("text/event-stream") {
-                warn!("Request missing SSE accept header");
-                return Err(StatusCode::BAD_REQUEST);
+            let session_id = require_session(&headers)?;
+            info!(
+                "MCP request - session: {}, protocol: {}, id: {}, method: {}",
+                session_id,
+                get_protocol_version(&headers),
+                id,
+                method
+            );
+
+            let started = {
+                let mut sessions = SESSIONS.lock().unwrap();
+                let session = sessions.get_mut(&session_id).ok_or(StatusCode::NOT_FOUND)?;
+                session.start_request(&id)
+            };
+            let Some(StartedRequest {
+                stream_key,
+                events: rx,
+                cancel,
+            }) = started
+            else {
+                warn!("Request id already in flight: {}", id);
+                let error = JsonRpcError::new(
+                    INVALID_REQUEST,
+                    format!("Request id {} is already in use by a running request", id),
+                );
+                return Ok(Json(rpc_response(id, Err(error))).into_response());
+            };
+            tokio::spawn(run_request(
+                state, session_id, stream_key, id, method, params, cancel,
+            ));
+
+            if sse {
+                Ok(event_stream(rx).into_response())
+            } else {
+                // plain json: only the final message, notifications stay in the replay buffer.
+                let mut stream = UnboundedReceiverStream::new(rx);
+                let mut last = None;
+                while let Some(event) = stream.next().await {
+                    if event.last {
+                        last = Some(event.data);
+                    }
+                }
+                Ok(match last {
+                    Some(data) => {
+                        ([(header::CONTENT_TYPE, "application/json")], data).into_response()
+                    }
+                    None => StatusCode::ACCEPTED.into_response(),
+                })
             }
-
-            info!("Processing request - id: {:?}, method: {}", id, method);
-
-            let stream = create_response_stream(id, method, params, session_id);
-
-            Ok(Sse::new(stream)
-                .keep_alive(
-                    axum::response::sse::KeepAlive::new()
-                        .interval(Duration::from_secs(30))
-                        .text("keep-alive"),
-                )
-                .into_response())
         }
         JsonRpcContent::Response { id, result, error } => {
-            info!("Received response - id: {:?}", id);
+            let session_id = require_session(&headers)?;
+            info!("Received response - session: {}, id: {:?}", session_id, id);
             if let Some(result) = &result {
-                info!(
-                    "Response result: {}",
-                    serde_json::to_string_pretty(result).unwrap_or_else(|_| result.to_string())
-                );
+                info!("Response result: {}", result);
             }
             if let Some(error) = &error {
                 warn!("Response error: {:?}", error);
@@ -182,12 +453,27 @@ async fn handle_mcp_post(
             Ok(StatusCode::ACCEPTED.into_response())
         }
         JsonRpcContent::Notification { method, params } => {
-            info!("Received notification - method: {}", method);
-            if let Some(params) = &params {
-                info!(
-                    "Notification params: {}",
-                    serde_json::to_string_pretty(params).unwrap_or_else(|_| params.to_string())
-                );
+            let session_id = require_session(&headers)?;
+            info!(
+                "Received notification - session: {}, method: {}",
+                session_id, method
+            );
+            let mut sessions = SESSIONS.lock().unwrap();
+            let session = sessions.get_mut(&session_id).ok_or(StatusCode::NOT_FOUND)?;
+            match method.as_str() {
+                "notifications/initialized" => session.initialized = true,
+                "notifications/cancelled" => {
+                    let request_id = params
+                        .as_ref()
+                        .and_then(|p| p.get("requestId"))
+                        .map(|id| id.to_string())
+                        .unwrap_or_default();
+                    if let Some(cancel) = session.in_flight.remove(&request_id) {
+                        info!("Cancelling request: {}", request_id);
+                        let _ = cancel.send(());
+                    }
+                }
+                _ => {}
             }
 
             Ok(StatusCode::ACCEPTED.into_response())
@@ -195,212 +481,253 @@ async fn handle_mcp_post(
     }
 }
 
-fn create_response_stream(
+fn initialize_result(protocol_version: &str) -> Value {
+    json!({
+        "protocolVersion": protocol_version,
+        "capabilities": {
+            "tools": {"listChanged": true},
+            "resources": {"listChanged": true},
+            "prompts": {"listChanged": true}
+        },
+        "serverInfo": {
+            "name": "play-server-mcp",
+            "version": env!("CARGO_PKG_VERSION")
+        }
+    })
+}
+
+/// runs one request, publishing progress and then the response on its stream,
+/// or nothing when it is cancelled first.
+async fn run_request(
+    state: Arc<AppState>,
+    session_id: String,
+    stream_key: String,
     id: Value,
     method: String,
     params: Option<Value>,
-    session_id: String,
-) -> impl Stream<Item = Result<Event, axum::Error>> {
-    let event_id = Uuid::new_v4().to_string();
-
-    // Create the response based on the method
-    let response_future = async move {
-        let response = match method.as_str() {
-            "ping" => {
-                json!({
-                    "jsonrpc": "2.0",
-                    "id": id,
-                    "result":{}
-                })
-            }
-            "initialize" => {
-                json!({
-                    "jsonrpc": "2.0",
-                    "id": id,
-                    "result": {
-                        "protocolVersion": "2025-03-26",
-                        "capabilities": {
-                            "tools": {},
-                            "resources": {},
-                            "prompts": {}
-                        },
-                        "serverInfo": {
-                            "name": "play-server-mcp",
-                            "version": "0.1.0"
-                        }
-                    }
-                })
-            }
-            "tools/list" => {
-                let tools = TOOL_REGISTRY.list();
-                json!({
-                    "jsonrpc": "2.0",
-                    "id": id,
-                    "result": {
-                        "tools": tools
-                    }
-                })
-            }
-            "tools/call" => {
-                if let Some(params) = params.clone() {
-                    if let Some(name) = params.get("name").and_then(|v| v.as_str()) {
-                        if let Some(tool) = TOOL_REGISTRY.get(name) {
-                            let default_args = json!({});
-                            let arguments =
-                                params.get("arguments").unwrap_or(&default_args).clone();
-
-                            match tool.execute(arguments).await {
-                                Ok(result) => {
-                                    json!({
-                                        "jsonrpc": "2.0",
-                                        "id": id,
-                                        "result": {
-                                            "content": [
-                                                {
-                                                    "type": "text",
-                                                    "text": serde_json::to_string_pretty(&result).unwrap_or_else(|_| "Error serializing result".to_string())
-                                                }
-                                            ]
-                                        }
-                                    })
-                                }
-                                Err(e) => {
-                                    json!({
-                                        "jsonrpc": "2.0",
-                                        "id": id,
-                                        "error": {
-                                            "code": -32603,
-                                            "message": format!("Tool execution failed: {}", e)
-                                        }
-                                    })
-                                }
-                            }
-                        } else {
-                            json!({
-                                "jsonrpc": "2.0",
-                                "id": id,
-                                "error": {
-                                    "code": -32602,
-                                    "message": format!("Unknown tool: {}", name)
-                                }
-                            })
-                        }
-                    } else {
-                        json!({
-                            "jsonrpc": "2.0",
-                            "id": id,
-                            "error": {
-                                "code": -32602,
-                                "message": "Missing tool name in params"
-                            }
-                        })
-                    }
-                } else {
-                    json!({
-                        "jsonrpc": "2.0",
-                        "id": id,
-                        "error": {
-                            "code": -32602,
-                            "message": "Invalid params for tools/call"
-                        }
-                    })
-                }
+    cancel_rx: oneshot::Receiver<()>,
+) {
+    let progress_token = params
+        .as_ref()
+        .and_then(|p| p.pointer("/_meta/progressToken"))
+        .cloned();
+    let progress = |progress: u32| {
+        if let Some(token) = &progress_token {
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/progress",
+                "params": {"progressToken": token, "progress": progress, "total": 1}
+            });
+            publish(&session_id, &stream_key, &notification, false);
+        }
+    };
+
+    progress(0);
+    let result = tokio::select! {
+        result = tokio::time::timeout(REQUEST_TIMEOUT, dispatch(&state, &method, params)) => {
+            Some(result.unwrap_or_else(|_| {
+                Err(JsonRpcError::new(INTERNAL_ERROR, "Request timed out"))
+            }))
+        }
+        _ = cancel_rx => None,
+    };
+
+    match result {
+        Some(result) => {
+            progress(1);
+            let response = rpc_response(id.clone(), result);
+            info!("Sending response - method: {}, id: {}", method, id);
+            let mut sessions = SESSIONS.lock().unwrap();
+            if let Some(session) = sessions.get_mut(&session_id) {
+                session.in_flight.remove(&id.to_string());
+                session.publish(&stream_key, response.to_string(), true);
             }
-            "resources/list" => {
-                json!({
-                    "jsonrpc": "2.0",
-                    "id": id,
-                    "result": {
-                        "resources": []
-                    }
-                })
+        }
+        None => {
+            info!("Request cancelled - method: {}, id: {}", method, id);
+            if let Some(session) = SESSIONS.lock().unwrap().get_mut(&session_id) {
+                session.streams.remove(&stream_key);
             }
-            "prompts/list" => {
-                json!({
-                    "jsonrpc": "2.0",
-                    "id": id,
-                    "result": {
-                        "prompts": []
-                    }
+        }
+    }
+}
+
+async fn dispatch(
+    state: &AppState,
+    method: &str,
+    params: Option<Value>,
+) -> Result<Value, JsonRpcError> {
+    let params = params.unwrap_or_else(|| json!({}));
+    let internal = |e: anyhow::Error| JsonRpcError::new(INTERNAL_ERROR, e.to_string());
+
+    match method {
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": TOOL_REGISTRY.list() })),
+        "tools/call" => {
+            let name = params
+                .get("name")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| JsonRpcError::new(INVALID_PARAMS, "Missing tool name in params"))?;
+            let tool = TOOL_REGISTRY.get(name).ok_or_else(|| {
+                JsonRpcError::new(INVALID_PARAMS, format!("Unknown tool: {}", name))
+            })?;
+            let arguments = params
+                .get("arguments")
+                .cloned()
+                .unwrap_or_else(|| json!({}));
+
+            // tool failures are results the model can see, not protocol errors.
+            Ok(match tool.execute(arguments).await {
+                Ok(result) => json!({
+                    "content": [{
+                        "type": "text",
+                        "text": serde_json::to_string_pretty(&result)
+                            .unwrap_or_else(|_| "Error serializing result".to_string())
+                    }],
+                    "isError": false
+                }),
+                Err(e) => json!({
+                    "content": [{"type": "text", "text": format!("Tool execution failed: {}", e)}],
+                    "isError": true
+                }),
+            })
+        }
+        "resources/list" => {
+            let resources = mcp_resource_service::list_resources(&state.db)
+                .await
+                .map_err(internal)?;
+            Ok(json!({ "resources": resources }))
+        }
+        "resources/templates/list" => Ok(json!({
+            "resourceTemplates": mcp_resource_service::resource_templates()
+        })),
+        "resources/read" => {
+            let uri = params
+                .get("uri")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| JsonRpcError::new(INVALID_PARAMS, "Missing uri in params"))?;
+            mcp_resource_service::read_resource(uri, &state.db)
+                .await
+                .map_err(internal)?
+                .ok_or_else(|| JsonRpcError {
+                    data: Some(json!({ "uri": uri })),
+                    ..JsonRpcError::new(RESOURCE_NOT_FOUND, "Resource not found")
                 })
-            }
-            _ => {
-                json!({
-                    "jsonrpc": "2.0",
-                    "id": id,
-                    "error": {
-                        "code": -32601,
-                        "message": "Method not found"
-                    }
+        }
+        "prompts/list" => {
+            let prompts = mcp_resource_service::list_prompts(&state.db)
+                .await
+                .map_err(internal)?;
+            Ok(json!({ "prompts": prompts }))
+        }
+        "prompts/get" => {
+            let name = params.get("name").and_then(|v| v.as_str()).ok_or_else(|| {
+                JsonRpcError::new(INVALID_PARAMS, "Missing prompt name in params")
+            })?;
+            let arguments: HashMap<String, String> = params
+                .get("arguments")
+                .cloned()
+                .map(serde_json::from_value)
+                .transpose()
+                .map_err(|e| JsonRpcError::new(INVALID_PARAMS, e.to_string()))?
+                .unwrap_or_default();
+            mcp_resource_service::get_prompt(name, &arguments, &state.db)
+                .await
+                .map_err(|e| JsonRpcError::new(INVALID_PARAMS, e.to_string()))?
+                .ok_or_else(|| {
+                    JsonRpcError::new(INVALID_PARAMS, format!("Unknown prompt: {}", name))
                 })
-            }
-        };
-
-        // Log the response
-        info!(
-            "Sending response - method: {}, id: {:?}, response: {}",
-            method,
-            id,
-            serde_json::to_string_pretty(&response).unwrap_or_else(|_| response.to_string())
-        );
-
-        // Update session with event ID
-        let mut sessions = SESSIONS.write().await;
-        if let Some(session) = sessions.get_mut(&session_id) {
-            session.last_event_id = Some(event_id.clone());
         }
-
-        Ok(Event::default()
-            .id(event_id)
-            .event("message")
-            .data(response.to_string()))
-    };
-
-    // Convert the future into a stream
-    stream::once(response_future)
+        _ => Err(JsonRpcError::new(METHOD_NOT_FOUND, "Method not found")),
+    }
 }
 
-async fn handle_mcp_sse(
-    State(_state): State<Arc<AppState>>,
-    headers: HeaderMap,
-    Query(params): Query<HashMap<String, String>>,
-) -> Result<impl IntoResponse, StatusCode> {
+async fn handle_mcp_sse(headers: HeaderMap) -> Result<Response, StatusCode> {
     validate_origin(&headers).await?;
+    if !accepts(&headers, "text/event-stream") {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
 
-    let session_id = get_or_create_session(&headers);
-    let protocol_version = get_protocol_version(&headers);
+    let session_id = require_session(&headers)?;
     let last_event_id = headers
         .get("last-event-id")
         .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
+        .and_then(|v| v.parse::<u64>().ok());
 
     info!(
-        "MCP SSE connection - session: {}, protocol: {}, last_event: {:?}",
-        session_id, protocol_version, last_event_id
+        "MCP SSE connection - session: {}, last_event: {:?}",
+        session_id, last_event_id
     );
 
-    let mut sessions = SESSIONS.write().await;
-    let session = sessions
-        .entry(session_id.clone())
-        .or_insert_with(|| McpSession {
-            id: session_id.clone(),
-            created_at: chrono::Utc::now(),
-            last_event_id: last_event_id.clone(),
-        });
-
-    if let Some(event_id) = last_event_id {
-        session.last_event_id = Some(event_id);
-    }
+    let mut sessions = SESSIONS.lock().unwrap();
+    let session = sessions.get_mut(&session_id).ok_or(StatusCode::NOT_FOUND)?;
+    let rx = match last_event_id.and_then(|id| session.resume_stream(id)) {
+        Some(rx) => rx,
+        None => session.open_stream(STANDALONE_STREAM),
+    };
 
-    let stream = IntervalStream::new(interval(Duration::from_secs(30))).map(
-        move |_| -> Result<Event, axum::Error> { Ok(Event::default().event("ping").data("")) },
-    );
+    Ok(event_stream(rx).into_response())
+}
 
-    Ok(Sse::new(stream).keep_alive(
-        axum::response::sse::KeepAlive::new()
-            .interval(Duration::from_secs(30))
-            .text("keep-alive"),
-    ))
+async fn handle_mcp_delete(headers: HeaderMap) -> Result<StatusCode, StatusCode> {
+    validate_origin(&headers).await?;
+    let session_id = require_session(&headers)?;
+    // dropping the session closes its streams and cancels its requests.
+    SESSIONS.lock().unwrap().remove(&session_id);
+    info!("MCP session deleted: {}", session_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// polls pages, prompts and data categories and tells sessions when their lists, the tools or
+/// the mcp hub change.
+fn start_watcher(db: DBPool) {
+    if WATCHER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let mut tool_changes = play_mcp::tools::subscribe_tool_changes();
+    tokio::spawn(async move {
+        while tool_changes.changed().await.is_ok() {
+            notify_all("notifications/tools/list_changed");
+        }
+    });
+    let mut hub_changes = crate::mcp_hub::subscribe_changes();
+    tokio::spawn(async move {
+        while hub_changes.changed().await.is_ok() {
+            notify_all("notifications/resources/list_changed");
+            notify_all("notifications/prompts/list_changed");
+        }
+    });
+    tokio::spawn(async move {
+        let mut resources = None;
+        let mut prompts = None;
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            if SESSIONS.lock().unwrap().is_empty() {
+                continue;
+            }
+            let current_resources = match (
+                category_marker(PAGES_CAT, &db).await,
+                GeneralData::query_distinct_categories(false, &db).await,
+            ) {
+                (Ok(pages), Ok(cats)) => Some((pages, cats)),
+                _ => None,
+            };
+            if current_resources.is_some() && resources.is_some() && current_resources != resources
+            {
+                notify_all("notifications/resources/list_changed");
+            }
+            if current_resources.is_some() {
+                resources = current_resources;
+            }
+
+            if let Ok(current) = category_marker(PROMPTS_CAT, &db).await {
+                if prompts.is_some_and(|p| p != current) {
+                    notify_all("notifications/prompts/list_changed");
+                }
+                prompts = Some(current);
+            }
+        }
+    });
 }
 
 #[cfg(test)]
@@ -424,5 +751,60 @@ mod tests {
 
         headers.insert("mcp-protocol-version", "2025-06-18".parse().unwrap());
         assert_eq!(get_protocol_version(&headers), "2025-06-18");
+
+        assert_eq!(negotiate_protocol_version(Some("2025-03-26")), "2025-03-26");
+        assert_eq!(negotiate_protocol_version(Some("1999-01-01")), "2025-06-18");
+        assert_eq!(negotiate_protocol_version(None), "2025-06-18");
+    }
+
+    #[test]
+    fn test_session_lifecycle() {
+        let session_id = create_session();
+        let mut headers = HeaderMap::new();
+        assert_eq!(require_session(&headers), Err(StatusCode::BAD_REQUEST));
+        headers.insert(SESSION_HEADER, "unknown".parse().unwrap());
+        assert_eq!(require_session(&headers), Err(StatusCode::NOT_FOUND));
+        headers.insert(SESSION_HEADER, session_id.parse().unwrap());
+        assert_eq!(require_session(&headers), Ok(session_id.clone()));
+
+        SESSIONS.lock().unwrap().remove(&session_id);
+        assert_eq!(require_session(&headers), Err(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_start_request() {
+        let mut session = McpSession::new();
+        let first = session.start_request(&json!(1)).unwrap();
+        // the id is taken while the first request runs
+        assert!(session.start_request(&json!(1)).is_none());
+        assert!(session.start_request(&json!("1")).is_some());
+
+        session.in_flight.remove("1");
+        let second = session.start_request(&json!(1)).unwrap();
+        assert_ne!(first.stream_key, second.stream_key);
+    }
+
+    #[test]
+    fn test_resume_stream() {
+        let mut session = McpSession::new();
+        let mut rx = session.open_stream("request-1");
+        let first = session.publish("request-1", "progress".to_string(), false);
+        session.publish(STANDALONE_STREAM, "notice".to_string(), false);
+        drop(rx.try_recv());
+        // the client drops the stream, the request then finishes.
+        drop(rx);
+        session.publish("request-1", "response".to_string(), true);
+
+        let mut rx = session.resume_stream(first).unwrap();
+        let replayed = rx.try_recv().unwrap();
+        assert_eq!(replayed.data, "response");
+        assert!(replayed.last);
+        assert!(rx.try_recv().is_err());
+        assert!(!session.streams.contains_key("request-1"));
+        assert!(session.resume_stream(999).is_none());
+
+        let mut rx = session.open_stream(STANDALONE_STREAM);
+        session.publish(STANDALONE_STREAM, "changed".to_string(), false);
+        assert_eq!(rx.try_recv().unwrap().data, "changed");
     }
 }
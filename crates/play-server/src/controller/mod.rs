@@ -10,7 +10,7 @@ pub mod cache_controller;
 mod data_v1_controller;
 mod data_v2_controller;
 mod data_v3_controller;
-mod data_v4_controller;
+pub mod data_v4_controller;
 pub mod files_controller;
 mod mcp_controller;
 pub mod pages_controller;
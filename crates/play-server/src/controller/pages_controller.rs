@@ -1,4 +1,5 @@
-use axum::extract::{Path, Query};
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
 use axum::response::{Html, IntoResponse, Response};
 use axum::Form;
 use chrono::{TimeZone, Utc};
@@ -13,6 +14,7 @@ use play_shared::timestamp_to_date_str;
 use play_shared::tpl_engine_api::Template;
 
 use crate::controller::function_controller::{text_compare, TextCompareReq};
+use crate::service::http_cache_service;
 use crate::tables::change_log::ChangeLog;
 use crate::tables::general_data::GeneralData;
 use crate::{
@@ -21,7 +23,7 @@ use crate::{
 use crate::{HTML, R, S};
 
 method_router!(
-    get : "/pages/{*url}"-> dynamic_pages,
+    get : "/pages/{*url}"-> cached_dynamic_pages,
     get : "/page-versions"-> page_versions,
 );
 
@@ -118,7 +120,35 @@ async fn page_versions(s: S, Query(q): Query<QueryPageVersion>) -> HTML {
     template!(s, PAGE_VERSIONS_HTML, json!({ "items": data}))
 }
 
-async fn dynamic_pages(
+// 经过 http 缓存（需要 http_cache.enabled 并且配置了缓存时间）
+async fn cached_dynamic_pages(s: S, request: http::Request<Body>) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let app_state = s.0.clone();
+    let fetch = move |request: http::Request<Body>| {
+        let app_state = app_state.clone();
+        async move {
+            let uri = request.uri();
+            let url = uri.path().strip_prefix("/pages/").unwrap_or_default();
+            let url = urlencoding::decode(url)
+                .map(|u| u.into_owned())
+                .unwrap_or_else(|_| url.to_string());
+            let params = Query::<HashMap<String, String>>::try_from_uri(uri)
+                .map(|q| q.0)
+                .unwrap_or_default();
+            dynamic_pages(State(app_state), Path(url), Query(params))
+                .await
+                .into_response()
+        }
+    };
+    http_cache_service::serve_with_cache(&s.config.http_cache, &host, request, fetch).await
+}
+
+pub(crate) async fn dynamic_pages(
     s: S,
     Path(url): Path<String>,
     Query(params): Query<HashMap<String, String>>,
@@ -1,15 +1,17 @@
 use crate::config::{read_config_file, PluginConfig};
+use crate::plugins;
 use crate::AppError;
 use crate::{return_error, AppState, S};
 use anyhow::{anyhow, bail, Context};
 use axum::body::Body;
+use axum::extract::ConnectInfo;
 use axum::response::{IntoResponse, Response};
 use futures_util::{StreamExt, TryStreamExt};
 use http::{Request, StatusCode};
 use play_shared::constants::DATA_DIR;
 use serde::Deserialize;
-use std::collections::HashMap;
 use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 // method_router!(
@@ -19,33 +21,17 @@ use std::sync::Arc;
 //     delete : "/plugin/*url"-> run_plugin,
 // );
 
+/// plugins are looked up on every request, so they can change without rebuilding the router.
 pub fn init(state: Arc<AppState>) -> axum::Router<Arc<AppState>> {
-    let mut router = axum::Router::new();
-    for plugin in &state.config.plugin_config {
-        if !plugin.url_prefix.is_empty() {
-            router = router.route(
-                &format!("{}/{{*url}}", plugin.url_prefix),
-                axum::routing::get(run_plugin)
-                    .post(run_plugin)
-                    .put(run_plugin)
-                    .delete(run_plugin),
-            );
-            router = router.route(
-                &format!("{}", plugin.url_prefix),
-                axum::routing::get(run_plugin)
-                    .post(run_plugin)
-                    .put(run_plugin)
-                    .delete(run_plugin),
-            );
-        }
-    }
-
-    router
+    axum::Router::new().fallback(run_plugin)
 }
 
 #[cfg(not(feature = "play-dylib-loader"))]
 #[axum::debug_handler]
 async fn run_plugin(s: axum::extract::State<Arc<AppState>>, request: Request<Body>) -> Response {
+    if plugins::find_by_url(&remove_trailing_slash(request.uri().path())).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
     (
         axum::http::StatusCode::INTERNAL_SERVER_ERROR,
         "play-dylib-loader feature not enabled!",
@@ -66,15 +52,13 @@ async fn run_plugin(s: axum::extract::State<Arc<AppState>>, request: Request<Bod
     let url = request.uri().path();
     let url = remove_trailing_slash(url);
 
-    match s.config.plugin_config.iter().find(|plugin| {
-        !plugin.disable
-            && !plugin.url_prefix.is_empty()
-            && (url.eq(&plugin.url_prefix) || url.starts_with(&format!("{}/", plugin.url_prefix)))
-    }) {
-        Some(plugin) => inner_run_plugin(plugin, request).await.unwrap_or_else(|e| {
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
-        }),
-        None => (StatusCode::NOT_FOUND, "Plugin not found for URL").into_response(),
+    match plugins::find_by_url(&url) {
+        Some(plugin) => run_registered_plugin(&s, &plugin, request)
+            .await
+            .unwrap_or_else(|e| {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+            }),
+        None => StatusCode::NOT_FOUND.into_response(),
     }
 }
 
@@ -82,68 +66,151 @@ async fn run_plugin(s: axum::extract::State<Arc<AppState>>, request: Request<Bod
 #[cfg(feature = "play-dylib-loader")]
 pub use play_dylib_loader::{clear_plugin_cache, remove_plugin_from_cache};
 
+/// runs the plugin and counts the outcome in its health stats.
 #[cfg(feature = "play-dylib-loader")]
-pub async fn inner_run_plugin(
-    plugin: &PluginConfig,
+pub(crate) async fn run_registered_plugin(
+    state: &AppState,
+    plugin: &plugins::Plugin,
     request: Request<Body>,
 ) -> Result<Response, AppError> {
-    use play_dylib_loader::*;
+    let result = async {
+        let host = Arc::new(crate::plugin_host::PluginHost::for_plugin(state, plugin)?);
+        match plugin.config.kind {
+            crate::config::PluginKind::Dylib => {
+                inner_run_plugin(&plugin.config, request, host).await
+            }
+            crate::config::PluginKind::Wasm => run_wasm_plugin(plugin, request, host).await,
+        }
+    }
+    .await;
+    match &result {
+        Ok(_) => plugin.stats.record_success(),
+        Err(e) => plugin.stats.record_error(e.to_string()),
+    }
+    result
+}
 
-    let url = request.uri().path();
-    let url = remove_trailing_slash(url);
+#[cfg(all(feature = "play-dylib-loader", not(feature = "play-wasm")))]
+async fn run_wasm_plugin(
+    _plugin: &plugins::Plugin,
+    _request: Request<Body>,
+    _host: Arc<crate::plugin_host::PluginHost>,
+) -> Result<Response, AppError> {
+    return_error!("play-wasm feature not enabled!")
+}
+
+#[cfg(feature = "play-wasm")]
+async fn run_wasm_plugin(
+    plugin: &plugins::Plugin,
+    request: Request<Body>,
+    host: Arc<crate::plugin_host::PluginHost>,
+) -> Result<Response, AppError> {
+    use play_wasm_loader::{call_wasm_plugin, WasmLimits, WasmSandbox};
+    use std::time::Duration;
+
+    let config = &plugin.config;
+    let sandbox = WasmSandbox {
+        name: plugin.key(),
+        limits: WasmLimits {
+            fuel: config.wasm.fuel,
+            memory_bytes: play_db::parse_chunk_size(&config.wasm.memory_limit)? as usize,
+            timeout: Duration::from_millis(config.wasm.timeout_ms),
+        },
+        files_dir: host.files_dir().map(|dir| dir.to_path_buf()),
+        host,
+    };
+    let plugin_request = to_plugin_request(config, request).await?;
+    let response = call_wasm_plugin(&config.file_path, plugin_request, sandbox).await?;
 
-    let mut headers = HashMap::new();
-    for (name, value) in request.headers() {
-        headers.insert(name.to_string(), value.to_str()?.to_string());
+    if let Some(e) = response.error {
+        return Err(anyhow!("{}", e).into());
+    }
+    let mut resp_builder = Response::builder().status(StatusCode::from_u16(response.status_code)?);
+    for (k, v) in response.headers {
+        resp_builder = resp_builder.header(k, v);
     }
+    Ok(resp_builder
+        .body(Body::from(response.body))?
+        .into_response())
+}
 
-    let method = match request.method().as_str() {
-        "GET" => HttpMethod::GET,
-        "POST" => HttpMethod::POST,
-        "PUT" => HttpMethod::PUT,
-        "DELETE" => HttpMethod::DELETE,
-        _ => return_error!("unsupported http method"),
-    };
+#[cfg(feature = "play-dylib-loader")]
+async fn to_plugin_request(
+    plugin: &PluginConfig,
+    request: Request<Body>,
+) -> Result<play_dylib_loader::HttpRequest, AppError> {
+    use play_dylib_loader::*;
 
-    let plugin_request = HttpRequest {
-        method,
+    let url = request.uri().path();
+    let url = remove_trailing_slash(url);
+
+    // every value in the order they came, repeated names included
+    let headers = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect();
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_canonical().to_string());
+
+    Ok(HttpRequest {
+        method: HttpMethod::from(request.method().as_str()),
         headers,
         query: request.uri().query().unwrap_or_default().to_string(),
+        path_params: plugins::path_params(&plugin.url_prefix, &url).unwrap_or_default(),
         url: url.to_string(),
+        client_ip,
         body: body_to_bytes(request.into_body()).await?,
         rendered_config: if plugin.render_config {
             Some(read_config_file(true).await?)
         } else {
             None
         },
-    };
+    })
+}
+
+#[cfg(feature = "play-dylib-loader")]
+pub async fn inner_run_plugin(
+    plugin: &PluginConfig,
+    request: Request<Body>,
+    host: Arc<crate::plugin_host::PluginHost>,
+) -> Result<Response, AppError> {
+    use play_dylib_loader::*;
 
-    // Use the new coordinated function from loader that handles environment setup
-    let plugin_resp = load_and_run_coordinated(&plugin.file_path, plugin_request).await?;
+    let plugin_request = to_plugin_request(plugin, request).await?;
 
-    if let Some(e) = plugin_resp.error {
+    // the body is streamed to the client while the plugin is still sending it
+    let PluginResponse { head, body } =
+        call_plugin(&plugin.file_path, plugin_request, Some(host)).await?;
+
+    if let Some(e) = head.error {
         Err(anyhow!("{}", e).into())
     } else {
-        let mut resp_builder =
-            Response::builder().status(StatusCode::from_u16(plugin_resp.status_code)?);
-        for (k, v) in plugin_resp.headers {
+        let mut resp_builder = Response::builder().status(StatusCode::from_u16(head.status_code)?);
+        for (k, v) in head.headers {
             resp_builder = resp_builder.header(k, v);
         }
 
-        let response: Response = resp_builder
-            .body(Body::from(plugin_resp.body))?
-            .into_response();
+        let body = futures_util::stream::unfold(body, |mut body| async move {
+            let chunk = body.recv().await?;
+            Some((Ok::<_, std::io::Error>(chunk), body))
+        });
+        let response: Response = resp_builder.body(Body::from_stream(body))?.into_response();
         Ok(response)
     }
 }
 
-async fn body_to_bytes(body: Body) -> anyhow::Result<String> {
+async fn body_to_bytes(body: Body) -> anyhow::Result<Vec<u8>> {
     use http_body_util::BodyExt;
 
-    let bytes = body.collect().await?.to_bytes().to_vec();
-    let body_string = String::from_utf8(bytes)?;
-
-    Ok(body_string)
+    Ok(body.collect().await?.to_bytes().to_vec())
 }
 
 #[cfg(test)]
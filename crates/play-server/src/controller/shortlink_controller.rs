@@ -3,9 +3,10 @@ use std::sync::Arc;
 
 use axum::extract::OriginalUri;
 use axum::response::{Html, IntoResponse, Redirect, Response};
-use http::StatusCode;
+use http::{header, HeaderMap, HeaderName, StatusCode};
 use serde::Deserialize;
 
+use crate::service::file_serve_service;
 use crate::R;
 use crate::{app_error, AppError, AppState, S};
 
@@ -30,6 +31,7 @@ struct ShortlinkPath {
 enum MyResponse {
     Text(String),
     Redirect(Redirect),
+    Raw(Response),
 }
 
 impl IntoResponse for MyResponse {
@@ -37,12 +39,29 @@ impl IntoResponse for MyResponse {
         match self {
             MyResponse::Text(text) => Html(text).into_response(),
             MyResponse::Redirect(redirect) => redirect.into_response(),
+            MyResponse::Raw(response) => response,
         }
     }
 }
 
+/// request headers passed to the download target, so resumed downloads keep working.
+const FORWARD_REQUEST_HEADERS: [HeaderName; 4] = [
+    header::RANGE,
+    header::IF_RANGE,
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+];
+const FORWARD_RESPONSE_HEADERS: [HeaderName; 6] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+    header::ETAG,
+    header::LAST_MODIFIED,
+];
+
 // #[axum::debug_handler]
-async fn link(s: S, OriginalUri(uri): OriginalUri) -> R<MyResponse> {
+async fn link(s: S, OriginalUri(uri): OriginalUri, headers: HeaderMap) -> R<MyResponse> {
     let path = uri.path();
     let shortlink = s
         .config
@@ -52,9 +71,40 @@ async fn link(s: S, OriginalUri(uri): OriginalUri) -> R<MyResponse> {
         .ok_or::<AppError>(app_error!("404 , link not found."))?;
     // s.config.finance
     if shortlink.download {
-        let res = reqwest::get(&shortlink.to).await?.text().await?;
-        Ok(MyResponse::Text(res))
+        Ok(MyResponse::Raw(download(&shortlink.to, &headers).await?))
     } else {
         Ok(MyResponse::Redirect(Redirect::temporary(&shortlink.to)))
     }
 }
+
+/// proxy `url` as an attachment, named after the last segment of its path.
+async fn download(url: &str, headers: &HeaderMap) -> anyhow::Result<Response> {
+    let mut req = reqwest::Client::new().get(url);
+    for name in FORWARD_REQUEST_HEADERS {
+        if let Some(value) = headers.get(&name) {
+            req = req.header(name, value);
+        }
+    }
+    let resp = req.send().await?;
+
+    let filename = resp
+        .url()
+        .path_segments()
+        .and_then(|segments| segments.last())
+        .filter(|name| !name.is_empty())
+        .and_then(|name| urlencoding::decode(name).ok())
+        .map(|name| name.into_owned())
+        .unwrap_or_else(|| "download".to_string());
+
+    let mut builder = Response::builder().status(resp.status().as_u16()).header(
+        header::CONTENT_DISPOSITION,
+        file_serve_service::content_disposition("attachment", &filename),
+    );
+    for name in FORWARD_RESPONSE_HEADERS {
+        if let Some(value) = resp.headers().get(name.as_str()) {
+            builder = builder.header(name, value.as_bytes());
+        }
+    }
+
+    Ok(builder.body(Body::from_stream(resp.bytes_stream()))?)
+}
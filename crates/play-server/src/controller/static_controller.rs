@@ -1,14 +1,17 @@
 use anyhow::anyhow;
-use axum::body::Body;
+use axum::body::{Body, Bytes};
 use axum::extract::Path;
-use axum::http::{header, HeaderValue, StatusCode};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
 use include_dir::{include_dir, Dir};
 use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 use tower_http::services::ServeDir;
 
+use crate::service::file_serve_service;
+use crate::service::file_serve_service::{FileMeta, FileSource};
 use crate::{AppError, AppState, R, S};
 
 pub static STATIC_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/static");
@@ -26,7 +29,12 @@ pub fn init() -> Router<Arc<AppState>> {
     )
 }
 
-async fn static_path(s: S, Path(path): Path<String>) -> R<impl IntoResponse> {
+async fn static_path(
+    s: S,
+    Path(path): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> R<impl IntoResponse> {
     let path = path.trim_start_matches('/');
     let mime_type = mime_guess::from_path(path).first_or_text_plain();
 
@@ -34,15 +42,34 @@ async fn static_path(s: S, Path(path): Path<String>) -> R<impl IntoResponse> {
         None => Ok(Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body(Body::empty())?),
-        Some(file) => Ok(Response::builder()
-            .status(StatusCode::OK)
-            .header(
-                header::CONTENT_TYPE,
-                HeaderValue::from_str(mime_type.as_ref())?,
-            )
-            .header("Cross-Origin-Opener-Policy", "same-origin")
-            .header("Cross-Origin-Embedder-Policy", "require-corp")
-            .header("x-compress", "1")
-            .body(Body::from(file.contents()))?),
+        Some(file) => {
+            // embedded files only change with a new build
+            let built_time = UNIX_EPOCH + Duration::from_millis(env!("BUILT_TIME").parse::<u64>()?);
+            let meta = FileMeta::new(file.contents().len() as u64, Some(built_time));
+            let mut response = file_serve_service::build_response(
+                FileSource::Bytes(Bytes::from_static(file.contents())),
+                &meta,
+                mime_type.as_ref(),
+                &method,
+                &headers,
+            )?;
+
+            let res_headers = response.headers_mut();
+            res_headers.insert(
+                "Cross-Origin-Opener-Policy",
+                HeaderValue::from_static("same-origin"),
+            );
+            res_headers.insert(
+                "Cross-Origin-Embedder-Policy",
+                HeaderValue::from_static("require-corp"),
+            );
+            // partial content must not be compressed
+            if response.status() == StatusCode::OK {
+                response
+                    .headers_mut()
+                    .insert("x-compress", HeaderValue::from_static("1"));
+            }
+            Ok(response)
+        }
     }
 }
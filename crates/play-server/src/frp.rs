@@ -1,14 +1,73 @@
 use std::collections::BTreeMap;
+#[cfg(feature = "frp-server")]
+use std::net::SocketAddr;
+use std::path::PathBuf;
+#[cfg(feature = "frp-server")]
+use std::sync::{Arc, RwLock};
 
+#[cfg(feature = "frp-server")]
+use anyhow::bail;
 use anyhow::{Context, Result};
 use serde::Serialize;
 use tokio::fs;
-#[cfg(feature = "frp-server")]
-use tokio::sync::broadcast;
 use tokio::sync::oneshot;
+#[cfg(feature = "frp-server")]
+use tokio::sync::{broadcast, Mutex};
 use tracing::{error, info, warn};
 
-use crate::config::{FrpServerConfig, FrpServerServiceConfig, FrpTransportConfig};
+#[cfg(feature = "frp-server")]
+use crate::config::replace_config_section;
+#[cfg(feature = "frp-server")]
+use crate::config::FrpServiceType;
+use crate::config::{get_config_path, FrpServerConfig, FrpServerServiceConfig, FrpTransportConfig};
+
+/// the running server's config, changed at runtime through rathole's config hot reload.
+#[cfg(feature = "frp-server")]
+struct FrpRuntime {
+    config: Mutex<FrpServerConfig>,
+    runtime_config_path: PathBuf,
+    /// the config file runtime changes are saved to, so they survive a restart.
+    config_file: Option<PathBuf>,
+}
+
+#[cfg(feature = "frp-server")]
+static FRP_RUNTIME: RwLock<Option<Arc<FrpRuntime>>> = RwLock::new(None);
+
+#[cfg(feature = "frp-server")]
+#[derive(Serialize, Debug)]
+pub struct FrpStatus {
+    pub bind_addr: String,
+    pub services: Vec<FrpServiceStatus>,
+    /// oldest first
+    pub recent_errors: Vec<FrpConnectionError>,
+}
+
+#[cfg(feature = "frp-server")]
+#[derive(Serialize, Debug)]
+pub struct FrpServiceStatus {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub service_type: FrpServiceType,
+    pub bind_addr: String,
+    /// false when the service uses `default_token`
+    pub own_token: bool,
+    pub connected: bool,
+    pub client_addr: Option<SocketAddr>,
+    pub connected_since: Option<u64>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub active_connections: u64,
+    pub total_connections: u64,
+}
+
+#[cfg(feature = "frp-server")]
+#[derive(Serialize, Debug)]
+pub struct FrpConnectionError {
+    pub time: u64,
+    pub service: Option<String>,
+    pub addr: Option<SocketAddr>,
+    pub message: String,
+}
 
 #[cfg(feature = "frp-server")]
 #[derive(Serialize)]
@@ -22,7 +81,8 @@ struct FrpServerRuntimeConfig {
     bind_addr: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     default_token: Option<String>,
-    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    // rathole requires the table, services may be added later at runtime
+    #[serde(default)]
     services: BTreeMap<String, FrpServerServiceConfig>,
     #[serde(default)]
     transport: FrpTransportConfig,
@@ -74,6 +134,7 @@ impl FrpServerHandle {
     pub async fn shutdown(self) {
         #[cfg(feature = "frp-server")]
         {
+            FRP_RUNTIME.write().unwrap().take();
             let _ = self.shutdown_tx.send(true);
             if let Err(error) = self.join_handle.await {
                 warn!("FRP server join failed: {}", error);
@@ -95,7 +156,9 @@ pub fn maybe_start_frp_server_in_background(
     let join_handle = tokio::spawn(async move {
         info!("Starting embedded FRP service in background");
 
-        let startup = maybe_start_frp_server(&config);
+        // services and tokens changed from the admin page are written back to config.toml
+        let config_file = get_config_path().ok().map(PathBuf::from);
+        let startup = maybe_start_frp_server(&config, config_file);
         tokio::pin!(startup);
 
         let handle = tokio::select! {
@@ -130,7 +193,12 @@ pub fn maybe_start_frp_server_in_background(
     })
 }
 
-pub async fn maybe_start_frp_server(config: &FrpServerConfig) -> Result<Option<FrpServerHandle>> {
+/// `config_file` gets the `[frp_server]` table rewritten on every runtime change, None keeps
+/// the changes in memory.
+pub async fn maybe_start_frp_server(
+    config: &FrpServerConfig,
+    config_file: Option<PathBuf>,
+) -> Result<Option<FrpServerHandle>> {
     if !config.enabled {
         return Ok(None);
     }
@@ -161,6 +229,12 @@ pub async fn maybe_start_frp_server(config: &FrpServerConfig) -> Result<Option<F
             runtime_config_path.display()
         );
 
+        *FRP_RUNTIME.write().unwrap() = Some(Arc::new(FrpRuntime {
+            config: Mutex::new(config.clone()),
+            runtime_config_path,
+            config_file,
+        }));
+
         let join_handle = tokio::spawn(async move {
             if let Err(error) = rathole::run(args, shutdown_rx).await {
                 error!("Embedded FRP server exited with error: {error:#}");
@@ -178,6 +252,7 @@ pub async fn maybe_start_frp_server(config: &FrpServerConfig) -> Result<Option<F
 
     #[cfg(not(feature = "frp-server"))]
     {
+        let _ = config_file;
         warn!(
             "frp_server.enabled is true, but play-server was built without the `frp-server` feature. The FRP server config in main config.toml is ignored"
         );
@@ -191,9 +266,7 @@ async fn write_runtime_config(
 ) -> Result<(tempfile::TempDir, std::path::PathBuf)> {
     let runtime_dir = tempfile::tempdir().context("create temporary FRP runtime dir failed")?;
     let runtime_config_path = runtime_dir.path().join("rathole-server.toml");
-    let runtime_config = FrpServerRuntimeDocument::from(config);
-    let runtime_config_text =
-        toml::to_string(&runtime_config).context("serialize embedded FRP server config failed")?;
+    let runtime_config_text = render_runtime_config(config)?;
 
     if let Some(parent) = runtime_config_path.parent() {
         fs::create_dir_all(parent)
@@ -213,6 +286,183 @@ async fn write_runtime_config(
     Ok((runtime_dir, runtime_config_path))
 }
 
+#[cfg(feature = "frp-server")]
+fn render_runtime_config(config: &FrpServerConfig) -> Result<String> {
+    let runtime_config = FrpServerRuntimeDocument::from(config);
+    toml::to_string(&runtime_config).context("serialize embedded FRP server config failed")
+}
+
+#[cfg(feature = "frp-server")]
+fn runtime() -> Result<Arc<FrpRuntime>> {
+    FRP_RUNTIME
+        .read()
+        .unwrap()
+        .clone()
+        .context("the embedded FRP server is not running")
+}
+
+/// services of the running server with their connection state, None when it is not running.
+#[cfg(feature = "frp-server")]
+pub async fn status() -> Option<FrpStatus> {
+    let runtime = FRP_RUNTIME.read().unwrap().clone()?;
+    let config = runtime.config.lock().await;
+    let stats = rathole::server_stats();
+    let snapshots = stats.services();
+
+    let services = config
+        .services
+        .iter()
+        .map(|(name, service)| {
+            let snapshot = snapshots.iter().find(|s| &s.name == name);
+            FrpServiceStatus {
+                name: name.clone(),
+                service_type: service.service_type,
+                bind_addr: service.bind_addr.clone(),
+                own_token: service.token.is_some(),
+                connected: snapshot.is_some_and(|s| s.connected),
+                client_addr: snapshot.and_then(|s| s.client_addr),
+                connected_since: snapshot.and_then(|s| s.connected_since),
+                bytes_in: snapshot.map_or(0, |s| s.bytes_in),
+                bytes_out: snapshot.map_or(0, |s| s.bytes_out),
+                active_connections: snapshot.map_or(0, |s| s.active_connections),
+                total_connections: snapshot.map_or(0, |s| s.total_connections),
+            }
+        })
+        .collect();
+
+    let recent_errors = stats
+        .recent_errors()
+        .into_iter()
+        .map(|e| FrpConnectionError {
+            time: e.time,
+            service: e.service,
+            addr: e.addr,
+            message: e.message,
+        })
+        .collect();
+
+    Some(FrpStatus {
+        bind_addr: config.bind_addr.clone(),
+        services,
+        recent_errors,
+    })
+}
+
+/// a stream to the local service behind the connected FRP client, used by `tunnel` proxy targets.
+pub async fn open_tunnel(service: &str) -> Result<tokio::io::DuplexStream> {
+    #[cfg(feature = "frp-server")]
+    {
+        rathole::open_service_stream(service).await
+    }
+
+    #[cfg(not(feature = "frp-server"))]
+    {
+        anyhow::bail!(
+            "tunnel {} needs play-server built with the `frp-server` feature",
+            service
+        )
+    }
+}
+
+/// add a service or replace its config, connected clients of a changed service reconnect.
+#[cfg(feature = "frp-server")]
+pub async fn upsert_service(name: &str, service: FrpServerServiceConfig) -> Result<()> {
+    if name.trim().is_empty() {
+        bail!("service name is empty");
+    }
+    // tcp services without bind_addr are only reachable as a domain_proxy tunnel
+    let tunnel_only =
+        service.bind_addr.is_empty() && matches!(service.service_type, FrpServiceType::Tcp);
+    if !tunnel_only {
+        service
+            .bind_addr
+            .parse::<SocketAddr>()
+            .with_context(|| format!("invalid bind_addr : {}", service.bind_addr))?;
+    }
+    update_config(|config| {
+        if let Some((other, _)) = config
+            .services
+            .iter()
+            .find(|(n, s)| *n != name && !tunnel_only && s.bind_addr == service.bind_addr)
+        {
+            bail!("{} is already used by service {}", service.bind_addr, other);
+        }
+        config.services.insert(name.to_string(), service);
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(feature = "frp-server")]
+pub async fn remove_service(name: &str) -> Result<()> {
+    update_config(|config| {
+        config
+            .services
+            .remove(name)
+            .with_context(|| format!("no such service : {}", name))?;
+        Ok(())
+    })
+    .await
+}
+
+/// a new random token for `service`, or `default_token` when None.
+/// changing `default_token` restarts the FRP server and all clients have to reconnect.
+#[cfg(feature = "frp-server")]
+pub async fn rotate_token(service: Option<&str>) -> Result<String> {
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    update_config(|config| {
+        match service {
+            Some(name) => {
+                config
+                    .services
+                    .get_mut(name)
+                    .with_context(|| format!("no such service : {}", name))?
+                    .token = Some(token.clone());
+            }
+            None => config.default_token = Some(token.clone()),
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(token)
+}
+
+/// validate the changed config, save it to the config file and write it where rathole's
+/// config watcher picks it up.
+#[cfg(feature = "frp-server")]
+async fn update_config(change: impl FnOnce(&mut FrpServerConfig) -> Result<()>) -> Result<()> {
+    let runtime = runtime()?;
+    let mut config = runtime.config.lock().await;
+    let mut new_config = config.clone();
+    change(&mut new_config)?;
+    let text = render_runtime_config(&new_config)?;
+
+    // the watcher only reacts to the runtime config itself, so check a copy first
+    let check_path = runtime.runtime_config_path.with_extension("check.toml");
+    fs::write(&check_path, &text)
+        .await
+        .with_context(|| format!("write {}", check_path.display()))?;
+    let checked = rathole::Config::from_file(&check_path).await;
+    let _ = fs::remove_file(&check_path).await;
+    checked.context("invalid FRP server config")?;
+
+    if let Some(config_file) = &runtime.config_file {
+        let content = fs::read_to_string(config_file)
+            .await
+            .with_context(|| format!("read {}", config_file.display()))?;
+        let content = replace_config_section(&content, "frp_server", &new_config)?;
+        fs::write(config_file, content)
+            .await
+            .with_context(|| format!("write {}", config_file.display()))?;
+    }
+    fs::write(&runtime.runtime_config_path, text)
+        .await
+        .with_context(|| format!("write {}", runtime.runtime_config_path.display()))?;
+    *config = new_config;
+    info!("embedded FRP server config updated, rathole reloads it");
+    Ok(())
+}
+
 #[cfg(all(test, feature = "frp-server"))]
 mod tests {
     use super::*;
@@ -220,14 +470,18 @@ mod tests {
     use std::time::Duration;
 
     use axum::{routing::get, Router};
-    use tokio::sync::{broadcast, oneshot};
+    use tokio::sync::{broadcast, oneshot, Mutex};
     use tokio::task::JoinHandle;
     use tokio::time::{sleep, Instant};
 
     const SMOKE_RESPONSE: &str = "FRP_SMOKE_OK";
 
+    /// the runtime config is global, run one embedded server at a time
+    static SERVER_LOCK: Mutex<()> = Mutex::const_new(());
+
     #[tokio::test]
     async fn embedded_frp_server_proxies_http_traffic() -> Result<()> {
+        let _lock = SERVER_LOCK.lock().await;
         let temp_dir = tempfile::tempdir().context("create temp data dir failed")?;
 
         let backend_port = free_port()?;
@@ -264,14 +518,17 @@ local_addr = "127.0.0.1:{backend_port}"
             },
         );
 
-        let server_handle = maybe_start_frp_server(&FrpServerConfig {
-            enabled: true,
-            bind_addr: format!("127.0.0.1:{frp_bind_port}"),
-            default_token: Some("smoke_token".to_string()),
-            services,
-            transport: FrpTransportConfig::default(),
-            heartbeat_interval: 30,
-        })
+        let server_handle = maybe_start_frp_server(
+            &FrpServerConfig {
+                enabled: true,
+                bind_addr: format!("127.0.0.1:{frp_bind_port}"),
+                default_token: Some("smoke_token".to_string()),
+                services,
+                transport: FrpTransportConfig::default(),
+                heartbeat_interval: 30,
+            },
+            None,
+        )
         .await?
         .expect("expected embedded FRP server to start");
 
@@ -299,6 +556,236 @@ local_addr = "127.0.0.1:{backend_port}"
         Ok(())
     }
 
+    #[tokio::test]
+    async fn embedded_frp_server_manages_services_at_runtime() -> Result<()> {
+        let _lock = SERVER_LOCK.lock().await;
+        let temp_dir = tempfile::tempdir().context("create temp data dir failed")?;
+
+        let backend_port = free_port()?;
+        let tunnel_port = free_port()?;
+        let frp_bind_port = free_port()?;
+        let config_file = temp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            "server_port = 3000\n\n[frp_server]\nenabled = true\n",
+        )
+        .await?;
+        let saved_services = || async {
+            let table: toml::Table = toml::from_str(&fs::read_to_string(&config_file).await?)?;
+            anyhow::Ok(
+                table["frp_server"]["services"]
+                    .as_table()
+                    .cloned()
+                    .unwrap_or_default(),
+            )
+        };
+
+        let server_handle = maybe_start_frp_server(
+            &FrpServerConfig {
+                enabled: true,
+                bind_addr: format!("127.0.0.1:{frp_bind_port}"),
+                default_token: Some("manage_token".to_string()),
+                services: BTreeMap::new(),
+                transport: FrpTransportConfig::default(),
+                heartbeat_interval: 30,
+            },
+            Some(config_file.clone()),
+        )
+        .await?
+        .expect("expected embedded FRP server to start");
+        // give rathole's config watcher time to start watching
+        sleep(Duration::from_millis(500)).await;
+
+        let service = FrpServerServiceConfig {
+            service_type: crate::config::FrpServiceType::Tcp,
+            bind_addr: format!("127.0.0.1:{tunnel_port}"),
+            token: None,
+            nodelay: None,
+        };
+        assert!(upsert_service(
+            "managed_http",
+            FrpServerServiceConfig {
+                bind_addr: "not an address".to_string(),
+                ..service.clone()
+            }
+        )
+        .await
+        .is_err());
+        upsert_service("managed_http", service).await?;
+        assert!(saved_services().await?.contains_key("managed_http"));
+
+        let client_config_path = temp_dir.path().join("client.toml");
+        fs::write(
+            &client_config_path,
+            format!(
+                r#"[client]
+remote_addr = "127.0.0.1:{frp_bind_port}"
+default_token = "manage_token"
+retry_interval = 1
+
+[client.services.managed_http]
+local_addr = "127.0.0.1:{backend_port}"
+"#
+            ),
+        )
+        .await
+        .with_context(|| format!("write {}", client_config_path.display()))?;
+
+        let (backend_shutdown_tx, backend_handle) = start_backend_server(backend_port).await?;
+        let (client_shutdown_tx, client_shutdown_rx) = broadcast::channel(1);
+        let client_args = rathole::Cli {
+            config_path: Some(client_config_path.clone()),
+            server: false,
+            client: true,
+            genkey: None,
+        };
+        let client_handle =
+            tokio::spawn(async move { rathole::run(client_args, client_shutdown_rx).await });
+
+        let result = async {
+            wait_for_tunnel(tunnel_port).await?;
+            let current = status().await.context("FRP server is not running")?;
+            let managed = &current.services[0];
+            assert_eq!(managed.name, "managed_http");
+            assert!(managed.connected);
+            assert!(!managed.own_token);
+            assert!(managed.client_addr.unwrap().ip().is_loopback());
+            assert!(managed.bytes_in > 0 && managed.bytes_out > 0);
+
+            // the client still uses the old token and fails to authenticate
+            let token = rotate_token(Some("managed_http")).await?;
+            assert_eq!(
+                saved_services().await?["managed_http"]["token"].as_str(),
+                Some(token.as_str())
+            );
+            let deadline = Instant::now() + Duration::from_secs(20);
+            loop {
+                let current = status().await.context("FRP server is not running")?;
+                let auth_failed = current.recent_errors.iter().any(|e| {
+                    e.service.as_deref() == Some("managed_http")
+                        && e.message.contains("authentication")
+                });
+                if auth_failed {
+                    assert!(current.services[0].own_token);
+                    break;
+                }
+                if Instant::now() > deadline {
+                    anyhow::bail!("no authentication error after rotating the token");
+                }
+                sleep(Duration::from_millis(250)).await;
+            }
+
+            remove_service("managed_http").await?;
+            assert!(status().await.unwrap().services.is_empty());
+            assert!(saved_services().await?.is_empty());
+            assert!(remove_service("managed_http").await.is_err());
+            Ok(())
+        }
+        .await;
+
+        let _ = client_shutdown_tx.send(true);
+        let _ = client_handle.await;
+        server_handle.shutdown().await;
+        assert!(status().await.is_none());
+        let _ = backend_shutdown_tx.send(());
+        backend_handle.await.context("join backend task failed")??;
+        result
+    }
+
+    #[tokio::test]
+    async fn embedded_frp_server_opens_tunnel_streams() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let _lock = SERVER_LOCK.lock().await;
+        let temp_dir = tempfile::tempdir().context("create temp data dir failed")?;
+
+        let backend_port = free_port()?;
+        let frp_bind_port = free_port()?;
+
+        let client_config_path = temp_dir.path().join("client.toml");
+        fs::write(
+            &client_config_path,
+            format!(
+                r#"[client]
+remote_addr = "127.0.0.1:{frp_bind_port}"
+default_token = "tunnel_token"
+
+[client.services.tunnel_http]
+local_addr = "127.0.0.1:{backend_port}"
+"#
+            ),
+        )
+        .await
+        .with_context(|| format!("write {}", client_config_path.display()))?;
+
+        // no bind_addr, only reachable in-process
+        let mut services = BTreeMap::new();
+        services.insert(
+            "tunnel_http".to_string(),
+            FrpServerServiceConfig {
+                service_type: crate::config::FrpServiceType::Tcp,
+                bind_addr: String::new(),
+                token: None,
+                nodelay: None,
+            },
+        );
+        let server_handle = maybe_start_frp_server(
+            &FrpServerConfig {
+                enabled: true,
+                bind_addr: format!("127.0.0.1:{frp_bind_port}"),
+                default_token: Some("tunnel_token".to_string()),
+                services,
+                transport: FrpTransportConfig::default(),
+                heartbeat_interval: 30,
+            },
+            None,
+        )
+        .await?
+        .expect("expected embedded FRP server to start");
+        assert!(open_tunnel("missing").await.is_err());
+
+        let (backend_shutdown_tx, backend_handle) = start_backend_server(backend_port).await?;
+        let (client_shutdown_tx, client_shutdown_rx) = broadcast::channel(1);
+        let client_args = rathole::Cli {
+            config_path: Some(client_config_path.clone()),
+            server: false,
+            client: true,
+            genkey: None,
+        };
+        let client_handle =
+            tokio::spawn(async move { rathole::run(client_args, client_shutdown_rx).await });
+
+        let deadline = Instant::now() + Duration::from_secs(20);
+        let result = loop {
+            let response = async {
+                let mut stream = open_tunnel("tunnel_http").await?;
+                stream
+                    .write_all(
+                        b"GET / HTTP/1.1\r\nHost: app.example.com\r\nConnection: close\r\n\r\n",
+                    )
+                    .await?;
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await?;
+                anyhow::Ok(response)
+            }
+            .await;
+            match response {
+                Ok(response) if response.contains(SMOKE_RESPONSE) => break Ok(()),
+                _ if Instant::now() > deadline => {
+                    break Err(anyhow::anyhow!("tunnel stream failed : {:?}", response))
+                }
+                _ => sleep(Duration::from_millis(250)).await,
+            }
+        };
+
+        let _ = client_shutdown_tx.send(true);
+        let _ = client_handle.await;
+        server_handle.shutdown().await;
+        let _ = backend_shutdown_tx.send(());
+        backend_handle.await.context("join backend task failed")??;
+        result
+    }
+
     async fn start_backend_server(
         port: u16,
     ) -> Result<(oneshot::Sender<()>, JoinHandle<Result<()>>)> {
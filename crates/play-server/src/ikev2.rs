@@ -1,6 +1,8 @@
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, bail, Context, Result};
 use rcgen::{
@@ -8,11 +10,14 @@ use rcgen::{
     ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair, KeyUsagePurpose, RsaKeySize, SanType,
     PKCS_RSA_SHA256,
 };
+use serde::{Deserialize, Serialize};
 use tokio::fs;
 use tokio::sync::oneshot;
 use tracing::{debug, info, warn};
 
 use crate::config::Ikev2ServerConfig;
+use crate::tables::general_data::GeneralData;
+use crate::tables::DBPool;
 use play_shared::constants::DATA_DIR;
 
 #[cfg(all(feature = "ikev2-server", target_os = "linux"))]
@@ -22,8 +27,17 @@ use tokio::io::{AsyncBufReadExt, BufReader};
 #[cfg(all(feature = "ikev2-server", target_os = "linux"))]
 use tokio::process::{Child, Command};
 #[cfg(all(feature = "ikev2-server", target_os = "linux"))]
+use std::sync::{Arc, RwLock};
+#[cfg(all(feature = "ikev2-server", target_os = "linux"))]
 use tokio::time::{sleep, Duration, Instant};
 
+pub const CAT_IKEV2_USERS: &str = "ikev2_users";
+
+const SERVER_CERT_NAME: &str = "play-server-cert.pem";
+
+#[cfg(all(feature = "ikev2-server", target_os = "linux"))]
+static IKEV2_RUNTIME: RwLock<Option<Arc<Ikev2Runtime>>> = RwLock::new(None);
+
 pub struct Ikev2ServerHandle {
     #[cfg(all(feature = "ikev2-server", target_os = "linux"))]
     child: Child,
@@ -52,6 +66,8 @@ impl Ikev2ServerHandle {
     pub async fn shutdown(mut self) {
         #[cfg(all(feature = "ikev2-server", target_os = "linux"))]
         {
+            *IKEV2_RUNTIME.write().unwrap() = None;
+
             if let Err(error) = self.child.start_kill() {
                 warn!("Failed to stop IKEv2 daemon child: {}", error);
             }
@@ -65,6 +81,7 @@ impl Ikev2ServerHandle {
 
 pub fn maybe_start_ikev2_server_in_background(
     config: &Ikev2ServerConfig,
+    db: DBPool,
 ) -> Option<Ikev2BackgroundHandle> {
     if !config.enabled {
         return None;
@@ -76,7 +93,11 @@ pub fn maybe_start_ikev2_server_in_background(
     let join_handle = tokio::spawn(async move {
         info!("Starting embedded IKEv2 service in background");
 
-        let startup = maybe_start_ikev2_server(&config);
+        let startup = async {
+            seed_users_from_config(&config, &db).await?;
+            let config = with_stored_users(&config, &db).await?;
+            maybe_start_ikev2_server(&config).await
+        };
         tokio::pin!(startup);
 
         let handle = tokio::select! {
@@ -173,6 +194,15 @@ pub async fn maybe_start_ikev2_server(
             config.port_nat_t
         );
 
+        *IKEV2_RUNTIME.write().unwrap() = Some(Arc::new(Ikev2Runtime {
+            config: config.clone(),
+            swanctl_bin: binaries.swanctl_bin.clone(),
+            swanctl_dir: runtime.swanctl_dir.clone(),
+            swanctl_conf_path: runtime.swanctl_conf_path.clone(),
+            vici_uri,
+            reload_lock: tokio::sync::Mutex::new(()),
+        }));
+
         return Ok(Some(Ikev2ServerHandle {
             child,
             _runtime_dir: runtime.root,
@@ -381,6 +411,71 @@ struct RuntimeFiles {
     vici_socket_path: PathBuf,
 }
 
+#[cfg(all(feature = "ikev2-server", target_os = "linux"))]
+struct Ikev2Runtime {
+    config: Ikev2ServerConfig,
+    swanctl_bin: PathBuf,
+    swanctl_dir: PathBuf,
+    swanctl_conf_path: PathBuf,
+    vici_uri: String,
+    // swanctl.conf is rewritten on every reload, one at a time
+    reload_lock: tokio::sync::Mutex<()>,
+}
+
+#[cfg(all(feature = "ikev2-server", target_os = "linux"))]
+impl Ikev2Runtime {
+    async fn swanctl(&self, args: &[&str], action: &str) -> Result<String> {
+        let output = Command::new(&self.swanctl_bin)
+            .args(args)
+            .arg("--uri")
+            .arg(&self.vici_uri)
+            .env("SWANCTL_DIR", &self.swanctl_dir)
+            .output()
+            .await
+            .with_context(|| format!("run `{}` to {}", self.swanctl_bin.display(), action))?;
+
+        if !output.status.success() {
+            bail!(
+                "{} {} failed: stdout=`{}` stderr=`{}`",
+                self.swanctl_bin.display(),
+                args.join(" "),
+                String::from_utf8_lossy(&output.stdout).trim(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    async fn reload_users(&self, db: &DBPool) -> Result<()> {
+        let _guard = self.reload_lock.lock().await;
+        let config = with_stored_users(&self.config, db).await?;
+        fs::write(
+            &self.swanctl_conf_path,
+            render_swanctl_conf(&config, SERVER_CERT_NAME),
+        )
+        .await
+        .with_context(|| format!("write {}", self.swanctl_conf_path.display()))?;
+
+        let swanctl_conf_path = self.swanctl_conf_path.to_string_lossy();
+        self.swanctl(
+            &["--load-creds", "--clear", "--noprompt", "--file", &swanctl_conf_path],
+            "reload IKEv2 credentials",
+        )
+        .await?;
+        info!(
+            "Reloaded {} enabled IKEv2 EAP users into charon",
+            config.eap_users.len()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "ikev2-server", target_os = "linux"))]
+fn runtime() -> Option<Arc<Ikev2Runtime>> {
+    IKEV2_RUNTIME.read().unwrap().clone()
+}
+
 #[derive(Debug)]
 struct ResolvedRuntimeBinaries {
     daemon_bin: PathBuf,
@@ -721,7 +816,7 @@ async fn validate_config(config: &Ikev2ServerConfig) -> Result<()> {
         bail!("ikev2_server.pool must not be empty");
     }
     if config.eap_users.is_empty() {
-        bail!("ikev2_server.eap_users must contain at least one user");
+        bail!("no enabled IKEv2 user: add one under ikev2_server.eap_users or from /admin/ikev2/users");
     }
 
     ensure_file_exists(&resolve_config_path(&config.server_cert)?).await?;
@@ -821,13 +916,12 @@ async fn write_runtime_files(config: &Ikev2ServerConfig) -> Result<RuntimeFiles>
         .await
         .with_context(|| format!("create {}", x509ca_dir.display()))?;
 
-    let server_cert_name = "play-server-cert.pem".to_string();
     let server_key_name = "play-server-key.pem".to_string();
 
     copy_file_into_dir(
         &resolve_config_path(&config.server_cert)?,
         &x509_dir,
-        &server_cert_name,
+        SERVER_CERT_NAME,
     )
     .await?;
     copy_file_into_dir(
@@ -853,7 +947,7 @@ async fn write_runtime_files(config: &Ikev2ServerConfig) -> Result<RuntimeFiles>
         .await
         .with_context(|| format!("write {}", strongswan_conf_path.display()))?;
 
-    let swanctl_conf = render_swanctl_conf(config, &server_cert_name);
+    let swanctl_conf = render_swanctl_conf(config, SERVER_CERT_NAME);
     fs::write(&swanctl_conf_path, swanctl_conf)
         .await
         .with_context(|| format!("write {}", swanctl_conf_path.display()))?;
@@ -876,15 +970,15 @@ async fn copy_file_into_dir(source: &Path, target_dir: &Path, target_name: &str)
 }
 
 #[derive(Debug)]
-struct ResolvedCredentialPaths {
+pub(crate) struct ResolvedCredentialPaths {
     bundle_dir: PathBuf,
     server_cert: PathBuf,
     server_key: PathBuf,
-    ca_cert: PathBuf,
-    ca_key: PathBuf,
+    pub(crate) ca_cert: PathBuf,
+    pub(crate) ca_key: PathBuf,
 }
 
-fn resolved_credential_paths(config: &Ikev2ServerConfig) -> Result<ResolvedCredentialPaths> {
+pub(crate) fn resolved_credential_paths(config: &Ikev2ServerConfig) -> Result<ResolvedCredentialPaths> {
     let server_cert = resolve_config_path(&config.server_cert)?;
     let server_key = resolve_config_path(&config.server_key)?;
     let ca_cert = resolve_config_path(
@@ -1004,7 +1098,7 @@ fn build_ca_issuer(config: &Ikev2ServerConfig) -> Result<(String, String, Issuer
     Ok((ca_cert.pem(), ca_key_pem, issuer))
 }
 
-fn generate_ikev2_rsa_key_pair(purpose: &str) -> Result<KeyPair> {
+pub(crate) fn generate_ikev2_rsa_key_pair(purpose: &str) -> Result<KeyPair> {
     KeyPair::generate_rsa_for(&PKCS_RSA_SHA256, RsaKeySize::_2048)
         .with_context(|| format!("generate IKEv2 {purpose} RSA private key failed"))
 }
@@ -1085,6 +1179,11 @@ fn derive_ca_key_path(ca_cert_path: &Path) -> PathBuf {
 
 fn render_strongswan_conf(config: &Ikev2ServerConfig, vici_socket_path: &Path) -> String {
     let handshake_level = config.log_level.max(2);
+    let eap_dynamic = if config.eap_tls {
+        "    eap-dynamic {\n      preferred = mschapv2, tls\n    }\n"
+    } else {
+        ""
+    };
     format!(
         r#"charon {{
   port = {}
@@ -1108,7 +1207,7 @@ fn render_strongswan_conf(config: &Ikev2ServerConfig, vici_socket_path: &Path) -
     vici {{
       socket = {}
     }}
-  }}
+{eap_dynamic}  }}
 }}
 "#,
         config.port,
@@ -1157,7 +1256,11 @@ fn render_swanctl_conf(
     ));
     text.push_str("    }\n");
     text.push_str("    remote {\n");
-    text.push_str("      auth = eap-mschapv2\n");
+    if config.eap_tls {
+        text.push_str("      auth = eap-dynamic\n");
+    } else {
+        text.push_str("      auth = eap-mschapv2\n");
+    }
     text.push_str("      eap_id = %any\n");
     text.push_str("    }\n");
     text.push_str("    children {\n");
@@ -1186,8 +1289,13 @@ fn render_swanctl_conf(
     text.push_str("}\n\n");
 
     text.push_str("secrets {\n");
-    for (user, password) in &config.eap_users {
-        text.push_str(&format!("  eap-{} {{\n", sanitize_section_name(user)));
+    for (index, (user, password)) in config.eap_users.iter().enumerate() {
+        // sanitized names may collide, the index keeps the sections apart
+        text.push_str(&format!(
+            "  eap-{}-{} {{\n",
+            index,
+            sanitize_section_name(user)
+        ));
         text.push_str(&format!("    id = {}\n", quote_value(user)));
         text.push_str(&format!("    secret = {}\n", quote_value(password)));
         text.push_str("  }\n");
@@ -1341,6 +1449,395 @@ fn render_connection_local_addrs(config: &Ikev2ServerConfig) -> String {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct StoredEapUser {
+    pub(crate) name: String,
+    pub(crate) password: String,
+    #[serde(default)]
+    pub(crate) disabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ikev2User {
+    pub name: String,
+    pub disabled: bool,
+    // unix timestamp in milliseconds
+    pub updated: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct Ikev2Sa {
+    pub ike_id: u64,
+    pub connection: String,
+    pub state: String,
+    pub remote_addr: String,
+    pub remote_id: String,
+    // the EAP identity the client authenticated with
+    pub user: Option<String>,
+    pub virtual_ips: Vec<String>,
+    // unix timestamp in seconds
+    pub connected_since: Option<u64>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub child_sas: Vec<Ikev2ChildSa>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct Ikev2ChildSa {
+    pub child_id: u64,
+    pub name: String,
+    pub state: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub remote_ts: Vec<String>,
+}
+
+async fn query_stored_users(db: &DBPool) -> Result<Vec<(GeneralData, StoredEapUser)>> {
+    let rows = GeneralData::query_by_cat_simple(CAT_IKEV2_USERS, 10000, db).await?;
+    rows.into_iter()
+        .filter(|row| !row.is_deleted)
+        .map(|row| {
+            let user = serde_json::from_str(&row.data)
+                .with_context(|| format!("invalid IKEv2 user record #{}", row.id))?;
+            Ok((row, user))
+        })
+        .collect()
+}
+
+async fn seed_users_from_config(config: &Ikev2ServerConfig, db: &DBPool) -> Result<()> {
+    if config.eap_users.is_empty() || GeneralData::query_count(CAT_IKEV2_USERS, db).await? > 0 {
+        return Ok(());
+    }
+
+    for (name, password) in &config.eap_users {
+        let user = StoredEapUser {
+            name: name.to_string(),
+            password: password.to_string(),
+            disabled: false,
+        };
+        GeneralData::insert(CAT_IKEV2_USERS, &serde_json::to_string(&user)?, db).await?;
+    }
+    warn!(
+        "Imported {} IKEv2 EAP users from ikev2_server.eap_users into the `{}` data table. Manage them from /admin/ikev2/users and remove them from config.toml",
+        config.eap_users.len(),
+        CAT_IKEV2_USERS
+    );
+    Ok(())
+}
+
+async fn with_stored_users(config: &Ikev2ServerConfig, db: &DBPool) -> Result<Ikev2ServerConfig> {
+    let mut config = config.clone();
+    config.eap_users = query_stored_users(db)
+        .await?
+        .into_iter()
+        .filter(|(_, user)| !user.disabled)
+        .map(|(_, user)| (user.name, user.password))
+        .collect::<BTreeMap<_, _>>();
+    Ok(config)
+}
+
+pub(crate) async fn find_user(db: &DBPool, name: &str) -> Result<Option<StoredEapUser>> {
+    Ok(query_stored_users(db)
+        .await?
+        .into_iter()
+        .map(|(_, user)| user)
+        .find(|user| user.name == name))
+}
+
+pub async fn list_users(db: &DBPool) -> Result<Vec<Ikev2User>> {
+    let mut users: Vec<Ikev2User> = query_stored_users(db)
+        .await?
+        .into_iter()
+        .map(|(row, user)| Ikev2User {
+            name: user.name,
+            disabled: user.disabled,
+            updated: row.updated.and_utc().timestamp_millis(),
+        })
+        .collect();
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(users)
+}
+
+/// adds the user when it doesn't exist yet, `password` is required then.
+pub async fn save_user(
+    db: &DBPool,
+    name: &str,
+    password: Option<&str>,
+    disabled: Option<bool>,
+) -> Result<()> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("user name must not be empty");
+    }
+    if password.is_some_and(|password| password.trim().is_empty()) {
+        bail!("password of `{name}` must not be empty");
+    }
+
+    let existing = query_stored_users(db)
+        .await?
+        .into_iter()
+        .find(|(_, user)| user.name == name);
+    let user = match existing {
+        Some((row, mut user)) => {
+            if let Some(password) = password {
+                user.password = password.to_string();
+            }
+            if let Some(disabled) = disabled {
+                user.disabled = disabled;
+            }
+            GeneralData::update_data_by_id(row.id, &serde_json::to_string(&user)?, db).await?;
+            user
+        }
+        None => {
+            let Some(password) = password else {
+                bail!("password is required to add user `{name}`");
+            };
+            let user = StoredEapUser {
+                name: name.to_string(),
+                password: password.to_string(),
+                disabled: disabled.unwrap_or_default(),
+            };
+            GeneralData::insert(CAT_IKEV2_USERS, &serde_json::to_string(&user)?, db).await?;
+            user
+        }
+    };
+
+    reload_users(db).await?;
+    if user.disabled {
+        disconnect_user(name).await?;
+    }
+    Ok(())
+}
+
+pub async fn remove_user(db: &DBPool, name: &str) -> Result<()> {
+    let rows: Vec<_> = query_stored_users(db)
+        .await?
+        .into_iter()
+        .filter(|(_, user)| user.name == name)
+        .collect();
+    if rows.is_empty() {
+        bail!("IKEv2 user `{name}` not found");
+    }
+    for (row, _) in rows {
+        GeneralData::delete(row.id, db).await?;
+    }
+
+    reload_users(db).await?;
+    disconnect_user(name).await
+}
+
+/// loads the stored users into the running daemon, false when it isn't running.
+pub async fn reload_users(db: &DBPool) -> Result<bool> {
+    #[cfg(all(feature = "ikev2-server", target_os = "linux"))]
+    if let Some(runtime) = runtime() {
+        runtime.reload_users(db).await?;
+        return Ok(true);
+    }
+
+    let _ = db;
+    Ok(false)
+}
+
+pub async fn list_sas() -> Result<Vec<Ikev2Sa>> {
+    let output = swanctl(&["--list-sas", "--pretty"], "list IKEv2 SAs").await?;
+    Ok(parse_sas(&output, now_secs()))
+}
+
+pub async fn disconnect(ike_id: u64) -> Result<()> {
+    let ike_id = ike_id.to_string();
+    swanctl(
+        &["--terminate", "--ike-id", &ike_id, "--force"],
+        "terminate IKEv2 SA",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn disconnect_user(name: &str) -> Result<()> {
+    if !is_running() {
+        return Ok(());
+    }
+
+    for sa in list_sas().await? {
+        if sa.user.as_deref() == Some(name) {
+            info!("Disconnecting IKEv2 SA #{} of user `{}`", sa.ike_id, name);
+            disconnect(sa.ike_id).await?;
+        }
+    }
+    Ok(())
+}
+
+pub fn is_running() -> bool {
+    #[cfg(all(feature = "ikev2-server", target_os = "linux"))]
+    {
+        runtime().is_some()
+    }
+
+    #[cfg(not(all(feature = "ikev2-server", target_os = "linux")))]
+    {
+        false
+    }
+}
+
+async fn swanctl(args: &[&str], action: &str) -> Result<String> {
+    #[cfg(all(feature = "ikev2-server", target_os = "linux"))]
+    if let Some(runtime) = runtime() {
+        return runtime.swanctl(args, action).await;
+    }
+
+    let _ = (args, action);
+    bail!("IKEv2 server is not running")
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// a message of `swanctl --pretty` output, sections nest with `{ }` and lists with `[ ]`
+#[derive(Debug, Default)]
+struct ViciSection {
+    values: BTreeMap<String, String>,
+    lists: BTreeMap<String, Vec<String>>,
+    sections: Vec<(String, ViciSection)>,
+}
+
+impl ViciSection {
+    fn value(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    fn number(&self, key: &str) -> u64 {
+        self.value(key)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    fn list(&self, key: &str) -> Vec<String> {
+        self.lists.get(key).cloned().unwrap_or_default()
+    }
+
+    fn section(&self, name: &str) -> Option<&ViciSection> {
+        self.sections
+            .iter()
+            .find(|(section_name, _)| section_name == name)
+            .map(|(_, section)| section)
+    }
+}
+
+fn parse_vici_pretty(text: &str) -> ViciSection {
+    let mut stack = vec![(String::new(), ViciSection::default())];
+    let mut list: Option<(String, Vec<String>)> = None;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some((key, items)) = list.as_mut() {
+            if line == "]" {
+                let key = std::mem::take(key);
+                let items = std::mem::take(items);
+                list = None;
+                stack.last_mut().unwrap().1.lists.insert(key, items);
+            } else {
+                items.push(line.to_string());
+            }
+            continue;
+        }
+
+        if line == "}" {
+            if stack.len() > 1 {
+                let section = stack.pop().unwrap();
+                stack.last_mut().unwrap().1.sections.push(section);
+            }
+        } else if let Some((key, value)) = line
+            .split_once(" = ")
+            .or_else(|| line.strip_suffix(" =").map(|key| (key, "")))
+        {
+            if value == "[" {
+                list = Some((key.to_string(), vec![]));
+            } else {
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .1
+                    .values
+                    .insert(key.to_string(), value.to_string());
+            }
+        } else if let Some(name) = line.strip_suffix('{') {
+            stack.push((name.trim().to_string(), ViciSection::default()));
+        }
+    }
+
+    while stack.len() > 1 {
+        let section = stack.pop().unwrap();
+        stack.last_mut().unwrap().1.sections.push(section);
+    }
+    stack.pop().unwrap().1
+}
+
+fn parse_sas(text: &str, now: u64) -> Vec<Ikev2Sa> {
+    parse_vici_pretty(text)
+        .sections
+        .iter()
+        .filter(|(name, _)| name == "list-sa event")
+        .flat_map(|(_, event)| event.sections.iter())
+        .map(|(connection, ike)| {
+            let child_sas: Vec<Ikev2ChildSa> = ike
+                .section("child-sas")
+                .map(|children| children.sections.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(|(_, child)| Ikev2ChildSa {
+                    child_id: child.number("uniqueid"),
+                    name: child.value("name").unwrap_or_default().to_string(),
+                    state: child.value("state").unwrap_or_default().to_string(),
+                    bytes_in: child.number("bytes-in"),
+                    bytes_out: child.number("bytes-out"),
+                    packets_in: child.number("packets-in"),
+                    packets_out: child.number("packets-out"),
+                    remote_ts: child.list("remote-ts"),
+                })
+                .collect();
+
+            Ikev2Sa {
+                ike_id: ike.number("uniqueid"),
+                connection: connection.to_string(),
+                state: ike.value("state").unwrap_or_default().to_string(),
+                remote_addr: match ike.value("remote-host").unwrap_or_default() {
+                    host if host.contains(':') => {
+                        format!("[{}]:{}", host, ike.number("remote-port"))
+                    }
+                    host => format!("{}:{}", host, ike.number("remote-port")),
+                },
+                remote_id: ike.value("remote-id").unwrap_or_default().to_string(),
+                user: ike
+                    .value("remote-eap-id")
+                    .or_else(|| ike.value("remote-xauth-id"))
+                    .map(str::to_string),
+                virtual_ips: ike.list("remote-vips"),
+                connected_since: ike
+                    .value("established")
+                    .and_then(|value| value.parse::<u64>().ok())
+                    .map(|established| now.saturating_sub(established)),
+                bytes_in: child_sas.iter().map(|child| child.bytes_in).sum(),
+                bytes_out: child_sas.iter().map(|child| child.bytes_out).sum(),
+                packets_in: child_sas.iter().map(|child| child.packets_in).sum(),
+                packets_out: child_sas.iter().map(|child| child.packets_out).sum(),
+                child_sas,
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1373,6 +1870,16 @@ mod tests {
         assert!(!conf.contains("private-play"));
     }
 
+    #[test]
+    fn eap_tls_lets_clients_choose_the_eap_method() {
+        let mut config = Ikev2ServerConfig::default();
+        config.eap_tls = true;
+        let conf = render_swanctl_conf(&config, "server-cert.pem");
+        assert!(conf.contains("auth = eap-dynamic"));
+        let conf = render_strongswan_conf(&config, Path::new("/tmp/play-ikev2/charon.vici"));
+        assert!(conf.contains("preferred = mschapv2, tls"));
+    }
+
     #[test]
     fn render_connection_local_addrs_uses_wildcard_for_unspecified_listen_addr() {
         let config = Ikev2ServerConfig::default();
@@ -1511,4 +2018,97 @@ VERSION_CODENAME=bookworm
 
         assert_eq!(resolved, bin_path);
     }
+
+    #[test]
+    fn parse_sas_reads_pretty_list_sas_output() {
+        let output = r#"list-sa event {
+  play-ikev2 {
+    uniqueid = 3
+    version = 2
+    state = ESTABLISHED
+    local-host = 203.0.113.10
+    local-port = 4500
+    local-id = vpn.example.com
+    remote-host = 198.51.100.7
+    remote-port = 4500
+    remote-id = 192.168.1.2
+    remote-eap-id = alice
+    established = 120
+    remote-vips = [
+      10.10.10.1
+    ]
+    child-sas {
+      play-ikev2-child-5 {
+        name = play-ikev2-child
+        uniqueid = 5
+        state = INSTALLED
+        bytes-in = 12345
+        packets-in = 100
+        bytes-out = 67890
+        packets-out = 120
+        local-ts = [
+          0.0.0.0/0
+        ]
+        remote-ts = [
+          10.10.10.1/32
+        ]
+      }
+    }
+  }
+}
+"#;
+
+        let sas = parse_sas(output, 1000);
+        assert_eq!(sas.len(), 1);
+        let sa = &sas[0];
+        assert_eq!(sa.ike_id, 3);
+        assert_eq!(sa.connection, "play-ikev2");
+        assert_eq!(sa.state, "ESTABLISHED");
+        assert_eq!(sa.remote_addr, "198.51.100.7:4500");
+        assert_eq!(sa.user.as_deref(), Some("alice"));
+        assert_eq!(sa.virtual_ips, vec!["10.10.10.1".to_string()]);
+        assert_eq!(sa.connected_since, Some(880));
+        assert_eq!(sa.bytes_in, 12345);
+        assert_eq!(sa.bytes_out, 67890);
+        assert_eq!(sa.child_sas.len(), 1);
+        assert_eq!(sa.child_sas[0].child_id, 5);
+        assert_eq!(sa.child_sas[0].name, "play-ikev2-child");
+        assert_eq!(sa.child_sas[0].remote_ts, vec!["10.10.10.1/32".to_string()]);
+
+        assert!(parse_sas("", 1000).is_empty());
+    }
+
+    #[tokio::test]
+    async fn stored_users_replace_config_users() {
+        let db = crate::tables::init_test_pool().await;
+        let mut config = Ikev2ServerConfig::default();
+        seed_users_from_config(&config, &db).await.unwrap();
+        config.eap_users.insert("ignored".to_string(), "secret".to_string());
+        // the table is only seeded while it is empty
+        seed_users_from_config(&config, &db).await.unwrap();
+
+        save_user(&db, "alice", Some("alice_password"), None)
+            .await
+            .unwrap();
+        save_user(&db, "demo", None, Some(true)).await.unwrap();
+        assert!(save_user(&db, "bob", None, None).await.is_err());
+
+        let users = list_users(&db).await.unwrap();
+        assert_eq!(
+            users
+                .iter()
+                .map(|user| (user.name.as_str(), user.disabled))
+                .collect::<Vec<_>>(),
+            vec![("alice", false), ("demo", true)]
+        );
+
+        let effective = with_stored_users(&config, &db).await.unwrap();
+        assert_eq!(effective.eap_users.len(), 1);
+        assert_eq!(effective.eap_users["alice"], "alice_password");
+
+        remove_user(&db, "alice").await.unwrap();
+        assert!(remove_user(&db, "alice").await.is_err());
+        let effective = with_stored_users(&config, &db).await.unwrap();
+        assert!(effective.eap_users.is_empty());
+    }
 }
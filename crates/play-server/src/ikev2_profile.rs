@@ -0,0 +1,755 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Datelike;
+use rcgen::{
+    date_time_ymd, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, Issuer,
+    KeyPair, KeyUsagePurpose,
+};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::info;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::config::Ikev2ServerConfig;
+use crate::ikev2::{find_user, generate_ikev2_rsa_key_pair, resolved_credential_paths};
+use crate::tables::DBPool;
+
+pub const PROFILE_PATH_PREFIX: &str = "/ikev2/profile/";
+
+const PROFILE_LINK_TTL_SECS: u64 = 24 * 3600;
+const CLIENT_CERT_VALID_YEARS: i32 = 3;
+
+static PROFILE_LINKS: Mutex<BTreeMap<String, ProfileLink>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    // apple `.mobileconfig`
+    Mobileconfig,
+    // strongSwan android `.sswan`
+    Sswan,
+    // windows powershell `Add-VpnConnection` script
+    Ps1,
+    // linux NetworkManager keyfile
+    Nmconnection,
+    // all of the above with the certificates
+    Zip,
+}
+
+impl ProfileFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ProfileFormat::Mobileconfig => "mobileconfig",
+            ProfileFormat::Sswan => "sswan",
+            ProfileFormat::Ps1 => "ps1",
+            ProfileFormat::Nmconnection => "nmconnection",
+            ProfileFormat::Zip => "zip",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ProfileFormat::Mobileconfig => "application/x-apple-aspen-config",
+            ProfileFormat::Sswan => "application/vnd.strongswan.profile",
+            ProfileFormat::Ps1 | ProfileFormat::Nmconnection => "text/plain; charset=utf-8",
+            ProfileFormat::Zip => "application/zip",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ProfileLink {
+    user: String,
+    format: ProfileFormat,
+    client_cert: bool,
+    expires_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedProfileLink {
+    pub url: String,
+    // unix timestamp in seconds
+    pub expires_at: u64,
+}
+
+pub struct ProfileFile {
+    pub file_name: String,
+    pub content_type: &'static str,
+    pub content: Vec<u8>,
+}
+
+// a certificate issued by the IKEv2 CA for EAP-TLS, the PKCS#12 is locked with the user's password
+struct ClientIdentity {
+    cert_pem: String,
+    key_pem: String,
+    p12: Vec<u8>,
+}
+
+struct ProfileInput<'a> {
+    config: &'a Ikev2ServerConfig,
+    user: &'a str,
+    password: &'a str,
+    ca_der: Vec<u8>,
+    ca_pem: String,
+    client: Option<ClientIdentity>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// the link can be downloaded once within a day.
+pub async fn create_link(
+    config: &Ikev2ServerConfig,
+    db: &DBPool,
+    user: &str,
+    format: ProfileFormat,
+    client_cert: bool,
+) -> Result<CreatedProfileLink> {
+    let Some(stored) = find_user(db, user).await? else {
+        bail!("IKEv2 user `{user}` not found");
+    };
+    if stored.disabled {
+        bail!("IKEv2 user `{user}` is disabled");
+    }
+    if config.local_id.trim().is_empty() {
+        bail!("ikev2_server.local_id must be set to generate client profiles");
+    }
+    if client_cert && !config.eap_tls {
+        bail!("client certificates need ikev2_server.eap_tls = true");
+    }
+    if client_cert && format == ProfileFormat::Nmconnection {
+        bail!("NetworkManager profiles with a client certificate are only available in the zip format");
+    }
+
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let expires_at = now_secs() + PROFILE_LINK_TTL_SECS;
+    let mut links = PROFILE_LINKS.lock().unwrap();
+    let now = now_secs();
+    links.retain(|_, link| link.expires_at > now);
+    links.insert(
+        token.clone(),
+        ProfileLink {
+            user: user.to_string(),
+            format,
+            client_cert,
+            expires_at,
+        },
+    );
+
+    Ok(CreatedProfileLink {
+        url: format!("{PROFILE_PATH_PREFIX}{token}"),
+        expires_at,
+    })
+}
+
+/// consumes the link, `None` when it is unknown, used or expired.
+pub async fn take_link(
+    config: &Ikev2ServerConfig,
+    db: &DBPool,
+    token: &str,
+) -> Result<Option<ProfileFile>> {
+    let link = PROFILE_LINKS.lock().unwrap().remove(token);
+    let Some(link) = link.filter(|link| link.expires_at > now_secs()) else {
+        return Ok(None);
+    };
+    let Some(stored) = find_user(db, &link.user).await? else {
+        bail!("IKEv2 user `{}` not found", link.user);
+    };
+    if stored.disabled {
+        bail!("IKEv2 user `{}` is disabled", link.user);
+    }
+
+    let paths = resolved_credential_paths(config)?;
+    let ca_pem = fs::read_to_string(&paths.ca_cert)
+        .await
+        .with_context(|| format!("read {}", paths.ca_cert.display()))?;
+    let client = if link.client_cert {
+        let ca_key_pem = fs::read_to_string(&paths.ca_key)
+            .await
+            .with_context(|| format!("read {}", paths.ca_key.display()))?;
+        let identity = issue_client_identity(&ca_pem, &ca_key_pem, &stored.name, &stored.password)?;
+        info!("Issued IKEv2 client certificate for `{}`", stored.name);
+        Some(identity)
+    } else {
+        None
+    };
+
+    let input = ProfileInput {
+        config,
+        user: &stored.name,
+        password: &stored.password,
+        ca_der: pem_to_der(&ca_pem)?,
+        ca_pem,
+        client,
+    };
+    let content = match link.format {
+        ProfileFormat::Mobileconfig => render_mobileconfig(&input).into_bytes(),
+        ProfileFormat::Sswan => render_sswan(&input)?.into_bytes(),
+        ProfileFormat::Ps1 => render_powershell(&input).into_bytes(),
+        ProfileFormat::Nmconnection => render_nmconnection(&input).into_bytes(),
+        ProfileFormat::Zip => render_zip(&input)?,
+    };
+
+    Ok(Some(ProfileFile {
+        file_name: format!(
+            "{}-{}.{}",
+            input.config.connection_name,
+            file_name_part(input.user),
+            link.format.extension()
+        ),
+        content_type: link.format.content_type(),
+        content,
+    }))
+}
+
+fn issue_client_identity(
+    ca_pem: &str,
+    ca_key_pem: &str,
+    user: &str,
+    password: &str,
+) -> Result<ClientIdentity> {
+    let ca_key = KeyPair::from_pem(ca_key_pem).context("parse IKEv2 CA private key failed")?;
+    let issuer =
+        Issuer::from_ca_cert_pem(ca_pem, ca_key).context("parse IKEv2 CA certificate failed")?;
+
+    let mut params = CertificateParams::new(Vec::<String>::new())
+        .context("build IKEv2 client certificate params failed")?;
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, user);
+    params.distinguished_name = distinguished_name;
+    params.key_usages = vec![
+        KeyUsagePurpose::DigitalSignature,
+        KeyUsagePurpose::KeyEncipherment,
+    ];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    params.use_authority_key_identifier_extension = true;
+    let today = chrono::Utc::now().date_naive();
+    params.not_before = date_time_ymd(today.year(), today.month() as u8, 1);
+    params.not_after = date_time_ymd(
+        today.year() + CLIENT_CERT_VALID_YEARS,
+        today.month() as u8,
+        1,
+    );
+
+    let key = generate_ikev2_rsa_key_pair("client")?;
+    let cert = params
+        .signed_by(&key, &issuer)
+        .context("generate IKEv2 client certificate failed")?;
+    let p12 = p12::PFX::new(
+        cert.der(),
+        &key.serialize_der(),
+        Some(&pem_to_der(ca_pem)?),
+        password,
+        user,
+    )
+    .ok_or_else(|| anyhow!("build PKCS#12 for `{user}` failed"))?
+    .to_der();
+
+    Ok(ClientIdentity {
+        cert_pem: cert.pem(),
+        key_pem: key.serialize_pem(),
+        p12,
+    })
+}
+
+fn pem_to_der(pem: &str) -> Result<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END"))
+        .collect();
+    if body.is_empty() {
+        bail!("no PEM block found");
+    }
+    STANDARD.decode(body).context("invalid PEM base64")
+}
+
+fn file_name_part(value: &str) -> String {
+    value
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.' {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn profile_name(input: &ProfileInput) -> String {
+    format!("{} ({})", input.config.connection_name, input.user)
+}
+
+fn uuid() -> String {
+    uuid::Uuid::new_v4().to_string().to_uppercase()
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_mobileconfig(input: &ProfileInput) -> String {
+    let config = input.config;
+    let identifier = format!(
+        "com.play.ikev2.{}.{}",
+        file_name_part(&config.connection_name),
+        file_name_part(input.user)
+    );
+    let ca_uuid = uuid();
+    let mut payloads = format!(
+        r#"    <dict>
+      <key>PayloadType</key>
+      <string>com.apple.security.root</string>
+      <key>PayloadIdentifier</key>
+      <string>{identifier}.ca</string>
+      <key>PayloadUUID</key>
+      <string>{ca_uuid}</string>
+      <key>PayloadVersion</key>
+      <integer>1</integer>
+      <key>PayloadDisplayName</key>
+      <string>{name} CA</string>
+      <key>PayloadContent</key>
+      <data>{ca}</data>
+    </dict>
+"#,
+        name = xml_escape(&config.connection_name),
+        ca = STANDARD.encode(&input.ca_der),
+    );
+
+    let auth = match &input.client {
+        Some(client) => {
+            let cert_uuid = uuid();
+            payloads.push_str(&format!(
+                r#"    <dict>
+      <key>PayloadType</key>
+      <string>com.apple.security.pkcs12</string>
+      <key>PayloadIdentifier</key>
+      <string>{identifier}.client</string>
+      <key>PayloadUUID</key>
+      <string>{cert_uuid}</string>
+      <key>PayloadVersion</key>
+      <integer>1</integer>
+      <key>PayloadDisplayName</key>
+      <string>{user}</string>
+      <key>Password</key>
+      <string>{password}</string>
+      <key>PayloadContent</key>
+      <data>{p12}</data>
+    </dict>
+"#,
+                user = xml_escape(input.user),
+                password = xml_escape(input.password),
+                p12 = STANDARD.encode(&client.p12),
+            ));
+            format!(
+                r#"        <key>PayloadCertificateUUID</key>
+        <string>{cert_uuid}</string>
+"#
+            )
+        }
+        None => format!(
+            r#"        <key>AuthName</key>
+        <string>{user}</string>
+        <key>AuthPassword</key>
+        <string>{password}</string>
+"#,
+            user = xml_escape(input.user),
+            password = xml_escape(input.password),
+        ),
+    };
+
+    payloads.push_str(&format!(
+        r#"    <dict>
+      <key>PayloadType</key>
+      <string>com.apple.vpn.managed</string>
+      <key>PayloadIdentifier</key>
+      <string>{identifier}.vpn</string>
+      <key>PayloadUUID</key>
+      <string>{vpn_uuid}</string>
+      <key>PayloadVersion</key>
+      <integer>1</integer>
+      <key>PayloadDisplayName</key>
+      <string>{name}</string>
+      <key>UserDefinedName</key>
+      <string>{name}</string>
+      <key>VPNType</key>
+      <string>IKEv2</string>
+      <key>IKEv2</key>
+      <dict>
+        <key>RemoteAddress</key>
+        <string>{remote}</string>
+        <key>RemoteIdentifier</key>
+        <string>{remote}</string>
+        <key>LocalIdentifier</key>
+        <string>{user}</string>
+        <key>AuthenticationMethod</key>
+        <string>None</string>
+        <key>ExtendedAuthEnabled</key>
+        <integer>1</integer>
+{auth}        <key>DeadPeerDetectionRate</key>
+        <string>Medium</string>
+        <key>DisableMOBIKE</key>
+        <integer>{disable_mobike}</integer>
+        <key>IKESecurityAssociationParameters</key>
+        <dict>
+          <key>EncryptionAlgorithm</key>
+          <string>AES-256</string>
+          <key>IntegrityAlgorithm</key>
+          <string>SHA2-256</string>
+          <key>DiffieHellmanGroup</key>
+          <integer>14</integer>
+        </dict>
+        <key>ChildSecurityAssociationParameters</key>
+        <dict>
+          <key>EncryptionAlgorithm</key>
+          <string>AES-256</string>
+          <key>IntegrityAlgorithm</key>
+          <string>SHA2-256</string>
+          <key>DiffieHellmanGroup</key>
+          <integer>14</integer>
+        </dict>
+      </dict>
+    </dict>
+"#,
+        vpn_uuid = uuid(),
+        name = xml_escape(&profile_name(input)),
+        remote = xml_escape(config.local_id.trim()),
+        user = xml_escape(input.user),
+        disable_mobike = u8::from(!config.mobike),
+    ));
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>PayloadType</key>
+  <string>Configuration</string>
+  <key>PayloadIdentifier</key>
+  <string>{identifier}</string>
+  <key>PayloadUUID</key>
+  <string>{uuid}</string>
+  <key>PayloadVersion</key>
+  <integer>1</integer>
+  <key>PayloadDisplayName</key>
+  <string>{name}</string>
+  <key>PayloadContent</key>
+  <array>
+{payloads}  </array>
+</dict>
+</plist>
+"#,
+        uuid = uuid(),
+        name = xml_escape(&profile_name(input)),
+    )
+}
+
+fn render_sswan(input: &ProfileInput) -> Result<String> {
+    let remote = input.config.local_id.trim();
+    let mut profile = serde_json::json!({
+        "uuid": uuid::Uuid::new_v4().to_string(),
+        "name": profile_name(input),
+        "type": "ikev2-eap",
+        "remote": {
+            "addr": remote,
+            "id": remote,
+            "cert": STANDARD.encode(&input.ca_der),
+        },
+        "local": {
+            "eap_id": input.user,
+        },
+    });
+    if let Some(client) = &input.client {
+        profile["type"] = "ikev2-eap-tls".into();
+        profile["local"] = serde_json::json!({ "p12": STANDARD.encode(&client.p12) });
+    }
+    Ok(serde_json::to_string_pretty(&profile)?)
+}
+
+fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn render_powershell(input: &ProfileInput) -> String {
+    let name = powershell_quote(&profile_name(input));
+    let mut script = format!(
+        r#"# IKEv2 VPN profile of {user}, run it in an elevated PowerShell.
+$ErrorActionPreference = "Stop"
+
+$caPath = Join-Path $env:TEMP "play-ikev2-ca.cer"
+[IO.File]::WriteAllBytes($caPath, [Convert]::FromBase64String({ca}))
+Import-Certificate -FilePath $caPath -CertStoreLocation Cert:\LocalMachine\Root | Out-Null
+Remove-Item $caPath
+"#,
+        user = input.user,
+        ca = powershell_quote(&STANDARD.encode(&input.ca_der)),
+    );
+
+    let eap_config = match &input.client {
+        Some(client) => {
+            script.push_str(&format!(
+                r#"
+$p12Path = Join-Path $env:TEMP "play-ikev2-client.p12"
+[IO.File]::WriteAllBytes($p12Path, [Convert]::FromBase64String({p12}))
+$p12Password = ConvertTo-SecureString -String {password} -AsPlainText -Force
+Import-PfxCertificate -FilePath $p12Path -CertStoreLocation Cert:\CurrentUser\My -Password $p12Password | Out-Null
+Remove-Item $p12Path
+"#,
+                p12 = powershell_quote(&STANDARD.encode(&client.p12)),
+                password = powershell_quote(input.password),
+            ));
+            " -EapConfigXmlStream (New-EapConfiguration -Tls).EapConfigXmlStream"
+        }
+        None => "",
+    };
+
+    script.push_str(&format!(
+        r#"
+Remove-VpnConnection -Name {name} -Force -ErrorAction SilentlyContinue
+Add-VpnConnection -Name {name} -ServerAddress {remote} -TunnelType Ikev2 -AuthenticationMethod Eap -EncryptionLevel Required -RememberCredential{eap_config}
+Set-VpnConnectionIPsecConfiguration -ConnectionName {name} -AuthenticationTransformConstants GCMAES256 -CipherTransformConstants GCMAES256 -EncryptionMethod AES256 -IntegrityCheckMethod SHA256 -DHGroup Group14 -PfsGroup None -Force
+"#,
+        remote = powershell_quote(input.config.local_id.trim()),
+    ));
+    if input.client.is_none() {
+        script.push_str(&format!(
+            "\nWrite-Host \"Connect with the user name {} and your VPN password.\"\n",
+            powershell_quote(input.user)
+        ));
+    }
+    script
+}
+
+fn nm_ca_path(config: &Ikev2ServerConfig) -> String {
+    format!(
+        "/etc/NetworkManager/{}/ca-cert.pem",
+        file_name_part(&config.connection_name)
+    )
+}
+
+fn render_nmconnection(input: &ProfileInput) -> String {
+    let config = input.config;
+    let ca_path = nm_ca_path(config);
+    let mut text = format!(
+        r#"# NetworkManager keyfile, needs the strongSwan plugin (network-manager-strongswan).
+# Save the CA certificate (ca-cert.pem of the zip profile) as {ca_path}, then run
+#   sudo install -m 600 this-file /etc/NetworkManager/system-connections/{file}.nmconnection
+#   sudo nmcli connection reload
+
+[connection]
+id={name}
+uuid={uuid}
+type=vpn
+autoconnect=false
+
+[vpn]
+service-type=org.freedesktop.NetworkManager.strongswan
+address={remote}
+certificate={ca_path}
+user={user}
+virtual=yes
+encap=no
+ipcomp=no
+proposal=no
+"#,
+        file = file_name_part(&profile_name(input)),
+        name = profile_name(input),
+        uuid = uuid::Uuid::new_v4(),
+        remote = config.local_id.trim(),
+        user = input.user,
+    );
+
+    match &input.client {
+        Some(_) => {
+            let dir = format!(
+                "/etc/NetworkManager/{}",
+                file_name_part(&config.connection_name)
+            );
+            text.push_str(&format!(
+                "method=cert\nusercert={dir}/client-cert.pem\nuserkey={dir}/client-key.pem\n"
+            ));
+        }
+        None => {
+            text.push_str("method=eap\npassword-flags=0\n\n[vpn-secrets]\n");
+            text.push_str(&format!("password={}\n", input.password));
+        }
+    }
+    text.push_str("\n[ipv4]\nmethod=auto\n\n[ipv6]\nmethod=auto\n");
+    text
+}
+
+fn render_zip(input: &ProfileInput) -> Result<Vec<u8>> {
+    let base = format!(
+        "{}-{}",
+        input.config.connection_name,
+        file_name_part(input.user)
+    );
+    let mut files = vec![
+        ("ca-cert.pem".to_string(), input.ca_pem.clone().into_bytes()),
+        (
+            format!("{base}.mobileconfig"),
+            render_mobileconfig(input).into_bytes(),
+        ),
+        (format!("{base}.sswan"), render_sswan(input)?.into_bytes()),
+        (format!("{base}.ps1"), render_powershell(input).into_bytes()),
+        (
+            format!("{base}.nmconnection"),
+            render_nmconnection(input).into_bytes(),
+        ),
+    ];
+    if let Some(client) = &input.client {
+        files.push((format!("{base}.p12"), client.p12.clone()));
+        files.push((
+            "client-cert.pem".to_string(),
+            client.cert_pem.clone().into_bytes(),
+        ));
+        files.push((
+            "client-key.pem".to_string(),
+            client.key_pem.clone().into_bytes(),
+        ));
+    }
+    files.push(("README.txt".to_string(), render_readme(input).into_bytes()));
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, content) in files {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(&content)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+fn render_readme(input: &ProfileInput) -> String {
+    let mut text = format!(
+        "IKEv2 VPN profiles of {} for {}\n\n\
+         - iOS / macOS: open the .mobileconfig file and install it from the settings.\n\
+         - Android: import the .sswan file into the strongSwan VPN Client app.\n\
+         - Windows: run the .ps1 script in an elevated PowerShell.\n\
+         - Linux: follow the comments at the top of the .nmconnection file, ca-cert.pem goes to {}.\n",
+        input.user,
+        input.config.local_id.trim(),
+        nm_ca_path(input.config)
+    );
+    if input.client.is_some() {
+        text.push_str(&format!(
+            "\nThe client certificate logs in with EAP-TLS. The .p12 file is protected with your VPN password, \
+             client-cert.pem and client-key.pem go next to ca-cert.pem on Linux.\n\
+             Removing or disabling the user doesn't revoke the certificate, it is valid until {} years from now.\n",
+            CLIENT_CERT_VALID_YEARS
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_ca() -> (String, String) {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Play IKEv2 CA test");
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn test_config() -> Ikev2ServerConfig {
+        let mut config = Ikev2ServerConfig::default();
+        config.local_id = "vpn.example.com".to_string();
+        config
+    }
+
+    #[test]
+    fn render_profiles_for_eap_user() {
+        let (ca_pem, _) = test_ca();
+        let config = test_config();
+        let input = ProfileInput {
+            config: &config,
+            user: "alice",
+            password: "a<b",
+            ca_der: pem_to_der(&ca_pem).unwrap(),
+            ca_pem,
+            client: None,
+        };
+
+        let mobileconfig = render_mobileconfig(&input);
+        assert!(mobileconfig.contains("<string>com.apple.security.root</string>"));
+        assert!(mobileconfig.contains("<string>vpn.example.com</string>"));
+        assert!(mobileconfig.contains("<string>a&lt;b</string>"));
+        assert!(!mobileconfig.contains("pkcs12"));
+
+        let sswan: serde_json::Value =
+            serde_json::from_str(&render_sswan(&input).unwrap()).unwrap();
+        assert_eq!(sswan["type"], "ikev2-eap");
+        assert_eq!(sswan["remote"]["addr"], "vpn.example.com");
+        assert_eq!(sswan["local"]["eap_id"], "alice");
+
+        let script = render_powershell(&input);
+        assert!(script.contains("-ServerAddress 'vpn.example.com' -TunnelType Ikev2"));
+        assert!(!script.contains("New-EapConfiguration"));
+
+        let keyfile = render_nmconnection(&input);
+        assert!(keyfile.contains("method=eap\n"));
+        assert!(keyfile.contains("password=a<b\n"));
+
+        let zip = render_zip(&input).unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(zip)).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "README.txt",
+                "ca-cert.pem",
+                "play-ikev2-alice.mobileconfig",
+                "play-ikev2-alice.nmconnection",
+                "play-ikev2-alice.ps1",
+                "play-ikev2-alice.sswan",
+            ]
+        );
+    }
+
+    #[test]
+    fn issue_client_identity_signs_with_ca() {
+        let (ca_pem, ca_key_pem) = test_ca();
+        let identity = issue_client_identity(&ca_pem, &ca_key_pem, "alice", "secret").unwrap();
+        assert!(identity.cert_pem.contains("BEGIN CERTIFICATE"));
+        assert!(identity.key_pem.contains("PRIVATE KEY"));
+        assert!(!identity.p12.is_empty());
+
+        let config = test_config();
+        let input = ProfileInput {
+            config: &config,
+            user: "alice",
+            password: "secret",
+            ca_der: pem_to_der(&ca_pem).unwrap(),
+            ca_pem,
+            client: Some(identity),
+        };
+        assert!(render_mobileconfig(&input).contains("<string>com.apple.security.pkcs12</string>"));
+        assert!(render_powershell(&input).contains("New-EapConfiguration -Tls"));
+        let sswan: serde_json::Value =
+            serde_json::from_str(&render_sswan(&input).unwrap()).unwrap();
+        assert_eq!(sswan["type"], "ikev2-eap-tls");
+        assert!(sswan["local"]["p12"].is_string());
+    }
+}
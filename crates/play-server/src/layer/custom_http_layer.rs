@@ -1,7 +1,7 @@
 use anyhow::anyhow;
 use axum::extract::{ConnectInfo, State};
 use axum::middleware::Next;
-use axum::response::{Html, IntoResponse};
+use axum::response::{Html, IntoResponse, Redirect};
 use axum::routing::get_service;
 use axum::ServiceExt;
 use axum::{body::Body, http::Request, response::Response};
@@ -9,18 +9,25 @@ use cookie::Cookie;
 use futures_util::future::BoxFuture;
 use std::convert::Infallible;
 use std::future::Future;
-use std::net::SocketAddr;
-use std::path::{Path, PathBuf};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tower::{Layer, Service};
 
 use crate::config::{AuthConfig, DomainProxy, OriginStrategy, ProxyTarget, WebSocketConfig};
 use crate::controller::cache_controller::get_cache_content;
 
 use crate::controller::static_controller::STATIC_DIR;
+use crate::service::file_serve_service;
+use crate::service::http_cache_service;
+use crate::service::proxy_route_service;
+use crate::service::proxy_route_service::MatchedRoute;
+use crate::service::upstream_service;
+use crate::service::upstream_service::{upstream_scheme, UpstreamMember, UpstreamPool};
 
 use crate::{files_dir, AppState, S};
 
@@ -77,7 +84,7 @@ impl rustls::client::danger::ServerCertVerifier for NoVerifier {
     }
 }
 use crate::controller::files_controller;
-use futures::TryStreamExt;
+use futures::{StreamExt, TryStreamExt};
 use http::{header, HeaderName, HeaderValue, Method, StatusCode, Uri};
 use mime_guess::mime;
 use tower_http::services::{ServeDir, ServeFile};
@@ -85,6 +92,40 @@ use tower_http::set_status::SetStatus;
 use tracing::{info, warn};
 
 pub async fn http_middleware(
+    state: State<Arc<AppState>>,
+    connect_info: ConnectInfo<SocketAddr>,
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let alt_svc = alt_svc_header(&state, &request);
+    let mut response = handle_request(state, connect_info, request, next).await;
+    if let Some(alt_svc) = alt_svc {
+        response.headers_mut().entry(header::ALT_SVC).or_insert(alt_svc);
+    }
+    response
+}
+
+/// 开启 HTTP/3 时通过 Alt-Svc 告知浏览器，要求客户端证书的域名除外（QUIC 上不校验客户端证书）
+fn alt_svc_header(state: &AppState, request: &Request<Body>) -> Option<HeaderValue> {
+    let https_cert = &state.config.https_cert;
+    let port = https_cert.http3_port().filter(|_| cfg!(feature = "http3"))?;
+    let host = request.headers().get(header::HOST)?.to_str().ok()?;
+    let host = host
+        .rsplit_once(':')
+        .filter(|(_, p)| p.chars().all(|c| c.is_ascii_digit()))
+        .map_or(host, |(name, _)| name);
+    let mtls = https_cert
+        .client_auth
+        .iter()
+        .flat_map(|c| c.hosts.iter())
+        .any(|h| proxy_route_service::host_matches(h, host));
+    if mtls {
+        return None;
+    }
+    HeaderValue::from_str(&format!("h3=\":{}\"; ma=86400", port)).ok()
+}
+
+async fn handle_request(
     state: State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     request: Request<axum::body::Body>,
@@ -118,38 +159,8 @@ pub async fn http_middleware(
         if let Some(header) = request.headers().get(axum::http::header::HOST) {
             if let Ok(host) = header.to_str() {
                 let host = host.to_string();
-                if let Some(domain) = domain_proxy.iter().find(|p| p.proxy_domain.eq(&host)) {
-                    info!(
-                        "Domain proxy matched for host: {} -> {:?}",
-                        host, domain.proxy_target
-                    );
-                    return match &domain.proxy_target {
-                        ProxyTarget::Folder { folder_path } => {
-                            serve_domain_folder(state.clone(), host, request, folder_path)
-                                .await
-                                .unwrap_or_else(|e| {
-                                    axum::response::Response::builder()
-                                        .status(axum::http::StatusCode::INTERNAL_SERVER_ERROR)
-                                        .body(format!("Unhandled internal error: {}", e).into())
-                                        .unwrap()
-                                })
-                        }
-                        ProxyTarget::Upstream { ip, port } => serve_upstream_proxy_with_config(
-                            state.clone(),
-                            host,
-                            request,
-                            ip,
-                            *port,
-                            domain,
-                        )
-                        .await
-                        .unwrap_or_else(|e| {
-                            axum::response::Response::builder()
-                                .status(axum::http::StatusCode::INTERNAL_SERVER_ERROR)
-                                .body(format!("Proxy error: {}", e).into())
-                                .unwrap()
-                        }),
-                    };
+                if let Some(domain) = proxy_route_service::find_domain(domain_proxy, &host) {
+                    return serve_domain_proxy(state.clone(), host, request, addr.ip(), domain).await;
                 }
             }
         }
@@ -240,23 +251,6 @@ async fn handle_404(uri: axum::http::Uri) -> (axum::http::StatusCode, &'static s
     }
 }
 
-#[derive(Clone)]
-struct NotFoundService;
-
-impl<B: Send + 'static> Service<Request<B>> for NotFoundService {
-    type Response = Response;
-    type Error = std::convert::Infallible;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
-
-    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
-    }
-
-    fn call(&mut self, req: Request<B>) -> Self::Future {
-        Box::pin(async move { Ok(handle_404(req.uri().clone()).await.into_response()) })
-    }
-}
-
 pub async fn serve_domain_folder(
     state: S,
     host: String,
@@ -266,14 +260,9 @@ pub async fn serve_domain_folder(
     //check if has plugin can handle this.
     #[cfg(feature = "play-dylib-loader")]
     {
-        use crate::controller::plugin_controller::inner_run_plugin;
-        let plugin = state
-            .config
-            .plugin_config
-            .iter()
-            .find(|plugin| !plugin.disable && plugin.proxy_domain.eq(&host));
-        if let Some(plugin) = plugin {
-            return Ok(inner_run_plugin(plugin, request)
+        use crate::controller::plugin_controller::run_registered_plugin;
+        if let Some(plugin) = crate::plugins::find_by_domain(&host) {
+            return Ok(run_registered_plugin(&state, &plugin, request)
                 .await
                 .map_err(|e| anyhow!("{:?}", e))?);
         }
@@ -300,28 +289,43 @@ pub async fn serve_domain_folder(
     //         .into_response())
     // }
 
-    let dir = PathBuf::from(folder_path);
-
-    // let resp  = files_controller::download_file(axum::extract::path::Path(format!("{}{}", host,request.uri().path()))).await;
-    let mut svc = get_service(ServeDir::new(dir).fallback(NotFoundService));
-
-    // 转发请求到 ServeDir
-    let uri = request.uri().path().to_string();
-    let mut resp = svc.call(request).await.unwrap();
-
-    // if uri.ends_with(".wasm") {
-    //     //let cloudflare dont compress wasm file (because ios safari has issue with it)
-    //     let headers = resp.headers_mut();
-    //     headers.insert(
-    //         axum::http::header::CONTENT_ENCODING,
-    //         axum::http::HeaderValue::from_static("identity"),
-    //     );
-    //     headers.insert(
-    //         axum::http::header::CACHE_CONTROL,
-    //         axum::http::HeaderValue::from_static("no-transform"),
-    //     );
-    // }
-    Ok(resp)
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let req_path = urlencoding::decode(uri.path())
+        .map(|p| p.into_owned())
+        .unwrap_or_default();
+    let rel_path = Path::new(req_path.trim_start_matches('/'));
+    if rel_path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Ok(handle_404(uri).await.into_response());
+    }
+
+    let mut file_path = PathBuf::from(folder_path).join(rel_path);
+    if file_path.is_dir() {
+        // 和 ServeDir 保持一致：目录需要以 / 结尾，相对路径才能正确解析
+        if !req_path.ends_with('/') {
+            let location = match uri.query() {
+                Some(query) => format!("{}/?{}", uri.path(), query),
+                None => format!("{}/", uri.path()),
+            };
+            return Ok(Redirect::temporary(&location).into_response());
+        }
+        file_path = file_path.join("index.html");
+    }
+    if !file_path.is_file() {
+        return Ok(handle_404(uri).await.into_response());
+    }
+
+    let mime_type = mime_guess::from_path(&file_path).first_or_octet_stream();
+    file_serve_service::serve_file(
+        &file_path,
+        mime_type.as_ref(),
+        &method,
+        request.headers(),
+    )
+    .await
 }
 
 fn extract_prefix(url: &str) -> String {
@@ -345,10 +349,346 @@ fn extract_prefix(url: &str) -> String {
     }
 }
 
+// 域名代理：先查 http 缓存，未命中时再转发
+pub async fn serve_domain_proxy(
+    state: S,
+    host: String,
+    request: Request<axum::body::Body>,
+    client_ip: IpAddr,
+    domain: &DomainProxy,
+) -> Response {
+    let cache_config = &state.config.http_cache;
+    if !cache_config.enabled {
+        return dispatch_domain_proxy(state, host, request, client_ip, domain).await;
+    }
+
+    // 后台刷新缓存时也会调用，所以按 host 重新查找域名配置
+    let app_state = state.0.clone();
+    let cache_host = host.clone();
+    let fetch = move |request: Request<axum::body::Body>| {
+        let app_state = app_state.clone();
+        let host = cache_host.clone();
+        async move {
+            match proxy_route_service::find_domain(&app_state.config.domain_proxy, &host) {
+                Some(domain) => {
+                    dispatch_domain_proxy(State(app_state.clone()), host.clone(), request, client_ip, domain)
+                        .await
+                }
+                None => handle_404(request.uri().clone()).await.into_response(),
+            }
+        }
+    };
+    http_cache_service::serve_with_cache(cache_config, &host, request, fetch).await
+}
+
+// 客户端连接是否是 TLS，只有 play-https 会终止 TLS
+fn is_tls(request: &Request<axum::body::Body>) -> bool {
+    #[cfg(feature = "play-https")]
+    {
+        request
+            .extensions()
+            .get::<play_https::TlsConnection>()
+            .is_some_and(|tls| tls.0)
+    }
+    #[cfg(not(feature = "play-https"))]
+    {
+        let _ = request;
+        false
+    }
+}
+
+// 按路径/方法匹配路由，改写请求后交给 folder / upstream / redirect 处理，最后改写响应头
+async fn dispatch_domain_proxy(
+    state: S,
+    host: String,
+    mut request: Request<axum::body::Body>,
+    client_ip: IpAddr,
+    domain: &DomainProxy,
+) -> Response {
+    let route = proxy_route_service::match_route(domain, request.method(), request.uri().path());
+    info!(
+        "Domain proxy matched for host: {} , route: {} -> {:?}",
+        host, route.key, route.target
+    );
+    let tls = is_tls(&request);
+    if let Err(e) =
+        proxy_route_service::rewrite_request(&route, &mut request, &host, client_ip, tls)
+    {
+        return axum::response::Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(format!("Invalid request rewrite: {}", e).into())
+            .unwrap();
+    }
+
+    let mut response = match route.target {
+        ProxyTarget::Folder { folder_path } => {
+            serve_domain_folder(state, host, request, folder_path)
+                .await
+                .unwrap_or_else(|e| {
+                    axum::response::Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(format!("Unhandled internal error: {}", e).into())
+                        .unwrap()
+                })
+        }
+        ProxyTarget::Upstream { .. } | ProxyTarget::Pool { .. } => {
+            serve_upstream_pool(state, host, request, client_ip, domain, &route)
+                .await
+                .unwrap_or_else(|e| {
+                    axum::response::Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .body(format!("Proxy error: {}", e).into())
+                        .unwrap()
+                })
+        }
+        ProxyTarget::Tunnel { service } => {
+            serve_tunnel(&host, request, service, domain)
+                .await
+                .unwrap_or_else(|e| {
+                    axum::response::Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .body(format!("Proxy error: {}", e).into())
+                        .unwrap()
+                })
+        }
+        ProxyTarget::Redirect { to, status, keep_path } => {
+            let status = StatusCode::from_u16(*status)
+                .ok()
+                .filter(|s| s.is_redirection())
+                .unwrap_or(StatusCode::FOUND);
+            let location = proxy_route_service::redirect_location(to, *keep_path, request.uri());
+            axum::response::Response::builder()
+                .status(status)
+                .header(header::LOCATION, location)
+                .body(Body::empty())
+                .unwrap_or_else(|e| {
+                    axum::response::Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(format!("Invalid redirect: {}", e).into())
+                        .unwrap()
+                })
+        }
+    };
+
+    proxy_route_service::apply_header_rules(response.headers_mut(), &route.rewrite.response_headers);
+    response
+}
+
+/// largest request body buffered so a failed request can be retried on another upstream.
+const MAX_RETRY_BODY_SIZE: usize = 1024 * 1024;
+
+// 负载均衡：按策略挑选 upstream 成员，失败时（幂等请求）换成员重试
+pub async fn serve_upstream_pool(
+    state: S,
+    host: String,
+    request: Request<axum::body::Body>,
+    client_ip: IpAddr,
+    domain_config: &DomainProxy,
+    route: &MatchedRoute<'_>,
+) -> anyhow::Result<Response> {
+    let pool = upstream_service::pool_for(domain_config, route)
+        .ok_or_else(|| anyhow!("{} is not an upstream route", route.key))?;
+
+    let is_websocket = request.headers().contains_key(axum::http::header::UPGRADE);
+    let body_len = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    let idempotent = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+    );
+    let retryable = domain_config.retries > 0
+        && idempotent
+        && !is_websocket
+        && body_len.map_or(true, |len| len <= MAX_RETRY_BODY_SIZE);
+
+    if !retryable {
+        let (index, member) = pool
+            .select(client_ip, &[])
+            .ok_or_else(|| anyhow!("no upstream member for {}", host))?;
+        let (response, _) = forward_to_member(&pool, &member, &host, request, domain_config).await;
+        info!("upstream {} served by member {}", host, index);
+        return response.or_else(|e| bad_gateway(&e));
+    }
+
+    // 缓存请求体，重试时重新构造请求
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, MAX_RETRY_BODY_SIZE).await?;
+    let mut tried = vec![];
+    let mut last_response = None;
+    for _ in 0..=domain_config.retries {
+        let Some((index, member)) = pool.select(client_ip, &tried) else {
+            break;
+        };
+        tried.push(index);
+
+        let mut attempt = Request::builder()
+            .method(parts.method.clone())
+            .uri(parts.uri.clone())
+            .version(parts.version)
+            .body(Body::from(body.clone()))?;
+        *attempt.headers_mut() = parts.headers.clone();
+
+        let (response, failed) = forward_to_member(&pool, &member, &host, attempt, domain_config).await;
+        if !failed {
+            return response;
+        }
+        warn!("upstream {} attempt {} on {} failed", host, tried.len(), member.address());
+        last_response = Some(response);
+    }
+
+    match last_response {
+        Some(response) => response.or_else(|e| bad_gateway(&e)),
+        None => bad_gateway(&anyhow!("no upstream member for {}", host)),
+    }
+}
+
+/// forward to one pool member, recording the outcome for passive health detection.
+/// the returned flag tells whether the attempt counts as failed.
+async fn forward_to_member(
+    pool: &UpstreamPool,
+    member: &Arc<UpstreamMember>,
+    host: &str,
+    request: Request<axum::body::Body>,
+    domain_config: &DomainProxy,
+) -> (anyhow::Result<Response>, bool) {
+    let guard = member.acquire();
+    let timeout = Duration::from_secs(domain_config.timeout_secs.max(1));
+    let result = match tokio::time::timeout(
+        timeout,
+        forward_to_upstream(host, request, &member.ip, member.port, domain_config),
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("upstream timeout after {:?}", timeout)),
+    };
+
+    match result {
+        Ok(response) => {
+            let status = response.status();
+            let failed = matches!(
+                status,
+                StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+            );
+            pool.report(
+                member,
+                if failed {
+                    Err(format!("status {}", status))
+                } else {
+                    Ok(())
+                },
+            );
+            // 连接数统计到响应体传输结束
+            let response = response.map(|body| {
+                Body::from_stream(body.into_data_stream().map(move |chunk| {
+                    let _ = &guard;
+                    chunk
+                }))
+            });
+            (Ok(response), failed)
+        }
+        Err(e) => {
+            pool.report(member, Err(e.to_string()));
+            (Err(e), true)
+        }
+    }
+}
+
+// 通过 FRP 隧道转发：每个请求打开一条隧道连接，用 HTTP/1.1 发送，支持 WebSocket 升级
+async fn serve_tunnel(
+    host: &str,
+    mut request: Request<axum::body::Body>,
+    service: &str,
+    domain_config: &DomainProxy,
+) -> anyhow::Result<Response> {
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/")
+        .to_string();
+    *request.uri_mut() = path_and_query.parse()?;
+    *request.version_mut() = http::Version::HTTP_11;
+    request.headers_mut().insert(
+        header::HOST,
+        HeaderValue::from_str(host).unwrap_or_else(|_| HeaderValue::from_static("localhost")),
+    );
+
+    let is_websocket = request.headers().contains_key(header::UPGRADE);
+    if is_websocket {
+        // 隧道后端的本地地址只有 FRP 客户端知道，backend 策略按 host 处理
+        let mut websocket_config = domain_config.websocket_config.clone();
+        if matches!(websocket_config.origin_strategy, OriginStrategy::Backend) {
+            websocket_config.origin_strategy = OriginStrategy::Host;
+        }
+        let scheme = upstream_scheme(domain_config, 80);
+        handle_websocket_origin(&mut request, host, scheme, "localhost", 80, &websocket_config)?;
+    }
+    let client_upgrade = is_websocket.then(|| hyper::upgrade::on(&mut request));
+
+    let timeout = Duration::from_secs(domain_config.timeout_secs.max(1));
+    let mut response = tokio::time::timeout(timeout, async {
+        let stream = crate::frp::open_tunnel(service).await?;
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+        let service = service.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = connection.with_upgrades().await {
+                warn!("tunnel {} connection error: {:?}", service, e);
+            }
+        });
+        anyhow::Ok(sender.send_request(request).await?)
+    })
+    .await
+    .map_err(|_| anyhow!("tunnel {} timeout after {:?}", service, timeout))??;
+    info!("{} {} served by tunnel {}", response.status(), path_and_query, service);
+
+    if let (StatusCode::SWITCHING_PROTOCOLS, Some(client_upgrade)) = (response.status(), client_upgrade) {
+        let upstream_upgrade = hyper::upgrade::on(&mut response);
+        tokio::spawn(async move {
+            match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                Ok((client, upstream)) => {
+                    let _ = tokio::io::copy_bidirectional(
+                        &mut hyper_util::rt::TokioIo::new(client),
+                        &mut hyper_util::rt::TokioIo::new(upstream),
+                    )
+                    .await;
+                }
+                Err(e) => warn!("tunnel upgrade failed: {:?}", e),
+            }
+        });
+    }
+    Ok(response.map(Body::new))
+}
+
+fn bad_gateway(e: &anyhow::Error) -> anyhow::Result<Response> {
+    // 返回502 Bad Gateway错误
+    Ok(axum::response::Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(format!("Proxy error: {:?}", e).into())?)
+}
+
 // 带配置的代理函数
 pub async fn serve_upstream_proxy_with_config(
     state: S,
     host: String,
+    request: Request<axum::body::Body>,
+    ip: &str,
+    port: u16,
+    domain_config: &DomainProxy,
+) -> anyhow::Result<Response> {
+    match forward_to_upstream(&host, request, ip, port, domain_config).await {
+        Ok(response) => Ok(response),
+        Err(e) => bad_gateway(&e),
+    }
+}
+
+/// proxy a single request to `ip:port`, connection failures are returned as errors.
+async fn forward_to_upstream(
+    host: &str,
     mut request: Request<axum::body::Body>,
     ip: &str,
     port: u16,
@@ -365,17 +705,7 @@ pub async fn serve_upstream_proxy_with_config(
         .unwrap_or("/");
 
     // 根据DomainProxy配置确定schema
-    let scheme = match domain_config.use_https {
-        Some(true) => "https",
-        Some(false) => "http",
-        None => {
-            if port == 443 {
-                "https"
-            } else {
-                "http"
-            }
-        } // 默认逻辑
-    };
+    let scheme = upstream_scheme(domain_config, port);
     let target_base = format!("{}://{}:{}", scheme, ip, port);
 
     // 检查是否是WebSocket升级请求
@@ -406,7 +736,7 @@ pub async fn serve_upstream_proxy_with_config(
     // 某些服务器（如Cloudflare CDN后的服务器）需要原始的Host头部
     request.headers_mut().insert(
         axum::http::header::HOST,
-        axum::http::HeaderValue::from_str(&host)
+        axum::http::HeaderValue::from_str(host)
             .unwrap_or_else(|_| axum::http::HeaderValue::from_static("localhost")),
     );
 
@@ -414,8 +744,8 @@ pub async fn serve_upstream_proxy_with_config(
     if is_websocket {
         handle_websocket_origin(
             &mut request,
-            &host,
-            &scheme,
+            host,
+            scheme,
             ip,
             port,
             &domain_config.websocket_config,
@@ -496,11 +826,7 @@ pub async fn serve_upstream_proxy_with_config(
             } else {
                 warn!("HTTP proxy error to {}: {:?}", target_base, e);
             }
-
-            // 返回502 Bad Gateway错误
-            Ok(axum::response::Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
-                .body(format!("Proxy error: {:?}", e).into())?)
+            Err(anyhow!("{:?}", e))
         }
     }
 }
@@ -805,9 +1131,7 @@ pub async fn serve_upstream_proxy(
             ip: ip.to_string(),
             port,
         },
-        use_https: None,
-        ignore_cert: false,
-        websocket_config: WebSocketConfig::default(),
+        ..Default::default()
     };
     serve_upstream_proxy_with_config(state, host, request, ip, port, &default_domain_config).await
 }
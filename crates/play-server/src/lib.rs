@@ -48,8 +48,6 @@ use crate::tables::general_data::GeneralData;
 use crate::tables::DBPool;
 use play_shared::tpl_engine_api::{Template, TemplateData};
 use std::env::set_var;
-#[cfg(unix)]
-use std::os::unix::fs::PermissionsExt;
 use tracing::level_filters::LevelFilter;
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::filter;
@@ -60,7 +58,16 @@ pub mod controller;
 pub mod extractor;
 mod frp;
 mod ikev2;
+#[cfg(feature = "ikev2-server")]
+mod ikev2_profile;
 pub mod layer;
+mod mcp_hub;
+mod mcp_tool_host;
+#[cfg(feature = "play-dylib-loader")]
+mod plugin_host;
+#[cfg(feature = "play-dylib-loader")]
+mod plugin_supervisor;
+mod plugins;
 pub mod service;
 pub mod tables;
 
@@ -206,31 +213,8 @@ pub async fn init_app_state(config: &Config, use_test_pool: bool) -> anyhow::Res
             .collect::<Vec<String>>();
     auth_config.fingerprints.append(&mut fingerprints);
 
-    //query plugin data
-    let mut plugin_config_list = &mut inner_app_state.config.plugin_config;
-
-    //remove disabled plugins
-    let mut new_plugin_config_list = vec![];
-    for plugin in plugin_config_list.iter_mut() {
-        if !plugin.disable {
-            new_plugin_config_list.push(plugin.clone());
-        }
-    }
-    plugin_config_list.clear();
-    plugin_config_list.append(&mut new_plugin_config_list);
-
-    let plugin_list =
-        GeneralData::query_by_cat_simple("plugins", 1000, &inner_app_state.db).await?;
-    for data in &plugin_list {
-        let plugin_config =
-            serde_json::from_value::<PluginConfig>(serde_json::from_str(&data.data)?)?;
-        if plugin_config.disable {
-            continue;
-        }
-
-        plugin_config_list.push(plugin_config);
-    }
-    info!("active plugin_config_list: {:?}", plugin_config_list);
+    //load plugins from config and db
+    plugins::load(&inner_app_state.config.plugin_config, &inner_app_state.db).await?;
 
     //query shortlinks data from db
     let mut shortlinks = &mut inner_app_state.config.shortlinks;
@@ -250,12 +234,22 @@ pub async fn init_app_state(config: &Config, use_test_pool: bool) -> anyhow::Res
         .map(|p| p.from.to_string())
         .collect();
     auth_config.whitelist.append(&mut shortlinks);
+    // 一次性下载链接本身就是凭证
+    #[cfg(feature = "ikev2-server")]
+    auth_config
+        .whitelist
+        .push(ikev2_profile::PROFILE_PATH_PREFIX.to_string());
 
     info!("whitelist : {:?}", auth_config.whitelist);
 
     // Create an instance of the shared state
     let app_state = Arc::new(inner_app_state);
 
+    play_mcp::tools::set_policy(app_state.config.mcp_tools.clone());
+    play_mcp::tools::set_host(Arc::new(mcp_tool_host::McpToolHost::new(
+        app_state.clone(),
+    )));
+
     Ok(app_state)
 }
 
@@ -268,13 +262,19 @@ pub async fn start_server(
         .install_default()
         .map_err(|_| info!("rustls crypto provider already installed"));
 
+    service::mcp_user_tool_service::start_watcher(app_state.clone());
+
     #[cfg(feature = "play-integration-xiaozhi")]
     {
-        let cfg = app_state.config.mcp_config.clone();
-        //start xiaozhi mcp client
+        let endpoints = if app_state.config.xiaozhi_endpoints.is_empty() {
+            vec![app_state.config.mcp_config.clone()]
+        } else {
+            app_state.config.xiaozhi_endpoints.clone()
+        };
+        //start xiaozhi mcp clients
         tokio::spawn(async move {
-            info!("starting xiaozhi mcp client...");
-            let r = play_integration_xiaozhi::start_xiaozhi_client(&cfg).await;
+            info!("starting xiaozhi mcp clients : {}", endpoints.len());
+            let r = play_integration_xiaozhi::start_xiaozhi_clients(&endpoints).await;
             error!("xiaozhi_mcp stop : {:?}", r);
         });
     }
@@ -286,36 +286,116 @@ pub async fn start_server(
 
     let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, server_port as u16));
 
-    #[cfg(not(feature = "play-https"))]
-    // run it with hyper on localhost:3000
     // 使用 with_state 重置状态类型，使其与 axum_server 兼容
     let app = router.with_state(app_state.clone());
+
+    #[cfg(not(feature = "play-https"))]
+    // run it with hyper on localhost:3000
     axum_server::bind(addr)
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
 
-    let certs_path = Path::new(env::var(DATA_DIR)?.as_str()).join("certs");
-
     #[cfg(feature = "play-https")]
-    play_https::start_https_server(
-        &play_https::HttpsConfig {
-            domains: app_state.config.https_cert.domains.clone(),
-            email: app_state.config.https_cert.emails.clone(),
-            cache_dir: certs_path.to_str().unwrap().to_string(),
-            prod: true,
-            http_port: server_port as u16,
-            https_port: app_state.config.https_cert.https_port,
-            auto_redirect: app_state.config.https_cert.auto_redirect,
-        },
-        router,
-    )
-    .await;
+    {
+        let https_cert = &app_state.config.https_cert;
+        let certs_path = Path::new(env::var(DATA_DIR)?.as_str()).join("certs");
+        let one_key = &app_state.config.one_key_change_ip;
+        let (api_token, zone_id) = if https_cert.cloudflare_api_token.is_empty() {
+            (&one_key.cloudflare_api_token, &one_key.cloudflare_zone_id)
+        } else {
+            (&https_cert.cloudflare_api_token, &https_cert.cloudflare_zone_id)
+        };
+        play_https::start_https_server(
+            &play_https::HttpsConfig {
+                domains: https_domains(&app_state.config),
+                email: https_cert.emails.clone(),
+                cache_dir: certs_path.to_str().unwrap().to_string(),
+                prod: !https_cert.staging,
+                source: match https_cert.mode {
+                    config::CertMode::Acme => play_https::CertSource::Acme,
+                    config::CertMode::LocalCa => play_https::CertSource::LocalCa,
+                },
+                challenge: match https_cert.challenge {
+                    config::CertChallenge::TlsAlpn01 => play_https::AcmeChallenge::TlsAlpn01,
+                    config::CertChallenge::Dns01 => play_https::AcmeChallenge::Dns01,
+                },
+                cloudflare: (!api_token.is_empty()).then(|| play_https::CloudflareDns {
+                    api_token: api_token.clone(),
+                    zone_id: zone_id.clone(),
+                }),
+                renew_before_days: https_cert.renew_before_days,
+                dns_propagation_timeout_secs: https_cert.dns_propagation_timeout_secs,
+                static_certs: https_cert
+                    .static_certs
+                    .iter()
+                    .map(|c| play_https::StaticCert {
+                        hosts: c.hosts.iter().map(|h| h.to_ascii_lowercase()).collect(),
+                        cert_path: data_dir_path(&c.cert),
+                        key_path: data_dir_path(&c.key),
+                    })
+                    .collect(),
+                client_auth: https_cert
+                    .client_auth
+                    .iter()
+                    .map(|c| play_https::ClientAuth {
+                        hosts: c.hosts.clone(),
+                        ca_path: data_dir_path(&c.ca),
+                    })
+                    .collect(),
+                http3_port: https_cert.http3_port(),
+                http_port: server_port as u16,
+                https_port: https_cert.https_port,
+                auto_redirect: https_cert.auto_redirect,
+            },
+            app,
+        )
+        .await?;
+    }
 
     // dont put code here (will never run!!!!)
 
     Ok(())
 }
 
+/// relative paths in the https config are under DATA_DIR.
+#[cfg(feature = "play-https")]
+fn data_dir_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    Path::new(&env::var(DATA_DIR).unwrap_or_default()).join(path)
+}
+
+/// certificate domains : `https_cert.domains` first, then every domain_proxy host without its port.
+#[cfg(feature = "play-https")]
+fn https_domains(config: &Config) -> Vec<String> {
+    let mut domains: Vec<String> = Vec::new();
+    let proxy_domains = config
+        .domain_proxy
+        .iter()
+        .filter(|_| config.https_cert.include_proxy_domains)
+        .map(|p| {
+            p.proxy_domain
+                .rsplit_once(':')
+                .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+                .map_or(p.proxy_domain.as_str(), |(name, _)| name)
+        });
+    for domain in config
+        .https_cert
+        .domains
+        .iter()
+        .map(|d| d.as_str())
+        .chain(proxy_domains)
+    {
+        let domain = domain.to_ascii_lowercase();
+        if !domain.is_empty() && !domains.contains(&domain) {
+            domains.push(domain);
+        }
+    }
+    domains
+}
+
 pub async fn shutdown_another_instance(local_url: &String) {
     //check if port is already in using. if it is , call /shutdown firstly.
     let shutdown_result = reqwest::get(&format!("{}/admin/shutdown", local_url)).await;
@@ -458,54 +538,19 @@ async fn handle_404(
     // 检查是否需要域名代理（后备保障）
     if let Some(header) = request.headers().get(axum::http::header::HOST) {
         if let Ok(host) = header.to_str() {
-            if let Some(domain) = state
-                .config
-                .domain_proxy
-                .iter()
-                .find(|p| p.proxy_domain == host)
+            if let Some(domain) =
+                service::proxy_route_service::find_domain(&state.config.domain_proxy, host)
             {
-                info!(
-                    "Fallback handling domain proxy for host: {} -> {:?}",
-                    host, domain.proxy_target
-                );
-
-                return match &domain.proxy_target {
-                    crate::config::ProxyTarget::Folder { folder_path } => {
-                        use crate::layer::custom_http_layer::serve_domain_folder;
-                        match serve_domain_folder(
-                            State(state.clone()),
-                            host.to_string(),
-                            request,
-                            folder_path,
-                        )
-                        .await
-                        {
-                            Ok(response) => response,
-                            Err(e) => (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                format!("Proxy error: {}", e),
-                            )
-                                .into_response(),
-                        }
-                    }
-                    crate::config::ProxyTarget::Upstream { ip, port } => {
-                        use crate::layer::custom_http_layer::serve_upstream_proxy_with_config;
-                        match serve_upstream_proxy_with_config(
-                            State(state.clone()),
-                            host.to_string(),
-                            request,
-                            ip,
-                            *port,
-                            domain,
-                        )
-                        .await
-                        {
-                            Ok(response) => response,
-                            Err(e) => (StatusCode::BAD_GATEWAY, format!("Proxy error: {}", e))
-                                .into_response(),
-                        }
-                    }
-                };
+                info!("Fallback handling domain proxy for host: {}", host);
+                use crate::layer::custom_http_layer::serve_domain_proxy;
+                return serve_domain_proxy(
+                    State(state.clone()),
+                    host.to_string(),
+                    request,
+                    addr.ip(),
+                    domain,
+                )
+                .await;
             }
         }
     }
@@ -716,47 +761,26 @@ pub async fn start_server_with_config(data_dir: String, config: &Config) -> anyh
     #[allow(unused_mut)]
     let mut router = routers(app_state.clone()).await.unwrap();
 
-    #[cfg(feature = "play-dylib-loader")]
-    {
-        use play_dylib_loader::{load_and_run_server, HostContext};
-        use tokio::process::Command;
-        use tokio::task::JoinHandle;
-        let copy_appstate = app_state.clone();
-        let plugins: Vec<&PluginConfig> = copy_appstate
-            .config
-            .plugin_config
-            .iter()
-            .filter(|plugin| !plugin.disable && plugin.is_server)
-            .collect();
-        for plugin in plugins {
-            let path = plugin.file_path.to_string();
-            let create_process = plugin.create_process;
-            let _: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-                info!("load_and_run_server >> {}", path);
-
-                //support external process
-                if create_process {
-                    info!("plugin.create_process is true");
-                    make_executable_if_needed(&path)?;
-                    if let Err(e) = Command::new(&path).output().await {
-                        error!(" plugin process load_and_run_server error: {:?}", e);
-                    }
-                } else {
-                    if let Err(e) = load_and_run_server(&path).await {
-                        error!(" plugin load_and_run_server error: {:?}", e);
-                    }
-                }
-
-                Ok(())
-            });
-        }
-    }
+    service::upstream_service::init_pools(&config.domain_proxy);
 
-    let ikev2_handle = ikev2::maybe_start_ikev2_server_in_background(&config.ikev2_server);
+    let ikev2_handle = ikev2::maybe_start_ikev2_server_in_background(
+        &config.ikev2_server,
+        app_state.db.clone(),
+    );
     let frp_handle = frp::maybe_start_frp_server_in_background(&config.frp_server);
 
-    start_server(router, app_state).await?;
+    #[cfg(feature = "play-dylib-loader")]
+    plugin_supervisor::start().await;
+
+    mcp_hub::start(&config.mcp_hub);
+
+    tokio::select! {
+        r = start_server(router, app_state) => r?,
+        _ = shutdown_signal() => info!("shutting down"),
+    }
 
+    #[cfg(feature = "play-dylib-loader")]
+    plugin_supervisor::shutdown().await;
     if let Some(handle) = frp_handle {
         handle.shutdown().await;
     }
@@ -767,43 +791,24 @@ pub async fn start_server_with_config(data_dir: String, config: &Config) -> anyh
     Ok(())
 }
 
-fn make_executable_if_needed(file_path: &str) -> std::io::Result<bool> {
-    #[cfg(windows)]
-    {
-        let _ = file_path;
-        info!("Skipping executable permission update on Windows");
-        return Ok(false);
-    }
-
+/// ctrl-c, or SIGTERM on unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
     #[cfg(unix)]
-    {
-        let path = Path::new(file_path);
-
-        // 获取当前文件权限
-        let mut perms = std::fs::metadata(path)?.permissions();
-
-        // 获取当前权限模式
-        let mode = perms.mode();
-
-        // 检查是否已经有执行权限
-        // 检查所有者、组和其他用户的执行权限
-        let has_execute_permission = (mode & 0o100 != 0) || // 所有者执行权限
-            (mode & 0o010 != 0) || // 组执行权限
-            (mode & 0o001 != 0); // 其他用户执行权限
-
-        if !has_execute_permission {
-            // 如果没有执行权限，则添加
-            let new_mode = mode | 0o111; // 为所有者、组和其他用户添加执行权限
-            perms.set_mode(new_mode);
-
-            // 应用新权限
-            std::fs::set_permissions(path, perms)?;
-
-            info!("Added execute permissions to {}", file_path);
-            Ok(true)
-        } else {
-            info!("File already has execute permissions");
-            Ok(false)
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
 }
@@ -17,6 +17,7 @@ use tracing_subscriber::util::SubscriberInitExt;
 
 use play_server::config::{init_config, read_config_file, PluginConfig};
 use play_server::routers;
+use play_server::service::asset_store_service;
 use play_server::{files_dir, init_app_state, shutdown_another_instance, start_server, Config};
 
 use play_shared::constants::DATA_DIR;
@@ -70,6 +71,17 @@ async fn main() -> anyhow::Result<()> {
     // init config
     let config = init_config(false).await?;
 
+    // `play-server migrate-files` : import existing files into the asset store, then exit
+    if env::args().nth(1).as_deref() == Some("migrate-files") {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stdout)
+            .finish()
+            .init();
+        let report = asset_store_service::import_dir(&config.file_storage, &files_dir!()).await?;
+        info!("migrate files done : {:?}", report);
+        return Ok(());
+    }
+
     play_server::start_server_with_config(data_dir, &config).await?;
 
     Ok(())
@@ -0,0 +1,1018 @@
+//! connects to the external mcp servers of `[mcp_hub]` and mirrors their tools, resources and
+//! prompts under the server's prefix, so `/mcp` and the xiaozhi client serve them too.
+
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use play_mcp::tools::{Tool, ToolMetadata};
+use play_shared::current_timestamp;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::config::{McpHubConfig, McpHubServerConfig, McpHubTransport};
+
+/// resources of a hub server are exposed as `play://hub/{server}/{original uri}`.
+pub const HUB_SCHEME: &str = "play://hub/";
+const PROTOCOL_VERSION: &str = "2025-06-18";
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// a connection that lasted this long starts the backoff over.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+const MAX_LIST_PAGES: usize = 100;
+const SESSION_HEADER: &str = "mcp-session-id";
+
+static SERVERS: Mutex<BTreeMap<String, Arc<HubServer>>> = Mutex::new(BTreeMap::new());
+
+lazy_static::lazy_static! {
+    /// bumped when the resources or prompts of any server change.
+    static ref CHANGES: watch::Sender<u64> = watch::channel(0).0;
+}
+
+struct HubServer {
+    config: McpHubServerConfig,
+    status: Mutex<HubServerStatus>,
+    mirror: Mutex<Mirror>,
+}
+
+/// what the server offered on its last refresh, names and uris already namespaced.
+#[derive(Default)]
+struct Mirror {
+    client: Option<Arc<McpClient>>,
+    resources: Vec<Value>,
+    resource_templates: Vec<Value>,
+    /// prefixed name and the prompt as listed by the server
+    prompts: Vec<(String, Value)>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HubServerStatus {
+    pub name: String,
+    /// `stdio`, `http` or `websocket`
+    pub transport: &'static str,
+    pub prefix: String,
+    /// `connecting`, `connected` or `disconnected`
+    pub state: &'static str,
+    pub server_info: Option<Value>,
+    pub tools: usize,
+    pub resources: usize,
+    pub prompts: usize,
+    pub connected_at: Option<i64>,
+    pub reconnects: u32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+}
+
+impl HubServer {
+    fn update(&self, f: impl FnOnce(&mut HubServerStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+
+    /// the `play-mcp` source of this server's tools.
+    fn source(&self) -> String {
+        format!("hub:{}", self.config.name)
+    }
+
+    /// drops the mirror of a lost connection.
+    fn clear(&self) {
+        if self.mirror.lock().unwrap().client.is_none() {
+            return;
+        }
+        play_mcp::tools::set_dynamic_tools(&self.source(), vec![]);
+        *self.mirror.lock().unwrap() = Mirror::default();
+        self.update(|s| {
+            s.tools = 0;
+            s.resources = 0;
+            s.prompts = 0;
+        });
+        CHANGES.send_modify(|v| *v += 1);
+    }
+
+    fn client(&self) -> Result<Arc<McpClient>> {
+        self.mirror
+            .lock()
+            .unwrap()
+            .client
+            .clone()
+            .with_context(|| format!("mcp server `{}` is not connected", self.config.name))
+    }
+}
+
+/// connects to every enabled server in the background, reconnecting when they go away.
+pub fn start(config: &McpHubConfig) {
+    for server in config.servers.iter().filter(|s| s.enabled) {
+        if !valid_server_name(&server.name) {
+            warn!("mcp hub server skipped, invalid name : {}", server.name);
+            continue;
+        }
+        let hub_server = Arc::new(HubServer {
+            status: Mutex::new(HubServerStatus {
+                name: server.name.clone(),
+                transport: transport_name(&server.transport),
+                prefix: server.prefix(),
+                state: "connecting",
+                ..Default::default()
+            }),
+            config: server.clone(),
+            mirror: Mutex::new(Mirror::default()),
+        });
+        let mut servers = SERVERS.lock().unwrap();
+        if servers.contains_key(&server.name) {
+            warn!("mcp hub server skipped, duplicated name : {}", server.name);
+            continue;
+        }
+        servers.insert(server.name.clone(), hub_server.clone());
+        tokio::spawn(supervise(hub_server));
+    }
+}
+
+pub fn status() -> Vec<HubServerStatus> {
+    SERVERS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|s| s.status.lock().unwrap().clone())
+        .collect()
+}
+
+/// changes whenever mirrored resources or prompts change.
+pub fn subscribe_changes() -> watch::Receiver<u64> {
+    CHANGES.subscribe()
+}
+
+fn servers() -> Vec<Arc<HubServer>> {
+    SERVERS.lock().unwrap().values().cloned().collect()
+}
+
+fn valid_server_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn transport_name(transport: &McpHubTransport) -> &'static str {
+    match transport {
+        McpHubTransport::Stdio { .. } => "stdio",
+        McpHubTransport::Http { .. } => "http",
+        McpHubTransport::WebSocket { .. } => "websocket",
+    }
+}
+
+async fn supervise(server: Arc<HubServer>) {
+    let max_backoff = Duration::from_secs(server.config.max_backoff_secs.max(1));
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        server.update(|s| s.state = "connecting");
+        let started = Instant::now();
+        let result = serve(&server).await;
+        server.clear();
+        let error = match result {
+            Ok(()) => "connection closed".to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+        warn!(
+            "mcp hub server `{}` disconnected : {}",
+            server.config.name, error
+        );
+        server.update(|s| {
+            s.state = "disconnected";
+            s.connected_at = None;
+            s.reconnects += 1;
+            s.last_error = Some(error);
+            s.last_error_at = Some(current_timestamp!());
+        });
+
+        if started.elapsed() > STABLE_AFTER {
+            backoff = Duration::from_secs(1);
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(max_backoff);
+    }
+}
+
+/// one connection, from initialize until it breaks.
+async fn serve(server: &Arc<HubServer>) -> Result<()> {
+    let config = &server.config;
+    let timeout = Duration::from_secs(config.timeout_secs.max(1));
+    // cancelled when this connection is over, which stops its transport tasks.
+    let closed = CancellationToken::new();
+    let _guard = closed.clone().drop_guard();
+
+    let (outgoing, mut incoming) = tokio::time::timeout(
+        timeout,
+        connect(&config.name, &config.transport, closed.clone()),
+    )
+    .await
+    .context("connect timed out")??;
+    let client = Arc::new(McpClient::new(outgoing, timeout));
+
+    let (notify_tx, mut notifications) = mpsc::unbounded_channel();
+    let reader = client.clone();
+    tokio::spawn(async move {
+        while let Some(message) = incoming.recv().await {
+            if let Some(method) = reader.handle_incoming(message) {
+                let _ = notify_tx.send(method);
+            }
+        }
+        reader.close();
+    });
+
+    let init = client
+        .request(
+            "initialize",
+            json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": {"name": "play-server", "version": env!("CARGO_PKG_VERSION")},
+            }),
+        )
+        .await
+        .context("initialize failed")?;
+    client.notify("notifications/initialized", None);
+    let capabilities = init["capabilities"].clone();
+
+    server.mirror.lock().unwrap().client = Some(client.clone());
+    refresh(server, &client, &capabilities).await?;
+    info!("mcp hub server `{}` connected", config.name);
+    server.update(|s| {
+        s.state = "connected";
+        s.server_info = init.get("serverInfo").cloned();
+        s.connected_at = Some(current_timestamp!());
+    });
+
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.tick().await;
+    loop {
+        tokio::select! {
+            notification = notifications.recv() => match notification.as_deref() {
+                None => return Ok(()),
+                Some(
+                    "notifications/tools/list_changed"
+                    | "notifications/resources/list_changed"
+                    | "notifications/prompts/list_changed",
+                ) => {
+                    if let Err(e) = refresh(server, &client, &capabilities).await {
+                        warn!("mcp hub server `{}` refresh failed : {:#}", config.name, e);
+                    }
+                }
+                Some(_) => {}
+            },
+            _ = ping.tick() => {
+                client.request("ping", json!({})).await.context("ping failed")?;
+            }
+        }
+    }
+}
+
+/// lists what the server offers and replaces the mirror.
+async fn refresh(server: &HubServer, client: &Arc<McpClient>, capabilities: &Value) -> Result<()> {
+    let config = &server.config;
+    let prefix = config.prefix();
+
+    let mut tools: Vec<Box<dyn Tool>> = vec![];
+    if capabilities.get("tools").is_some() {
+        for tool in list_all(client, "tools/list", "tools").await? {
+            let Some(name) = tool["name"].as_str() else {
+                continue;
+            };
+            tools.push(Box::new(ProxyTool {
+                metadata: ToolMetadata::new(
+                    tool_name(&prefix, name),
+                    tool["description"].as_str().unwrap_or_default(),
+                    tool.get("inputSchema")
+                        .cloned()
+                        .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+                )
+                .with_annotations(tool.get("annotations").cloned()),
+                name: name.to_string(),
+                client: client.clone(),
+            }));
+        }
+    }
+
+    let (mut resources, mut resource_templates) = (vec![], vec![]);
+    if capabilities.get("resources").is_some() {
+        resources = list_all(client, "resources/list", "resources").await?;
+        for resource in &mut resources {
+            namespace_field(resource, "uri", &config.name);
+        }
+        resource_templates =
+            list_all(client, "resources/templates/list", "resourceTemplates").await?;
+        for template in &mut resource_templates {
+            namespace_field(template, "uriTemplate", &config.name);
+        }
+    }
+
+    let mut prompts = vec![];
+    if capabilities.get("prompts").is_some() {
+        for prompt in list_all(client, "prompts/list", "prompts").await? {
+            if let Some(name) = prompt["name"].as_str() {
+                prompts.push((format!("{}{}", prefix, name), prompt));
+            }
+        }
+    }
+
+    let tool_count = tools.len();
+    for rejected in play_mcp::tools::set_dynamic_tools(&server.source(), tools) {
+        warn!("mcp hub tool skipped, {}", rejected);
+    }
+    server.update(|s| {
+        s.tools = tool_count;
+        s.resources = resources.len();
+        s.prompts = prompts.len();
+    });
+    {
+        let mut mirror = server.mirror.lock().unwrap();
+        mirror.resources = resources;
+        mirror.resource_templates = resource_templates;
+        mirror.prompts = prompts;
+    }
+    CHANGES.send_modify(|v| *v += 1);
+    Ok(())
+}
+
+/// every page of a paginated list method.
+async fn list_all(client: &McpClient, method: &str, key: &str) -> Result<Vec<Value>> {
+    let mut items = vec![];
+    let mut cursor: Option<String> = None;
+    for _ in 0..MAX_LIST_PAGES {
+        let params = match &cursor {
+            Some(cursor) => json!({ "cursor": cursor }),
+            None => json!({}),
+        };
+        let result = client.request(method, params).await?;
+        if let Some(list) = result[key].as_array() {
+            items.extend(list.iter().cloned());
+        }
+        cursor = result["nextCursor"].as_str().map(str::to_string);
+        if cursor.is_none() {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+/// `prefix` + `name`, with the characters tool names can't have replaced by `_`.
+fn tool_name(prefix: &str, name: &str) -> String {
+    format!("{}{}", prefix, name)
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_lowercase() || c.is_ascii_digit() || "_.:".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn namespace_field(item: &mut Value, field: &str, server: &str) {
+    if let Some(uri) = item[field].as_str() {
+        item[field] = json!(format!("{}{}/{}", HUB_SCHEME, server, uri));
+    }
+}
+
+/// the server name and the original uri of a hub resource uri.
+fn split_hub_uri(uri: &str) -> Option<(&str, &str)> {
+    let (server, original) = uri.strip_prefix(HUB_SCHEME)?.split_once('/')?;
+    (!server.is_empty() && !original.is_empty()).then_some((server, original))
+}
+
+pub fn list_resources() -> Vec<Value> {
+    servers()
+        .iter()
+        .flat_map(|s| s.mirror.lock().unwrap().resources.clone())
+        .collect()
+}
+
+pub fn resource_templates() -> Vec<Value> {
+    servers()
+        .iter()
+        .flat_map(|s| s.mirror.lock().unwrap().resource_templates.clone())
+        .collect()
+}
+
+/// reads a `play://hub/` resource, `None` when no server has that name.
+pub async fn read_resource(uri: &str) -> Result<Option<Value>> {
+    let Some((name, original)) = split_hub_uri(uri) else {
+        return Ok(None);
+    };
+    let Some(server) = SERVERS.lock().unwrap().get(name).cloned() else {
+        return Ok(None);
+    };
+    let mut result = server
+        .client()?
+        .request("resources/read", json!({ "uri": original }))
+        .await?;
+    if let Some(contents) = result["contents"].as_array_mut() {
+        for content in contents {
+            namespace_field(content, "uri", name);
+        }
+    }
+    Ok(Some(result))
+}
+
+pub fn list_prompts() -> Vec<Value> {
+    servers()
+        .iter()
+        .flat_map(|s| {
+            s.mirror
+                .lock()
+                .unwrap()
+                .prompts
+                .iter()
+                .map(|(name, prompt)| {
+                    let mut prompt = prompt.clone();
+                    prompt["name"] = json!(name);
+                    prompt
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// renders a mirrored prompt, `None` when no server has a prompt by that name.
+pub async fn get_prompt(name: &str, arguments: &HashMap<String, String>) -> Result<Option<Value>> {
+    for server in servers() {
+        let original = server
+            .mirror
+            .lock()
+            .unwrap()
+            .prompts
+            .iter()
+            .find(|(prefixed, _)| prefixed == name)
+            .and_then(|(_, prompt)| prompt["name"].as_str().map(str::to_string));
+        if let Some(original) = original {
+            let result = server
+                .client()?
+                .request(
+                    "prompts/get",
+                    json!({ "name": original, "arguments": arguments }),
+                )
+                .await?;
+            return Ok(Some(result));
+        }
+    }
+    Ok(None)
+}
+
+/// a tool of a hub server, called through its connection.
+struct ProxyTool {
+    metadata: ToolMetadata,
+    /// the name on the hub server
+    name: String,
+    client: Arc<McpClient>,
+}
+
+#[async_trait]
+impl Tool for ProxyTool {
+    fn metadata(&self) -> &ToolMetadata {
+        &self.metadata
+    }
+
+    async fn execute(&self, input: Value) -> Result<Value> {
+        let result = self
+            .client
+            .request(
+                "tools/call",
+                json!({ "name": self.name, "arguments": input }),
+            )
+            .await?;
+        call_result(result)
+    }
+}
+
+/// the value of a `tools/call` result: its structured content, its only text parsed as json
+/// when possible, or the content list.
+fn call_result(mut result: Value) -> Result<Value> {
+    let texts: Vec<&str> = result["content"]
+        .as_array()
+        .map(|c| c.iter().filter_map(|c| c["text"].as_str()).collect())
+        .unwrap_or_default();
+    if result["isError"].as_bool().unwrap_or(false) {
+        bail!("{}", texts.join("\n"));
+    }
+    if let Some(structured) = result.get("structuredContent") {
+        return Ok(structured.clone());
+    }
+    let content = result["content"].take();
+    Ok(match content.as_array().map(Vec::as_slice) {
+        Some([item]) if item["type"] == "text" => {
+            let text = item["text"].as_str().unwrap_or_default();
+            serde_json::from_str(text).unwrap_or_else(|_| json!(text))
+        }
+        _ => content,
+    })
+}
+
+/// json-rpc over any transport: requests are matched to responses by id.
+struct McpClient {
+    outgoing: mpsc::UnboundedSender<Value>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Result<Value>>>>,
+    next_id: AtomicU64,
+    timeout: Duration,
+}
+
+impl McpClient {
+    fn new(outgoing: mpsc::UnboundedSender<Value>, timeout: Duration) -> Self {
+        Self {
+            outgoing,
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            timeout,
+        }
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        if self.outgoing.send(message).is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            bail!("connection closed");
+        }
+        match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => bail!("connection closed"),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                self.notify(
+                    "notifications/cancelled",
+                    Some(json!({"requestId": id, "reason": "timeout"})),
+                );
+                bail!("`{}` timed out", method)
+            }
+        }
+    }
+
+    fn notify(&self, method: &str, params: Option<Value>) {
+        let mut message = json!({"jsonrpc": "2.0", "method": method});
+        if let Some(params) = params {
+            message["params"] = params;
+        }
+        let _ = self.outgoing.send(message);
+    }
+
+    /// resolves responses and answers server requests; returns the method of a notification.
+    fn handle_incoming(&self, message: Value) -> Option<String> {
+        let method = message["method"].as_str().map(str::to_string);
+        match (message.get("id"), method) {
+            (Some(id), Some(method)) => {
+                // we offer no client capabilities, only ping is answered.
+                let reply = if method == "ping" {
+                    json!({"jsonrpc": "2.0", "id": id, "result": {}})
+                } else {
+                    json!({"jsonrpc": "2.0", "id": id,
+                        "error": {"code": -32601, "message": "Method not found"}})
+                };
+                let _ = self.outgoing.send(reply);
+                None
+            }
+            (None, Some(method)) => Some(method),
+            (Some(id), None) => {
+                let id = id.as_u64()?;
+                let tx = self.pending.lock().unwrap().remove(&id)?;
+                let result = match message.get("error") {
+                    Some(error) => Err(anyhow!(
+                        "{} ({})",
+                        error["message"].as_str().unwrap_or("unknown error"),
+                        error["code"]
+                    )),
+                    None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                };
+                let _ = tx.send(result);
+                None
+            }
+            (None, None) => None,
+        }
+    }
+
+    /// fails the requests still waiting.
+    fn close(&self) {
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+type Channels = (mpsc::UnboundedSender<Value>, mpsc::UnboundedReceiver<Value>);
+
+/// opens the transport; messages sent to the first channel go to the server, the second one
+/// yields what the server sends and ends when the connection is lost.
+async fn connect(
+    name: &str,
+    transport: &McpHubTransport,
+    closed: CancellationToken,
+) -> Result<Channels> {
+    match transport {
+        McpHubTransport::Stdio {
+            command,
+            args,
+            env,
+            cwd,
+        } => connect_stdio(name, command, args, env, cwd.as_deref(), closed),
+        McpHubTransport::Http { url, headers } => connect_http(url, headers, closed),
+        McpHubTransport::WebSocket { url } => connect_websocket(url, closed).await,
+    }
+}
+
+fn connect_stdio(
+    name: &str,
+    command: &str,
+    args: &[String],
+    env: &BTreeMap<String, String>,
+    cwd: Option<&str>,
+    closed: CancellationToken,
+) -> Result<Channels> {
+    let mut cmd = Command::new(command);
+    cmd.args(args)
+        .envs(env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(cwd) = cwd {
+        cmd.current_dir(cwd);
+    }
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("failed to start {}", command))?;
+    let mut stdin = child.stdin.take().context("no stdin")?;
+    let stdout = child.stdout.take().context("no stdout")?;
+    let stderr = child.stderr.take().context("no stderr")?;
+
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+    let (in_tx, in_rx) = mpsc::unbounded_channel();
+
+    let stop = closed.clone();
+    tokio::spawn(async move {
+        loop {
+            let message = tokio::select! {
+                message = out_rx.recv() => message,
+                _ = stop.cancelled() => None,
+            };
+            let Some(message) = message else { break };
+            let line = format!("{}\n", message);
+            if stdin.write_all(line.as_bytes()).await.is_err() || stdin.flush().await.is_err() {
+                stop.cancel();
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => line,
+                _ = closed.cancelled() => break,
+            };
+            let Ok(Some(line)) = line else { break };
+            match serde_json::from_str(&line) {
+                Ok(message) => {
+                    let _ = in_tx.send(message);
+                }
+                Err(_) => debug!("mcp hub stdout : {}", line),
+            }
+        }
+        // dropping the child kills it
+        drop(child);
+    });
+
+    let name = name.to_string();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            debug!("mcp hub `{}` stderr : {}", name, line);
+        }
+    });
+
+    Ok((out_tx, in_rx))
+}
+
+async fn connect_websocket(url: &str, closed: CancellationToken) -> Result<Channels> {
+    let (ws, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .with_context(|| format!("failed to connect {}", url))?;
+    let (mut write, mut read) = ws.split();
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+    let (in_tx, in_rx) = mpsc::unbounded_channel();
+
+    let stop = closed.clone();
+    tokio::spawn(async move {
+        loop {
+            let message = tokio::select! {
+                message = out_rx.recv() => message,
+                _ = stop.cancelled() => None,
+            };
+            let Some(message) = message else { break };
+            if write
+                .send(Message::Text(message.to_string()))
+                .await
+                .is_err()
+            {
+                stop.cancel();
+                break;
+            }
+        }
+        let _ = write.send(Message::Close(None)).await;
+    });
+
+    tokio::spawn(async move {
+        loop {
+            let message = tokio::select! {
+                message = read.next() => message,
+                _ = closed.cancelled() => break,
+            };
+            match message {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(message) = serde_json::from_str(&text) {
+                        let _ = in_tx.send(message);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            }
+        }
+    });
+
+    Ok((out_tx, in_rx))
+}
+
+/// streamable http: every message is a POST, answered with json or an sse stream.
+fn connect_http(
+    url: &str,
+    headers: &BTreeMap<String, String>,
+    closed: CancellationToken,
+) -> Result<Channels> {
+    let mut default_headers = reqwest::header::HeaderMap::new();
+    for (name, value) in headers {
+        default_headers.insert(
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())?,
+            reqwest::header::HeaderValue::from_str(value)?,
+        );
+    }
+    let client = reqwest::Client::builder()
+        .default_headers(default_headers)
+        .build()?;
+    let url = url.to_string();
+    let session: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+    let (in_tx, in_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut listening = false;
+        loop {
+            let message = tokio::select! {
+                message = out_rx.recv() => message,
+                _ = closed.cancelled() => None,
+            };
+            let Some(message) = message else { break };
+            // server initiated messages come on a GET stream, opened once initialized.
+            if !listening && message["method"] == "notifications/initialized" {
+                listening = true;
+                let get = client
+                    .get(&url)
+                    .header(reqwest::header::ACCEPT, "text/event-stream");
+                tokio::spawn(http_stream(
+                    with_session(get, &session),
+                    None,
+                    in_tx.clone(),
+                    session.clone(),
+                    closed.clone(),
+                ));
+            }
+            let post = client
+                .post(&url)
+                .header(
+                    reqwest::header::ACCEPT,
+                    "application/json, text/event-stream",
+                )
+                .json(&message);
+            tokio::spawn(http_stream(
+                with_session(post, &session),
+                message.get("id").cloned(),
+                in_tx.clone(),
+                session.clone(),
+                closed.clone(),
+            ));
+        }
+        if let Some(session_id) = session.lock().unwrap().clone() {
+            let delete = client
+                .delete(&url)
+                .header(SESSION_HEADER, session_id)
+                .send();
+            tokio::spawn(delete);
+        }
+    });
+
+    Ok((out_tx, in_rx))
+}
+
+fn with_session(
+    request: reqwest::RequestBuilder,
+    session: &Mutex<Option<String>>,
+) -> reqwest::RequestBuilder {
+    let request = request.header("mcp-protocol-version", PROTOCOL_VERSION);
+    match session.lock().unwrap().as_ref() {
+        Some(session_id) => request.header(SESSION_HEADER, session_id),
+        None => request,
+    }
+}
+
+/// sends one http request and forwards the messages of its response. failures of a request
+/// are turned into its error response; lost sessions and unreachable servers end the connection.
+async fn http_stream(
+    request: reqwest::RequestBuilder,
+    id: Option<Value>,
+    incoming: mpsc::UnboundedSender<Value>,
+    session: Arc<Mutex<Option<String>>>,
+    closed: CancellationToken,
+) {
+    let fail = |message: String| {
+        if let Some(id) = &id {
+            let _ = incoming.send(json!({"jsonrpc": "2.0", "id": id,
+                "error": {"code": -32603, "message": message}}));
+        }
+    };
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            fail(e.to_string());
+            if e.is_connect() {
+                closed.cancel();
+            }
+            return;
+        }
+    };
+    if let Some(session_id) = response
+        .headers()
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        session.lock().unwrap().replace(session_id.to_string());
+    }
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND && session.lock().unwrap().is_some() {
+        fail("session expired".to_string());
+        closed.cancel();
+        return;
+    }
+    if !status.is_success() {
+        // a server without the GET stream answers 405, that's fine.
+        fail(format!("http {}", status));
+        return;
+    }
+    let is_sse = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+    if !is_sse {
+        match response.json::<Value>().await {
+            Ok(Value::Array(messages)) => messages.into_iter().for_each(|m| {
+                let _ = incoming.send(m);
+            }),
+            Ok(message) => {
+                let _ = incoming.send(message);
+            }
+            // 202 for notifications and responses
+            Err(_) => {}
+        }
+        return;
+    }
+
+    let mut body = response.bytes_stream();
+    let mut buffer = String::new();
+    loop {
+        let chunk = tokio::select! {
+            chunk = body.next() => chunk,
+            _ = closed.cancelled() => return,
+        };
+        let Some(Ok(chunk)) = chunk else { break };
+        buffer.push_str(&String::from_utf8_lossy(&chunk).replace("\r\n", "\n"));
+        while let Some(end) = buffer.find("\n\n") {
+            let event: String = buffer.drain(..end + 2).collect();
+            if let Some(message) = sse_data(&event) {
+                let _ = incoming.send(message);
+            }
+        }
+    }
+}
+
+/// the json in the `data:` lines of an sse event.
+fn sse_data(event: &str) -> Option<Value> {
+    let data: Vec<&str> = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    if data.is_empty() {
+        return None;
+    }
+    serde_json::from_str(&data.join("\n")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_and_uris() {
+        assert_eq!(tool_name("gh.", "Create-Issue"), "gh.create_issue");
+        assert!(valid_server_name("git_hub2"));
+        assert!(!valid_server_name("git-hub"));
+
+        let mut resource = json!({"uri": "file:///tmp/a.txt"});
+        namespace_field(&mut resource, "uri", "fs");
+        assert_eq!(resource["uri"], "play://hub/fs/file:///tmp/a.txt");
+        assert_eq!(
+            split_hub_uri("play://hub/fs/file:///tmp/a.txt"),
+            Some(("fs", "file:///tmp/a.txt"))
+        );
+        assert_eq!(split_hub_uri("play://hub/fs"), None);
+    }
+
+    #[test]
+    fn test_call_result_and_sse() {
+        let text = json!({"content": [{"type": "text", "text": "{\"a\": 1}"}]});
+        assert_eq!(call_result(text).unwrap(), json!({"a": 1}));
+        let error = json!({"content": [{"type": "text", "text": "boom"}], "isError": true});
+        assert_eq!(call_result(error).unwrap_err().to_string(), "boom");
+        let structured = json!({"content": [], "structuredContent": {"b": 2}});
+        assert_eq!(call_result(structured).unwrap(), json!({"b": 2}));
+
+        assert_eq!(
+            sse_data("id: 1\nevent: message\ndata: {\"id\": 1}\n\n"),
+            Some(json!({"id": 1}))
+        );
+        assert_eq!(sse_data(": keep-alive\n\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_client_matches_responses() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Arc::new(McpClient::new(tx, Duration::from_secs(5)));
+
+        let caller = client.clone();
+        let call = tokio::spawn(async move { caller.request("tools/list", json!({})).await });
+        let request = rx.recv().await.unwrap();
+        assert_eq!(request["method"], "tools/list");
+
+        // a server request is answered, a notification is handed back
+        assert_eq!(
+            client.handle_incoming(json!({"jsonrpc": "2.0", "id": "s1", "method": "ping"})),
+            None
+        );
+        assert_eq!(rx.recv().await.unwrap()["result"], json!({}));
+        assert_eq!(
+            client.handle_incoming(
+                json!({"jsonrpc": "2.0", "method": "notifications/tools/list_changed"})
+            ),
+            Some("notifications/tools/list_changed".to_string())
+        );
+
+        client.handle_incoming(
+            json!({"jsonrpc": "2.0", "id": request["id"], "result": {"tools": []}}),
+        );
+        assert_eq!(call.await.unwrap().unwrap(), json!({"tools": []}));
+
+        let caller = client.clone();
+        let call = tokio::spawn(async move { caller.request("tools/call", json!({})).await });
+        rx.recv().await.unwrap();
+        client.close();
+        assert!(call.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stdio_transport() -> Result<()> {
+        // a tiny server answering every line with a response to id 1
+        let script =
+            r#"while read line; do echo '{"jsonrpc":"2.0","id":1,"result":{"ok":true}}'; done"#;
+        let closed = CancellationToken::new();
+        let (tx, mut rx) = connect_stdio(
+            "test",
+            "sh",
+            &["-c".to_string(), script.to_string()],
+            &BTreeMap::new(),
+            None,
+            closed.clone(),
+        )?;
+        tx.send(json!({"jsonrpc": "2.0", "id": 1, "method": "ping"}))?;
+        assert_eq!(rx.recv().await.unwrap()["result"]["ok"], true);
+        closed.cancel();
+        assert!(rx.recv().await.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use async_trait::async_trait;
+use axum::extract::{Path, Query, State};
+use axum::response::IntoResponse;
+use play_mcp::tools::{DataQuery, ToolHost};
+use serde_json::{json, Map, Value};
+use tokio::fs;
+
+use crate::controller::data_v4_controller::{
+    check_category_valid, insert_data, parse_and_convert_to_json_extract, SYSTEM_FIELDS,
+};
+use crate::controller::files_controller::{self, is_reserved_entry, resolve_files_path};
+use crate::controller::pages_controller::dynamic_pages;
+use crate::ikev2::CAT_IKEV2_USERS;
+use crate::plugins::CAT_PLUGINS;
+use crate::service::asset_store_service;
+use crate::service::mcp_resource_service::PROMPTS_CAT;
+use crate::service::mcp_user_tool_service::USER_TOOLS_CAT;
+use crate::tables::general_data::GeneralData;
+use crate::{files_dir, AppState};
+
+const DEFAULT_QUERY_LIMIT: u32 = 20;
+const MAX_QUERY_LIMIT: u32 = 1000;
+/// file_read refuses anything bigger, the output goes into a model context.
+pub(crate) const MAX_READ_BYTES: u64 = 1024 * 1024;
+const MAX_PAGE_BYTES: usize = 4 * 1024 * 1024;
+
+/// categories holding credentials or plugin setup, never read or written through mcp.
+const PRIVATE_CATEGORIES: &[&str] = &[CAT_IKEV2_USERS, CAT_PLUGINS];
+const PRIVATE_CATEGORY_PREFIXES: &[&str] = &["plugin-kv-"];
+/// categories defining what mcp clients are served, read only through mcp.
+const READ_ONLY_CATEGORIES: &[&str] = &[USER_TOOLS_CAT, PROMPTS_CAT];
+
+pub(crate) fn is_private_category(category: &str) -> bool {
+    PRIVATE_CATEGORIES.contains(&category)
+        || PRIVATE_CATEGORY_PREFIXES
+            .iter()
+            .any(|prefix| category.starts_with(prefix))
+}
+
+/// checks a category mcp tools and resources are about to read.
+pub(crate) fn check_mcp_readable(category: &str) -> Result<()> {
+    check_category_valid(category)?;
+    ensure!(
+        !is_private_category(category),
+        "category not accessible through mcp : {}",
+        category
+    );
+    Ok(())
+}
+
+/// checks a category mcp tools are about to write.
+pub(crate) fn check_mcp_writable(category: &str) -> Result<()> {
+    check_mcp_readable(category)?;
+    ensure!(
+        !READ_ONLY_CATEGORIES.contains(&category),
+        "category is read only through mcp : {}",
+        category
+    );
+    Ok(())
+}
+
+/// backs the built-in mcp tools with the data table, the files dir and pages.
+pub struct McpToolHost {
+    state: Arc<AppState>,
+}
+
+impl McpToolHost {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+fn check_data_fields(data: &Map<String, Value>) -> Result<()> {
+    ensure!(!data.is_empty(), "data cant be empty!");
+    for field in SYSTEM_FIELDS {
+        ensure!(
+            !data.contains_key(field),
+            "cant use system field `{field}` in data."
+        );
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl ToolHost for McpToolHost {
+    async fn data_query(&self, category: &str, query: DataQuery) -> Result<Vec<Value>> {
+        check_mcp_readable(category)?;
+        let where_ = match query.where_.as_deref().map(str::trim) {
+            Some(w) if !w.is_empty() => parse_and_convert_to_json_extract(w),
+            _ => "1=1".to_string(),
+        };
+        let order_by = query.order_by.unwrap_or_else(|| "id desc".to_string());
+        ensure!(
+            order_by
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || " _,.".contains(c)),
+            "invalid order_by : {}",
+            order_by
+        );
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .clamp(1, MAX_QUERY_LIMIT);
+
+        let rows = GeneralData::query_composite(
+            "*",
+            category,
+            &limit.to_string(),
+            &where_,
+            false,
+            &order_by,
+            &self.state.db,
+        )
+        .await?;
+        rows.iter()
+            .map(|row| row.to_flat_map().map(Value::Object))
+            .collect()
+    }
+
+    async fn data_insert(&self, category: &str, data: Map<String, Value>) -> Result<Value> {
+        check_mcp_writable(category)?;
+        check_data_fields(&data)?;
+        let row = insert_data(
+            State(self.state.clone()),
+            Path(category.to_string()),
+            serde_json::to_string(&data)?,
+        )
+        .await?;
+        Ok(Value::Object(row.to_flat_map()?))
+    }
+
+    async fn data_update(
+        &self,
+        category: &str,
+        id: u32,
+        data: Map<String, Value>,
+        replace: bool,
+    ) -> Result<Value> {
+        check_mcp_writable(category)?;
+        check_data_fields(&data)?;
+        let db = &self.state.db;
+        let rows = GeneralData::query_by_id_with_cat_select("*", id, category, db).await?;
+        ensure!(rows.len() == 1, "data not found for id : {}", id);
+
+        let data = serde_json::to_string(&data)?;
+        if replace {
+            GeneralData::update_data_by_id(id, &data, db).await?;
+        } else {
+            GeneralData::update_with_json_patch(db, id, data).await?;
+        }
+        let rows = GeneralData::query_by_id(id, db).await?;
+        let row = rows.first().context("data not found after update")?;
+        Ok(Value::Object(row.to_flat_map()?))
+    }
+
+    async fn file_list(&self, dir: &str) -> Result<Vec<Value>> {
+        let option = serde_json::from_value(json!({ "dir": dir }))?;
+        let result = files_controller::list_dir(Query(option))
+            .await
+            .map_err(|e| anyhow!("{}", e))?;
+        let result = serde_json::to_value(&result.0)?;
+        Ok(result["entries"].as_array().cloned().unwrap_or_default())
+    }
+
+    async fn file_read(&self, path: &str) -> Result<String> {
+        let file = resolve_files_path(path)?;
+        ensure!(file.is_file(), "file not found : {}", path);
+        let bytes = match asset_store_service::read_pointer(&file) {
+            Some(pointer) => {
+                ensure!(pointer.size <= MAX_READ_BYTES, "file too large : {}", path);
+                asset_store_service::read_all(&pointer).await?
+            }
+            None => {
+                ensure!(
+                    fs::metadata(&file).await?.len() <= MAX_READ_BYTES,
+                    "file too large : {}",
+                    path
+                );
+                fs::read(&file).await?
+            }
+        };
+        String::from_utf8(bytes).map_err(|_| anyhow!("not a text file : {}", path))
+    }
+
+    async fn file_write(&self, path: &str, content: &str) -> Result<Value> {
+        let file = resolve_files_path(path)?;
+        let root = files_dir!();
+        ensure!(file != root, "invalid path : {}", path);
+        // internal folders such as the trash are not for tools.
+        if let Some(first) = file.strip_prefix(&root)?.components().next() {
+            ensure!(
+                !is_reserved_entry(&root, &root.join(first)),
+                "reserved path : {}",
+                path
+            );
+        }
+        if file.is_dir() {
+            bail!("{} is a directory", path);
+        }
+        let existed = file.exists();
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&file, content).await?;
+        // stored like an upload, e.g. into the asset store when that backend is on
+        files_controller::finish_stored_file(&self.state.config.file_storage, &file).await?;
+        Ok(json!({
+            "path": path.trim_matches('/'),
+            "size": content.len(),
+            "created": !existed,
+        }))
+    }
+
+    async fn render_page(&self, url: &str, params: HashMap<String, String>) -> Result<String> {
+        let url = url.trim_start_matches("/pages/").trim_start_matches('/');
+        let response = dynamic_pages(
+            State(self.state.clone()),
+            Path(url.to_string()),
+            Query(params),
+        )
+        .await
+        .map_err(|e| anyhow!("{}", e))?
+        .into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), MAX_PAGE_BYTES).await?;
+        let body = String::from_utf8_lossy(&body).to_string();
+        ensure!(status.is_success(), "render failed ({}) : {}", status, body);
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_data_fields() {
+        let mut data = Map::new();
+        assert!(check_data_fields(&data).is_err());
+        data.insert("title".to_string(), json!("a"));
+        assert!(check_data_fields(&data).is_ok());
+        data.insert("id".to_string(), json!(1));
+        assert!(check_data_fields(&data).is_err());
+    }
+
+    #[test]
+    fn test_check_mcp_category() {
+        assert!(check_mcp_writable("notes").is_ok());
+        assert!(check_mcp_readable("ikev2_users").is_err());
+        assert!(check_mcp_readable("plugins").is_err());
+        assert!(check_mcp_readable("plugin-kv-demo").is_err());
+        assert!(check_mcp_readable("mcp_tools").is_ok());
+        assert!(check_mcp_writable("mcp_tools").is_err());
+        assert!(check_mcp_writable("prompts").is_err());
+    }
+}
@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use play_dylib_loader::host_services::{FileEntry, HostCall, HostServices, LogLevel};
+use play_dylib_loader::HttpResponse;
+use play_shared::current_timestamp;
+use reqwest::redirect::Policy;
+use reqwest::{ClientBuilder, Method, Proxy, Url};
+use serde_json::{json, Map, Value};
+use tokio::fs;
+use tracing::{debug, error, info, trace, warn};
+
+use crate::config::{PluginConfig, PluginPermissions};
+use crate::get_last_insert_id;
+use crate::plugins::{self, Plugin};
+use crate::service::proxy_route_service::host_matches;
+use crate::tables::general_data::GeneralData;
+use crate::tables::DBPool;
+use crate::AppState;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_DATA_LIST: u32 = 1000;
+const MAX_REDIRECTS: usize = 10;
+
+/// the services of the host a plugin can call, limited to its `permissions`.
+pub struct PluginHost {
+    name: String,
+    permissions: PluginPermissions,
+    db: DBPool,
+    /// `None` when the plugin has no files permission
+    files_dir: Option<PathBuf>,
+    http_proxy: Option<String>,
+    #[cfg(feature = "play-redis")]
+    redis: Option<std::sync::Arc<crate::controller::redis_controller::RedisState>>,
+}
+
+/// the json of a `kv` entry in the data table, used without redis.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct KvEntry {
+    key: String,
+    value: String,
+    /// unix millis
+    expires_at: Option<i64>,
+}
+
+impl PluginHost {
+    pub fn new(config: &PluginConfig, db: DBPool) -> Self {
+        Self {
+            name: config.name.clone(),
+            permissions: config.permissions.clone(),
+            db,
+            files_dir: None,
+            http_proxy: None,
+            #[cfg(feature = "play-redis")]
+            redis: None,
+        }
+    }
+
+    pub fn for_plugin(state: &AppState, plugin: &Plugin) -> Result<Self> {
+        let mut host = Self::new(&plugin.config, state.db.clone());
+        if host.permissions.files {
+            host.files_dir = Some(plugins::files_dir(plugin)?);
+        }
+        host.http_proxy = state.config.http_proxy.clone();
+        #[cfg(feature = "play-redis")]
+        {
+            host.redis = state.redis_state.clone();
+        }
+        Ok(host)
+    }
+
+    pub fn files_dir(&self) -> Option<&Path> {
+        self.files_dir.as_deref()
+    }
+
+    fn check_http(&self, url: &Url) -> Result<()> {
+        if !host_allowed(&self.permissions.http_allowlist, url) {
+            bail!(
+                "plugin `{}` may not request host `{}`",
+                self.name,
+                host_port(url)
+            );
+        }
+        Ok(())
+    }
+
+    fn check_cat(&self, cat: &str) -> Result<()> {
+        if !self.permissions.data_categories.iter().any(|c| c == cat) {
+            bail!(
+                "plugin `{}` may not access data category `{}`",
+                self.name,
+                cat
+            );
+        }
+        Ok(())
+    }
+
+    fn check(&self, granted: bool, service: &str) -> Result<()> {
+        if !granted {
+            bail!("plugin `{}` may not use {}", self.name, service);
+        }
+        Ok(())
+    }
+
+    /// the row `id`, if it is in `cat`.
+    async fn find_row(&self, cat: &str, id: u32) -> Result<GeneralData> {
+        self.check_cat(cat)?;
+        GeneralData::query_by_id(id, &self.db)
+            .await?
+            .into_iter()
+            .find(|row| row.cat == cat && !row.is_deleted)
+            .with_context(|| format!("data {} not found in category `{}`", id, cat))
+    }
+
+    /// `path` inside the files dir, it can't leave it, neither by `..` nor through a symlink.
+    async fn file_path(&self, path: &str) -> Result<PathBuf> {
+        let dir = self
+            .files_dir
+            .as_ref()
+            .with_context(|| format!("plugin `{}` may not use files", self.name))?;
+        let path = Path::new(path);
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            bail!("invalid path `{}`", path.display());
+        }
+
+        fs::create_dir_all(dir).await?;
+        let root = fs::canonicalize(dir).await?;
+        let mut current = root.clone();
+        for component in path.components() {
+            current.push(component);
+            match fs::symlink_metadata(&current).await {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    bail!("symlinks are not allowed : `{}`", path.display())
+                }
+                Ok(_) => {}
+                // the rest doesn't exist yet, e.g. a new file
+                Err(_) => break,
+            }
+        }
+        let mut existing = current.as_path();
+        while !fs::try_exists(existing).await? {
+            existing = existing.parent().context("no parent dir")?;
+        }
+        if !fs::canonicalize(existing).await?.starts_with(&root) {
+            bail!("invalid path `{}`", path.display());
+        }
+        Ok(root.join(path))
+    }
+
+    async fn list_files(&self, dir: &str) -> Result<Value> {
+        let dir = self.file_path(dir).await?;
+        let mut entries = vec![];
+        if fs::try_exists(&dir).await? {
+            let mut read_dir = fs::read_dir(&dir).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                let metadata = fs::symlink_metadata(entry.path()).await?;
+                if metadata.file_type().is_symlink() {
+                    continue;
+                }
+                entries.push(FileEntry {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    is_dir: metadata.is_dir(),
+                    size: metadata.len(),
+                });
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(serde_json::to_value(entries)?)
+    }
+
+    fn kv_cat(&self) -> String {
+        format!("plugin-kv-{}", self.name)
+    }
+
+    async fn kv_row(&self, key: &str) -> Result<Option<(GeneralData, KvEntry)>> {
+        let rows =
+            GeneralData::query_by_json_field("*", &self.kv_cat(), "key", key, 1, &self.db).await?;
+        match rows.into_iter().next() {
+            Some(row) => {
+                let entry = serde_json::from_str(&row.data)?;
+                Ok(Some((row, entry)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn kv_get(&self, key: &str) -> Result<Option<String>> {
+        #[cfg(feature = "play-redis")]
+        if let Some(redis) = &self.redis {
+            return Ok(redis.client.get::<String>(&self.redis_key(key)).await?);
+        }
+        Ok(self.kv_row(key).await?.and_then(|(_, entry)| {
+            let expired = entry.expires_at.is_some_and(|t| t <= current_timestamp!());
+            (!expired).then_some(entry.value)
+        }))
+    }
+
+    async fn kv_set(&self, key: &str, value: String, ttl_secs: Option<u64>) -> Result<()> {
+        #[cfg(feature = "play-redis")]
+        if let Some(redis) = &self.redis {
+            let ttl = ttl_secs.map(Duration::from_secs);
+            return Ok(redis.client.set(&self.redis_key(key), &value, ttl).await?);
+        }
+        let entry = serde_json::to_string(&KvEntry {
+            key: key.to_string(),
+            value,
+            expires_at: ttl_secs.map(|secs| current_timestamp!() + secs as i64 * 1000),
+        })?;
+        match self.kv_row(key).await? {
+            Some((row, _)) => GeneralData::update_data_by_id(row.id, &entry, &self.db).await?,
+            None => GeneralData::insert(&self.kv_cat(), &entry, &self.db).await?,
+        };
+        Ok(())
+    }
+
+    async fn kv_delete(&self, key: &str) -> Result<()> {
+        #[cfg(feature = "play-redis")]
+        if let Some(redis) = &self.redis {
+            redis.client.delete(&self.redis_key(key)).await?;
+            return Ok(());
+        }
+        if let Some((row, _)) = self.kv_row(key).await? {
+            GeneralData::delete(row.id, &self.db).await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "play-redis")]
+    fn redis_key(&self, key: &str) -> String {
+        format!("plugin:{}:{}", self.name, key)
+    }
+
+    fn log(&self, level: LogLevel, message: &str, fields: Map<String, Value>) {
+        let plugin = self.name.as_str();
+        let fields = Value::Object(fields);
+        match level {
+            LogLevel::Trace => trace!(plugin, %fields, "{}", message),
+            LogLevel::Debug => debug!(plugin, %fields, "{}", message),
+            LogLevel::Info => info!(plugin, %fields, "{}", message),
+            LogLevel::Warn => warn!(plugin, %fields, "{}", message),
+            LogLevel::Error => error!(plugin, %fields, "{}", message),
+        }
+    }
+
+    async fn http(
+        &self,
+        method: &str,
+        url: &str,
+        headers: HashMap<String, String>,
+        body: String,
+    ) -> Result<Value> {
+        let url = Url::parse(url)?;
+        self.check_http(&url)?;
+        // every redirect hop must be allowed too, or an allowed host could bounce to any address
+        let allowlist = self.permissions.http_allowlist.clone();
+        let redirect = Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if host_allowed(&allowlist, attempt.url()) {
+                attempt.follow()
+            } else {
+                let error = format!("redirect to `{}` is not allowed", host_port(attempt.url()));
+                attempt.error(error)
+            }
+        });
+        let mut client = ClientBuilder::new()
+            .timeout(HTTP_TIMEOUT)
+            .redirect(redirect);
+        if let Some(proxy) = &self.http_proxy {
+            client = client.proxy(Proxy::all(proxy)?);
+        }
+        let client = client.build()?;
+        let mut request = client.request(Method::from_bytes(method.as_bytes())?, url);
+        for (k, v) in headers {
+            request = request.header(k, v);
+        }
+        let response = request.body(body).send().await?;
+        let status_code = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
+            .collect();
+        let body = response.bytes().await?.to_vec();
+        Ok(serde_json::to_value(HttpResponse {
+            headers,
+            body,
+            status_code,
+            error: None,
+        })?)
+    }
+}
+
+fn host_port(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// whether the host (or host:port) of `url` matches one of the patterns.
+fn host_allowed(allowlist: &[String], url: &Url) -> bool {
+    let host = url.host_str().unwrap_or_default();
+    let host_port = host_port(url);
+    allowlist
+        .iter()
+        .any(|pattern| host_matches(pattern, host) || host_matches(pattern, &host_port))
+}
+
+fn row_json(row: GeneralData) -> Value {
+    let data = serde_json::from_str(&row.data).unwrap_or(Value::String(row.data));
+    json!({
+        "id": row.id,
+        "data": data,
+        "created": row.created.and_utc().timestamp_millis(),
+        "updated": row.updated.and_utc().timestamp_millis(),
+    })
+}
+
+#[async_trait]
+impl HostServices for PluginHost {
+    async fn call(&self, call: HostCall) -> Result<Value> {
+        match call {
+            HostCall::Http {
+                method,
+                url,
+                headers,
+                body,
+            } => self.http(&method, &url, headers, body).await,
+            HostCall::DataList { cat, limit } => {
+                self.check_cat(&cat)?;
+                let rows =
+                    GeneralData::query_by_cat("*", &cat, limit.min(MAX_DATA_LIST) as i32, &self.db)
+                        .await?;
+                Ok(rows
+                    .into_iter()
+                    .filter(|row| !row.is_deleted)
+                    .map(row_json)
+                    .collect())
+            }
+            HostCall::DataGet { cat, id } => Ok(row_json(self.find_row(&cat, id).await?)),
+            HostCall::DataInsert { cat, data } => {
+                self.check_cat(&cat)?;
+                let ret = GeneralData::insert(&cat, &data.to_string(), &self.db).await?;
+                Ok(json!(get_last_insert_id!(ret)))
+            }
+            HostCall::DataUpdate { cat, id, data } => {
+                self.find_row(&cat, id).await?;
+                GeneralData::update_data_by_id(id, &data.to_string(), &self.db).await?;
+                Ok(Value::Null)
+            }
+            HostCall::DataDelete { cat, id } => {
+                self.find_row(&cat, id).await?;
+                GeneralData::soft_delete(id, &self.db).await?;
+                Ok(Value::Null)
+            }
+            HostCall::FileRead { path } => Ok(json!(fs::read(self.file_path(&path).await?).await?)),
+            HostCall::FileWrite { path, content } => {
+                let path = self.file_path(&path).await?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                fs::write(path, content).await?;
+                Ok(Value::Null)
+            }
+            HostCall::FileList { dir } => self.list_files(&dir).await,
+            HostCall::FileDelete { path } => {
+                fs::remove_file(self.file_path(&path).await?).await?;
+                Ok(Value::Null)
+            }
+            HostCall::KvGet { key } => {
+                self.check(self.permissions.kv, "kv")?;
+                Ok(json!(self.kv_get(&key).await?))
+            }
+            HostCall::KvSet {
+                key,
+                value,
+                ttl_secs,
+            } => {
+                self.check(self.permissions.kv, "kv")?;
+                self.kv_set(&key, value, ttl_secs).await?;
+                Ok(Value::Null)
+            }
+            HostCall::KvDelete { key } => {
+                self.check(self.permissions.kv, "kv")?;
+                self.kv_delete(&key).await?;
+                Ok(Value::Null)
+            }
+            HostCall::Log {
+                level,
+                message,
+                fields,
+            } => {
+                self.check(self.permissions.log, "log")?;
+                self.log(level, &message, fields);
+                Ok(Value::Null)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::init_test_pool;
+
+    async fn host(permissions: PluginPermissions) -> PluginHost {
+        let config = PluginConfig {
+            name: "foo".to_string(),
+            permissions,
+            ..Default::default()
+        };
+        PluginHost::new(&config, init_test_pool().await)
+    }
+
+    #[tokio::test]
+    async fn test_permissions() {
+        let host = host(PluginPermissions {
+            http_allowlist: vec!["*.example.com".to_string(), "127.0.0.1:8080".to_string()],
+            data_categories: vec!["notes".to_string()],
+            ..Default::default()
+        })
+        .await;
+        for (url, allowed) in [
+            ("https://api.example.com/x", true),
+            ("https://example.com/x", false),
+            ("http://127.0.0.1:8080/", true),
+            ("http://127.0.0.1:9090/", false),
+        ] {
+            assert_eq!(
+                host.check_http(&Url::parse(url).unwrap()).is_ok(),
+                allowed,
+                "{url}"
+            );
+        }
+
+        let id = host
+            .call(HostCall::DataInsert {
+                cat: "notes".to_string(),
+                data: json!({"title": "hi"}),
+            })
+            .await
+            .unwrap();
+        let id = id.as_u64().unwrap() as u32;
+        let row = host
+            .call(HostCall::DataGet {
+                cat: "notes".to_string(),
+                id,
+            })
+            .await
+            .unwrap();
+        assert_eq!(row["data"]["title"], "hi");
+
+        let denied = HostCall::DataList {
+            cat: "secrets".to_string(),
+            limit: 10,
+        };
+        assert!(host.call(denied).await.is_err());
+        // ids of other categories are not reachable through an allowed one
+        let other = GeneralData::insert("secrets", "{}", &host.db)
+            .await
+            .unwrap();
+        let other = HostCall::DataGet {
+            cat: "notes".to_string(),
+            id: get_last_insert_id!(other) as u32,
+        };
+        assert!(host.call(other).await.is_err());
+
+        for call in [
+            HostCall::KvGet {
+                key: "a".to_string(),
+            },
+            HostCall::FileList { dir: String::new() },
+            HostCall::Log {
+                level: LogLevel::Info,
+                message: "hi".to_string(),
+                fields: Map::new(),
+            },
+        ] {
+            assert!(host.call(call).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_redirect() {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = axum::Router::new()
+            .route(
+                "/out",
+                axum::routing::get(|| async {
+                    axum::response::Redirect::temporary("http://localhost:1/")
+                }),
+            )
+            .route(
+                "/in",
+                axum::routing::get(|| async { axum::response::Redirect::temporary("/ok") }),
+            )
+            .route("/ok", axum::routing::get(|| async { "ok" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let host = host(PluginPermissions {
+            http_allowlist: vec![format!("127.0.0.1:{}", port)],
+            ..Default::default()
+        })
+        .await;
+        let get = |path: &str| HostCall::Http {
+            method: "GET".to_string(),
+            url: format!("http://127.0.0.1:{}{}", port, path),
+            headers: HashMap::new(),
+            body: String::new(),
+        };
+        let response = host.call(get("/in")).await.unwrap();
+        assert_eq!(response["body"], json!(b"ok"));
+        assert!(host.call(get("/out")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_files_and_kv() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut host = host(PluginPermissions {
+            files: true,
+            kv: true,
+            ..Default::default()
+        })
+        .await;
+        host.files_dir = Some(dir.path().to_path_buf());
+
+        let write = HostCall::FileWrite {
+            path: "a/b.txt".to_string(),
+            content: b"hello".to_vec(),
+        };
+        host.call(write).await.unwrap();
+        let read = HostCall::FileRead {
+            path: "a/b.txt".to_string(),
+        };
+        assert_eq!(host.call(read).await.unwrap(), json!(b"hello"));
+        let list = host
+            .call(HostCall::FileList {
+                dir: "a".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(list[0]["name"], "b.txt");
+        let escape = HostCall::FileRead {
+            path: "../secret".to_string(),
+        };
+        assert!(host.call(escape).await.is_err());
+        #[cfg(unix)]
+        {
+            let outside = tempfile::tempdir().unwrap();
+            std::fs::write(outside.path().join("secret"), b"x").unwrap();
+            std::os::unix::fs::symlink(outside.path(), dir.path().join("link")).unwrap();
+            for call in [
+                HostCall::FileRead {
+                    path: "link/secret".to_string(),
+                },
+                HostCall::FileWrite {
+                    path: "link/new.txt".to_string(),
+                    content: b"x".to_vec(),
+                },
+            ] {
+                assert!(host.call(call).await.is_err());
+            }
+            assert!(!outside.path().join("new.txt").exists());
+            let list = host
+                .call(HostCall::FileList { dir: String::new() })
+                .await
+                .unwrap();
+            assert_eq!(list.as_array().unwrap().len(), 1);
+        }
+
+        let get = || HostCall::KvGet {
+            key: "a".to_string(),
+        };
+        assert_eq!(host.call(get()).await.unwrap(), Value::Null);
+        for value in ["1", "2"] {
+            let set = HostCall::KvSet {
+                key: "a".to_string(),
+                value: value.to_string(),
+                ttl_secs: None,
+            };
+            host.call(set).await.unwrap();
+        }
+        assert_eq!(host.call(get()).await.unwrap(), json!("2"));
+        let delete = HostCall::KvDelete {
+            key: "a".to_string(),
+        };
+        host.call(delete).await.unwrap();
+        assert_eq!(host.call(get()).await.unwrap(), Value::Null);
+    }
+}
@@ -0,0 +1,562 @@
+//! keeps the `is_server` plugins running: restarts them by their `supervisor` policy, probes
+//! their health and keeps the last lines of their output for `/admin/plugins/{name}/logs`.
+
+use std::collections::{BTreeMap, VecDeque};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::config::{PluginConfig, RestartPolicy, SupervisorConfig};
+use crate::plugins::Plugin;
+use play_shared::current_timestamp;
+
+/// lines kept for clients connecting to the logs later.
+const MAX_LOG_LINES: usize = 500;
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// the `stream` of the lines written by the supervisor itself.
+const SUPERVISOR: &str = "supervisor";
+
+/// false until the server started, plugins loaded before are only started by `start`.
+static STARTED: AtomicBool = AtomicBool::new(false);
+static SUPERVISORS: Mutex<BTreeMap<String, Supervisor>> = Mutex::new(BTreeMap::new());
+/// kept across restarts and reloads of the plugin.
+static LOGS: Mutex<BTreeMap<String, Arc<PluginLogs>>> = Mutex::new(BTreeMap::new());
+
+struct Supervisor {
+    config: PluginConfig,
+    shared: Arc<Shared>,
+    stop: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+struct Shared {
+    name: String,
+    status: Mutex<ProcessStatus>,
+    logs: Arc<PluginLogs>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ProcessStatus {
+    /// `running`, `restarting`, `exited` when it won't be restarted, or `stopped`
+    pub state: &'static str,
+    /// only for `create_process` plugins
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    pub started_at: Option<i64>,
+    pub last_exit: Option<String>,
+    pub last_exit_at: Option<i64>,
+    /// `None` without `health_url` or before the first probe
+    pub healthy: Option<bool>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LogLine {
+    pub time: i64,
+    /// `stdout`, `stderr` or `supervisor`
+    pub stream: &'static str,
+    pub line: String,
+}
+
+pub struct PluginLogs {
+    lines: Mutex<VecDeque<LogLine>>,
+    tx: broadcast::Sender<LogLine>,
+}
+
+impl PluginLogs {
+    fn new() -> Self {
+        Self {
+            lines: Mutex::new(VecDeque::new()),
+            tx: broadcast::channel(MAX_LOG_LINES).0,
+        }
+    }
+
+    fn push(&self, stream: &'static str, line: String) {
+        let line = LogLine {
+            time: current_timestamp!(),
+            stream,
+            line,
+        };
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == MAX_LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line.clone());
+        let _ = self.tx.send(line);
+    }
+
+    /// the kept lines, and the ones after them.
+    pub fn subscribe(&self) -> (Vec<LogLine>, broadcast::Receiver<LogLine>) {
+        let lines = self.lines.lock().unwrap();
+        (lines.iter().cloned().collect(), self.tx.subscribe())
+    }
+}
+
+impl Shared {
+    fn update(&self, f: impl FnOnce(&mut ProcessStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+
+    fn log(&self, message: String) {
+        info!(plugin = self.name, "{}", message);
+        self.logs.push(SUPERVISOR, message);
+    }
+}
+
+enum Exit {
+    Stopped,
+    Exited { success: bool, reason: String },
+}
+
+/// starts the enabled server plugins, called once the server is up.
+pub async fn start() {
+    STARTED.store(true, Ordering::Relaxed);
+    sync(&crate::plugins::server_plugins()).await;
+}
+
+/// stops the supervisors of plugins that are gone or changed, and starts the new ones.
+///
+/// in-process plugins can't be stopped, they keep running until the server exits even when
+/// changed or removed.
+pub async fn sync(plugins: &[Arc<Plugin>]) {
+    if !STARTED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut stopped = vec![];
+    {
+        let mut supervisors = SUPERVISORS.lock().unwrap();
+        let keys: Vec<String> = supervisors.keys().cloned().collect();
+        for key in keys {
+            let old = &supervisors[&key].config;
+            let keep = !old.create_process
+                || plugins
+                    .iter()
+                    .any(|p| p.key() == key && same_config(old, &p.config));
+            if !keep {
+                stopped.extend(supervisors.remove(&key));
+            }
+        }
+    }
+    for supervisor in stopped {
+        supervisor.stop().await;
+    }
+
+    let mut supervisors = SUPERVISORS.lock().unwrap();
+    for plugin in plugins {
+        let key = plugin.key();
+        if !supervisors.contains_key(&key) {
+            supervisors.insert(key.clone(), Supervisor::spawn(key, plugin.config.clone()));
+        }
+    }
+}
+
+/// stops a `create_process` plugin, the next `sync` starts it again.
+pub async fn stop(name: &str) {
+    let supervisor = {
+        let mut supervisors = SUPERVISORS.lock().unwrap();
+        match supervisors.get(name) {
+            Some(s) if s.config.create_process => supervisors.remove(name),
+            _ => None,
+        }
+    };
+    if let Some(supervisor) = supervisor {
+        supervisor.stop().await;
+    }
+}
+
+/// stops all plugins gracefully, called on shutdown.
+pub async fn shutdown() {
+    STARTED.store(false, Ordering::Relaxed);
+    let supervisors = std::mem::take(&mut *SUPERVISORS.lock().unwrap());
+    futures_util::future::join_all(supervisors.into_values().map(|s| s.stop())).await;
+}
+
+pub fn status(name: &str) -> Option<ProcessStatus> {
+    let supervisors = SUPERVISORS.lock().unwrap();
+    supervisors
+        .get(name)
+        .map(|s| s.shared.status.lock().unwrap().clone())
+}
+
+/// `None` for plugins that were never supervised.
+pub fn logs(name: &str) -> Option<Arc<PluginLogs>> {
+    LOGS.lock().unwrap().get(name).cloned()
+}
+
+fn same_config(a: &PluginConfig, b: &PluginConfig) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+impl Supervisor {
+    fn spawn(name: String, config: PluginConfig) -> Self {
+        let logs = LOGS
+            .lock()
+            .unwrap()
+            .entry(name.clone())
+            .or_insert_with(|| Arc::new(PluginLogs::new()))
+            .clone();
+        let shared = Arc::new(Shared {
+            name,
+            status: Mutex::new(ProcessStatus::default()),
+            logs,
+        });
+        let stop = CancellationToken::new();
+        let task = tokio::spawn(supervise(config.clone(), shared.clone(), stop.clone()));
+        Self {
+            config,
+            shared,
+            stop,
+            task,
+        }
+    }
+
+    async fn stop(self) {
+        self.stop.cancel();
+        // `terminate` gives up after `stop_timeout_secs`, this is only a safety net
+        let timeout = Duration::from_secs(self.config.supervisor.stop_timeout_secs + 5);
+        if tokio::time::timeout(timeout, self.task).await.is_err() {
+            warn!(plugin = self.shared.name, "supervisor did not stop in time");
+        }
+    }
+}
+
+async fn supervise(config: PluginConfig, shared: Arc<Shared>, stop: CancellationToken) {
+    let policy = &config.supervisor;
+    let base_delay = Duration::from_millis(policy.restart_delay_ms);
+    let max_delay = Duration::from_millis(policy.max_restart_delay_ms).max(base_delay);
+    let mut delay = base_delay;
+    loop {
+        let started = Instant::now();
+        shared.update(|s| {
+            s.state = "running";
+            s.started_at = Some(current_timestamp!());
+            s.healthy = None;
+        });
+        shared.log(format!("starting {}", config.file_path));
+        let exit = if config.create_process {
+            run_process(&config, &shared, &stop).await
+        } else {
+            run_in_process(&config, &shared, &stop).await
+        };
+        let (success, reason) = match exit {
+            Ok(Exit::Stopped) => {
+                shared.log("stopped".to_string());
+                shared.update(|s| {
+                    s.state = "stopped";
+                    s.pid = None;
+                });
+                return;
+            }
+            Ok(Exit::Exited { success, reason }) => (success, reason),
+            Err(e) => (false, format!("{:#}", e)),
+        };
+        shared.log(format!("exited: {}", reason));
+        shared.update(|s| {
+            s.pid = None;
+            s.last_exit = Some(reason);
+            s.last_exit_at = Some(current_timestamp!());
+        });
+
+        let restart = match policy.restart {
+            RestartPolicy::Never => false,
+            RestartPolicy::Always | RestartPolicy::Backoff => true,
+            RestartPolicy::OnFailure => !success,
+        };
+        if !restart {
+            shared.update(|s| s.state = "exited");
+            return;
+        }
+        if policy.restart == RestartPolicy::Backoff && started.elapsed() >= max_delay {
+            delay = base_delay;
+        }
+        shared.update(|s| s.state = "restarting");
+        shared.log(format!("restarting in {:?}", delay));
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop.cancelled() => {
+                shared.update(|s| s.state = "stopped");
+                return;
+            }
+        }
+        if policy.restart == RestartPolicy::Backoff {
+            delay = (delay * 2).min(max_delay);
+        }
+        shared.update(|s| s.restarts += 1);
+    }
+}
+
+async fn run_process(
+    config: &PluginConfig,
+    shared: &Shared,
+    stop: &CancellationToken,
+) -> Result<Exit> {
+    make_executable_if_needed(&config.file_path)?;
+    let mut child = Command::new(&config.file_path)
+        .args(&config.args)
+        .envs(&config.env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to start {}", config.file_path))?;
+    shared.update(|s| s.pid = child.id());
+    pipe(child.stdout.take(), "stdout", shared);
+    pipe(child.stderr.take(), "stderr", shared);
+
+    let stop_timeout = Duration::from_secs(config.supervisor.stop_timeout_secs);
+    let exit = tokio::select! {
+        status = child.wait() => {
+            let status = status?;
+            Exit::Exited { success: status.success(), reason: status.to_string() }
+        }
+        _ = stop.cancelled() => {
+            terminate(&mut child, stop_timeout).await;
+            Exit::Stopped
+        }
+        _ = probe(&config.supervisor, shared) => {
+            terminate(&mut child, stop_timeout).await;
+            Exit::Exited { success: false, reason: "health check failed".to_string() }
+        }
+    };
+    Ok(exit)
+}
+
+async fn run_in_process(
+    config: &PluginConfig,
+    shared: &Shared,
+    stop: &CancellationToken,
+) -> Result<Exit> {
+    let unhealthy = async {
+        probe(&config.supervisor, shared).await;
+        shared.log("health check failed, in-process plugins can't be restarted".to_string());
+        std::future::pending::<()>().await
+    };
+    tokio::select! {
+        result = play_dylib_loader::run_server(&config.file_path) => {
+            result?;
+            Ok(Exit::Exited { success: true, reason: "run returned".to_string() })
+        }
+        _ = stop.cancelled() => {
+            shared.log("in-process plugins can't be stopped, it keeps running".to_string());
+            Ok(Exit::Stopped)
+        }
+        _ = unhealthy => unreachable!(),
+    }
+}
+
+fn make_executable_if_needed(file_path: &str) -> std::io::Result<bool> {
+    #[cfg(windows)]
+    {
+        let _ = file_path;
+        info!("Skipping executable permission update on Windows");
+        return Ok(false);
+    }
+
+    #[cfg(unix)]
+    {
+        let path = Path::new(file_path);
+
+        // 获取当前文件权限
+        let mut perms = std::fs::metadata(path)?.permissions();
+
+        // 获取当前权限模式
+        let mode = perms.mode();
+
+        // 检查是否已经有执行权限
+        // 检查所有者、组和其他用户的执行权限
+        let has_execute_permission = (mode & 0o100 != 0) || // 所有者执行权限
+            (mode & 0o010 != 0) || // 组执行权限
+            (mode & 0o001 != 0); // 其他用户执行权限
+
+        if !has_execute_permission {
+            // 如果没有执行权限，则添加
+            let new_mode = mode | 0o111; // 为所有者、组和其他用户添加执行权限
+            perms.set_mode(new_mode);
+
+            // 应用新权限
+            std::fs::set_permissions(path, perms)?;
+
+            info!("Added execute permissions to {}", file_path);
+            Ok(true)
+        } else {
+            info!("File already has execute permissions");
+            Ok(false)
+        }
+    }
+}
+
+fn pipe(
+    reader: Option<impl AsyncRead + Unpin + Send + 'static>,
+    stream: &'static str,
+    shared: &Shared,
+) {
+    let Some(reader) = reader else {
+        return;
+    };
+    let name = shared.name.clone();
+    let logs = shared.logs.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            info!(plugin = name, stream, "{}", line);
+            logs.push(stream, line);
+        }
+    });
+}
+
+/// SIGTERM first, SIGKILL when it is still running after `timeout`.
+async fn terminate(child: &mut Child, timeout: Duration) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+        if tokio::time::timeout(timeout, child.wait()).await.is_ok() {
+            return;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = timeout;
+    let _ = child.kill().await;
+}
+
+/// returns once `health_failures` probes in a row failed, never without `health_url`.
+async fn probe(config: &SupervisorConfig, shared: &Shared) {
+    let Some(url) = &config.health_url else {
+        return std::future::pending().await;
+    };
+    let client = reqwest::Client::builder()
+        .timeout(HEALTH_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let interval = Duration::from_secs(config.health_interval_secs.max(1));
+    let mut failures = 0;
+    loop {
+        tokio::time::sleep(interval).await;
+        let healthy = match client.get(url).send().await {
+            Ok(response) => response.status().is_success(),
+            Err(_) => false,
+        };
+        shared.update(|s| s.healthy = Some(healthy));
+        if healthy {
+            failures = 0;
+            continue;
+        }
+        failures += 1;
+        shared.log(format!(
+            "health check {} failed ({}/{})",
+            url, failures, config.health_failures
+        ));
+        if failures >= config.health_failures {
+            return;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn script(dir: &tempfile::TempDir, content: &str) -> String {
+        let path = dir.path().join("plugin.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", content)).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn process_config(file_path: String, restart: RestartPolicy) -> PluginConfig {
+        PluginConfig {
+            file_path,
+            create_process: true,
+            is_server: true,
+            args: vec!["world".to_string()],
+            env: BTreeMap::from([("GREETING".to_string(), "hello".to_string())]),
+            supervisor: SupervisorConfig {
+                restart,
+                restart_delay_ms: 10,
+                stop_timeout_secs: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    async fn wait_for(supervisor: &Supervisor, f: impl Fn(&ProcessStatus) -> bool) {
+        for _ in 0..500 {
+            if f(&supervisor.shared.status.lock().unwrap()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out, {:?}", supervisor.shared.status.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_restart_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = script(&dir, r#"echo "$GREETING $1"; exit 3"#);
+        let supervisor = Supervisor::spawn(
+            "test-restart".to_string(),
+            process_config(path, RestartPolicy::OnFailure),
+        );
+        wait_for(&supervisor, |s| s.restarts >= 2).await;
+
+        let (lines, _) = supervisor.shared.logs.subscribe();
+        assert!(lines
+            .iter()
+            .any(|l| l.stream == "stdout" && l.line == "hello world"));
+        let status = supervisor.shared.status.lock().unwrap().clone();
+        assert!(status.last_exit.unwrap().contains('3'));
+
+        let shared = supervisor.shared.clone();
+        supervisor.stop().await;
+        assert_eq!(shared.status.lock().unwrap().state, "stopped");
+    }
+
+    #[tokio::test]
+    async fn test_no_restart_after_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = script(&dir, "exit 0");
+        let supervisor = Supervisor::spawn(
+            "test-success".to_string(),
+            process_config(path, RestartPolicy::OnFailure),
+        );
+        wait_for(&supervisor, |s| s.state == "exited").await;
+        assert_eq!(supervisor.shared.status.lock().unwrap().restarts, 0);
+    }
+
+    #[tokio::test]
+    async fn test_graceful_stop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = script(
+            &dir,
+            "trap 'echo bye; exit 0' TERM\necho ready\nwhile true; do sleep 0.05; done",
+        );
+        let supervisor = Supervisor::spawn(
+            "test-stop".to_string(),
+            process_config(path, RestartPolicy::Always),
+        );
+        let logs = supervisor.shared.logs.clone();
+        for _ in 0..500 {
+            if logs.subscribe().0.iter().any(|l| l.line == "ready") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        supervisor.stop().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(logs.subscribe().0.iter().any(|l| l.line == "bye"));
+    }
+}
@@ -0,0 +1,650 @@
+// without the loader plugins are only matched, to answer that they can't run
+#![cfg_attr(not(feature = "play-dylib-loader"), allow(dead_code))]
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::config::PluginConfig;
+use crate::tables::general_data::GeneralData;
+use crate::tables::DBPool;
+use play_shared::constants::DATA_DIR;
+use play_shared::current_timestamp;
+
+pub const CAT_PLUGINS: &str = "plugins";
+
+/// uploaded versions kept for rollback, older ones are deleted.
+const MAX_VERSIONS: usize = 5;
+
+/// the plugins requests are dispatched to, swapped as a whole on every change.
+static PLUGINS: RwLock<Option<Arc<Vec<Arc<Plugin>>>>> = RwLock::new(None);
+
+/// a plugin from `plugin_config` in config.toml or from the `plugins` data table.
+#[derive(Debug)]
+pub struct Plugin {
+    pub config: PluginConfig,
+    /// the data row, `None` for plugins from config.toml
+    pub id: Option<u32>,
+    pub versions: Vec<PluginVersion>,
+    pub stats: Arc<PluginStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PluginVersion {
+    pub version: u32,
+    pub file_path: String,
+    pub sha256: String,
+    pub size: u64,
+    pub uploaded_at: i64,
+}
+
+/// the json stored in the data table, plain `PluginConfig` rows have no versions.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct StoredPlugin {
+    #[serde(flatten)]
+    config: PluginConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    versions: Vec<PluginVersion>,
+}
+
+/// request counters, kept across reloads of the same plugin.
+#[derive(Debug, Default)]
+pub struct PluginStats {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    last_success_at: AtomicI64,
+    last_error: Mutex<Option<(i64, String)>>,
+    /// the latest request failed
+    failing: AtomicBool,
+}
+
+impl PluginStats {
+    pub fn record_success(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.last_success_at
+            .store(current_timestamp!(), Ordering::Relaxed);
+        self.failing.store(false, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self, error: String) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some((current_timestamp!(), error));
+        self.failing.store(true, Ordering::Relaxed);
+    }
+
+    fn health(&self, disabled: bool) -> PluginHealth {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let last_success_at = self.last_success_at.load(Ordering::Relaxed);
+        let last_error = self.last_error.lock().unwrap().clone();
+        let status = if disabled {
+            "disabled"
+        } else if requests == 0 {
+            "idle"
+        } else if self.failing.load(Ordering::Relaxed) {
+            "failing"
+        } else {
+            "ok"
+        };
+        PluginHealth {
+            status,
+            requests,
+            errors: self.errors.load(Ordering::Relaxed),
+            last_success_at: (last_success_at > 0).then_some(last_success_at),
+            last_error_at: last_error.as_ref().map(|(time, _)| *time),
+            last_error: last_error.map(|(_, error)| error),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PluginHealth {
+    /// `ok`, `failing` when the latest request failed, `idle` or `disabled`
+    pub status: &'static str,
+    pub requests: u64,
+    pub errors: u64,
+    pub last_success_at: Option<i64>,
+    pub last_error_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PluginStatus {
+    #[serde(flatten)]
+    pub config: PluginConfig,
+    /// `config` for plugins from config.toml, `data` for the ones managed from /admin/plugins
+    pub source: &'static str,
+    /// the uploaded version in use, `None` for a file path set by hand
+    pub version: Option<u32>,
+    pub versions: Vec<PluginVersion>,
+    /// the library is loaded in the process
+    pub loaded: bool,
+    pub health: PluginHealth,
+    /// the supervisor of a running server plugin
+    #[cfg(feature = "play-dylib-loader")]
+    pub process: Option<crate::plugin_supervisor::ProcessStatus>,
+}
+
+impl Plugin {
+    /// the name plugins are managed by, unnamed config plugins use their file name.
+    pub fn key(&self) -> String {
+        plugin_key(&self.config)
+    }
+
+    fn status(&self) -> PluginStatus {
+        PluginStatus {
+            config: self.config.clone(),
+            source: if self.id.is_some() { "data" } else { "config" },
+            version: current_version(&self.config, &self.versions),
+            versions: self.versions.clone(),
+            loaded: is_loaded(&self.config.file_path),
+            health: self.stats.health(self.config.disable),
+            #[cfg(feature = "play-dylib-loader")]
+            process: crate::plugin_supervisor::status(&self.key()),
+        }
+    }
+}
+
+fn plugin_key(config: &PluginConfig) -> String {
+    if !config.name.is_empty() {
+        return config.name.to_string();
+    }
+    Path::new(&config.file_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn current_version(config: &PluginConfig, versions: &[PluginVersion]) -> Option<u32> {
+    versions
+        .iter()
+        .find(|v| v.file_path == config.file_path)
+        .map(|v| v.version)
+}
+
+fn snapshot() -> Arc<Vec<Arc<Plugin>>> {
+    PLUGINS.read().unwrap().clone().unwrap_or_default()
+}
+
+/// the enabled plugin serving `url` by its `url_prefix`.
+pub fn find_by_url(url: &str) -> Option<Arc<Plugin>> {
+    match_url(&snapshot(), url).cloned()
+}
+
+fn match_url<'a>(plugins: &'a [Arc<Plugin>], url: &str) -> Option<&'a Arc<Plugin>> {
+    plugins
+        .iter()
+        .filter(|p| !p.config.disable && !p.config.url_prefix.is_empty())
+        .filter(|p| path_params(&p.config.url_prefix, url).is_some())
+        .max_by_key(|p| p.config.url_prefix.len())
+}
+
+/// matches `url` to a `url_prefix`, a `{name}` segment of it matches any one segment.
+/// the values are as they are in the url, not decoded.
+pub fn path_params(prefix: &str, url: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut segments = url.split('/');
+    for pattern in prefix.trim_end_matches('/').split('/') {
+        let segment = segments.next()?;
+        match pattern.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            Some(name) if !segment.is_empty() => {
+                params.insert(name.to_string(), segment.to_string());
+            }
+            _ if pattern == segment => {}
+            _ => return None,
+        }
+    }
+    Some(params)
+}
+
+/// the enabled plugin serving `host` by its `proxy_domain`.
+pub fn find_by_domain(host: &str) -> Option<Arc<Plugin>> {
+    snapshot()
+        .iter()
+        .find(|p| !p.config.disable && p.config.proxy_domain.eq(host))
+        .cloned()
+}
+
+/// enabled plugins that run as servers, kept running by `plugin_supervisor`.
+pub fn server_plugins() -> Vec<Arc<Plugin>> {
+    snapshot()
+        .iter()
+        .filter(|p| !p.config.disable && p.config.is_server)
+        .cloned()
+        .collect()
+}
+
+pub fn list() -> Vec<PluginStatus> {
+    snapshot().iter().map(|p| p.status()).collect()
+}
+
+#[cfg(feature = "play-dylib-loader")]
+fn is_loaded(file_path: &str) -> bool {
+    #[cfg(feature = "play-wasm")]
+    if play_wasm_loader::is_module_cached(file_path) {
+        return true;
+    }
+    play_dylib_loader::is_plugin_cached(file_path)
+}
+
+#[cfg(not(feature = "play-dylib-loader"))]
+fn is_loaded(_file_path: &str) -> bool {
+    false
+}
+
+/// drops the cached library so the next request loads the file again.
+fn unload(file_path: &str) {
+    #[cfg(feature = "play-dylib-loader")]
+    play_dylib_loader::remove_plugin_from_cache(file_path);
+    #[cfg(feature = "play-wasm")]
+    play_wasm_loader::remove_module_from_cache(file_path);
+    #[cfg(not(feature = "play-dylib-loader"))]
+    let _ = file_path;
+}
+
+async fn query_stored_plugins(db: &DBPool) -> Result<Vec<(GeneralData, StoredPlugin)>> {
+    let rows = GeneralData::query_by_cat_simple(CAT_PLUGINS, 1000, db).await?;
+    rows.into_iter()
+        .filter(|row| !row.is_deleted)
+        .map(|row| {
+            let plugin = serde_json::from_str(&row.data)
+                .with_context(|| format!("invalid plugin record #{}", row.id))?;
+            Ok((row, plugin))
+        })
+        .collect()
+}
+
+/// reads the plugins from config.toml and the data table and swaps them in.
+///
+/// counters of plugins that are still there are kept, libraries whose file is no longer used
+/// are unloaded.
+pub async fn load(config_plugins: &[PluginConfig], db: &DBPool) -> Result<()> {
+    let old = snapshot();
+    let take_stats = |config: &PluginConfig| {
+        let key = plugin_key(config);
+        old.iter()
+            .find(|p| p.key() == key)
+            .map(|p| p.stats.clone())
+            .unwrap_or_default()
+    };
+
+    let mut plugins = vec![];
+    for config in config_plugins {
+        plugins.push(Arc::new(Plugin {
+            config: config.clone(),
+            id: None,
+            versions: vec![],
+            stats: take_stats(config),
+        }));
+    }
+    for (row, stored) in query_stored_plugins(db).await? {
+        let key = plugin_key(&stored.config);
+        if plugins.iter().any(|p| p.key() == key) {
+            warn!(
+                "plugin `{}` of data row #{} is defined twice, ignored",
+                key, row.id
+            );
+            continue;
+        }
+        plugins.push(Arc::new(Plugin {
+            stats: take_stats(&stored.config),
+            config: stored.config,
+            id: Some(row.id),
+            versions: stored.versions,
+        }));
+    }
+
+    for plugin in old.iter() {
+        let path = &plugin.config.file_path;
+        if !plugins
+            .iter()
+            .any(|p| !p.config.disable && &p.config.file_path == path)
+        {
+            unload(path);
+        }
+    }
+
+    info!(
+        "active plugins: {:?}",
+        plugins
+            .iter()
+            .filter(|p| !p.config.disable)
+            .map(|p| p.key())
+            .collect::<Vec<_>>()
+    );
+    *PLUGINS.write().unwrap() = Some(Arc::new(plugins));
+    #[cfg(feature = "play-dylib-loader")]
+    crate::plugin_supervisor::sync(&server_plugins()).await;
+    Ok(())
+}
+
+async fn find_stored(db: &DBPool, name: &str) -> Result<(GeneralData, StoredPlugin)> {
+    if snapshot().iter().any(|p| p.id.is_none() && p.key() == name) {
+        bail!("plugin `{name}` is defined in config.toml, change it there");
+    }
+    query_stored_plugins(db)
+        .await?
+        .into_iter()
+        .find(|(_, plugin)| plugin_key(&plugin.config) == name)
+        .with_context(|| format!("plugin `{name}` not found"))
+}
+
+async fn update_stored(db: &DBPool, row: &GeneralData, plugin: &StoredPlugin) -> Result<()> {
+    GeneralData::update_data_by_id(row.id, &serde_json::to_string(plugin)?, db).await?;
+    Ok(())
+}
+
+fn validate(config: &PluginConfig) -> Result<()> {
+    let name = &config.name;
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!("plugin name `{name}` must be made of letters, digits, `-` and `_`");
+    }
+    let prefix = &config.url_prefix;
+    if !prefix.is_empty() && (!prefix.starts_with('/') || prefix.trim_end_matches('/').is_empty()) {
+        bail!("url_prefix `{prefix}` must start with `/` and not be `/` itself");
+    }
+    for other in snapshot().iter().filter(|p| p.key() != *name) {
+        if !prefix.is_empty()
+            && other.config.url_prefix.trim_end_matches('/') == prefix.trim_end_matches('/')
+        {
+            bail!("url_prefix `{prefix}` is used by plugin `{}`", other.key());
+        }
+        if !config.proxy_domain.is_empty() && other.config.proxy_domain == config.proxy_domain {
+            bail!(
+                "proxy_domain `{}` is used by plugin `{}`",
+                config.proxy_domain,
+                other.key()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// adds or updates a plugin, the uploaded versions of an existing one are kept.
+pub async fn save(
+    db: &DBPool,
+    config_plugins: &[PluginConfig],
+    mut config: PluginConfig,
+) -> Result<()> {
+    config.name = config.name.trim().to_string();
+    validate(&config)?;
+
+    match find_stored(db, &config.name).await {
+        Ok((row, mut stored)) => {
+            if config.file_path.is_empty() {
+                config.file_path = stored.config.file_path;
+            }
+            stored.config = config;
+            update_stored(db, &row, &stored).await?;
+        }
+        Err(_) if !snapshot().iter().any(|p| p.key() == config.name) => {
+            let stored = StoredPlugin {
+                config,
+                versions: vec![],
+            };
+            GeneralData::insert(CAT_PLUGINS, &serde_json::to_string(&stored)?, db).await?;
+        }
+        Err(e) => return Err(e),
+    }
+    load(config_plugins, db).await
+}
+
+pub async fn set_disabled(
+    db: &DBPool,
+    config_plugins: &[PluginConfig],
+    name: &str,
+    disable: bool,
+) -> Result<()> {
+    let (row, mut stored) = find_stored(db, name).await?;
+    stored.config.disable = disable;
+    update_stored(db, &row, &stored).await?;
+    load(config_plugins, db).await
+}
+
+fn plugins_dir() -> Result<PathBuf> {
+    Ok(Path::new(&std::env::var(DATA_DIR)?).join("plugins"))
+}
+
+/// the dir a wasm plugin sees as `/data`, kept across versions.
+pub fn files_dir(plugin: &Plugin) -> Result<PathBuf> {
+    let name = &plugin.config.name;
+    if name.is_empty()
+        || name.contains(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_')
+    {
+        bail!(
+            "plugin `{}` needs a name made of letters, digits, `-` and `_` to get a files dir",
+            plugin.key()
+        );
+    }
+    Ok(plugins_dir()?.join(name).join("files"))
+}
+
+/// stores `content` as the next version of the plugin and switches to it.
+pub async fn upload(
+    db: &DBPool,
+    config_plugins: &[PluginConfig],
+    name: &str,
+    file_name: &str,
+    content: &[u8],
+) -> Result<PluginVersion> {
+    let (row, mut stored) = find_stored(db, name).await?;
+    let file_name = Path::new(file_name)
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .filter(|f| !f.is_empty())
+        .context("file name is missing")?;
+
+    let version = stored.versions.iter().map(|v| v.version).max().unwrap_or(0) + 1;
+    let dir = plugins_dir()?.join(name).join(version.to_string());
+    fs::create_dir_all(&dir).await?;
+    let path = dir.join(&file_name);
+    fs::write(&path, content).await?;
+
+    let uploaded = PluginVersion {
+        version,
+        file_path: path.to_string_lossy().to_string(),
+        sha256: hex::encode(Sha256::digest(content)),
+        size: content.len() as u64,
+        uploaded_at: current_timestamp!(),
+    };
+    stored.versions.push(uploaded.clone());
+    stored.config.file_path = uploaded.file_path.to_string();
+    for pruned in prune_versions(&mut stored.versions) {
+        if let Some(dir) = Path::new(&pruned.file_path).parent() {
+            let _ = fs::remove_dir_all(dir).await;
+        }
+    }
+    update_stored(db, &row, &stored).await?;
+    load(config_plugins, db).await?;
+    info!("plugin `{}` updated to version {}", name, version);
+    Ok(uploaded)
+}
+
+/// removes the oldest versions beyond `MAX_VERSIONS`.
+fn prune_versions(versions: &mut Vec<PluginVersion>) -> Vec<PluginVersion> {
+    versions.sort_by_key(|v| v.version);
+    let excess = versions.len().saturating_sub(MAX_VERSIONS);
+    versions.drain(..excess).collect()
+}
+
+/// the version to roll back to: `version` itself, or the one before the current one.
+fn rollback_target<'a>(
+    versions: &'a [PluginVersion],
+    current: Option<u32>,
+    version: Option<u32>,
+) -> Result<&'a PluginVersion> {
+    match version {
+        Some(version) => versions
+            .iter()
+            .find(|v| v.version == version)
+            .with_context(|| format!("version {version} not found")),
+        None => versions
+            .iter()
+            .filter(|v| current.is_none_or(|current| v.version < current))
+            .max_by_key(|v| v.version)
+            .context("there is no previous version"),
+    }
+}
+
+pub async fn rollback(
+    db: &DBPool,
+    config_plugins: &[PluginConfig],
+    name: &str,
+    version: Option<u32>,
+) -> Result<u32> {
+    let (row, mut stored) = find_stored(db, name).await?;
+    let current = current_version(&stored.config, &stored.versions);
+    let target = rollback_target(&stored.versions, current, version)?.clone();
+    if !fs::try_exists(&target.file_path).await? {
+        bail!("the file of version {} is missing", target.version);
+    }
+    stored.config.file_path = target.file_path;
+    update_stored(db, &row, &stored).await?;
+    load(config_plugins, db).await?;
+    info!(
+        "plugin `{}` rolled back to version {}",
+        name, target.version
+    );
+    Ok(target.version)
+}
+
+/// loads the plugin file again on the next request, and re-reads all plugins.
+/// a server plugin running as a process is restarted.
+///
+/// `name` may be a plugin that was just added or removed through the data api.
+pub async fn reload(db: &DBPool, config_plugins: &[PluginConfig], name: &str) -> Result<()> {
+    if let Some(plugin) = snapshot().iter().find(|p| p.key() == name) {
+        unload(&plugin.config.file_path);
+    }
+    #[cfg(feature = "play-dylib-loader")]
+    crate::plugin_supervisor::stop(name).await;
+    load(config_plugins, db).await
+}
+
+/// removes the plugin and its uploaded versions.
+pub async fn remove(db: &DBPool, config_plugins: &[PluginConfig], name: &str) -> Result<()> {
+    let (row, stored) = find_stored(db, name).await?;
+    GeneralData::delete(row.id, db).await?;
+    load(config_plugins, db).await?;
+    if !stored.versions.is_empty() {
+        let _ = fs::remove_dir_all(plugins_dir()?.join(name)).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(name: &str, url_prefix: &str, disable: bool) -> Arc<Plugin> {
+        Arc::new(Plugin {
+            config: PluginConfig {
+                name: name.to_string(),
+                url_prefix: url_prefix.to_string(),
+                disable,
+                ..Default::default()
+            },
+            id: None,
+            versions: vec![],
+            stats: Default::default(),
+        })
+    }
+
+    fn version(version: u32) -> PluginVersion {
+        PluginVersion {
+            version,
+            file_path: format!("/data/plugins/foo/{version}/libfoo.so"),
+            sha256: String::new(),
+            size: 0,
+            uploaded_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_match_url() {
+        let plugins = vec![
+            plugin("a", "/api", false),
+            plugin("b", "/api/b", false),
+            plugin("c", "/c", true),
+        ];
+        assert_eq!(match_url(&plugins, "/api").unwrap().key(), "a");
+        assert_eq!(match_url(&plugins, "/api/x").unwrap().key(), "a");
+        assert_eq!(match_url(&plugins, "/api/b/x").unwrap().key(), "b");
+        assert!(match_url(&plugins, "/apix").is_none());
+        assert!(match_url(&plugins, "/c").is_none());
+    }
+
+    #[test]
+    fn test_path_params() {
+        let params = path_params("/users/{id}/posts/{post}", "/users/42/posts/7/edit").unwrap();
+        assert_eq!(params["id"], "42");
+        assert_eq!(params["post"], "7");
+        assert!(path_params("/users/{id}", "/users").is_none());
+        assert!(path_params("/users/{id}", "/users/").is_none());
+        assert!(path_params("/users/{id}", "/people/1").is_none());
+        assert!(path_params("/api/", "/api").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_stored_plugin_compat() {
+        let stored: StoredPlugin =
+            serde_json::from_str(r#"{"name":"foo","url_prefix":"/foo","file_path":"/a.so"}"#)
+                .unwrap();
+        assert_eq!(stored.config.url_prefix, "/foo");
+        assert!(stored.versions.is_empty());
+        let json = serde_json::to_value(&stored).unwrap();
+        assert_eq!(json["file_path"], "/a.so");
+        assert!(json.get("versions").is_none());
+    }
+
+    #[test]
+    fn test_rollback_target() {
+        let versions = vec![version(1), version(2), version(3)];
+        assert_eq!(
+            rollback_target(&versions, Some(3), None).unwrap().version,
+            2
+        );
+        assert_eq!(
+            rollback_target(&versions, Some(2), Some(3))
+                .unwrap()
+                .version,
+            3
+        );
+        assert_eq!(rollback_target(&versions, None, None).unwrap().version, 3);
+        assert!(rollback_target(&versions, Some(1), None).is_err());
+        assert!(rollback_target(&versions, Some(1), Some(4)).is_err());
+    }
+
+    #[test]
+    fn test_prune_versions() {
+        let mut versions: Vec<_> = (1..=7).rev().map(version).collect();
+        let pruned = prune_versions(&mut versions);
+        assert_eq!(pruned.iter().map(|v| v.version).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(versions.first().unwrap().version, 3);
+    }
+
+    #[test]
+    fn test_health() {
+        let stats = PluginStats::default();
+        assert_eq!(stats.health(false).status, "idle");
+        stats.record_success();
+        assert_eq!(stats.health(false).status, "ok");
+        stats.record_error("boom".to_string());
+        let health = stats.health(false);
+        assert_eq!(health.status, "failing");
+        assert_eq!((health.requests, health.errors), (2, 1));
+        assert_eq!(health.last_error.as_deref(), Some("boom"));
+        assert_eq!(stats.health(true).status, "disabled");
+    }
+}
@@ -0,0 +1,323 @@
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+use axum::body::Bytes;
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use play_db::AssetMetadata;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use walkdir::WalkDir;
+
+use crate::config::FileStorageConfig;
+use crate::controller::files_controller::is_reserved_entry;
+use crate::{data_dir, files_dir};
+
+/// files stored in the asset backend are replaced by a small pointer file, so the
+/// directory tree (listing, move, rename, trash...) keeps working on the plain file system.
+const POINTER_MAGIC: &[u8] = b"play-asset-pointer v1\n";
+const MAX_POINTER_LEN: u64 = 512;
+/// unreferenced assets younger than this are kept, an upload may not have written its pointer yet.
+const GC_GRACE_MILLIS: i64 = 10 * 60 * 1000;
+
+lazy_static::lazy_static! {
+    /// held shared while a file is stored and exclusively by gc, so gc never sees an asset
+    /// (possibly a deduplicated, old one) whose pointer is not written yet.
+    static ref STORE_LOCK: tokio::sync::RwLock<()> = tokio::sync::RwLock::new(());
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AssetPointer {
+    pub asset_id: String,
+    /// sha256 of the content
+    pub checksum: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    /// files stored as new assets
+    pub imported: usize,
+    /// files whose content already existed in the store
+    pub deduplicated: usize,
+    pub failed: usize,
+    pub bytes: u64,
+}
+
+/// content of an asset, read chunk by chunk on demand.
+#[derive(Clone)]
+pub struct AssetBlob {
+    metadata: Arc<AssetMetadata>,
+    chunk_db_paths: Arc<Vec<PathBuf>>,
+}
+
+impl AssetBlob {
+    pub fn len(&self) -> u64 {
+        self.metadata.size as u64
+    }
+
+    /// stream `len` bytes starting at `start`, verifying every chunk it touches.
+    pub fn range_stream(&self, start: u64, len: u64) -> BoxStream<'static, io::Result<Bytes>> {
+        let blob = self.clone();
+        let chunk_size = self.metadata.chunk_size as u64;
+        let end = start + len;
+        stream::unfold(start, move |pos| {
+            let blob = blob.clone();
+            async move {
+                if pos >= end {
+                    return None;
+                }
+                let chunk_index = pos / chunk_size;
+                let chunk_start = chunk_index * chunk_size;
+                let data = match play_db::read_asset_chunk(
+                    &blob.chunk_db_paths,
+                    &blob.metadata,
+                    chunk_index as i64,
+                )
+                .await
+                {
+                    Ok(data) => Bytes::from(data),
+                    Err(e) => return Some((Err(io::Error::other(e)), end)),
+                };
+                let from = (pos - chunk_start) as usize;
+                let to = ((end - chunk_start) as usize).min(data.len());
+                if from >= to {
+                    let e = io::Error::new(io::ErrorKind::UnexpectedEof, "asset chunk too short");
+                    return Some((Err(e), end));
+                }
+                Some((Ok(data.slice(from..to)), chunk_start + to as u64))
+            }
+        })
+        .boxed()
+    }
+}
+
+/// where the asset databases live, backups copy it next to the files dir.
+pub(crate) fn store_dir() -> PathBuf {
+    data_dir!().join("assets")
+}
+
+fn meta_db_path() -> PathBuf {
+    store_dir().join("meta.db")
+}
+
+fn chunk_db_path(index: usize) -> PathBuf {
+    store_dir().join(format!("chunks_{:02}.db", index))
+}
+
+/// shard dbs are addressed by index, so never hand out fewer shards than already exist.
+fn chunk_db_paths(min_shards: usize) -> Vec<PathBuf> {
+    let mut count = min_shards.max(1);
+    while chunk_db_path(count).exists() {
+        count += 1;
+    }
+    (0..count).map(chunk_db_path).collect()
+}
+
+/// the pointer stored at `path`, if it is one.
+pub fn read_pointer(path: &Path) -> Option<AssetPointer> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_file()
+        || metadata.len() > MAX_POINTER_LEN
+        || metadata.len() < POINTER_MAGIC.len() as u64
+    {
+        return None;
+    }
+    let content = std::fs::read(path).ok()?;
+    serde_json::from_slice(content.strip_prefix(POINTER_MAGIC)?).ok()
+}
+
+pub async fn open_blob(pointer: &AssetPointer) -> anyhow::Result<AssetBlob> {
+    let metadata = play_db::get_asset(meta_db_path(), &pointer.asset_id)
+        .await?
+        .with_context(|| format!("asset not found : {}", pointer.asset_id))?;
+    if metadata.size as u64 != pointer.size
+        || metadata.checksum.as_deref() != Some(pointer.checksum.as_str())
+    {
+        bail!("asset {} does not match its pointer", pointer.asset_id);
+    }
+    Ok(AssetBlob {
+        metadata: Arc::new(metadata),
+        chunk_db_paths: Arc::new(chunk_db_paths(0)),
+    })
+}
+
+/// read the whole content of an asset into memory.
+pub async fn read_all(pointer: &AssetPointer) -> anyhow::Result<Vec<u8>> {
+    let bytes =
+        play_db::read_asset_bytes(meta_db_path(), chunk_db_paths(0), &pointer.asset_id).await?;
+    Ok(bytes)
+}
+
+/// move the content of a plain file into the asset store and leave a pointer in its place.
+/// returns `None` for pointers and empty files, which are left untouched.
+pub async fn store_file(
+    config: &FileStorageConfig,
+    path: &Path,
+) -> anyhow::Result<Option<(AssetPointer, bool)>> {
+    let metadata = tokio::fs::metadata(path).await?;
+    if metadata.len() == 0 || read_pointer(path).is_some() {
+        return Ok(None);
+    }
+    let chunk_size = play_db::parse_chunk_size(&config.chunk_size)?;
+    tokio::fs::create_dir_all(store_dir()).await?;
+    let _store = STORE_LOCK.read().await;
+
+    let (asset, reused) = play_db::insert_asset_dedup_from_path(
+        meta_db_path(),
+        chunk_db_paths(config.shards),
+        path.file_name().map(|n| n.to_string_lossy().to_string()),
+        None,
+        chunk_size,
+        path,
+    )
+    .await?;
+    if !asset.valid {
+        bail!("asset {} was not stored completely", asset.id);
+    }
+    let pointer = AssetPointer {
+        asset_id: asset.id,
+        checksum: asset.checksum.unwrap_or_default(),
+        size: asset.size as u64,
+    };
+
+    let mut content = POINTER_MAGIC.to_vec();
+    serde_json::to_writer(&mut content, &pointer)?;
+    let modified = metadata.modified().ok();
+    let dir = path
+        .parent()
+        .context("file has no parent dir")?
+        .to_path_buf();
+    let target = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut tmp = tempfile::NamedTempFile::new_in(&dir)?;
+        tmp.write_all(&content)?;
+        // keep the original modify time, listings sort by it
+        if let Some(modified) = modified {
+            tmp.as_file().set_modified(modified)?;
+        }
+        tmp.persist(&target)?;
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+    Ok(Some((pointer, reused)))
+}
+
+/// import every plain file under `dir` into the asset store.
+pub async fn import_dir(config: &FileStorageConfig, dir: &Path) -> anyhow::Result<ImportReport> {
+    let files_root = files_dir!();
+    let root = dir.to_path_buf();
+    let files = tokio::task::spawn_blocking(move || {
+        WalkDir::new(&root)
+            .into_iter()
+            .filter_entry(|e| !is_reserved_entry(&files_root, e.path()))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect::<Vec<PathBuf>>()
+    })
+    .await?;
+
+    let mut report = ImportReport::default();
+    for path in files {
+        match store_file(config, &path).await {
+            Ok(Some((pointer, reused))) => {
+                if reused {
+                    report.deduplicated += 1;
+                } else {
+                    report.imported += 1;
+                }
+                report.bytes += pointer.size;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("import {:?} failed : {:?}", path, e);
+                report.failed += 1;
+            }
+        }
+    }
+    info!("import {:?} into asset store : {:?}", dir, report);
+    Ok(report)
+}
+
+/// delete assets no pointer under files dir (trash included) refers to any more.
+pub async fn gc() -> anyhow::Result<usize> {
+    let meta_db = meta_db_path();
+    if !meta_db.exists() {
+        return Ok(0);
+    }
+
+    let _gc = STORE_LOCK.write().await;
+    let root = files_dir!();
+    let (referenced, assets) = tokio::task::spawn_blocking(move || {
+        let referenced = WalkDir::new(&root)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| read_pointer(e.path()))
+            .map(|p| p.asset_id)
+            .collect::<std::collections::HashSet<String>>();
+        let conn = play_db::Connection::open(&meta_db)?;
+        play_db::init_main_db(&conn)?;
+        let assets = play_db::assets_list(&conn)?;
+        Ok::<_, anyhow::Error>((referenced, assets))
+    })
+    .await??;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    let mut deleted = 0;
+    for asset in assets {
+        if referenced.contains(&asset.id) || now - asset.created_at < GC_GRACE_MILLIS {
+            continue;
+        }
+        play_db::delete_asset(meta_db_path(), chunk_db_paths(0), &asset.id).await?;
+        deleted += 1;
+    }
+    if deleted > 0 {
+        info!("asset store gc : {} unreferenced assets deleted", deleted);
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_file_and_range_stream() -> anyhow::Result<()> {
+        std::env::set_var("DATA_DIR", std::env::temp_dir());
+        let dir = files_dir!().join(format!(
+            "asset_store_test_{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join("a.txt");
+        let data = b"0123456789".repeat(10);
+        tokio::fs::write(&path, &data).await?;
+
+        let config = FileStorageConfig {
+            chunk_size: "16B".to_string(),
+            ..Default::default()
+        };
+        let (pointer, _) = store_file(&config, &path).await?.unwrap();
+        assert_eq!(pointer.size, 100);
+        assert_eq!(read_pointer(&path), Some(pointer.clone()));
+        // already a pointer
+        assert!(store_file(&config, &path).await?.is_none());
+
+        let blob = open_blob(&pointer).await?;
+        let bytes: Vec<Bytes> = blob.range_stream(10, 30).try_collect().await?;
+        assert_eq!(bytes.concat(), &data[10..40]);
+        let bytes: Vec<Bytes> = blob.range_stream(0, blob.len()).try_collect().await?;
+        assert_eq!(bytes.concat(), data);
+
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,374 @@
+use std::io;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::body::{Body, Bytes};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::service::asset_store_service;
+use crate::service::asset_store_service::AssetBlob;
+
+/// more ranges than this in one request are ignored and the full content is served.
+const MAX_RANGES: usize = 16;
+
+/// where the served bytes come from.
+pub enum FileSource {
+    Path(PathBuf),
+    Bytes(Bytes),
+    Asset(AssetBlob),
+}
+
+impl FileSource {
+    fn slice_stream(&self, start: u64, len: u64) -> BoxStream<'static, io::Result<Bytes>> {
+        match self {
+            FileSource::Path(path) => {
+                let path = path.clone();
+                stream::once(async move {
+                    let mut file = File::open(&path).await?;
+                    file.seek(SeekFrom::Start(start)).await?;
+                    Ok::<_, io::Error>(ReaderStream::new(file.take(len)))
+                })
+                .try_flatten()
+                .boxed()
+            }
+            FileSource::Bytes(bytes) => {
+                let slice = bytes.slice(start as usize..(start + len) as usize);
+                stream::once(async move { Ok(slice) }).boxed()
+            }
+            FileSource::Asset(blob) => blob.range_stream(start, len),
+        }
+    }
+}
+
+/// validators and size of the served content.
+pub struct FileMeta {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub etag: String,
+}
+
+impl FileMeta {
+    pub fn new(len: u64, modified: Option<SystemTime>) -> Self {
+        let mtime = modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        Self {
+            len,
+            modified,
+            etag: format!("\"{:x}-{:x}\"", len, mtime),
+        }
+    }
+
+    pub async fn from_path(path: &Path) -> io::Result<Self> {
+        let metadata = tokio::fs::metadata(path).await?;
+        Ok(Self::new(metadata.len(), metadata.modified().ok()))
+    }
+}
+
+/// serve a file from disk with `Range`, `ETag` and conditional GET support.
+/// asset pointers are resolved and served from the asset store.
+pub async fn serve_file(
+    path: &Path,
+    mime_type: &str,
+    method: &Method,
+    req_headers: &HeaderMap,
+) -> anyhow::Result<Response> {
+    let meta = FileMeta::from_path(path).await?;
+    if let Some(pointer) = asset_store_service::read_pointer(path) {
+        let blob = asset_store_service::open_blob(&pointer).await?;
+        return build_response(
+            FileSource::Asset(blob),
+            &FileMeta::new(pointer.size, meta.modified),
+            mime_type,
+            method,
+            req_headers,
+        );
+    }
+    build_response(
+        FileSource::Path(path.to_path_buf()),
+        &meta,
+        mime_type,
+        method,
+        req_headers,
+    )
+}
+
+/// build a (possibly partial or not-modified) response for `source`.
+pub fn build_response(
+    source: FileSource,
+    meta: &FileMeta,
+    mime_type: &str,
+    method: &Method,
+    req_headers: &HeaderMap,
+) -> anyhow::Result<Response> {
+    let builder = Response::builder()
+        .header(header::ETAG, &meta.etag)
+        .header(header::ACCEPT_RANGES, "bytes");
+    let builder = match meta.modified {
+        Some(modified) => builder.header(header::LAST_MODIFIED, http_date(modified)),
+        None => builder,
+    };
+
+    if is_not_modified(meta, method, req_headers) {
+        return Ok(builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())?);
+    }
+
+    let range_header = req_headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| if_range_matches(meta, req_headers));
+
+    let ranges = match range_header.map(|r| parse_range(r, meta.len)) {
+        None | Some(RangeSpec::Ignored) => None,
+        Some(RangeSpec::Unsatisfiable) => {
+            return Ok(builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", meta.len))
+                .body(Body::empty())?);
+        }
+        Some(RangeSpec::Ranges(ranges)) => Some(ranges),
+    };
+
+    match ranges {
+        None => Ok(builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, mime_type)
+            .header(header::CONTENT_LENGTH, meta.len)
+            .body(Body::from_stream(source.slice_stream(0, meta.len)))?),
+        Some(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            Ok(builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, mime_type)
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, meta.len),
+                )
+                .body(Body::from_stream(
+                    source.slice_stream(start, end - start + 1),
+                ))?)
+        }
+        Some(ranges) => {
+            let boundary = Uuid::new_v4().simple().to_string();
+            let mut parts = vec![];
+            let mut content_length = 0;
+            for (start, end) in ranges {
+                let part_header = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, mime_type, start, end, meta.len
+                );
+                content_length += part_header.len() as u64 + end - start + 1;
+                parts.push(stream::once(async move { Ok(Bytes::from(part_header)) }).boxed());
+                parts.push(source.slice_stream(start, end - start + 1));
+            }
+            let closing = format!("\r\n--{}--\r\n", boundary);
+            content_length += closing.len() as u64;
+            parts.push(stream::once(async move { Ok(Bytes::from(closing)) }).boxed());
+
+            Ok(builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={}", boundary),
+                )
+                .header(header::CONTENT_LENGTH, content_length)
+                .body(Body::from_stream(stream::iter(parts).flatten()))?)
+        }
+    }
+}
+
+/// `Content-Disposition` value that survives non-ascii file names.
+pub fn content_disposition(disposition: &str, filename: &str) -> HeaderValue {
+    let ascii_name: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && c != '"' && c != '\\' && !c.is_ascii_control() {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let value = format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        ascii_name,
+        urlencoding::encode(filename)
+    );
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+pub fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|d| SystemTime::from(d.with_timezone(&Utc)))
+}
+
+/// compare with second precision, as http dates do not carry sub-seconds.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn etag_matches(list: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    list.split(',')
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+fn is_not_modified(meta: &FileMeta, method: &Method, req_headers: &HeaderMap) -> bool {
+    if method != Method::GET && method != Method::HEAD {
+        return false;
+    }
+    if let Some(if_none_match) = req_headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    {
+        // If-None-Match takes precedence over If-Modified-Since
+        return etag_matches(if_none_match, &meta.etag);
+    }
+    match (
+        meta.modified,
+        req_headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_http_date),
+    ) {
+        (Some(modified), Some(since)) => unix_secs(modified) <= unix_secs(since),
+        _ => false,
+    }
+}
+
+/// a `Range` only applies when `If-Range` is absent or still matches the current content.
+fn if_range_matches(meta: &FileMeta, req_headers: &HeaderMap) -> bool {
+    let Some(if_range) = req_headers
+        .get(header::IF_RANGE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return true;
+    };
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        // weak validators never match for If-Range
+        return !if_range.starts_with("W/") && if_range == meta.etag;
+    }
+    match (meta.modified, parse_http_date(if_range)) {
+        (Some(modified), Some(date)) => unix_secs(modified) == unix_secs(date),
+        _ => false,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeSpec {
+    /// malformed or unsupported, serve the full content
+    Ignored,
+    Unsatisfiable,
+    /// inclusive (start, end) byte positions
+    Ranges(Vec<(u64, u64)>),
+}
+
+fn parse_range(value: &str, len: u64) -> RangeSpec {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeSpec::Ignored;
+    };
+
+    let mut ranges = vec![];
+    for part in spec.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let Some((start, end)) = part.split_once('-') else {
+            return RangeSpec::Ignored;
+        };
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return RangeSpec::Ignored,
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => None,
+                Ok(n) if len > 0 => Some((len.saturating_sub(n), len - 1)),
+                Ok(_) => None,
+                Err(_) => return RangeSpec::Ignored,
+            },
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return RangeSpec::Ignored;
+                };
+                let end = match end {
+                    "" => len.saturating_sub(1),
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+                        _ => return RangeSpec::Ignored,
+                    },
+                };
+                (start < len).then_some((start, end))
+            }
+        };
+        if let Some(range) = range {
+            ranges.push(range);
+        }
+    }
+
+    if ranges.len() > MAX_RANGES {
+        return RangeSpec::Ignored;
+    }
+    if ranges.is_empty() {
+        return RangeSpec::Unsatisfiable;
+    }
+    RangeSpec::Ranges(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            parse_range("bytes=0-499", 1000),
+            RangeSpec::Ranges(vec![(0, 499)])
+        );
+        assert_eq!(
+            parse_range("bytes=900-", 1000),
+            RangeSpec::Ranges(vec![(900, 999)])
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            RangeSpec::Ranges(vec![(900, 999)])
+        );
+        assert_eq!(
+            parse_range("bytes=0-0, 10-19", 1000),
+            RangeSpec::Ranges(vec![(0, 0), (10, 19)])
+        );
+        assert_eq!(
+            parse_range("bytes=0-5000", 1000),
+            RangeSpec::Ranges(vec![(0, 999)])
+        );
+        assert_eq!(parse_range("bytes=1000-", 1000), RangeSpec::Unsatisfiable);
+        assert_eq!(parse_range("items=0-1", 1000), RangeSpec::Ignored);
+        assert_eq!(parse_range("bytes=5-1", 1000), RangeSpec::Ignored);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("W/\"abc\", \"def\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"def\"", "\"abc\""));
+    }
+}
@@ -0,0 +1,853 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::body::{Body, Bytes};
+use axum::response::Response;
+use futures::{stream, StreamExt};
+use http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::config::{CacheConfig, HttpCacheConfig, HttpCacheRule};
+use crate::data_dir;
+use crate::service::proxy_route_service::{host_matches, path_matches};
+
+const X_PLAY_CACHE: &str = "x-play-cache";
+/// cloudflare style `Cache-Tag: a,b` response header, stored as tags of the entry
+const CACHE_TAG: &str = "cache-tag";
+/// statuses cacheable by default (RFC 9110 section 15.1)
+const CACHEABLE_STATUS: [u16; 8] = [200, 203, 204, 300, 301, 308, 404, 410];
+/// response headers never stored
+const HOP_BY_HOP: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+lazy_static::lazy_static! {
+    static ref MEMORY: Mutex<MemoryStore> = Mutex::new(MemoryStore::default());
+    /// keys with a background revalidation in flight
+    static ref REVALIDATING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+#[derive(Default)]
+struct MemoryStore {
+    /// variants keyed by host + path and query
+    entries: HashMap<String, Vec<Arc<CacheEntry>>>,
+    size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheMeta {
+    key: String,
+    /// identifies the variant, also the name of the body file
+    variant: String,
+    /// request header values selected by `Vary`
+    vary: Vec<(String, String)>,
+    status: u16,
+    headers: Vec<(String, String)>,
+    stored_at: u64,
+    ttl: u64,
+    swr: u64,
+    tags: Vec<String>,
+    size: usize,
+}
+
+struct CacheEntry {
+    meta: CacheMeta,
+    body: Bytes,
+    last_access: AtomicU64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Freshness {
+    Fresh,
+    Stale,
+    Expired,
+}
+
+impl CacheMeta {
+    fn age(&self) -> u64 {
+        now_secs().saturating_sub(self.stored_at)
+    }
+
+    fn freshness(&self) -> Freshness {
+        let age = self.age();
+        if age < self.ttl {
+            Freshness::Fresh
+        } else if age < self.ttl + self.swr {
+            Freshness::Stale
+        } else {
+            Freshness::Expired
+        }
+    }
+
+    fn vary_matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| header_value(headers, name) == *value)
+    }
+}
+
+/// what to store for a response, `None` when it is not cacheable.
+#[derive(Debug, PartialEq)]
+struct StorePolicy {
+    ttl: u64,
+    swr: u64,
+    vary: Vec<String>,
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct CachePurge {
+    #[serde(default)]
+    pub everything: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// full urls, e.g. `https://example.com/a?b=1`
+    #[serde(default)]
+    pub urls: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct PurgeResult {
+    /// local entries removed (memory and disk)
+    pub local: usize,
+    /// status of every cloudflare purge call
+    pub cloudflare: Vec<u16>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn cache_dir() -> PathBuf {
+    data_dir!().join("http_cache")
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> String {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn parse_cache_control(headers: &HeaderMap) -> HashMap<String, Option<String>> {
+    header_value(headers, header::CACHE_CONTROL.as_str())
+        .split(',')
+        .filter_map(|d| {
+            let d = d.trim();
+            if d.is_empty() {
+                return None;
+            }
+            Some(match d.split_once('=') {
+                Some((k, v)) => (
+                    k.trim().to_ascii_lowercase(),
+                    Some(v.trim().trim_matches('"').to_string()),
+                ),
+                None => (d.to_ascii_lowercase(), None),
+            })
+        })
+        .collect()
+}
+
+fn directive_secs(directives: &HashMap<String, Option<String>>, name: &str) -> Option<u64> {
+    directives.get(name)?.as_ref()?.parse().ok()
+}
+
+fn cache_key(host: &str, request: &Request<Body>) -> String {
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    format!("{}{}", host.to_ascii_lowercase(), path_and_query)
+}
+
+fn variant_id(key: &str, vary: &[(String, String)]) -> String {
+    play_utils_common_crypt::md5(&format!("{}|{:?}", key, vary))
+}
+
+/// the longest `path_prefix` rule for the host.
+fn find_rule<'a>(config: &'a HttpCacheConfig, host: &str, path: &str) -> Option<&'a HttpCacheRule> {
+    config
+        .rules
+        .iter()
+        .filter(|r| {
+            r.host.as_deref().map_or(true, |h| host_matches(h, host))
+                && path_matches(&r.path_prefix, path)
+        })
+        .rev()
+        .max_by_key(|r| r.path_prefix.trim_end_matches('/').len())
+}
+
+fn store_policy(
+    config: &HttpCacheConfig,
+    rule: Option<&HttpCacheRule>,
+    request_headers: &HeaderMap,
+    status: StatusCode,
+    headers: &HeaderMap,
+) -> Option<StorePolicy> {
+    if !CACHEABLE_STATUS.contains(&status.as_u16()) || headers.contains_key(header::SET_COOKIE) {
+        return None;
+    }
+    let directives = parse_cache_control(headers);
+    if ["no-store", "private", "no-cache"]
+        .iter()
+        .any(|d| directives.contains_key(*d))
+    {
+        return None;
+    }
+    // responses to authorized or cookie carrying requests are only shared when explicitly allowed,
+    // they may be personalized even when upstream sends no cache-control at all
+    if (request_headers.contains_key(header::AUTHORIZATION)
+        || request_headers.contains_key(header::COOKIE))
+        && !directives.contains_key("public")
+        && !directives.contains_key("s-maxage")
+    {
+        return None;
+    }
+    let vary: Vec<String> = header_value(headers, header::VARY.as_str())
+        .split(',')
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect();
+    if vary.iter().any(|v| v == "*") {
+        return None;
+    }
+
+    let ttl = rule
+        .and_then(|r| r.ttl_secs)
+        .or_else(|| directive_secs(&directives, "s-maxage"))
+        .or_else(|| directive_secs(&directives, "max-age"))
+        .unwrap_or(config.default_ttl_secs);
+    if ttl == 0 {
+        return None;
+    }
+    let swr = rule
+        .and_then(|r| r.stale_while_revalidate_secs)
+        .or_else(|| directive_secs(&directives, "stale-while-revalidate"))
+        .unwrap_or(config.stale_while_revalidate_secs);
+
+    let mut tags: Vec<String> = rule.map(|r| r.tags.clone()).unwrap_or_default();
+    tags.extend(
+        header_value(headers, CACHE_TAG)
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty()),
+    );
+    Some(StorePolicy {
+        ttl,
+        swr,
+        vary,
+        tags,
+    })
+}
+
+fn size_limit(value: &str, fallback: usize) -> usize {
+    play_db::parse_chunk_size(value)
+        .map(|v| v as usize)
+        .unwrap_or_else(|e| {
+            warn!("invalid http cache size {} : {:?}", value, e);
+            fallback
+        })
+}
+
+/// serve a GET/HEAD request from the cache, calling `fetch` on a miss or to revalidate.
+/// other requests go straight to `fetch`.
+pub async fn serve_with_cache<F, Fut>(
+    config: &HttpCacheConfig,
+    host: &str,
+    request: Request<Body>,
+    fetch: F,
+) -> Response
+where
+    F: Fn(Request<Body>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    let method = request.method().clone();
+    if !config.enabled
+        || !(method == Method::GET || method == Method::HEAD)
+        || request.headers().contains_key(header::UPGRADE)
+    {
+        return fetch(request).await;
+    }
+    let rule = find_rule(config, host, request.uri().path());
+    let request_directives = parse_cache_control(request.headers());
+    if rule.map_or(false, |r| r.bypass) || request_directives.contains_key("no-store") {
+        return with_cache_status(fetch(request).await, "BYPASS");
+    }
+
+    let key = cache_key(host, &request);
+    let reload = request_directives.contains_key("no-cache")
+        || directive_secs(&request_directives, "max-age") == Some(0)
+        || header_value(request.headers(), header::PRAGMA.as_str()).contains("no-cache");
+    if !reload {
+        if let Some(entry) = lookup(config, &key, request.headers()).await {
+            match entry.meta.freshness() {
+                Freshness::Fresh => {
+                    return entry_response(&entry, &method, request.headers(), "HIT");
+                }
+                Freshness::Stale => {
+                    spawn_revalidate(config.clone(), host.to_string(), key, &request, fetch);
+                    return entry_response(&entry, &method, request.headers(), "STALE");
+                }
+                Freshness::Expired => {}
+            }
+        }
+    }
+
+    let request_headers = request.headers().clone();
+    let response = fetch(request).await;
+    if method != Method::GET {
+        return with_cache_status(response, "MISS");
+    }
+    let Some(policy) = store_policy(
+        config,
+        rule,
+        &request_headers,
+        response.status(),
+        response.headers(),
+    ) else {
+        return with_cache_status(response, "MISS");
+    };
+    let max_entry_size = size_limit(&config.max_entry_size, 8 * 1024 * 1024);
+    let content_length = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if content_length.map_or(false, |len| len > max_entry_size) {
+        return with_cache_status(response, "MISS");
+    }
+
+    // 边转发边缓存响应体，传输完成后写入缓存
+    let (parts, body) = response.into_parts();
+    let meta = new_meta(key, &policy, &request_headers, parts.status, &parts.headers);
+    let buffer = Arc::new(Mutex::new(Some(Vec::new())));
+    let buffer_copy = buffer.clone();
+    let config = config.clone();
+    let data = body.into_data_stream().map(move |chunk| {
+        let mut buffer = buffer_copy.lock().unwrap();
+        match &chunk {
+            Ok(bytes) => {
+                if let Some(buf) = buffer.as_mut() {
+                    if buf.len() + bytes.len() > max_entry_size {
+                        *buffer = None;
+                    } else {
+                        buf.extend_from_slice(bytes);
+                    }
+                }
+            }
+            Err(_) => *buffer = None,
+        }
+        chunk
+    });
+    let tail = stream::once(async move {
+        let body = buffer.lock().unwrap().take();
+        if let Some(body) = body {
+            if let Err(e) = store(&config, meta, Bytes::from(body)).await {
+                error!("http cache store error : {:?}", e);
+            }
+        }
+    })
+    .filter_map(|_| async { None::<Result<Bytes, axum::Error>> });
+    let response = Response::from_parts(parts, Body::from_stream(data.chain(tail)));
+    with_cache_status(response, "MISS")
+}
+
+fn new_meta(
+    key: String,
+    policy: &StorePolicy,
+    request_headers: &HeaderMap,
+    status: StatusCode,
+    headers: &HeaderMap,
+) -> CacheMeta {
+    let vary: Vec<(String, String)> = policy
+        .vary
+        .iter()
+        .map(|name| (name.clone(), header_value(request_headers, name)))
+        .collect();
+    CacheMeta {
+        variant: variant_id(&key, &vary),
+        key,
+        vary,
+        status: status.as_u16(),
+        headers: headers
+            .iter()
+            .filter(|(name, _)| !HOP_BY_HOP.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        stored_at: now_secs(),
+        ttl: policy.ttl,
+        swr: policy.swr,
+        tags: policy.tags.clone(),
+        size: 0,
+    }
+}
+
+fn with_cache_status(mut response: Response, status: &'static str) -> Response {
+    response
+        .headers_mut()
+        .insert(X_PLAY_CACHE, HeaderValue::from_static(status));
+    response
+}
+
+fn entry_response(
+    entry: &CacheEntry,
+    method: &Method,
+    request_headers: &HeaderMap,
+    status: &'static str,
+) -> Response {
+    let meta = &entry.meta;
+    let mut builder = Response::builder().status(meta.status);
+    let mut etag = None;
+    for (name, value) in &meta.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            if name == header::ETAG {
+                etag = Some(value.clone());
+            }
+            builder = builder.header(name, value);
+        }
+    }
+    builder = builder
+        .header(header::AGE, meta.age())
+        .header(X_PLAY_CACHE, status);
+
+    let not_modified =
+        etag.is_some() && request_headers.get(header::IF_NONE_MATCH) == etag.as_ref();
+    let response = if not_modified {
+        builder.status(StatusCode::NOT_MODIFIED).body(Body::empty())
+    } else if method == Method::HEAD {
+        builder
+            .header(header::CONTENT_LENGTH, entry.body.len())
+            .body(Body::empty())
+    } else {
+        builder
+            .header(header::CONTENT_LENGTH, entry.body.len())
+            .body(Body::from(entry.body.clone()))
+    };
+    response.unwrap_or_else(|e| {
+        Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(format!("Invalid cache entry: {}", e).into())
+            .unwrap()
+    })
+}
+
+fn spawn_revalidate<F, Fut>(
+    config: HttpCacheConfig,
+    host: String,
+    key: String,
+    request: &Request<Body>,
+    fetch: F,
+) where
+    F: Fn(Request<Body>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    if !REVALIDATING.lock().unwrap().insert(key.clone()) {
+        return;
+    }
+    let uri = request.uri().clone();
+    let path = uri.path().to_string();
+    let mut headers = request.headers().clone();
+    // always ask for the full response
+    headers.remove(header::IF_NONE_MATCH);
+    headers.remove(header::IF_MODIFIED_SINCE);
+    tokio::spawn(async move {
+        let result = async {
+            let mut request = Request::builder()
+                .method(Method::GET)
+                .uri(uri)
+                .body(Body::empty())?;
+            *request.headers_mut() = headers.clone();
+            let response = fetch(request).await;
+            let rule = find_rule(&config, &host, &path);
+            let Some(policy) = store_policy(
+                &config,
+                rule,
+                &headers,
+                response.status(),
+                response.headers(),
+            ) else {
+                return Ok::<_, anyhow::Error>(());
+            };
+            let (parts, body) = response.into_parts();
+            let max_entry_size = size_limit(&config.max_entry_size, 8 * 1024 * 1024);
+            let body = axum::body::to_bytes(body, max_entry_size).await?;
+            let meta = new_meta(key.clone(), &policy, &headers, parts.status, &parts.headers);
+            store(&config, meta, body).await?;
+            info!("http cache revalidated : {}", key);
+            Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("http cache revalidate {} error : {:?}", key, e);
+        }
+        REVALIDATING.lock().unwrap().remove(&key);
+    });
+}
+
+async fn lookup(
+    config: &HttpCacheConfig,
+    key: &str,
+    headers: &HeaderMap,
+) -> Option<Arc<CacheEntry>> {
+    let found = MEMORY
+        .lock()
+        .unwrap()
+        .entries
+        .get(key)
+        .and_then(|variants| {
+            variants
+                .iter()
+                .find(|e| e.meta.vary_matches(headers))
+                .cloned()
+        });
+    if let Some(entry) = found {
+        entry.last_access.store(now_secs(), Ordering::Relaxed);
+        return Some(entry);
+    }
+    if !config.disk {
+        return None;
+    }
+
+    // 内存中没有时从磁盘加载
+    let metas = read_disk_metas(key).await;
+    let meta = metas.into_iter().find(|m| m.vary_matches(headers))?;
+    if meta.freshness() == Freshness::Expired {
+        return None;
+    }
+    let body = tokio::fs::read(cache_dir().join(format!("{}.body", meta.variant)))
+        .await
+        .ok()?;
+    if body.len() != meta.size {
+        return None;
+    }
+    let entry = Arc::new(CacheEntry {
+        meta,
+        body: Bytes::from(body),
+        last_access: AtomicU64::new(now_secs()),
+    });
+    memory_insert(
+        entry.clone(),
+        size_limit(&config.memory_size, 64 * 1024 * 1024),
+    );
+    Some(entry)
+}
+
+fn memory_insert(entry: Arc<CacheEntry>, limit: usize) {
+    let mut store = MEMORY.lock().unwrap();
+    let variants = store.entries.entry(entry.meta.key.clone()).or_default();
+    let mut removed = 0;
+    variants.retain(|e| {
+        let same = e.meta.variant == entry.meta.variant;
+        if same {
+            removed += e.meta.size;
+        }
+        !same
+    });
+    variants.push(entry.clone());
+    store.size = store.size - removed + entry.meta.size;
+
+    // 超出内存限制时淘汰最久未访问的
+    while store.size > limit {
+        let oldest = store
+            .entries
+            .values()
+            .flatten()
+            .min_by_key(|e| e.last_access.load(Ordering::Relaxed))
+            .map(|e| (e.meta.key.clone(), e.meta.variant.clone()));
+        let Some((key, variant)) = oldest else {
+            break;
+        };
+        remove_from_memory(&mut store, |m| m.key == key && m.variant == variant);
+    }
+}
+
+/// remove matching entries from memory, returns how many were removed.
+fn remove_from_memory(store: &mut MemoryStore, matches: impl Fn(&CacheMeta) -> bool) -> usize {
+    let mut removed = 0;
+    let mut freed = 0;
+    store.entries.retain(|_, variants| {
+        variants.retain(|e| {
+            let hit = matches(&e.meta);
+            if hit {
+                removed += 1;
+                freed += e.meta.size;
+            }
+            !hit
+        });
+        !variants.is_empty()
+    });
+    store.size -= freed;
+    removed
+}
+
+async fn store(config: &HttpCacheConfig, mut meta: CacheMeta, body: Bytes) -> anyhow::Result<()> {
+    meta.size = body.len();
+    if config.disk {
+        let dir = cache_dir();
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(dir.join(format!("{}.body", meta.variant)), &body).await?;
+        let mut metas = read_disk_metas(&meta.key).await;
+        metas.retain(|m| m.variant != meta.variant);
+        metas.push(meta.clone());
+        write_disk_metas(&meta.key, &metas).await?;
+    }
+    let entry = Arc::new(CacheEntry {
+        meta,
+        body,
+        last_access: AtomicU64::new(now_secs()),
+    });
+    memory_insert(entry, size_limit(&config.memory_size, 64 * 1024 * 1024));
+    Ok(())
+}
+
+fn meta_path(key: &str) -> PathBuf {
+    cache_dir().join(format!("{}.json", play_utils_common_crypt::md5(key)))
+}
+
+async fn read_disk_metas(key: &str) -> Vec<CacheMeta> {
+    match tokio::fs::read(meta_path(key)).await {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_default(),
+        Err(_) => vec![],
+    }
+}
+
+async fn write_disk_metas(key: &str, metas: &[CacheMeta]) -> anyhow::Result<()> {
+    let path = meta_path(key);
+    if metas.is_empty() {
+        if path.exists() {
+            tokio::fs::remove_file(&path).await?;
+        }
+        return Ok(());
+    }
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(metas)?).await?;
+    tokio::fs::rename(&tmp, &path).await?;
+    Ok(())
+}
+
+/// `https://example.com/a?b=1` -> `example.com/a?b=1`
+fn url_to_key(url: &str) -> Option<String> {
+    let uri: http::Uri = url.parse().ok()?;
+    let authority = uri.authority()?.as_str().to_ascii_lowercase();
+    let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    Some(format!("{}{}", authority, path_and_query))
+}
+
+/// remove matching entries from memory and disk.
+pub async fn purge_local(purge: &CachePurge) -> anyhow::Result<usize> {
+    let keys: HashSet<String> = purge.urls.iter().filter_map(|u| url_to_key(u)).collect();
+    let matches = |m: &CacheMeta| {
+        purge.everything || keys.contains(&m.key) || m.tags.iter().any(|t| purge.tags.contains(t))
+    };
+    let mut removed = remove_from_memory(&mut MEMORY.lock().unwrap(), &matches);
+
+    let dir = cache_dir();
+    if !dir.exists() {
+        return Ok(removed);
+    }
+    let mut disk_removed = 0;
+    let mut read_dir = tokio::fs::read_dir(&dir).await?;
+    while let Some(file) = read_dir.next_entry().await? {
+        let path = file.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let metas: Vec<CacheMeta> = match tokio::fs::read(&path).await {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_default(),
+            Err(_) => continue,
+        };
+        let Some(key) = metas.first().map(|m| m.key.clone()) else {
+            continue;
+        };
+        let (purged, kept): (Vec<CacheMeta>, Vec<CacheMeta>) =
+            metas.into_iter().partition(|m| matches(m));
+        if purged.is_empty() {
+            continue;
+        }
+        for meta in &purged {
+            let _ = tokio::fs::remove_file(dir.join(format!("{}.body", meta.variant))).await;
+        }
+        disk_removed += purged.len();
+        write_disk_metas(&key, &kept).await?;
+    }
+    // entries on disk are usually also in memory, count each once
+    removed = removed.max(disk_removed);
+    info!("http cache purged {} entries : {:?}", removed, purge);
+    Ok(removed)
+}
+
+/// purge the local cache and the cloudflare cache with the same scope.
+pub async fn purge(cache_config: &CacheConfig, purge: &CachePurge) -> anyhow::Result<PurgeResult> {
+    let mut result = PurgeResult {
+        local: purge_local(purge).await?,
+        cloudflare: vec![],
+    };
+    if cache_config.cf_purge_cache_url.is_empty() {
+        return Ok(result);
+    }
+
+    // cloudflare accepts one kind of purge per call
+    let mut bodies = vec![];
+    if purge.everything {
+        bodies.push(json!({"purge_everything": true}));
+    } else {
+        if !purge.tags.is_empty() {
+            bodies.push(json!({"tags": purge.tags}));
+        }
+        if !purge.urls.is_empty() {
+            bodies.push(json!({"files": purge.urls}));
+        }
+    }
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()?;
+    for body in bodies {
+        let resp = client
+            .post(&cache_config.cf_purge_cache_url)
+            .header("Authorization", &cache_config.cf_token)
+            .json(&body)
+            .send()
+            .await?;
+        info!("delete cf cache : {} , resp: {}", body, resp.status());
+        result.cloudflare.push(resp.status().as_u16());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> HttpCacheConfig {
+        HttpCacheConfig {
+            enabled: true,
+            disk: false,
+            rules: vec![HttpCacheRule {
+                host: None,
+                path_prefix: "/api".to_string(),
+                ttl_secs: Some(60),
+                stale_while_revalidate_secs: None,
+                tags: vec!["api".to_string()],
+                bypass: false,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn response_headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (k, v) in pairs {
+            headers.append(*k, HeaderValue::from_static(v));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_store_policy() {
+        let config = test_config();
+        let empty = HeaderMap::new();
+        let headers = response_headers(&[
+            (
+                "cache-control",
+                "public, max-age=30, stale-while-revalidate=10",
+            ),
+            ("vary", "Accept-Encoding"),
+            ("cache-tag", "a, b"),
+        ]);
+        let policy = store_policy(&config, None, &empty, StatusCode::OK, &headers).unwrap();
+        assert_eq!((policy.ttl, policy.swr), (30, 10));
+        assert_eq!(policy.vary, vec!["accept-encoding"]);
+        assert_eq!(policy.tags, vec!["a", "b"]);
+
+        // the rule ttl overrides max-age
+        let rule = find_rule(&config, "example.com", "/api/x");
+        let policy = store_policy(&config, rule, &empty, StatusCode::OK, &headers).unwrap();
+        assert_eq!(policy.ttl, 60);
+        assert_eq!(policy.tags, vec!["api", "a", "b"]);
+
+        let private = response_headers(&[("cache-control", "private, max-age=30")]);
+        assert!(store_policy(&config, rule, &empty, StatusCode::OK, &private).is_none());
+        // no freshness information and no default ttl
+        assert!(store_policy(&config, None, &empty, StatusCode::OK, &empty).is_none());
+        assert!(store_policy(&config, rule, &empty, StatusCode::BAD_GATEWAY, &empty).is_none());
+
+        // a logged in user's page is not shared, even under a rule ttl
+        let cookie = response_headers(&[("cookie", "session=abc")]);
+        assert!(store_policy(&config, rule, &cookie, StatusCode::OK, &empty).is_none());
+        let public = response_headers(&[("cache-control", "public, max-age=30")]);
+        assert!(store_policy(&config, rule, &cookie, StatusCode::OK, &public).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_serve_with_cache_and_purge() -> anyhow::Result<()> {
+        std::env::set_var("DATA_DIR", std::env::temp_dir());
+        let config = test_config();
+        let calls = Arc::new(AtomicU64::new(0));
+        let fetch = {
+            let calls = calls.clone();
+            move |_req: Request<Body>| {
+                let calls = calls.clone();
+                async move {
+                    let n = calls.fetch_add(1, Ordering::Relaxed);
+                    Response::builder()
+                        .header(header::VARY, "accept-language")
+                        .body(Body::from(format!("v{}", n)))
+                        .unwrap()
+                }
+            }
+        };
+        let request = |lang: &str| {
+            Request::builder()
+                .uri("/api/cache_test?x=1")
+                .header(header::ACCEPT_LANGUAGE, lang)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let read = |resp: Response| async move {
+            let status = resp.headers()[X_PLAY_CACHE].to_str().unwrap().to_string();
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        };
+
+        let host = "cache-test.example.com";
+        let first = read(serve_with_cache(&config, host, request("en"), fetch.clone()).await).await;
+        assert_eq!(first, ("MISS".to_string(), "v0".to_string()));
+        let second =
+            read(serve_with_cache(&config, host, request("en"), fetch.clone()).await).await;
+        assert_eq!(second, ("HIT".to_string(), "v0".to_string()));
+        // another variant
+        let other = read(serve_with_cache(&config, host, request("fr"), fetch.clone()).await).await;
+        assert_eq!(other, ("MISS".to_string(), "v1".to_string()));
+
+        let removed = purge_local(&CachePurge {
+            tags: vec!["api".to_string()],
+            ..Default::default()
+        })
+        .await?;
+        assert_eq!(removed, 2);
+        let after = read(serve_with_cache(&config, host, request("en"), fetch).await).await;
+        assert_eq!(after, ("MISS".to_string(), "v2".to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,497 @@
+use std::io::{BufRead, BufWriter, Cursor, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::config::ImageConfig;
+use crate::files_dir;
+use crate::service::asset_store_service;
+
+/// derived image variants are cached here, in a folder mirroring the source path, so they can be
+/// dropped with their source. file names start with the source version (mtime and size).
+pub(crate) const THUMB_FOLDER: &'static str = "__thumbs__";
+/// a sweep removes variants until the cache is back under this share of `cache_max_size`.
+const SWEEP_TARGET_PERCENT: u64 = 80;
+
+/// approximate total size of the cache, `None` until it is first measured.
+static CACHE_SIZE: Mutex<Option<u64>> = Mutex::new(None);
+/// bounds the variants decoded and encoded at the same time, sized by `max_concurrent_transforms`.
+static TRANSFORM_PERMITS: OnceLock<Semaphore> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFit {
+    /// scale down to fit inside `w`x`h`, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// scale and center-crop to exactly `w`x`h`
+    Cover,
+    /// stretch to exactly `w`x`h`
+    Fill,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl OutputFormat {
+    fn from_source(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Jpeg => OutputFormat::Jpeg,
+            ImageFormat::WebP => OutputFormat::Webp,
+            _ => OutputFormat::Png,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+        }
+    }
+}
+
+/// query parameters of an image transform, e.g. `?w=200&h=200&fit=cover&format=webp`.
+/// every transform re-encodes the image, which drops EXIF and other metadata.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ImageTransform {
+    #[serde(default)]
+    pub w: Option<u32>,
+    #[serde(default)]
+    pub h: Option<u32>,
+    #[serde(default)]
+    pub fit: ImageFit,
+    /// "x,y,width,height" region cut from the source before resizing
+    #[serde(default)]
+    pub crop: Option<String>,
+    #[serde(default)]
+    pub format: Option<OutputFormat>,
+    /// jpeg quality 1-100, refused for webp which is always encoded lossless
+    #[serde(default)]
+    pub q: Option<u8>,
+    /// re-encode without metadata even if nothing else changes
+    #[serde(default)]
+    pub strip: bool,
+}
+
+impl ImageTransform {
+    pub fn is_requested(&self) -> bool {
+        self.w.is_some()
+            || self.h.is_some()
+            || self.crop.is_some()
+            || self.format.is_some()
+            || self.q.is_some()
+            || self.strip
+    }
+
+    fn parse_crop(&self) -> anyhow::Result<Option<(u32, u32, u32, u32)>> {
+        let Some(crop) = &self.crop else {
+            return Ok(None);
+        };
+        let parts = crop
+            .split(',')
+            .map(|p| p.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .with_context(|| format!("invalid crop : {}", crop))?;
+        ensure!(
+            parts.len() == 4 && parts[2] > 0 && parts[3] > 0,
+            "crop should be x,y,width,height : {}",
+            crop
+        );
+        Ok(Some((parts[0], parts[1], parts[2], parts[3])))
+    }
+}
+
+/// render (or reuse) the variant of `src` described by `transform`.
+/// returns the path of the cached variant and its mime type.
+pub async fn transform_image(
+    src: &Path,
+    rel_path: &str,
+    transform: &ImageTransform,
+    config: &ImageConfig,
+) -> anyhow::Result<(PathBuf, &'static str)> {
+    for size in [transform.w, transform.h].into_iter().flatten() {
+        ensure!(
+            size > 0 && size <= config.max_dimension,
+            "requested size {} exceeds max dimension {}",
+            size,
+            config.max_dimension
+        );
+    }
+    let crop = transform.parse_crop()?;
+
+    let source_format = ImageFormat::from_path(src)
+        .ok()
+        .or_else(|| {
+            infer::get_from_path(src)
+                .ok()
+                .flatten()
+                .and_then(|kind| ImageFormat::from_mime_type(kind.mime_type()))
+        })
+        .ok_or_else(|| anyhow!("unsupported image : {}", rel_path))?;
+    let format = transform
+        .format
+        .unwrap_or_else(|| OutputFormat::from_source(source_format));
+    ensure!(
+        !(format == OutputFormat::Webp && transform.q.is_some()),
+        "q is not supported for webp, which is encoded lossless"
+    );
+    let quality = transform.q.unwrap_or(config.default_quality).clamp(1, 100);
+
+    let metadata = tokio::fs::metadata(src).await?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+    let version = &play_utils_common_crypt::md5(&format!("{}|{}", mtime, metadata.len()))[..8];
+    let cache_key = play_utils_common_crypt::md5(&format!(
+        "{:?}|{:?}|{:?}|{:?}|{:?}|{}",
+        transform.w, transform.h, transform.fit, crop, format, quality,
+    ));
+
+    let cache_dir = variants_dir(rel_path);
+    let target = cache_dir.join(format!("{}_{}.{}", version, cache_key, format.extension()));
+    if target.exists() {
+        touch(&target);
+        return Ok((target, format.mime_type()));
+    }
+
+    let _permit = TRANSFORM_PERMITS
+        .get_or_init(|| Semaphore::new(config.max_concurrent_transforms.max(1)))
+        .acquire()
+        .await?;
+    // another request may have rendered it while this one waited
+    if target.exists() {
+        touch(&target);
+        return Ok((target, format.mime_type()));
+    }
+    tokio::fs::create_dir_all(&cache_dir).await?;
+    remove_stale_variants(&cache_dir, version).await;
+
+    // sources kept in the asset store are decoded from memory
+    let asset_bytes = match asset_store_service::read_pointer(src) {
+        Some(pointer) => Some(asset_store_service::read_all(&pointer).await?),
+        None => None,
+    };
+    let src = src.to_path_buf();
+    let transform = transform.clone();
+    let max_source_dimension = config.max_source_dimension;
+    let target_copy = target.clone();
+    let size = tokio::task::spawn_blocking(move || {
+        let img = match asset_bytes {
+            Some(bytes) => decode_image(
+                ImageReader::new(Cursor::new(bytes)).with_guessed_format()?,
+                max_source_dimension,
+            )?,
+            None => decode_image(
+                ImageReader::open(&src)?.with_guessed_format()?,
+                max_source_dimension,
+            )?,
+        };
+        let img = apply_transform(img, &transform, crop)?;
+
+        // write to a temp file first, so concurrent requests never see half-written variants
+        let tmp = tempfile::NamedTempFile::new_in(&cache_dir)?;
+        let mut writer = BufWriter::new(tmp.as_file());
+        encode_image(&img, format, quality, &mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        let file = tmp.persist(&target_copy)?;
+        Ok::<_, anyhow::Error>(file.metadata()?.len())
+    })
+    .await??;
+
+    info!("image variant created : {} -> {:?}", rel_path, target);
+    add_to_cache(size, config);
+    Ok((target, format.mime_type()))
+}
+
+fn thumbs_dir() -> PathBuf {
+    files_dir!().join(THUMB_FOLDER)
+}
+
+fn variants_dir(rel_path: &str) -> PathBuf {
+    thumbs_dir().join(rel_path.trim_matches('/'))
+}
+
+/// drop the cached variants of a file, or of everything under a directory,
+/// once it is overwritten, moved or deleted.
+pub(crate) async fn remove_variants(rel_path: &str) {
+    let dir = variants_dir(rel_path);
+    if rel_path.trim_matches('/').is_empty() || !dir.is_dir() {
+        return;
+    }
+    match tokio::fs::remove_dir_all(&dir).await {
+        Ok(_) => *CACHE_SIZE.lock().unwrap() = None,
+        Err(e) => warn!("remove image variants of {} failed : {:?}", rel_path, e),
+    }
+}
+
+/// variants made for an older version of the source are never served again.
+async fn remove_stale_variants(dir: &Path, version: &str) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let is_file = entry.file_type().await.is_ok_and(|t| t.is_file());
+        if is_file && !entry.file_name().to_string_lossy().starts_with(version) {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+}
+
+/// mark a variant as recently used, sweeps remove the least recently modified first.
+fn touch(path: &Path) {
+    if let Ok(file) = std::fs::File::options().append(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// count a new variant and sweep the cache in the background when it grows too big.
+fn add_to_cache(size: u64, config: &ImageConfig) {
+    let max_size = match play_db::parse_chunk_size(&config.cache_max_size) {
+        Ok(max_size) => max_size as u64,
+        Err(e) => {
+            warn!(
+                "invalid image cache_max_size {} : {}",
+                config.cache_max_size, e
+            );
+            return;
+        }
+    };
+    let mut cache_size = CACHE_SIZE.lock().unwrap();
+    if let Some(total) = cache_size.as_mut() {
+        *total += size;
+        if *total <= max_size {
+            return;
+        }
+    }
+    // measured (again) by the sweep
+    *cache_size = Some(0);
+    drop(cache_size);
+    tokio::task::spawn_blocking(move || {
+        let total = sweep_cache(
+            &thumbs_dir(),
+            max_size * SWEEP_TARGET_PERCENT / 100,
+            max_size,
+        );
+        *CACHE_SIZE.lock().unwrap() = Some(total);
+    });
+}
+
+/// when the variants under `dir` exceed `max_size`, remove the least recently used ones until
+/// they fit in `target_size`. returns the size left.
+fn sweep_cache(dir: &Path, target_size: u64, max_size: u64) -> u64 {
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let metadata = e.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), e.into_path()))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    if total <= max_size {
+        return total;
+    }
+    files.sort_by_key(|(modified, _, _)| *modified);
+    let mut removed = 0;
+    for (_, size, path) in files {
+        if total <= target_size {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total -= size;
+            removed += 1;
+        }
+    }
+    info!(
+        "image cache sweep : {} variants removed, {} bytes left",
+        removed, total
+    );
+    total
+}
+
+fn decode_image<R: BufRead + Seek>(
+    mut reader: ImageReader<R>,
+    max_source_dimension: u32,
+) -> anyhow::Result<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(max_source_dimension);
+    limits.max_image_height = Some(max_source_dimension);
+
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder()?;
+    // the EXIF orientation is lost on re-encoding, so bake it into the pixels
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+fn apply_transform(
+    mut img: DynamicImage,
+    transform: &ImageTransform,
+    crop: Option<(u32, u32, u32, u32)>,
+) -> anyhow::Result<DynamicImage> {
+    if let Some((x, y, width, height)) = crop {
+        if x >= img.width() || y >= img.height() {
+            bail!(
+                "crop origin ({}, {}) is outside of the image {}x{}",
+                x,
+                y,
+                img.width(),
+                img.height()
+            );
+        }
+        img = img.crop_imm(x, y, width, height);
+    }
+
+    let filter = FilterType::CatmullRom;
+    img = match (transform.fit, transform.w, transform.h) {
+        (_, None, None) => img,
+        (ImageFit::Cover, Some(w), Some(h)) => img.resize_to_fill(w, h, filter),
+        (ImageFit::Fill, w, h) => {
+            img.resize_exact(w.unwrap_or(img.width()), h.unwrap_or(img.height()), filter)
+        }
+        (_, w, h) => {
+            let (w, h) = (w.unwrap_or(u32::MAX), h.unwrap_or(u32::MAX));
+            // thumbnails never upscale
+            if w >= img.width() && h >= img.height() {
+                img
+            } else {
+                img.resize(w, h, filter)
+            }
+        }
+    };
+    Ok(img)
+}
+
+fn encode_image<W: std::io::Write + std::io::Seek>(
+    img: &DynamicImage,
+    format: OutputFormat,
+    quality: u8,
+    mut writer: W,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Jpeg => {
+            // jpeg has no alpha channel
+            DynamicImage::ImageRgb8(img.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut writer, quality))?;
+        }
+        OutputFormat::Png => img.write_to(&mut writer, ImageFormat::Png)?,
+        OutputFormat::Webp => {
+            DynamicImage::ImageRgba8(img.to_rgba8())
+                .write_with_encoder(WebPEncoder::new_lossless(&mut writer))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform_resize() -> anyhow::Result<()> {
+        let img = DynamicImage::new_rgb8(800, 600);
+
+        let transform = ImageTransform {
+            w: Some(200),
+            ..Default::default()
+        };
+        let out = apply_transform(img.clone(), &transform, None)?;
+        assert_eq!((out.width(), out.height()), (200, 150));
+
+        let transform = ImageTransform {
+            w: Some(100),
+            h: Some(100),
+            fit: ImageFit::Cover,
+            ..Default::default()
+        };
+        let out = apply_transform(img.clone(), &transform, None)?;
+        assert_eq!((out.width(), out.height()), (100, 100));
+
+        let transform = ImageTransform {
+            w: Some(2000),
+            ..Default::default()
+        };
+        let out = apply_transform(img, &transform, None)?;
+        assert_eq!((out.width(), out.height()), (800, 600));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sweep_cache() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let now = SystemTime::now();
+        for (i, name) in ["old.png", "mid.png", "new.png"].iter().enumerate() {
+            let path = dir.path().join("a").join(name);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(&path, [0u8; 100])?;
+            let file = std::fs::File::options().append(true).open(&path)?;
+            file.set_modified(now - std::time::Duration::from_secs(100 - i as u64))?;
+        }
+
+        assert_eq!(sweep_cache(dir.path(), 150, 300), 300);
+        assert_eq!(sweep_cache(dir.path(), 150, 250), 100);
+        assert!(!dir.path().join("a/old.png").exists());
+        assert!(!dir.path().join("a/mid.png").exists());
+        assert!(dir.path().join("a/new.png").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_crop() {
+        let transform = ImageTransform {
+            crop: Some("10,20,100,50".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(transform.parse_crop().unwrap(), Some((10, 20, 100, 50)));
+
+        let transform = ImageTransform {
+            crop: Some("10,20,0,50".to_string()),
+            ..Default::default()
+        };
+        assert!(transform.parse_crop().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_webp_refuses_quality() {
+        let transform = ImageTransform {
+            format: Some(OutputFormat::Webp),
+            q: Some(50),
+            ..Default::default()
+        };
+        let result = transform_image(
+            Path::new("a.png"),
+            "a.png",
+            &transform,
+            &ImageConfig::default(),
+        )
+        .await;
+        assert!(result.unwrap_err().to_string().contains("webp"));
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, ensure, Context};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::files_dir;
+use crate::mcp_hub;
+use crate::mcp_tool_host::{check_mcp_readable, is_private_category, MAX_READ_BYTES};
+use crate::service::asset_store_service;
+use crate::tables::general_data::GeneralData;
+use crate::tables::DBPool;
+
+pub const PAGES_CAT: &str = "pages";
+pub const PROMPTS_CAT: &str = "prompts";
+
+const PAGE_SCHEME: &str = "play://pages/";
+const FILE_SCHEME: &str = "play://files/";
+const DATA_SCHEME: &str = "play://data/";
+
+const MAX_LISTED_PAGES: i32 = 500;
+const MAX_LISTED_FILES: usize = 500;
+const MAX_READ_ROWS: i32 = 100;
+
+/// a resource addressed by one of the `play://` uris.
+#[derive(Debug, PartialEq)]
+pub enum ResourceUri {
+    Page(String),
+    File(String),
+    DataCategory(String),
+    DataRow(String, u32),
+}
+
+impl ResourceUri {
+    pub fn parse(uri: &str) -> Option<Self> {
+        if let Some(url) = uri.strip_prefix(PAGE_SCHEME) {
+            return (!url.is_empty()).then(|| ResourceUri::Page(format!("/{}", url)));
+        }
+        if let Some(path) = uri.strip_prefix(FILE_SCHEME) {
+            return (!path.is_empty()).then(|| ResourceUri::File(path.to_string()));
+        }
+        let rest = uri.strip_prefix(DATA_SCHEME)?;
+        match rest.split_once('/') {
+            Some((cat, id)) if !cat.is_empty() => {
+                Some(ResourceUri::DataRow(cat.to_string(), id.parse().ok()?))
+            }
+            None if !rest.is_empty() => Some(ResourceUri::DataCategory(rest.to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PageData {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    content: String,
+}
+
+/// a prompt stored in the `prompts` data category.
+#[derive(Deserialize)]
+struct PromptData {
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    arguments: Vec<PromptArgument>,
+    #[serde(default)]
+    messages: Vec<PromptMessage>,
+    /// shorthand for a single user message.
+    #[serde(default)]
+    template: Option<String>,
+}
+
+#[derive(Deserialize, serde::Serialize)]
+struct PromptArgument {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default)]
+    required: bool,
+}
+
+#[derive(Deserialize)]
+struct PromptMessage {
+    #[serde(default = "default_role")]
+    role: String,
+    content: String,
+}
+
+fn default_role() -> String {
+    "user".to_string()
+}
+
+fn mime_type(name: &str, fallback: &str) -> String {
+    mime_guess::from_path(name)
+        .first()
+        .map(|m| m.essence_str().to_string())
+        .unwrap_or_else(|| fallback.to_string())
+}
+
+pub async fn list_resources(db: &DBPool) -> anyhow::Result<Vec<Value>> {
+    let mut resources = vec![];
+
+    for row in GeneralData::query_by_cat("*", PAGES_CAT, MAX_LISTED_PAGES, db).await? {
+        if row.is_deleted {
+            continue;
+        }
+        let Ok(page) = serde_json::from_str::<PageData>(&row.data) else {
+            continue;
+        };
+        let url = page.url.trim_start_matches('/');
+        if url.is_empty() {
+            continue;
+        }
+        resources.push(json!({
+            "uri": format!("{}{}", PAGE_SCHEME, url),
+            "name": if page.title.is_empty() { url.to_string() } else { page.title },
+            "mimeType": mime_type(url, "text/html"),
+        }));
+    }
+
+    let root = files_dir!();
+    let files = tokio::task::spawn_blocking(move || list_files(&root)).await?;
+    for (path, size) in files {
+        resources.push(json!({
+            "uri": format!("{}{}", FILE_SCHEME, path),
+            "name": path,
+            "mimeType": mime_type(&path, "application/octet-stream"),
+            "size": size,
+        }));
+    }
+
+    for cat in GeneralData::query_distinct_categories(false, db).await? {
+        if is_private_category(&cat) {
+            continue;
+        }
+        resources.push(json!({
+            "uri": format!("{}{}", DATA_SCHEME, cat),
+            "name": format!("data: {}", cat),
+            "mimeType": "application/json",
+        }));
+    }
+
+    resources.extend(mcp_hub::list_resources());
+    Ok(resources)
+}
+
+pub fn resource_templates() -> Vec<Value> {
+    vec![
+        json!({
+            "uriTemplate": format!("{}{{url}}", PAGE_SCHEME),
+            "name": "page",
+            "description": "source of a page by its url",
+        }),
+        json!({
+            "uriTemplate": format!("{}{{path}}", FILE_SCHEME),
+            "name": "file",
+            "description": "a file under the files dir",
+        }),
+        json!({
+            "uriTemplate": format!("{}{{cat}}/{{id}}", DATA_SCHEME),
+            "name": "data row",
+            "description": "one row of a data category",
+            "mimeType": "application/json",
+        }),
+    ]
+    .into_iter()
+    .chain(mcp_hub::resource_templates())
+    .collect()
+}
+
+/// files under `root` with their sizes, skipping hidden and internal (`__*__`) folders.
+fn list_files(root: &Path) -> Vec<(String, u64)> {
+    let mut files = vec![];
+    let walker = walkdir::WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0 || {
+                let name = e.file_name().to_string_lossy();
+                !name.starts_with('.') && !name.starts_with("__")
+            }
+        });
+    for entry in walker.flatten() {
+        if files.len() >= MAX_LISTED_FILES {
+            break;
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        let size = asset_store_service::read_pointer(entry.path())
+            .map(|p| p.size)
+            .or_else(|| entry.metadata().ok().map(|m| m.len()))
+            .unwrap_or_default();
+        files.push((relative.to_string_lossy().replace('\\', "/"), size));
+    }
+    files
+}
+
+/// `path` joined onto `root`, refusing anything but plain path components.
+fn confined_path(root: &Path, path: &str) -> anyhow::Result<PathBuf> {
+    let relative = Path::new(path);
+    if path.is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        bail!("invalid file path : {}", path);
+    }
+    Ok(root.join(relative))
+}
+
+/// the contents of a resource, or `None` when it does not exist.
+pub async fn read_resource(uri: &str, db: &DBPool) -> anyhow::Result<Option<Value>> {
+    if uri.starts_with(mcp_hub::HUB_SCHEME) {
+        return mcp_hub::read_resource(uri).await;
+    }
+    let Some(resource) = ResourceUri::parse(uri) else {
+        return Ok(None);
+    };
+    let content = match resource {
+        ResourceUri::Page(url) => {
+            let rows = GeneralData::query_by_json_field("*", PAGES_CAT, "url", &url, 1, db).await?;
+            let Some(row) = rows.into_iter().find(|r| !r.is_deleted) else {
+                return Ok(None);
+            };
+            let page = serde_json::from_str::<PageData>(&row.data)?;
+            let text = String::from_utf8(hex::decode(&page.content)?)?;
+            json!({"uri": uri, "mimeType": mime_type(&url, "text/html"), "text": text})
+        }
+        ResourceUri::File(path) => {
+            let path = confined_path(&files_dir!(), &path)?;
+            if !path.is_file() {
+                return Ok(None);
+            }
+            let bytes = match asset_store_service::read_pointer(&path) {
+                Some(pointer) => {
+                    ensure!(pointer.size <= MAX_READ_BYTES, "file too large : {}", uri);
+                    asset_store_service::read_all(&pointer).await?
+                }
+                None => {
+                    let size = tokio::fs::metadata(&path).await?.len();
+                    ensure!(size <= MAX_READ_BYTES, "file too large : {}", uri);
+                    tokio::fs::read(&path).await?
+                }
+            };
+            let mime = mime_type(&path.to_string_lossy(), "application/octet-stream");
+            match String::from_utf8(bytes) {
+                Ok(text) => json!({"uri": uri, "mimeType": mime, "text": text}),
+                Err(e) => {
+                    json!({"uri": uri, "mimeType": mime, "blob": STANDARD.encode(e.into_bytes())})
+                }
+            }
+        }
+        ResourceUri::DataCategory(cat) => {
+            check_mcp_readable(&cat)?;
+            let rows = GeneralData::query_composite(
+                "*",
+                &cat,
+                &MAX_READ_ROWS.to_string(),
+                "1=1",
+                false,
+                "id desc",
+                db,
+            )
+            .await?;
+            if rows.is_empty() {
+                return Ok(None);
+            }
+            let rows = rows
+                .iter()
+                .map(|r| r.to_flat_map().map(Value::Object))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            json!({"uri": uri, "mimeType": "application/json", "text": Value::Array(rows).to_string()})
+        }
+        ResourceUri::DataRow(cat, id) => {
+            check_mcp_readable(&cat)?;
+            let rows = GeneralData::query_by_id(id, db).await?;
+            let Some(row) = rows.into_iter().find(|r| r.cat == cat && !r.is_deleted) else {
+                return Ok(None);
+            };
+            json!({"uri": uri, "mimeType": "application/json", "text": Value::Object(row.to_flat_map()?).to_string()})
+        }
+    };
+    Ok(Some(json!({ "contents": [content] })))
+}
+
+/// count and last update of a category, changes when rows are added, edited or removed.
+pub async fn category_marker(cat: &str, db: &DBPool) -> anyhow::Result<(i64, Option<String>)> {
+    let count = GeneralData::query_count(cat, db).await?;
+    let latest = GeneralData::query_latest_by_cat_with_limit(cat, 1, db).await?;
+    Ok((count, latest.first().map(|r| r.updated.to_string())))
+}
+
+pub async fn list_prompts(db: &DBPool) -> anyhow::Result<Vec<Value>> {
+    let rows =
+        GeneralData::query_composite("*", PROMPTS_CAT, "1000", "1=1", false, "id asc", db).await?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| serde_json::from_str::<PromptData>(&row.data).ok())
+        .map(|prompt| {
+            json!({
+                "name": prompt.name,
+                "description": prompt.description,
+                "arguments": prompt.arguments,
+            })
+        })
+        .chain(mcp_hub::list_prompts())
+        .collect())
+}
+
+/// the rendered prompt, or `None` when no prompt has that name.
+pub async fn get_prompt(
+    name: &str,
+    arguments: &HashMap<String, String>,
+    db: &DBPool,
+) -> anyhow::Result<Option<Value>> {
+    let rows = GeneralData::query_by_json_field("*", PROMPTS_CAT, "name", name, 1, db).await?;
+    let Some(row) = rows.into_iter().find(|r| !r.is_deleted) else {
+        return mcp_hub::get_prompt(name, arguments).await;
+    };
+    let prompt = serde_json::from_str::<PromptData>(&row.data)
+        .with_context(|| format!("invalid prompt : {}", name))?;
+    render_prompt(prompt, arguments).map(Some)
+}
+
+fn render_prompt(prompt: PromptData, arguments: &HashMap<String, String>) -> anyhow::Result<Value> {
+    for arg in &prompt.arguments {
+        if arg.required && !arguments.contains_key(&arg.name) {
+            bail!("missing required argument : {}", arg.name);
+        }
+    }
+    let mut messages = prompt.messages;
+    if let Some(template) = prompt.template {
+        messages.push(PromptMessage {
+            role: default_role(),
+            content: template,
+        });
+    }
+    let messages = messages
+        .into_iter()
+        .map(|message| {
+            let text = prompt.arguments.iter().fold(message.content, |text, arg| {
+                let value = arguments.get(&arg.name).map(String::as_str).unwrap_or("");
+                text.replace(&format!("{{{{{}}}}}", arg.name), value)
+            });
+            json!({"role": message.role, "content": {"type": "text", "text": text}})
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "description": prompt.description,
+        "messages": messages,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::init_test_pool;
+
+    #[test]
+    fn test_parse_uri() {
+        assert_eq!(
+            ResourceUri::parse("play://pages/a/b.html"),
+            Some(ResourceUri::Page("/a/b.html".to_string()))
+        );
+        assert_eq!(
+            ResourceUri::parse("play://files/x/y.txt"),
+            Some(ResourceUri::File("x/y.txt".to_string()))
+        );
+        assert_eq!(
+            ResourceUri::parse("play://data/notes"),
+            Some(ResourceUri::DataCategory("notes".to_string()))
+        );
+        assert_eq!(
+            ResourceUri::parse("play://data/notes/12"),
+            Some(ResourceUri::DataRow("notes".to_string(), 12))
+        );
+        assert_eq!(ResourceUri::parse("play://data/notes/x"), None);
+        assert_eq!(ResourceUri::parse("http://pages/a"), None);
+        assert!(confined_path(Path::new("/tmp"), "../etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_prompts_and_pages() -> anyhow::Result<()> {
+        let pool = init_test_pool().await;
+        GeneralData::insert(
+            PROMPTS_CAT,
+            &json!({
+                "name": "greet",
+                "description": "say hi",
+                "arguments": [{"name": "who", "required": true}],
+                "template": "hello {{who}}!"
+            })
+            .to_string(),
+            &pool,
+        )
+        .await?;
+        GeneralData::insert(
+            PAGES_CAT,
+            &json!({"title": "Home", "url": "/home", "content": hex::encode("<p>hi</p>")})
+                .to_string(),
+            &pool,
+        )
+        .await?;
+
+        let prompts = list_prompts(&pool).await?;
+        assert_eq!(prompts.len(), 1);
+        assert_eq!(prompts[0]["arguments"][0]["name"], "who");
+
+        let args = HashMap::from([("who".to_string(), "play".to_string())]);
+        let prompt = get_prompt("greet", &args, &pool).await?.unwrap();
+        assert_eq!(prompt["messages"][0]["content"]["text"], "hello play!");
+        assert!(get_prompt("greet", &HashMap::new(), &pool).await.is_err());
+        assert!(get_prompt("missing", &args, &pool).await?.is_none());
+
+        let page = read_resource("play://pages/home", &pool).await?.unwrap();
+        assert_eq!(page["contents"][0]["text"], "<p>hi</p>");
+        assert!(read_resource("play://pages/none", &pool).await?.is_none());
+
+        let row = read_resource("play://data/prompts/1", &pool)
+            .await?
+            .unwrap();
+        assert!(row["contents"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("greet"));
+
+        // credentials never leave through mcp
+        GeneralData::insert("ikev2_users", r#"{"name":"a","password":"b"}"#, &pool).await?;
+        assert!(read_resource("play://data/ikev2_users", &pool)
+            .await
+            .is_err());
+        assert!(read_resource("play://data/ikev2_users/3", &pool)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Context};
+use async_trait::async_trait;
+use play_mcp::tools::{Tool, ToolMetadata};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::{info, warn};
+
+use crate::service::mcp_resource_service::category_marker;
+use crate::tables::general_data::GeneralData;
+use crate::AppState;
+
+/// rows of this category are served as mcp tools.
+pub const USER_TOOLS_CAT: &str = "mcp_tools";
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_USER_TOOLS: usize = 1000;
+const MAX_BODY_CHARS: usize = 64 * 1024;
+
+/// a tool row, e.g. `{"name": "weather", "kind": "http", "url": "https://x/?city={{city}}"}`.
+#[derive(Debug, Deserialize)]
+struct UserToolData {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default = "default_input_schema")]
+    input_schema: Value,
+    #[serde(default)]
+    annotations: Option<Value>,
+    #[serde(default = "default_timeout_secs")]
+    timeout_secs: u64,
+    #[serde(flatten)]
+    implementation: UserToolImpl,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum UserToolImpl {
+    /// the arguments are the global `args`, the returned value is the result.
+    Lua { script: String },
+    /// `{{arg}}` placeholders in the url, headers and body are filled from the arguments.
+    Http {
+        url: String,
+        #[serde(default)]
+        method: Option<String>,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default)]
+        body: Option<String>,
+    },
+    /// the arguments are posted as json to a registered plugin route.
+    Plugin { url: String },
+}
+
+fn default_input_schema() -> Value {
+    json!({"type": "object", "properties": {}})
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+struct UserTool {
+    metadata: ToolMetadata,
+    implementation: UserToolImpl,
+    timeout: Duration,
+    state: Arc<AppState>,
+}
+
+impl UserTool {
+    fn new(data: UserToolData, state: Arc<AppState>) -> Self {
+        Self {
+            metadata: ToolMetadata::new(data.name, data.description, data.input_schema)
+                .with_annotations(data.annotations),
+            implementation: data.implementation,
+            timeout: Duration::from_secs(data.timeout_secs.max(1)),
+            state,
+        }
+    }
+}
+
+#[async_trait]
+impl Tool for UserTool {
+    fn metadata(&self) -> &ToolMetadata {
+        &self.metadata
+    }
+
+    async fn execute(&self, input: Value) -> anyhow::Result<Value> {
+        let args = match input {
+            Value::Object(args) => args,
+            Value::Null => Map::new(),
+            _ => bail!("arguments must be an object"),
+        };
+        let run = async {
+            match &self.implementation {
+                UserToolImpl::Lua { script } => run_lua(script, args, self.timeout).await,
+                UserToolImpl::Http {
+                    url,
+                    method,
+                    headers,
+                    body,
+                } => run_http(url, method.as_deref(), headers, body.as_deref(), &args).await,
+                UserToolImpl::Plugin { url } => run_plugin(&self.state, url, args).await,
+            }
+        };
+        tokio::time::timeout(self.timeout, run)
+            .await
+            .map_err(|_| anyhow!("tool `{}` timed out", self.metadata.name))?
+    }
+}
+
+/// the script is stopped by play-lua itself, a busy loop never yields to the outer timeout.
+#[cfg(feature = "play-lua")]
+async fn run_lua(
+    script: &str,
+    args: Map<String, Value>,
+    timeout: Duration,
+) -> anyhow::Result<Value> {
+    let (result, output) = play_lua::run_lua_with_args(script, Value::Object(args), timeout)
+        .await
+        .map_err(|e| anyhow!("lua error : {}", e))?;
+    // scripts that only print still give something back.
+    Ok(if result.is_null() {
+        json!({ "output": output })
+    } else {
+        result
+    })
+}
+
+#[cfg(not(feature = "play-lua"))]
+async fn run_lua(
+    _script: &str,
+    _args: Map<String, Value>,
+    _timeout: Duration,
+) -> anyhow::Result<Value> {
+    bail!("play-lua feature not enabled!")
+}
+
+async fn run_http(
+    url: &str,
+    method: Option<&str>,
+    headers: &HashMap<String, String>,
+    body: Option<&str>,
+    args: &Map<String, Value>,
+) -> anyhow::Result<Value> {
+    let method = reqwest::Method::from_bytes(method.unwrap_or("GET").to_uppercase().as_bytes())
+        .context("invalid http method")?;
+    let url = render_template(url, args, Escape::Url)?;
+    let mut request = reqwest::Client::new().request(method, url);
+    for (name, value) in headers {
+        request = request.header(name, render_template(value, args, Escape::Header)?);
+    }
+    if let Some(body) = body {
+        request = request.body(render_template(body, args, Escape::Json)?);
+    }
+
+    let response = request.send().await?;
+    let status = response.status();
+    let text: String = response
+        .text()
+        .await?
+        .chars()
+        .take(MAX_BODY_CHARS)
+        .collect();
+    ensure!(status.is_success(), "http {} : {}", status, text);
+    Ok(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+}
+
+#[cfg(feature = "play-dylib-loader")]
+async fn run_plugin(
+    state: &AppState,
+    url: &str,
+    args: Map<String, Value>,
+) -> anyhow::Result<Value> {
+    use axum::body::Body;
+    use http::{header, Method, Request};
+
+    let plugin =
+        crate::plugins::find_by_url(url).with_context(|| format!("no plugin serves : {}", url))?;
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(Value::Object(args).to_string()))?;
+    let response =
+        crate::controller::plugin_controller::run_registered_plugin(state, &plugin, request)
+            .await
+            .map_err(|e| anyhow!("{}", e))?;
+
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), MAX_BODY_CHARS * 4).await?;
+    let text = String::from_utf8_lossy(&body).to_string();
+    ensure!(status.is_success(), "plugin returned {} : {}", status, text);
+    Ok(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+}
+
+#[cfg(not(feature = "play-dylib-loader"))]
+async fn run_plugin(
+    _state: &AppState,
+    _url: &str,
+    _args: Map<String, Value>,
+) -> anyhow::Result<Value> {
+    bail!("play-dylib-loader feature not enabled!")
+}
+
+/// how arguments are written into a template.
+#[derive(Clone, Copy)]
+enum Escape {
+    /// url-encoded.
+    Url,
+    /// escaped as inside a json string, an argument can't add fields to a json body.
+    Json,
+    /// as is, control characters are refused so an argument can't add headers.
+    Header,
+}
+
+/// replaces `{{name}}` with the argument, strings as is and other values as json.
+/// missing arguments become empty.
+fn render_template(
+    template: &str,
+    args: &Map<String, Value>,
+    escape: Escape,
+) -> anyhow::Result<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let name = rest[start + 2..start + 2 + end].trim();
+        let value = match args.get(name) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(v) => v.to_string(),
+        };
+        match escape {
+            Escape::Url => result.push_str(&urlencoding::encode(&value)),
+            Escape::Json => {
+                let quoted = Value::String(value).to_string();
+                result.push_str(&quoted[1..quoted.len() - 1]);
+            }
+            Escape::Header => {
+                ensure!(
+                    !value.chars().any(char::is_control),
+                    "argument `{}` can't be used in a header",
+                    name
+                );
+                result.push_str(&value);
+            }
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// reads the tool rows and replaces the served user tools.
+pub async fn reload(state: &Arc<AppState>) -> anyhow::Result<usize> {
+    let rows = GeneralData::query_composite(
+        "*",
+        USER_TOOLS_CAT,
+        &MAX_USER_TOOLS.to_string(),
+        "1=1",
+        false,
+        "id asc",
+        &state.db,
+    )
+    .await?;
+    let mut tools: Vec<Box<dyn Tool>> = vec![];
+    for row in rows {
+        match serde_json::from_str::<UserToolData>(&row.data) {
+            Ok(data) => tools.push(Box::new(UserTool::new(data, state.clone()))),
+            Err(e) => warn!("invalid mcp tool row {} : {}", row.id, e),
+        }
+    }
+    let count = tools.len();
+    for rejected in play_mcp::tools::set_dynamic_tools(USER_TOOLS_CAT, tools) {
+        warn!("mcp tool skipped, {}", rejected);
+    }
+    Ok(count)
+}
+
+/// loads the user tools and reloads them whenever their category changes.
+pub fn start_watcher(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut marker = None;
+        loop {
+            match category_marker(USER_TOOLS_CAT, &state.db).await {
+                Ok(current) if marker.as_ref() != Some(&current) => match reload(&state).await {
+                    Ok(count) => {
+                        info!("mcp user tools loaded : {}", count);
+                        marker = Some(current);
+                    }
+                    Err(e) => warn!("reload mcp user tools failed : {}", e),
+                },
+                Ok(_) => {}
+                Err(e) => warn!("check mcp user tools failed : {}", e),
+            }
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let args = json!({"city": "new york", "days": 3, "empty": null})
+            .as_object()
+            .cloned()
+            .unwrap();
+        assert_eq!(
+            render_template(
+                "https://x/?q={{city}}&d={{ days }}&e={{empty}}{{missing}}",
+                &args,
+                Escape::Url
+            )
+            .unwrap(),
+            "https://x/?q=new%20york&d=3&e="
+        );
+        assert_eq!(
+            render_template(r#"{"city": "{{city}}"} {{"#, &args, Escape::Json).unwrap(),
+            r#"{"city": "new york"} {{"#
+        );
+
+        let args = json!({"city": "x\", \"admin\": true, \"y\": \"", "token": "a\r\nX-Admin: 1"})
+            .as_object()
+            .cloned()
+            .unwrap();
+        let body = render_template(r#"{"city": "{{city}}"}"#, &args, Escape::Json).unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body.as_object().unwrap().len(), 1);
+        assert_eq!(body["city"], args["city"]);
+        assert!(render_template("Bearer {{token}}", &args, Escape::Header).is_err());
+        assert_eq!(
+            render_template("{{city}}", &args, Escape::Header).unwrap(),
+            args["city"]
+        );
+    }
+
+    #[test]
+    fn test_parse_tool_data() {
+        let data: UserToolData = serde_json::from_value(json!({
+            "name": "weather",
+            "kind": "http",
+            "url": "https://x/?city={{city}}",
+        }))
+        .unwrap();
+        assert_eq!(data.timeout_secs, 30);
+        assert_eq!(data.input_schema["type"], "object");
+        assert!(matches!(data.implementation, UserToolImpl::Http { .. }));
+
+        let data: UserToolData = serde_json::from_value(json!({
+            "name": "add",
+            "kind": "lua",
+            "script": "return args.a + args.b",
+        }))
+        .unwrap();
+        assert!(matches!(data.implementation, UserToolImpl::Lua { .. }));
+
+        assert!(
+            serde_json::from_value::<UserToolData>(json!({"name": "x", "kind": "shell"})).is_err()
+        );
+    }
+}
@@ -1 +1,9 @@
+pub mod asset_store_service;
+pub mod file_serve_service;
 pub mod template_service;
+pub mod image_service;
+pub mod upstream_service;
+pub mod proxy_route_service;
+pub mod http_cache_service;
+pub mod mcp_resource_service;
+pub mod mcp_user_tool_service;
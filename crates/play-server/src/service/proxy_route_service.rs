@@ -0,0 +1,319 @@
+use std::net::IpAddr;
+
+use axum::body::Body;
+use http::header::HeaderName;
+use http::{HeaderMap, HeaderValue, Method, Request, Uri};
+use tracing::warn;
+
+use crate::config::{DomainProxy, HeaderRules, ProxyRewrite, ProxyRoute, ProxyTarget};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+/// the part of a domain proxy config that handles a request.
+pub struct MatchedRoute<'a> {
+    /// identifies the upstream pool of the route
+    pub key: String,
+    pub target: &'a ProxyTarget,
+    pub rewrite: &'a ProxyRewrite,
+    /// path prefix that matched, "/" for the domain itself
+    pub prefix: &'a str,
+}
+
+/// the domain proxy for `host`: exact names first, then the longest matching `*.` wildcard.
+pub fn find_domain<'a>(domains: &'a [DomainProxy], host: &str) -> Option<&'a DomainProxy> {
+    if let Some(domain) = domains
+        .iter()
+        .find(|p| p.proxy_domain.eq_ignore_ascii_case(host))
+    {
+        return Some(domain);
+    }
+    domains
+        .iter()
+        .filter(|p| wildcard_matches(&p.proxy_domain, host))
+        .max_by_key(|p| p.proxy_domain.len())
+}
+
+/// `host` is `pattern` itself or, for `*.example.com`, one of its subdomains.
+pub(crate) fn host_matches(pattern: &str, host: &str) -> bool {
+    pattern.eq_ignore_ascii_case(host) || wildcard_matches(pattern, host)
+}
+
+fn wildcard_matches(pattern: &str, host: &str) -> bool {
+    let Some(suffix) = pattern.strip_prefix("*.") else {
+        return false;
+    };
+    // patterns without a port match any port
+    let host = if suffix.contains(':') {
+        host
+    } else {
+        host.rsplit_once(':')
+            .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+            .map_or(host, |(name, _)| name)
+    };
+    let host = host.to_ascii_lowercase();
+    let suffix = suffix.to_ascii_lowercase();
+    host.len() > suffix.len() + 1
+        && host.ends_with(&suffix)
+        && host.as_bytes()[host.len() - suffix.len() - 1] == b'.'
+}
+
+/// `/api` matches `/api` and `/api/x`, but not `/apix`.
+pub(crate) fn path_matches(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+fn method_matches(methods: &[String], method: &Method) -> bool {
+    methods.is_empty()
+        || methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method.as_str()))
+}
+
+fn route_key(domain: &DomainProxy, route: Option<&ProxyRoute>) -> String {
+    match route {
+        Some(route) => format!("{} {}", domain.proxy_domain, route.path_prefix),
+        None => domain.proxy_domain.clone(),
+    }
+}
+
+fn to_matched<'a>(domain: &'a DomainProxy, route: Option<&'a ProxyRoute>) -> MatchedRoute<'a> {
+    match route {
+        Some(r) => MatchedRoute {
+            key: route_key(domain, route),
+            target: &r.proxy_target,
+            rewrite: &r.rewrite,
+            prefix: &r.path_prefix,
+        },
+        None => MatchedRoute {
+            key: route_key(domain, None),
+            target: &domain.proxy_target,
+            rewrite: &domain.rewrite,
+            prefix: "/",
+        },
+    }
+}
+
+/// the longest `path_prefix` route accepting the request, or the domain itself.
+pub fn match_route<'a>(domain: &'a DomainProxy, method: &Method, path: &str) -> MatchedRoute<'a> {
+    let route = domain
+        .routes
+        .iter()
+        .filter(|r| path_matches(&r.path_prefix, path) && method_matches(&r.methods, method))
+        // max_by_key keeps the last maximum, reverse so the first configured route wins ties
+        .rev()
+        .max_by_key(|r| r.path_prefix.trim_end_matches('/').len());
+    to_matched(domain, route)
+}
+
+/// every route of a domain, the domain itself included.
+pub fn all_routes(domain: &DomainProxy) -> Vec<MatchedRoute> {
+    std::iter::once(to_matched(domain, None))
+        .chain(domain.routes.iter().map(|r| to_matched(domain, Some(r))))
+        .collect()
+}
+
+/// the path sent to the target after prefix stripping and adding.
+pub fn rewrite_path(route: &MatchedRoute, path: &str) -> String {
+    let mut path = path.to_string();
+    if route.rewrite.strip_prefix {
+        let rest = &path[route.prefix.trim_end_matches('/').len()..];
+        path = if rest.starts_with('/') {
+            rest.to_string()
+        } else {
+            format!("/{}", rest)
+        };
+    }
+    if let Some(add) = &route.rewrite.add_prefix {
+        let add = add.trim_end_matches('/');
+        if !add.is_empty() {
+            let add = if add.starts_with('/') {
+                add.to_string()
+            } else {
+                format!("/{}", add)
+            };
+            path = format!("{}{}", add, path);
+        }
+    }
+    path
+}
+
+/// rewrite the request path and headers as configured for the route.
+/// `tls` is whether the client connected over TLS, `X-Forwarded-Proto` is always set from it.
+pub fn rewrite_request(
+    route: &MatchedRoute,
+    request: &mut Request<Body>,
+    host: &str,
+    client_ip: IpAddr,
+    tls: bool,
+) -> anyhow::Result<()> {
+    let rewrite = route.rewrite;
+    if rewrite.strip_prefix || rewrite.add_prefix.is_some() {
+        let uri = request.uri();
+        let path = rewrite_path(route, uri.path());
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse()?);
+        *request.uri_mut() = Uri::from_parts(parts)?;
+    }
+
+    if rewrite.x_forwarded {
+        let proto = if tls { "https" } else { "http" };
+        let headers = request.headers_mut();
+        let ip = client_ip.to_canonical().to_string();
+        let forwarded_for = match headers.get(X_FORWARDED_FOR).and_then(|v| v.to_str().ok()) {
+            Some(existing) if !existing.is_empty() => format!("{}, {}", existing, ip),
+            _ => ip,
+        };
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_str(&forwarded_for)?);
+        headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+        headers.insert(X_FORWARDED_HOST, HeaderValue::from_str(host)?);
+        if rewrite.strip_prefix && route.prefix.trim_end_matches('/') != "" {
+            headers.insert(
+                X_FORWARDED_PREFIX,
+                HeaderValue::from_str(route.prefix.trim_end_matches('/'))?,
+            );
+        }
+    }
+
+    apply_header_rules(request.headers_mut(), &rewrite.request_headers);
+    Ok(())
+}
+
+/// apply remove, set and append in that order. invalid names or values are skipped.
+pub fn apply_header_rules(headers: &mut HeaderMap, rules: &HeaderRules) {
+    for name in &rules.remove {
+        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+            headers.remove(name);
+        }
+    }
+    for (name, value) in &rules.set {
+        match parse_header(name, value) {
+            Some((name, value)) => {
+                headers.insert(name, value);
+            }
+            None => warn!("invalid header rule : {} = {}", name, value),
+        }
+    }
+    for (name, value) in &rules.append {
+        match parse_header(name, value) {
+            Some((name, value)) => {
+                headers.append(name, value);
+            }
+            None => warn!("invalid header rule : {} = {}", name, value),
+        }
+    }
+}
+
+fn parse_header(name: &str, value: &str) -> Option<(HeaderName, HeaderValue)> {
+    Some((
+        HeaderName::from_bytes(name.as_bytes()).ok()?,
+        HeaderValue::from_str(value).ok()?,
+    ))
+}
+
+/// `Location` of a redirect target, optionally keeping the request path and query.
+pub fn redirect_location(to: &str, keep_path: bool, uri: &Uri) -> String {
+    if !keep_path {
+        return to.to_string();
+    }
+    let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    format!("{}{}", to.trim_end_matches('/'), path_and_query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_domain() -> DomainProxy {
+        toml::from_str(
+            r#"
+            proxy_domain = "*.example.com"
+            type = "folder"
+            folder_path = "/var/www"
+
+            [[routes]]
+            path_prefix = "/api"
+            type = "upstream"
+            port = 8080
+            strip_prefix = true
+            add_prefix = "/v1"
+            x_forwarded = true
+            request_headers = { set = { "x-app" = "play" }, remove = ["cookie"] }
+
+            [[routes]]
+            path_prefix = "/api/admin"
+            methods = ["POST"]
+            type = "redirect"
+            to = "https://admin.example.com"
+            keep_path = true
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_find_domain_wildcard() {
+        let domains = vec![
+            test_domain(),
+            DomainProxy {
+                proxy_domain: "www.example.com".to_string(),
+                ..Default::default()
+            },
+        ];
+        let find = |host| find_domain(&domains, host).map(|d| d.proxy_domain.as_str());
+        assert_eq!(find("www.example.com"), Some("www.example.com"));
+        assert_eq!(find("app.example.com:8443"), Some("*.example.com"));
+        assert_eq!(find("a.b.example.com"), Some("*.example.com"));
+        assert_eq!(find("example.com"), None);
+        assert_eq!(find("badexample.com"), None);
+    }
+
+    #[test]
+    fn test_match_and_rewrite() {
+        let domain = test_domain();
+        let route = match_route(&domain, &Method::GET, "/index.html");
+        assert!(matches!(route.target, ProxyTarget::Folder { .. }));
+        assert!(matches!(
+            match_route(&domain, &Method::GET, "/apix").target,
+            ProxyTarget::Folder { .. }
+        ));
+
+        // the admin route only accepts POST
+        let route = match_route(&domain, &Method::GET, "/api/admin/users");
+        assert_eq!(route.prefix, "/api");
+        assert_eq!(rewrite_path(&route, "/api/admin/users"), "/v1/admin/users");
+        assert_eq!(rewrite_path(&route, "/api"), "/v1/");
+        let route = match_route(&domain, &Method::POST, "/api/admin/users");
+        assert!(matches!(route.target, ProxyTarget::Redirect { .. }));
+
+        let route = match_route(&domain, &Method::GET, "/api/users?id=1");
+        let mut request = Request::builder()
+            .uri("/api/users?id=1")
+            .header("cookie", "a=b")
+            .header(X_FORWARDED_FOR, "10.0.0.1")
+            .header(X_FORWARDED_PROTO, "https")
+            .body(Body::empty())
+            .unwrap();
+        let ip: IpAddr = "::ffff:1.2.3.4".parse().unwrap();
+        rewrite_request(&route, &mut request, "app.example.com", ip, false).unwrap();
+        assert_eq!(request.uri(), "/v1/users?id=1");
+        let headers = request.headers();
+        assert_eq!(headers[X_FORWARDED_FOR], "10.0.0.1, 1.2.3.4");
+        assert_eq!(headers[X_FORWARDED_HOST], "app.example.com");
+        // a client can't claim https on a plain connection
+        assert_eq!(headers[X_FORWARDED_PROTO], "http");
+        assert_eq!(headers[X_FORWARDED_PREFIX], "/api");
+        assert_eq!(headers["x-app"], "play");
+        assert!(headers.get("cookie").is_none());
+    }
+}
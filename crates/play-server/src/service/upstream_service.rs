@@ -0,0 +1,417 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::{BalanceStrategy, DomainProxy, HealthCheckConfig, ProxyTarget, UpstreamServer};
+use crate::service::proxy_route_service::{all_routes, MatchedRoute};
+
+lazy_static::lazy_static! {
+    /// upstream pools keyed by route, see `MatchedRoute::key`
+    static ref POOLS: RwLock<HashMap<String, Arc<UpstreamPool>>> = RwLock::new(HashMap::new());
+}
+
+pub struct UpstreamMember {
+    pub ip: String,
+    pub port: u16,
+    /// result of the active health check
+    healthy: AtomicBool,
+    active_connections: AtomicUsize,
+    total_requests: AtomicU64,
+    total_failures: AtomicU64,
+    /// consecutive passive failures
+    fails: AtomicU32,
+    /// consecutive active check results, positive for successes and negative for failures
+    check_streak: Mutex<i64>,
+    ejected_until: Mutex<Option<Instant>>,
+    last_error: Mutex<Option<String>>,
+}
+
+/// counts a request as in flight until dropped.
+pub struct ConnectionGuard(Arc<UpstreamMember>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl UpstreamMember {
+    fn new(server: &UpstreamServer) -> Self {
+        Self {
+            ip: server.ip.clone(),
+            port: server.port,
+            healthy: AtomicBool::new(true),
+            active_connections: AtomicUsize::new(0),
+            total_requests: AtomicU64::new(0),
+            total_failures: AtomicU64::new(0),
+            fails: AtomicU32::new(0),
+            check_streak: Mutex::new(0),
+            ejected_until: Mutex::new(None),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+
+    fn ejected_for(&self) -> Option<Duration> {
+        self.ejected_until
+            .lock()
+            .unwrap()
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+    }
+
+    fn is_available(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed) && self.ejected_for().is_none()
+    }
+
+    pub fn acquire(self: &Arc<Self>) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.clone())
+    }
+}
+
+pub struct UpstreamPool {
+    pub domain: String,
+    pub balance: BalanceStrategy,
+    pub health_check: HealthCheckConfig,
+    pub members: Vec<Arc<UpstreamMember>>,
+    next: AtomicUsize,
+}
+
+impl UpstreamPool {
+    fn new(key: &str, target: &ProxyTarget) -> Option<Self> {
+        let (servers, balance, health_check) = match target {
+            ProxyTarget::Upstream { ip, port } => (
+                vec![UpstreamServer {
+                    ip: ip.clone(),
+                    port: *port,
+                }],
+                BalanceStrategy::default(),
+                HealthCheckConfig::default(),
+            ),
+            ProxyTarget::Pool {
+                members,
+                balance,
+                health_check,
+            } => (members.clone(), *balance, health_check.clone()),
+            _ => return None,
+        };
+        Some(Self {
+            domain: key.to_string(),
+            balance,
+            health_check,
+            members: servers
+                .iter()
+                .map(|s| Arc::new(UpstreamMember::new(s)))
+                .collect(),
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// pick a member for the next attempt, skipping the ones already tried.
+    /// when every member is down the request is still tried against one of them.
+    pub fn select(
+        &self,
+        client_ip: IpAddr,
+        tried: &[usize],
+    ) -> Option<(usize, Arc<UpstreamMember>)> {
+        let untried: Vec<usize> = (0..self.members.len())
+            .filter(|i| !tried.contains(i))
+            .collect();
+        let available: Vec<usize> = untried
+            .iter()
+            .copied()
+            .filter(|&i| self.members[i].is_available())
+            .collect();
+        let candidates = if available.is_empty() {
+            untried
+        } else {
+            available
+        };
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let index = match self.balance {
+            BalanceStrategy::RoundRobin => {
+                candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            BalanceStrategy::LeastConn => {
+                // rotate the start so ties are spread evenly
+                let offset = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..candidates.len())
+                    .map(|i| candidates[(i + offset) % candidates.len()])
+                    .min_by_key(|&i| self.members[i].active_connections.load(Ordering::Relaxed))
+                    .unwrap()
+            }
+            BalanceStrategy::IpHash => {
+                let mut hasher = DefaultHasher::new();
+                client_ip.hash(&mut hasher);
+                let hash = hasher.finish() as usize;
+                // keep the mapping stable while the preferred member is up
+                let preferred = hash % self.members.len();
+                if candidates.contains(&preferred) {
+                    preferred
+                } else {
+                    candidates[hash % candidates.len()]
+                }
+            }
+        };
+        Some((index, self.members[index].clone()))
+    }
+
+    /// passive failure detection, called with the outcome of every proxied request.
+    pub fn report(&self, member: &UpstreamMember, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                member.fails.store(0, Ordering::Relaxed);
+            }
+            Err(e) => {
+                member.total_failures.fetch_add(1, Ordering::Relaxed);
+                *member.last_error.lock().unwrap() = Some(e);
+                let fails = member.fails.fetch_add(1, Ordering::Relaxed) + 1;
+                if fails >= self.health_check.max_fails.max(1) {
+                    member.fails.store(0, Ordering::Relaxed);
+                    let timeout = Duration::from_secs(self.health_check.fail_timeout_secs);
+                    *member.ejected_until.lock().unwrap() = Some(Instant::now() + timeout);
+                    warn!(
+                        "upstream {} of {} ejected for {:?} after {} failures",
+                        member.address(),
+                        self.domain,
+                        timeout,
+                        fails
+                    );
+                }
+            }
+        }
+    }
+
+    fn record_check(&self, member: &UpstreamMember, result: Result<(), String>) {
+        let mut streak = member.check_streak.lock().unwrap();
+        match result {
+            Ok(()) => {
+                *streak = (*streak).max(0) + 1;
+                if !member.healthy.load(Ordering::Relaxed)
+                    && *streak >= self.health_check.healthy_threshold.max(1) as i64
+                {
+                    member.healthy.store(true, Ordering::Relaxed);
+                    info!(
+                        "upstream {} of {} is healthy again",
+                        member.address(),
+                        self.domain
+                    );
+                }
+            }
+            Err(e) => {
+                *streak = (*streak).min(0) - 1;
+                *member.last_error.lock().unwrap() = Some(e);
+                if member.healthy.load(Ordering::Relaxed)
+                    && -*streak >= self.health_check.unhealthy_threshold.max(1) as i64
+                {
+                    member.healthy.store(false, Ordering::Relaxed);
+                    warn!(
+                        "upstream {} of {} marked unhealthy",
+                        member.address(),
+                        self.domain
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// `http` or `https` for an upstream, honoring `use_https` of the domain.
+pub fn upstream_scheme(domain: &DomainProxy, port: u16) -> &'static str {
+    match domain.use_https {
+        Some(true) => "https",
+        Some(false) => "http",
+        None => {
+            if port == 443 {
+                "https"
+            } else {
+                "http"
+            }
+        }
+    }
+}
+
+/// the pool of an upstream route, created (and health checked) on first use.
+pub fn pool_for(domain: &DomainProxy, route: &MatchedRoute) -> Option<Arc<UpstreamPool>> {
+    if let Some(pool) = POOLS.read().unwrap().get(&route.key) {
+        return Some(pool.clone());
+    }
+    let mut pools = POOLS.write().unwrap();
+    if let Some(pool) = pools.get(&route.key) {
+        return Some(pool.clone());
+    }
+    let pool = Arc::new(UpstreamPool::new(&route.key, route.target)?);
+    if pool.health_check.enabled {
+        spawn_health_check(pool.clone(), domain.clone());
+    }
+    pools.insert(route.key.clone(), pool.clone());
+    Some(pool)
+}
+
+/// create all upstream pools at startup so health checks run before the first request.
+pub fn init_pools(domains: &[DomainProxy]) {
+    for domain in domains {
+        for route in all_routes(domain) {
+            pool_for(domain, &route);
+        }
+    }
+}
+
+fn spawn_health_check(pool: Arc<UpstreamPool>, domain: DomainProxy) {
+    tokio::spawn(async move {
+        let check = &pool.health_check;
+        let client = match reqwest::Client::builder()
+            .timeout(Duration::from_secs(check.timeout_secs))
+            .danger_accept_invalid_certs(domain.ignore_cert)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("health check client for {} error : {:?}", pool.domain, e);
+                return;
+            }
+        };
+        info!("health check started for upstream pool : {}", pool.domain);
+
+        let mut interval = tokio::time::interval(Duration::from_secs(check.interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            for member in &pool.members {
+                let url = format!(
+                    "{}://{}{}",
+                    upstream_scheme(&domain, member.port),
+                    member.address(),
+                    check.path
+                );
+                let mut request = client.get(&url);
+                // wildcard domains have no host to check with
+                if !domain.proxy_domain.starts_with("*.") {
+                    request = request.header(reqwest::header::HOST, &domain.proxy_domain);
+                }
+                let result = match request.send().await {
+                    Ok(resp) if resp.status().is_success() || resp.status().is_redirection() => {
+                        Ok(())
+                    }
+                    Ok(resp) => Err(format!("health check status {}", resp.status())),
+                    Err(e) => Err(format!("health check error : {}", e)),
+                };
+                pool.record_check(member, result);
+            }
+        }
+    });
+}
+
+#[derive(Serialize, Debug)]
+pub struct PoolStatus {
+    pub domain: String,
+    pub balance: BalanceStrategy,
+    pub health_check: bool,
+    pub members: Vec<MemberStatus>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MemberStatus {
+    pub address: String,
+    pub healthy: bool,
+    /// seconds left until a passively ejected member is tried again
+    pub ejected_secs: Option<u64>,
+    pub active_connections: usize,
+    pub total_requests: u64,
+    pub total_failures: u64,
+    pub last_error: Option<String>,
+}
+
+pub fn pools_status() -> Vec<PoolStatus> {
+    let mut status: Vec<PoolStatus> = POOLS
+        .read()
+        .unwrap()
+        .values()
+        .map(|pool| PoolStatus {
+            domain: pool.domain.clone(),
+            balance: pool.balance,
+            health_check: pool.health_check.enabled,
+            members: pool
+                .members
+                .iter()
+                .map(|m| MemberStatus {
+                    address: m.address(),
+                    healthy: m.healthy.load(Ordering::Relaxed),
+                    ejected_secs: m.ejected_for().map(|d| d.as_secs()),
+                    active_connections: m.active_connections.load(Ordering::Relaxed),
+                    total_requests: m.total_requests.load(Ordering::Relaxed),
+                    total_failures: m.total_failures.load(Ordering::Relaxed),
+                    last_error: m.last_error.lock().unwrap().clone(),
+                })
+                .collect(),
+        })
+        .collect();
+    status.sort_by(|a, b| a.domain.cmp(&b.domain));
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_pool(balance: BalanceStrategy) -> UpstreamPool {
+        let target = ProxyTarget::Pool {
+            members: (1..=3)
+                .map(|i| UpstreamServer {
+                    ip: format!("10.0.0.{}", i),
+                    port: 8080,
+                })
+                .collect(),
+            balance,
+            health_check: HealthCheckConfig {
+                max_fails: 2,
+                ..Default::default()
+            },
+        };
+        UpstreamPool::new("app.example.com", &target).unwrap()
+    }
+
+    #[test]
+    fn test_round_robin_skips_ejected() {
+        let pool = test_pool(BalanceStrategy::RoundRobin);
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let picked: Vec<usize> = (0..3).map(|_| pool.select(ip, &[]).unwrap().0).collect();
+        assert_eq!(picked, vec![0, 1, 2]);
+
+        pool.report(&pool.members[1], Err("down".to_string()));
+        pool.report(&pool.members[1], Err("down".to_string()));
+        for _ in 0..6 {
+            assert_ne!(pool.select(ip, &[]).unwrap().0, 1);
+        }
+        // retries never pick the same member twice
+        assert_eq!(pool.select(ip, &[0, 2]).unwrap().0, 1);
+        assert!(pool.select(ip, &[0, 1, 2]).is_none());
+    }
+
+    #[test]
+    fn test_ip_hash_and_least_conn() {
+        let pool = test_pool(BalanceStrategy::IpHash);
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let first = pool.select(ip, &[]).unwrap().0;
+        assert!((0..5).all(|_| pool.select(ip, &[]).unwrap().0 == first));
+
+        let pool = test_pool(BalanceStrategy::LeastConn);
+        let _busy0 = pool.members[0].acquire();
+        let _busy2 = pool.members[2].acquire();
+        assert_eq!(pool.select(ip, &[]).unwrap().0, 1);
+    }
+}
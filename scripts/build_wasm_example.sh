@@ -1,7 +1,9 @@
 #!/usr/bin/env bash
 set -eux
 
-# for mac
-export WASI_SDK_PATH=/Users/zhouzhipeng/Downloads/wasi-sdk-24.0-arm64-macos
-export CC="${WASI_SDK_PATH}/bin/clang --sysroot=${WASI_SDK_PATH}/share/wasi-sysroot"
-RUSTFLAGS="--cfg wasmedge --cfg tokio_unstable"  cargo build --target wasm32-wasi  --release -p play-wasm-example
+# the plugin is a plain WASI command, no wasi-sdk needed
+rustup target add wasm32-wasip1
+cargo build --target wasm32-wasip1 --release -p play-wasm-example
+
+# upload target/wasm32-wasip1/release/play-wasm-example.wasm to a plugin with kind = "wasm"
+ls -lh target/wasm32-wasip1/release/play-wasm-example.wasm
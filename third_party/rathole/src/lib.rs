@@ -5,12 +5,18 @@ mod constants;
 mod helper;
 mod multi_map;
 mod protocol;
+#[cfg(feature = "server")]
+mod stats;
 mod transport;
 
 pub use cli::Cli;
 use cli::KeypairType;
 pub use config::Config;
 pub use constants::UDP_BUFFER_SIZE;
+#[cfg(feature = "server")]
+pub use stats::{server_stats, ConnectionError, ServerStats, ServiceSnapshot};
+#[cfg(feature = "server")]
+pub use server::open_service_stream;
 
 use anyhow::Result;
 use tokio::sync::{broadcast, mpsc};
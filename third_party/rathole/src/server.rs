@@ -8,6 +8,7 @@ use crate::protocol::{
     self, read_auth, read_hello, Ack, ControlChannelCmd, DataChannelCmd, Hello, UdpTraffic,
     HASH_WIDTH_IN_BYTES,
 };
+use crate::stats::{server_stats, CountingStream, ServiceError, ServiceStats};
 use crate::transport::{SocketOpts, TcpTransport, Transport};
 use anyhow::{anyhow, bail, Context, Result};
 use backoff::backoff::Backoff;
@@ -15,9 +16,12 @@ use backoff::ExponentialBackoff;
 
 use rand::RngCore;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
-use tokio::io::{self, copy_bidirectional, AsyncReadExt, AsyncWriteExt};
+use tokio::io::{
+    self, copy_bidirectional, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream,
+};
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::{broadcast, mpsc, RwLock};
 use tokio::time;
@@ -37,6 +41,7 @@ const TCP_POOL_SIZE: usize = 8; // The number of cached connections for TCP serv
 const UDP_POOL_SIZE: usize = 2; // The number of cached connections for UDP services
 const CHAN_SIZE: usize = 2048; // The capacity of various chans
 const HANDSHAKE_TIMEOUT: u64 = 5; // Timeout for transport handshake
+const LOCAL_VISITOR_BUFFER_SIZE: usize = 64 * 1024; // The buffer of in-process visitor streams
 
 // The entrypoint of running a server
 pub async fn run_server(
@@ -188,17 +193,21 @@ impl<T: 'static + Transport> Server<T> {
                                             let control_channels = self.control_channels.clone();
                                             let server_config = self.config.clone();
                                             tokio::spawn(async move {
-                                                if let Err(err) = handle_connection(conn, services, control_channels, server_config).await {
+                                                if let Err(err) = handle_connection(conn, addr, services, control_channels, server_config).await {
                                                     error!("{:#}", err);
+                                                    let service = err.downcast_ref::<ServiceError>().map(|e| e.service.as_str());
+                                                    server_stats().record_error(service, Some(addr), format!("{:#}", err));
                                                 }
                                             }.instrument(info_span!("connection", %addr)));
                                         }, Err(e) => {
                                             error!("{:#}", e);
+                                            server_stats().record_error(None, Some(addr), format!("{:#}", e));
                                         }
                                     }
                                 },
                                 Err(e) => {
                                     error!("Transport handshake timeout: {}", e);
+                                    server_stats().record_error(None, Some(addr), format!("Transport handshake timeout: {}", e));
                                 }
                             }
                         }
@@ -236,6 +245,7 @@ impl<T: 'static + Transport> Server<T> {
                 ServerServiceChange::Delete(s) => {
                     let hash = protocol::digest(s.as_bytes());
                     let _ = self.services.write().await.remove(&hash);
+                    server_stats().remove_service(&s);
 
                     let mut wg = self.control_channels.write().await;
                     let _ = wg.remove1(&hash);
@@ -249,6 +259,7 @@ impl<T: 'static + Transport> Server<T> {
 // Handle connections to `server.bind_addr`
 async fn handle_connection<T: 'static + Transport>(
     mut conn: T::Stream,
+    addr: SocketAddr,
     services: Arc<RwLock<HashMap<ServiceDigest, ServerServiceConfig>>>,
     control_channels: Arc<RwLock<ControlChannelMap<T>>>,
     server_config: Arc<ServerConfig>,
@@ -259,6 +270,7 @@ async fn handle_connection<T: 'static + Transport>(
         ControlChannelHello(_, service_digest) => {
             do_control_channel_handshake(
                 conn,
+                addr,
                 services,
                 control_channels,
                 service_digest,
@@ -275,6 +287,7 @@ async fn handle_connection<T: 'static + Transport>(
 
 async fn do_control_channel_handshake<T: 'static + Transport>(
     mut conn: T::Stream,
+    addr: SocketAddr,
     services: Arc<RwLock<HashMap<ServiceDigest, ServerServiceConfig>>>,
     control_channels: Arc<RwLock<ControlChannelMap<T>>>,
     service_digest: ServiceDigest,
@@ -327,7 +340,10 @@ async fn do_control_channel_handshake<T: 'static + Transport>(
             hex::encode(session_key),
             hex::encode(d)
         );
-        bail!("Service {} failed the authentication", service_name);
+        bail!(ServiceError {
+            service: service_name.clone(),
+            message: format!("Service {} failed the authentication", service_name),
+        });
     } else {
         let mut h = control_channels.write().await;
 
@@ -349,7 +365,7 @@ async fn do_control_channel_handshake<T: 'static + Transport>(
 
         info!(service = %service_config.name, "Control channel established");
         let handle =
-            ControlChannelHandle::new(conn, service_config, server_config.heartbeat_interval);
+            ControlChannelHandle::new(conn, addr, service_config, server_config.heartbeat_interval);
 
         // Insert the new handle
         let _ = h.insert(service_digest, session_key, handle);
@@ -401,9 +417,14 @@ where
     #[instrument(name = "handle", skip_all, fields(service = %service.name))]
     fn new(
         conn: T::Stream,
+        client_addr: SocketAddr,
         service: ServerServiceConfig,
         heartbeat_interval: u64,
     ) -> ControlChannelHandle<T> {
+        let stats = server_stats().service(&service.name);
+        let session = server_stats().next_session();
+        stats.connect(session, client_addr);
+
         // Create a shutdown channel
         let (shutdown_tx, shutdown_rx) = broadcast::channel::<bool>(1);
 
@@ -427,19 +448,24 @@ where
 
         let shutdown_rx_clone = shutdown_tx.subscribe();
         let bind_addr = service.bind_addr.clone();
+        let pool_stats = stats.clone();
+        let service_name = service.name.clone();
         match service.service_type {
             ServiceType::Tcp => tokio::spawn(
                 async move {
                     if let Err(e) = run_tcp_connection_pool::<T>(
+                        service_name.clone(),
                         bind_addr,
                         data_ch_rx,
                         data_ch_req_tx,
                         shutdown_rx_clone,
+                        pool_stats,
                     )
                     .await
                     .with_context(|| "Failed to run TCP connection pool")
                     {
                         error!("{:#}", e);
+                        server_stats().record_error(Some(&service_name), None, format!("{:#}", e));
                     }
                 }
                 .instrument(Span::current()),
@@ -451,11 +477,13 @@ where
                         data_ch_rx,
                         data_ch_req_tx,
                         shutdown_rx_clone,
+                        pool_stats,
                     )
                     .await
                     .with_context(|| "Failed to run TCP connection pool")
                     {
                         error!("{:#}", e);
+                        server_stats().record_error(Some(&service_name), None, format!("{:#}", e));
                     }
                 }
                 .instrument(Span::current()),
@@ -471,11 +499,18 @@ where
         };
 
         // Run the control channel
+        let service_name = service.name.clone();
         tokio::spawn(
             async move {
                 if let Err(err) = ch.run().await {
                     error!("{:#}", err);
+                    server_stats().record_error(
+                        Some(&service_name),
+                        Some(client_addr),
+                        format!("{:#}", err),
+                    );
                 }
+                stats.disconnect(session);
             }
             .instrument(Span::current()),
         );
@@ -622,21 +657,122 @@ fn tcp_listen_and_send(
     rx
 }
 
+// A visitor of a TCP service, from its listener or opened in-process
+trait Visitor: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Visitor for S {}
+
+// Senders of in-process visitors, indexed by service name
+struct LocalVisitorSender {
+    visitor_tx: mpsc::Sender<DuplexStream>,
+    data_ch_req_tx: mpsc::UnboundedSender<bool>,
+}
+
+fn local_visitors() -> &'static Mutex<HashMap<String, LocalVisitorSender>> {
+    static LOCAL_VISITORS: OnceLock<Mutex<HashMap<String, LocalVisitorSender>>> = OnceLock::new();
+    LOCAL_VISITORS.get_or_init(Default::default)
+}
+
+// Unregisters the in-process visitors of a connection pool when dropped
+struct LocalVisitorRegistration {
+    service: String,
+    visitor_tx: mpsc::Sender<DuplexStream>,
+}
+
+impl LocalVisitorRegistration {
+    fn new(
+        service: String,
+        visitor_tx: mpsc::Sender<DuplexStream>,
+        data_ch_req_tx: mpsc::UnboundedSender<bool>,
+    ) -> Self {
+        local_visitors().lock().unwrap().insert(
+            service.clone(),
+            LocalVisitorSender {
+                visitor_tx: visitor_tx.clone(),
+                data_ch_req_tx,
+            },
+        );
+        LocalVisitorRegistration {
+            service,
+            visitor_tx,
+        }
+    }
+}
+
+impl Drop for LocalVisitorRegistration {
+    fn drop(&mut self) {
+        let mut visitors = local_visitors().lock().unwrap();
+        // A newer control channel of the service may have registered already
+        if visitors
+            .get(&self.service)
+            .is_some_and(|v| v.visitor_tx.same_channel(&self.visitor_tx))
+        {
+            visitors.remove(&self.service);
+        }
+    }
+}
+
+// Open a stream to the local service behind a connected client, as if a visitor
+// connected to the service's `bind_addr`. Works for TCP services without `bind_addr` too.
+pub async fn open_service_stream(service: &str) -> Result<DuplexStream> {
+    let (visitor_tx, data_ch_req_tx) = {
+        let visitors = local_visitors().lock().unwrap();
+        let sender = visitors
+            .get(service)
+            .ok_or_else(|| anyhow!("Service {} has no connected client", service))?;
+        (sender.visitor_tx.clone(), sender.data_ch_req_tx.clone())
+    };
+    let (local, remote) = io::duplex(LOCAL_VISITOR_BUFFER_SIZE);
+    data_ch_req_tx
+        .send(true)
+        .map_err(|_| anyhow!("The control channel of service {} is closed", service))?;
+    visitor_tx
+        .send(remote)
+        .await
+        .map_err(|_| anyhow!("The connection pool of service {} is closed", service))?;
+    Ok(local)
+}
+
+async fn recv_listener(rx: &mut Option<mpsc::Receiver<TcpStream>>) -> Option<TcpStream> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
 #[instrument(skip_all)]
 async fn run_tcp_connection_pool<T: Transport>(
+    service_name: String,
     bind_addr: String,
     mut data_ch_rx: mpsc::Receiver<T::Stream>,
     data_ch_req_tx: mpsc::UnboundedSender<bool>,
-    shutdown_rx: broadcast::Receiver<bool>,
+    mut shutdown_rx: broadcast::Receiver<bool>,
+    stats: Arc<ServiceStats>,
 ) -> Result<()> {
-    let mut visitor_rx = tcp_listen_and_send(bind_addr, data_ch_req_tx.clone(), shutdown_rx);
+    // Services without `bind_addr` are only reachable through `open_service_stream`
+    let mut visitor_rx = (!bind_addr.is_empty())
+        .then(|| tcp_listen_and_send(bind_addr, data_ch_req_tx.clone(), shutdown_rx.resubscribe()));
+    let (local_visitor_tx, mut local_visitor_rx) = mpsc::channel(CHAN_SIZE);
+    let _registration =
+        LocalVisitorRegistration::new(service_name, local_visitor_tx, data_ch_req_tx.clone());
     let cmd = bincode::serialize(&DataChannelCmd::StartForwardTcp).unwrap();
 
-    'pool: while let Some(mut visitor) = visitor_rx.recv().await {
+    'pool: loop {
+        let visitor: Box<dyn Visitor> = tokio::select! {
+            visitor = recv_listener(&mut visitor_rx) => match visitor {
+                Some(visitor) => Box::new(visitor),
+                None => break,
+            },
+            Some(visitor) = local_visitor_rx.recv() => Box::new(visitor),
+            _ = shutdown_rx.recv() => break,
+        };
         loop {
             if let Some(mut ch) = data_ch_rx.recv().await {
                 if write_and_flush(&mut ch, &cmd).await.is_ok() {
+                    let stats = stats.clone();
                     tokio::spawn(async move {
+                        let _visitor = stats.visitor();
+                        let mut visitor = CountingStream::new(visitor, stats);
                         let _ = copy_bidirectional(&mut ch, &mut visitor).await;
                     });
                     break;
@@ -662,6 +798,7 @@ async fn run_udp_connection_pool<T: Transport>(
     mut data_ch_rx: mpsc::Receiver<T::Stream>,
     _data_ch_req_tx: mpsc::UnboundedSender<bool>,
     mut shutdown_rx: broadcast::Receiver<bool>,
+    stats: Arc<ServiceStats>,
 ) -> Result<()> {
     // TODO: Load balance
 
@@ -693,6 +830,7 @@ async fn run_udp_connection_pool<T: Transport>(
             // Forward inbound traffic to the client
             val = l.recv_from(&mut buf) => {
                 let (n, from) = val?;
+                stats.add_in(n);
                 UdpTraffic::write_slice(&mut conn, from, &buf[..n]).await?;
             },
 
@@ -700,6 +838,7 @@ async fn run_udp_connection_pool<T: Transport>(
             hdr_len = conn.read_u8() => {
                 let t = UdpTraffic::read(&mut conn, hdr_len?).await?;
                 l.send_to(&t.data, t.from).await?;
+                stats.add_out(t.data.len());
             }
 
             _ = shutdown_rx.recv() => {
@@ -0,0 +1,285 @@
+// Runtime statistics of the server, for embedders that want to show them
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// The number of connection errors kept for `ServerStats::recent_errors`
+const MAX_RECENT_ERRORS: usize = 100;
+
+static SERVER_STATS: OnceLock<ServerStats> = OnceLock::new();
+
+// The statistics of all the servers running in this process
+pub fn server_stats() -> &'static ServerStats {
+    SERVER_STATS.get_or_init(ServerStats::default)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone)]
+struct ControlChannelInfo {
+    session: u64,
+    client_addr: SocketAddr,
+    connected_since: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ServiceStats {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    active_connections: AtomicU64,
+    total_connections: AtomicU64,
+    control_channel: Mutex<Option<ControlChannelInfo>>,
+}
+
+impl ServiceStats {
+    pub(crate) fn connect(&self, session: u64, client_addr: SocketAddr) {
+        *self.control_channel.lock().unwrap() = Some(ControlChannelInfo {
+            session,
+            client_addr,
+            connected_since: now_secs(),
+        });
+    }
+
+    // A replaced control channel shuts down after the new one is connected
+    pub(crate) fn disconnect(&self, session: u64) {
+        let mut control_channel = self.control_channel.lock().unwrap();
+        if control_channel.as_ref().map(|c| c.session) == Some(session) {
+            *control_channel = None;
+        }
+    }
+
+    pub(crate) fn add_in(&self, n: usize) {
+        self.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_out(&self, n: usize) {
+        self.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    // Count a visitor until the returned guard is dropped
+    pub(crate) fn visitor(self: &Arc<Self>) -> VisitorGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        VisitorGuard(self.clone())
+    }
+}
+
+pub(crate) struct VisitorGuard(Arc<ServiceStats>);
+
+impl Drop for VisitorGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceSnapshot {
+    pub name: String,
+    pub connected: bool,
+    // The address of the client holding the control channel
+    pub client_addr: Option<SocketAddr>,
+    // Unix timestamp in seconds
+    pub connected_since: Option<u64>,
+    // Bytes received from visitors
+    pub bytes_in: u64,
+    // Bytes sent to visitors
+    pub bytes_out: u64,
+    pub active_connections: u64,
+    pub total_connections: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionError {
+    // Unix timestamp in seconds
+    pub time: u64,
+    pub service: Option<String>,
+    pub addr: Option<SocketAddr>,
+    pub message: String,
+}
+
+// An error that can be attributed to a service, recorded with its name
+#[derive(Debug)]
+pub(crate) struct ServiceError {
+    pub(crate) service: String,
+    pub(crate) message: String,
+}
+
+impl Display for ServiceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    services: RwLock<HashMap<String, Arc<ServiceStats>>>,
+    errors: Mutex<VecDeque<ConnectionError>>,
+    sessions: AtomicU64,
+}
+
+impl ServerStats {
+    pub(crate) fn service(&self, name: &str) -> Arc<ServiceStats> {
+        if let Some(stats) = self.services.read().unwrap().get(name) {
+            return stats.clone();
+        }
+        self.services
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub(crate) fn remove_service(&self, name: &str) {
+        self.services.write().unwrap().remove(name);
+    }
+
+    pub(crate) fn next_session(&self) -> u64 {
+        self.sessions.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_error(
+        &self,
+        service: Option<&str>,
+        addr: Option<SocketAddr>,
+        message: String,
+    ) {
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() == MAX_RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(ConnectionError {
+            time: now_secs(),
+            service: service.map(str::to_string),
+            addr,
+            message,
+        });
+    }
+
+    // Services that have been seen since they were configured
+    pub fn services(&self) -> Vec<ServiceSnapshot> {
+        let services = self.services.read().unwrap();
+        let mut ret: Vec<_> = services
+            .iter()
+            .map(|(name, s)| {
+                let control_channel = s.control_channel.lock().unwrap().clone();
+                ServiceSnapshot {
+                    name: name.clone(),
+                    connected: control_channel.is_some(),
+                    client_addr: control_channel.as_ref().map(|c| c.client_addr),
+                    connected_since: control_channel.as_ref().map(|c| c.connected_since),
+                    bytes_in: s.bytes_in.load(Ordering::Relaxed),
+                    bytes_out: s.bytes_out.load(Ordering::Relaxed),
+                    active_connections: s.active_connections.load(Ordering::Relaxed),
+                    total_connections: s.total_connections.load(Ordering::Relaxed),
+                }
+            })
+            .collect();
+        ret.sort_by(|a, b| a.name.cmp(&b.name));
+        ret
+    }
+
+    // The latest connection errors, oldest first
+    pub fn recent_errors(&self) -> Vec<ConnectionError> {
+        self.errors.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// Counts the bytes read from and written to a visitor
+pub(crate) struct CountingStream<S> {
+    inner: S,
+    stats: Arc<ServiceStats>,
+}
+
+impl<S> CountingStream<S> {
+    pub(crate) fn new(inner: S, stats: Arc<ServiceStats>) -> Self {
+        CountingStream { inner, stats }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let ret = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.stats.add_in(buf.filled().len() - before);
+        ret
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let ret = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = ret {
+            self.stats.add_out(n);
+        }
+        ret
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_counting_stream() {
+        let stats = ServerStats::default();
+        let service = stats.service("foo");
+        let (a, mut b) = tokio::io::duplex(64);
+        let mut a = CountingStream::new(a, service.clone());
+
+        let guard = service.visitor();
+        b.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        a.read_exact(&mut buf).await.unwrap();
+        a.write_all(b"hi").await.unwrap();
+
+        service.connect(1, "127.0.0.1:1000".parse().unwrap());
+        let snapshot = &stats.services()[0];
+        assert_eq!(snapshot.bytes_in, 5);
+        assert_eq!(snapshot.bytes_out, 2);
+        assert_eq!(snapshot.active_connections, 1);
+        assert!(snapshot.connected);
+
+        // a stale session doesn't disconnect the current one
+        service.disconnect(0);
+        assert!(stats.services()[0].connected);
+        drop(guard);
+        service.disconnect(1);
+        let snapshot = &stats.services()[0];
+        assert!(!snapshot.connected);
+        assert_eq!(snapshot.active_connections, 0);
+        assert_eq!(snapshot.total_connections, 1);
+    }
+}